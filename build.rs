@@ -0,0 +1,60 @@
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tonic_build::manual::{Builder, Method, Service};
+
+fn main() {
+  record_build_info();
+  build_grpc_service();
+}
+
+// Records which commit the binary is built from and when, for /$version
+fn record_build_info() {
+  let git_commit = Command::new("git")
+    .args(["rev-parse", "--short=12", "HEAD"])
+    .output()
+    .ok()
+    .filter(|output| output.status.success())
+    .and_then(|output| String::from_utf8(output.stdout).ok())
+    .map(|git_commit| git_commit.trim().to_owned())
+    .unwrap_or_default();
+  let build_time =
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+  println!("cargo:rustc-env=MAXWELL_GIT_COMMIT={}", git_commit);
+  println!("cargo:rustc-env=MAXWELL_BUILD_TIME={}", build_time);
+  println!("cargo:rerun-if-changed=.git/HEAD");
+  println!("cargo:rerun-if-changed=.git/refs/heads");
+}
+
+// The messages are those of maxwell-protocol, so the service is defined here
+// rather than generated from a proto, which proto/maxwell_master.proto mirrors
+// for the clients
+fn build_grpc_service() {
+  let methods = [
+    ("pick_frontend", "PickFrontend", "PickFrontendReq", "PickFrontendRep"),
+    ("get_routes", "GetRoutes", "GetRoutesReq", "GetRoutesRep"),
+    ("locate_topic", "LocateTopic", "LocateTopicReq", "LocateTopicRep"),
+    ("set_routes", "SetRoutes", "SetRoutesReq", "SetRoutesRep"),
+    ("register_frontend", "RegisterFrontend", "RegisterFrontendReq", "RegisterFrontendRep"),
+    ("register_backend", "RegisterBackend", "RegisterBackendReq", "RegisterBackendRep"),
+    ("register_service", "RegisterService", "RegisterServiceReq", "RegisterServiceRep"),
+  ];
+  let service = methods
+    .iter()
+    .fold(
+      Service::builder().name("Master").package("maxwell.master"),
+      |service, (name, route_name, input_type, output_type)| {
+        service.method(
+          Method::builder()
+            .name(name)
+            .route_name(route_name)
+            .input_type(format!("maxwell_protocol::{}", input_type))
+            .output_type(format!("maxwell_protocol::{}", output_type))
+            .codec_path("tonic::codec::ProstCodec")
+            .build(),
+        )
+      },
+    )
+    .build();
+  Builder::new().build_client(false).compile(&[service]);
+}
@@ -0,0 +1,233 @@
+use std::{
+  fs,
+  io::BufReader,
+  path::PathBuf,
+  sync::{Arc, RwLock},
+  time::Duration,
+};
+
+use ahash::RandomState as AHasher;
+use anyhow::{anyhow, Context, Result};
+use dashmap::DashMap;
+use instant_acme::{
+  Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, NewAccount,
+  NewOrder, Order, OrderStatus,
+};
+use once_cell::sync::Lazy;
+use rcgen::{CertificateParams, DistinguishedName, KeyPair};
+use rustls::{
+  crypto::aws_lc_rs::sign::any_supported_type,
+  server::{ClientHello, ResolvesServerCert},
+  sign::CertifiedKey,
+};
+use rustls_pemfile::{certs, private_key};
+use x509_parser::prelude::*;
+
+use crate::{clock, config::CONFIG};
+
+const ACCOUNT_FILE: &str = "account.json";
+const CERT_FILE: &str = "cert.pem";
+const KEY_FILE: &str = "key.pem";
+
+// How many times, a second apart at first, the order is polled before giving up
+const MAX_POLLS: u32 = 10;
+
+// Token to key authorization, of the http-01 challenges being validated
+static CHALLENGES: Lazy<DashMap<String, String, AHasher>> =
+  Lazy::new(|| DashMap::with_hasher(AHasher::default()));
+
+// Serves the https listener, with the certificate last issued, so that renewals
+// take effect without restarting
+pub static CERT_RESOLVER: Lazy<Arc<CertResolver>> = Lazy::new(|| {
+  let resolver = CertResolver::default();
+  match load_certified_key() {
+    Ok(Some(certified_key)) => resolver.set(certified_key),
+    Ok(None) => {
+      log::info!("No certificate has been issued yet: domain: {:?}", CONFIG.load().acme.domain)
+    }
+    Err(err) => log::error!("Failed to load the issued certificate: err: {:?}", err),
+  }
+  Arc::new(resolver)
+});
+
+#[derive(Debug, Default)]
+pub struct CertResolver {
+  certified_key: RwLock<Option<Arc<CertifiedKey>>>,
+}
+
+impl CertResolver {
+  #[inline]
+  fn set(&self, certified_key: CertifiedKey) {
+    *self.certified_key.write().unwrap() = Some(Arc::new(certified_key));
+  }
+
+  // Seconds since the epoch, none if nothing has been issued
+  fn expires_at(&self) -> Option<i64> {
+    let certified_key = self.certified_key.read().unwrap().clone()?;
+    let cert = certified_key.end_entity_cert().ok()?;
+    let (_, cert) = parse_x509_certificate(cert.as_ref()).ok()?;
+    Some(cert.validity().not_after.timestamp())
+  }
+}
+
+impl ResolvesServerCert for CertResolver {
+  fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+    self.certified_key.read().unwrap().clone()
+  }
+}
+
+// Served at /.well-known/acme-challenge/{token}
+#[inline]
+pub fn key_authorization_of(token: &str) -> Option<String> {
+  CHALLENGES.get(token).map(|key_authorization| key_authorization.clone())
+}
+
+// Issues the certificate once missing, and renews it once about to expire
+pub fn spawn_renew_task() {
+  if !CONFIG.load().acme.enabled {
+    return;
+  }
+  actix_web::rt::spawn(async {
+    let mut interval =
+      tokio::time::interval(Duration::from_secs(CONFIG.load().acme.check_interval.max(60)));
+    loop {
+      interval.tick().await;
+      let renew_at = CERT_RESOLVER
+        .expires_at()
+        .map(|expires_at| expires_at - (CONFIG.load().acme.renew_before * 24 * 3600) as i64);
+      if renew_at.is_some_and(|renew_at| (clock::now() as i64) < renew_at) {
+        continue;
+      }
+      log::info!(
+        "Issuing certificate: domain: {:?}, renew_at: {:?}",
+        CONFIG.load().acme.domain,
+        renew_at
+      );
+      match issue().await {
+        Ok(certified_key) => {
+          CERT_RESOLVER.set(certified_key);
+          log::info!("Issued certificate: domain: {:?}", CONFIG.load().acme.domain);
+        }
+        Err(err) => log::error!("Failed to issue certificate: err: {:?}", err),
+      }
+    }
+  });
+}
+
+async fn issue() -> Result<CertifiedKey> {
+  let account = load_or_create_account().await?;
+  let identifiers = [Identifier::Dns(CONFIG.load().acme.domain.clone())];
+  let mut order = account.new_order(&NewOrder { identifiers: &identifiers }).await?;
+
+  let mut tokens = vec![];
+  let result = async {
+    for authorization in order.authorizations().await? {
+      match authorization.status {
+        AuthorizationStatus::Pending => {}
+        AuthorizationStatus::Valid => continue,
+        status => return Err(anyhow!("Unexpected authorization status: {:?}", status)),
+      }
+      let challenge = authorization
+        .challenges
+        .iter()
+        .find(|challenge| challenge.r#type == ChallengeType::Http01)
+        .ok_or_else(|| anyhow!("No http-01 challenge is offered"))?;
+      let key_authorization = order.key_authorization(challenge).as_str().to_owned();
+      CHALLENGES.insert(challenge.token.clone(), key_authorization);
+      tokens.push(challenge.token.clone());
+      order.set_challenge_ready(&challenge.url).await?;
+    }
+    finalize(&mut order).await
+  }
+  .await;
+  for token in tokens {
+    CHALLENGES.remove(&token);
+  }
+  result
+}
+
+async fn finalize(order: &mut Order) -> Result<CertifiedKey> {
+  let mut delay = Duration::from_secs(1);
+  let mut polls = 0;
+  loop {
+    let status = order.refresh().await?.status;
+    match status {
+      OrderStatus::Ready => break,
+      OrderStatus::Invalid => return Err(anyhow!("The order is invalid: {:?}", order.state())),
+      _ if polls >= MAX_POLLS => return Err(anyhow!("The order is not ready in time")),
+      _ => {
+        polls += 1;
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(Duration::from_secs(10));
+      }
+    }
+  }
+
+  let mut params = CertificateParams::new(vec![CONFIG.load().acme.domain.clone()])?;
+  params.distinguished_name = DistinguishedName::new();
+  let key_pair = KeyPair::generate()?;
+  let csr = params.serialize_request(&key_pair)?;
+  order.finalize(csr.der()).await?;
+
+  let mut polls = 0;
+  let cert_chain = loop {
+    if let Some(cert_chain) = order.certificate().await? {
+      break cert_chain;
+    }
+    if polls >= MAX_POLLS {
+      return Err(anyhow!("The certificate is not issued in time"));
+    }
+    polls += 1;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+  };
+
+  let dir = acme_dir();
+  fs::create_dir_all(&dir)?;
+  fs::write(dir.join(KEY_FILE), key_pair.serialize_pem())?;
+  fs::write(dir.join(CERT_FILE), &cert_chain)?;
+  load_certified_key()?.ok_or_else(|| anyhow!("The issued certificate is not found"))
+}
+
+// Reuses the account registered before, so that the rate limits of the ca apply
+// to a single account
+async fn load_or_create_account() -> Result<Account> {
+  let path = acme_dir().join(ACCOUNT_FILE);
+  if path.exists() {
+    let credentials: AccountCredentials = serde_json::from_slice(&fs::read(&path)?)
+      .with_context(|| format!("Failed to parse account from: {:?}", path))?;
+    return Ok(Account::from_credentials(credentials).await?);
+  }
+
+  let contact = format!("mailto:{}", CONFIG.load().acme.contact_email);
+  let contacts: Vec<&str> =
+    if CONFIG.load().acme.contact_email.is_empty() { vec![] } else { vec![contact.as_str()] };
+  let (account, credentials) = Account::create(
+    &NewAccount { contact: &contacts, terms_of_service_agreed: true, only_return_existing: false },
+    &CONFIG.load().acme.directory_url,
+    None,
+  )
+  .await?;
+  fs::create_dir_all(acme_dir())?;
+  fs::write(&path, serde_json::to_vec(&credentials)?)?;
+  log::info!("Created acme account: directory_url: {:?}", CONFIG.load().acme.directory_url);
+  Ok(account)
+}
+
+fn load_certified_key() -> Result<Option<CertifiedKey>> {
+  let dir = acme_dir();
+  let (cert_path, key_path) = (dir.join(CERT_FILE), dir.join(KEY_FILE));
+  if !cert_path.exists() || !key_path.exists() {
+    return Ok(None);
+  }
+  let cert_chain =
+    certs(&mut BufReader::new(fs::File::open(&cert_path)?)).collect::<Result<Vec<_>, _>>()?;
+  let key = private_key(&mut BufReader::new(fs::File::open(&key_path)?))?
+    .ok_or_else(|| anyhow!("No key found in: {:?}", key_path))?;
+  Ok(Some(CertifiedKey::new(cert_chain, any_supported_type(&key)?)))
+}
+
+// Next to the db, in the data directory
+#[inline]
+fn acme_dir() -> PathBuf {
+  PathBuf::from(&CONFIG.load().db.path).join("acme")
+}
@@ -0,0 +1,214 @@
+use std::{
+  borrow::Borrow,
+  sync::atomic::{AtomicU32, Ordering},
+  time::Duration,
+};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use once_cell::sync::Lazy;
+use serde_json::Value;
+use seriesdb::coder::Coder;
+
+use crate::{
+  clock,
+  config::CONFIG,
+  event_bus::{self, Event},
+  store::{open_store, Store},
+};
+
+// Acting on behalf of nobody, e.g. sweeping stale nodes
+pub const SYSTEM_ACTOR: &str = "system";
+
+// Ordered by time, the seq tells apart the records of the same second
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct AuditKey {
+  pub at: u32,
+  pub seq: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AuditRecord {
+  pub at: u32,
+  // Who asked for the mutation, e.g. "backend:backend-0@10.0.0.1:50000"
+  pub actor: String,
+  pub action: String,
+  #[serde(default)]
+  pub details: Value,
+}
+
+type AuditStore = dyn Store<AuditKey, AuditRecord>;
+
+pub(crate) struct AuditCoder;
+
+impl Coder<AuditKey, AuditRecord> for AuditCoder {
+  type EncodedKey = Bytes;
+  type EncodedValue = Bytes;
+
+  #[inline(always)]
+  fn encode_key<K: Borrow<AuditKey>>(key: K) -> Self::EncodedKey {
+    let key = key.borrow();
+    let mut buf = BytesMut::with_capacity(8);
+    buf.put_u32(key.at);
+    buf.put_u32(key.seq);
+    buf.freeze()
+  }
+
+  #[inline(always)]
+  fn decode_key(key: &[u8]) -> AuditKey {
+    AuditKey {
+      at: u32::from_be_bytes(key[..4].try_into().unwrap()),
+      seq: u32::from_be_bytes(key[4..8].try_into().unwrap()),
+    }
+  }
+
+  // Json, as the details are schemaless
+  #[inline(always)]
+  fn encode_value<V: Borrow<AuditRecord>>(value: V) -> Self::EncodedValue {
+    serde_json::to_vec(value.borrow()).unwrap().into()
+  }
+
+  #[inline(always)]
+  fn decode_value(value: &[u8]) -> AuditRecord {
+    serde_json::from_slice(value).unwrap()
+  }
+}
+
+// Append only, except for the records older than the retention
+pub struct AuditLog {
+  audit_store: Box<AuditStore>,
+  seq: AtomicU32,
+}
+
+impl AuditLog {
+  #[inline]
+  fn new(audit_store: Box<AuditStore>) -> Self {
+    AuditLog { audit_store, seq: AtomicU32::new(0) }
+  }
+
+  pub fn record(&self, actor: String, action: &str, details: Value) {
+    let at = clock::now();
+    let key = AuditKey { at, seq: self.seq.fetch_add(1, Ordering::Relaxed) };
+    let record = AuditRecord { at, actor, action: action.to_owned(), details };
+    self.audit_store.put(&key, &record).unwrap_or_else(|err| {
+      log::warn!("Failed to add audit record: {:?}, err: {:?}", record, err);
+    });
+  }
+
+  // Ordered by time, at most limit records, and whether more matched
+  pub fn query(
+    &self, since: u32, until: Option<u32>, actor: Option<&str>, action: Option<&str>, limit: usize,
+  ) -> (Vec<AuditRecord>, bool) {
+    let mut records = vec![];
+    let mut has_more = false;
+    self.audit_store.scan(Some(&AuditKey { at: since, seq: 0 }), &mut |_, record| {
+      if until.is_some_and(|until| record.at > until) {
+        return false;
+      }
+      if actor.is_none_or(|actor| record.actor.contains(actor))
+        && action.is_none_or(|action| record.action == action)
+      {
+        if records.len() == limit {
+          has_more = true;
+          return false;
+        }
+        records.push(record);
+      }
+      true
+    });
+    (records, has_more)
+  }
+
+  // Returns the number of records deleted
+  pub fn prune(&self, before: u32) -> usize {
+    let mut keys = vec![];
+    self.audit_store.scan(None, &mut |key, _| {
+      if key.at >= before {
+        return false;
+      }
+      keys.push(key);
+      true
+    });
+    for key in &keys {
+      self.audit_store.delete(key).unwrap_or_else(|err| {
+        log::warn!("Failed to delete audit record: {:?}, err: {:?}", key, err);
+      });
+    }
+    keys.len()
+  }
+}
+
+pub static AUDIT_LOG: Lazy<AuditLog> = Lazy::new(|| {
+  AuditLog::new(open_store::<AuditKey, AuditRecord, AuditCoder>("audit.records").unwrap())
+});
+
+#[inline]
+pub fn record(actor: impl Into<String>, action: &str, details: Value) {
+  if CONFIG.load().audit.enabled {
+    AUDIT_LOG.record(actor.into(), action, details);
+  }
+}
+
+// The transitions no request asked for, e.g. a service turning unhealthy, as
+// published on the event bus
+pub fn spawn_event_task() {
+  if !CONFIG.load().audit.enabled {
+    return;
+  }
+  actix_web::rt::spawn(async {
+    let mut receiver = event_bus::subscribe();
+    loop {
+      match receiver.recv().await {
+        Ok(event) => {
+          let action = match &event {
+            Event::NodeAdded { .. } => "node-added",
+            Event::NodeRemoved { .. } => "node-removed",
+            Event::NodeHealthChanged { .. } => "node-health-changed",
+            Event::TopicReassigned { .. } => "topic-reassigned",
+            // Recorded as the set-routes causing it
+            Event::RoutesChanged { .. } => continue,
+          };
+          record(SYSTEM_ACTOR, action, serde_json::to_value(&event).unwrap_or_default());
+        }
+        Err(tokio::sync::broadcast::error::RecvError::Lagged(count)) => {
+          log::warn!("Missed events to audit: count: {:?}", count);
+        }
+        Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+      }
+    }
+  });
+}
+
+pub fn spawn_prune_task() {
+  if !CONFIG.load().audit.enabled || CONFIG.load().audit.retention == 0 {
+    return;
+  }
+  actix_web::rt::spawn(async {
+    let mut interval =
+      tokio::time::interval(Duration::from_secs(CONFIG.load().audit.prune_interval));
+    loop {
+      interval.tick().await;
+      let retention = CONFIG.load().audit.retention.saturating_mul(86400);
+      let before = clock::now().saturating_sub(retention);
+      let count = AUDIT_LOG.prune(before);
+      if count > 0 {
+        log::info!("Pruned audit records: count: {:?}, before: {:?}", count, before);
+      }
+    }
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_key_coder() {
+    let key = AuditKey { at: 1_700_000_000, seq: 7 };
+    let encoded = <AuditCoder as Coder<AuditKey, AuditRecord>>::encode_key(&key);
+    assert_eq!(<AuditCoder as Coder<AuditKey, AuditRecord>>::decode_key(&encoded), key);
+
+    let next_key = AuditKey { at: 1_700_000_001, seq: 0 };
+    let next_encoded = <AuditCoder as Coder<AuditKey, AuditRecord>>::encode_key(&next_key);
+    assert!(encoded < next_encoded);
+  }
+}
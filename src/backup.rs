@@ -0,0 +1,328 @@
+use std::{
+  fs,
+  path::{Path, PathBuf},
+  sync::atomic::{AtomicU32, AtomicU64, Ordering},
+  time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::Bytes;
+use chrono::DateTime;
+use serde_json::json;
+
+use crate::{clock, config::CONFIG, cron::Schedule, db_pool::DB_POOL, encryption, store};
+
+// Bumped when the layout below changes, the older backups are then rejected
+const BACKUP_VERSION: u32 = 2;
+
+const BACKUP_PREFIX: &str = "maxwell-master-";
+const BACKUP_EXTENSION: &str = "backup";
+
+// Every table of the db, the topics, routes, services, settings and audit
+// records alike, as encoded in the db. Each table is read by a single cursor,
+// so it is consistent by itself, unlike copying the dir of the db while it
+// compacts, but the tables are read one after the other.
+#[derive(Debug, Serialize, Deserialize)]
+struct Backup {
+  version: u32,
+  created_at: u32,
+  // Of the encryption, as the values are kept encrypted, so that the backup
+  // is restored by the same key only
+  canary: Option<Vec<u8>>,
+  tables: Vec<TableBackup>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TableBackup {
+  name: String,
+  entries: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupInfo {
+  // None if the backup was not written to a file
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub path: Option<String>,
+  pub size: u64,
+  pub tables: usize,
+  pub entries: usize,
+  pub checksum: u32,
+  pub created_at: u32,
+}
+
+// The bincode of the backup, followed by its crc32, little endian
+pub fn create() -> Result<(Vec<u8>, BackupInfo)> {
+  let backup = Backup {
+    version: BACKUP_VERSION,
+    created_at: clock::now(),
+    canary: encryption::canary(),
+    tables: store::dump_tables()
+      .into_iter()
+      .map(|(name, entries)| TableBackup {
+        name,
+        entries: entries.into_iter().map(|(key, value)| (key.to_vec(), value.to_vec())).collect(),
+      })
+      .collect(),
+  };
+  let mut bytes = bincode::serialize(&backup)?;
+  let checksum = crc32fast::hash(&bytes);
+  bytes.extend_from_slice(&checksum.to_le_bytes());
+  let info = BackupInfo {
+    path: None,
+    size: bytes.len() as u64,
+    tables: backup.tables.len(),
+    entries: backup.tables.iter().map(|table| table.entries.len()).sum(),
+    checksum,
+    created_at: backup.created_at,
+  };
+  Ok((bytes, info))
+}
+
+// Written aside first, so that a backup file is never seen half written
+pub fn create_file(dir: &Path) -> Result<BackupInfo> {
+  let (bytes, mut info) = create()?;
+  fs::create_dir_all(dir).with_context(|| format!("Failed to create: {:?}", dir))?;
+  let path = dir.join(file_name(info.created_at));
+  let tmp_path = path.with_extension("tmp");
+  fs::write(&tmp_path, &bytes).with_context(|| format!("Failed to write: {:?}", tmp_path))?;
+  fs::rename(&tmp_path, &path).with_context(|| format!("Failed to rename: {:?}", tmp_path))?;
+  log::info!("Created backup: path: {:?}, info: {:?}", path, info);
+  info.path = Some(path.display().to_string());
+  Ok(info)
+}
+
+#[inline]
+pub fn file_name(created_at: u32) -> PathBuf {
+  PathBuf::from(format!("{}{}.{}", BACKUP_PREFIX, created_at, BACKUP_EXTENSION))
+}
+
+#[inline]
+pub(crate) fn created_at_of(file_name: &str) -> Option<u32> {
+  file_name
+    .strip_prefix(BACKUP_PREFIX)?
+    .strip_suffix(BACKUP_EXTENSION)?
+    .strip_suffix('.')?
+    .parse()
+    .ok()
+}
+
+// Deletes the backups in dir but the newest keep ones, the other files are left
+pub fn prune(dir: &Path, keep: usize) -> Result<Vec<PathBuf>> {
+  if keep == 0 {
+    return Ok(vec![]);
+  }
+  let mut backups = vec![];
+  for entry in fs::read_dir(dir).with_context(|| format!("Failed to read dir: {:?}", dir))? {
+    let path = entry?.path();
+    if let Some(created_at) =
+      path.file_name().and_then(|name| created_at_of(&name.to_string_lossy()))
+    {
+      backups.push((created_at, path));
+    }
+  }
+  backups.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+  let mut removed = vec![];
+  for (_, path) in backups.into_iter().skip(keep) {
+    fs::remove_file(&path).with_context(|| format!("Failed to remove: {:?}", path))?;
+    removed.push(path);
+  }
+  Ok(removed)
+}
+
+// Of the scheduled backups
+#[derive(Debug, Clone, Copy)]
+pub struct BackupStats {
+  pub succeeded: u64,
+  pub failed: u64,
+  // 0 if none has succeeded yet
+  pub last_succeeded_at: u32,
+  pub last_size: u64,
+}
+
+static SUCCEEDED_COUNT: AtomicU64 = AtomicU64::new(0);
+static FAILED_COUNT: AtomicU64 = AtomicU64::new(0);
+static LAST_SUCCEEDED_AT: AtomicU32 = AtomicU32::new(0);
+static LAST_SIZE: AtomicU64 = AtomicU64::new(0);
+
+pub fn stats() -> BackupStats {
+  BackupStats {
+    succeeded: SUCCEEDED_COUNT.load(Ordering::Relaxed),
+    failed: FAILED_COUNT.load(Ordering::Relaxed),
+    last_succeeded_at: LAST_SUCCEEDED_AT.load(Ordering::Relaxed),
+    last_size: LAST_SIZE.load(Ordering::Relaxed),
+  }
+}
+
+// Backs up as scheduled by db.backup.schedule, if it is configured
+pub fn spawn_schedule_task() {
+  let config = CONFIG.load();
+  let backup_config = &config.db.backup;
+  if backup_config.schedule.is_empty() {
+    return;
+  }
+  // Validated with the config
+  let schedule: Schedule = backup_config.schedule.parse().unwrap();
+  actix_web::rt::spawn(async move {
+    let client = awc::Client::default();
+    loop {
+      let Some(now) = DateTime::from_timestamp_millis(clock::now_millis()) else {
+        return;
+      };
+      let Some(next) = schedule.next_after(now) else {
+        log::warn!("No time matches the backup schedule: {}", schedule);
+        return;
+      };
+      tokio::time::sleep((next - now).to_std().unwrap_or_default()).await;
+      match back_up_as_scheduled(&client).await {
+        Ok(info) => {
+          SUCCEEDED_COUNT.fetch_add(1, Ordering::Relaxed);
+          LAST_SUCCEEDED_AT.store(info.created_at, Ordering::Relaxed);
+          LAST_SIZE.store(info.size, Ordering::Relaxed);
+        }
+        Err(err) => {
+          log::error!("Failed to back up as scheduled: err: {:?}", err);
+          FAILED_COUNT.fetch_add(1, Ordering::Relaxed);
+          notify_failure(&client, &err).await;
+        }
+      }
+    }
+  });
+}
+
+async fn back_up_as_scheduled(client: &awc::Client) -> Result<BackupInfo> {
+  let config = CONFIG.load();
+  let backup_config = &config.db.backup;
+  let dir = PathBuf::from(&backup_config.dir);
+  let info = DB_POOL
+    .run({
+      let dir = dir.clone();
+      move || create_file(&dir)
+    })
+    .await??;
+  if !backup_config.upload_url.is_empty() {
+    upload(client, &dir.join(file_name(info.created_at))).await?;
+  }
+  for path in prune(&dir, backup_config.keep)? {
+    log::info!("Removed old backup: {:?}", path);
+  }
+  Ok(info)
+}
+
+async fn upload(client: &awc::Client, path: &Path) -> Result<()> {
+  let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+  let url = CONFIG.load().db.backup.upload_url.replace("{name}", &name);
+  let bytes = fs::read(path).with_context(|| format!("Failed to read: {:?}", path))?;
+  let rep = client
+    .put(&url)
+    .timeout(Duration::from_secs(300))
+    .content_type("application/octet-stream")
+    .send_body(bytes)
+    .await
+    .map_err(|err| anyhow!("Failed to upload backup: url: {}, err: {:?}", url, err))?;
+  if !rep.status().is_success() {
+    bail!("Failed to upload backup: url: {}, status: {}", url, rep.status());
+  }
+  log::info!("Uploaded backup: path: {:?}, url: {:?}", path, url);
+  Ok(())
+}
+
+async fn notify_failure(client: &awc::Client, err: &anyhow::Error) {
+  let config = CONFIG.load();
+  let webhook = &config.db.backup.failure_webhook;
+  if webhook.is_empty() {
+    return;
+  }
+  let notice = json!({
+    "kind": "backup_failed",
+    "master_id": config.cluster.master_id,
+    "error": format!("{:#}", err),
+    "failed_at": clock::now(),
+  });
+  match client.post(webhook).send_json(&notice).await {
+    Ok(rep) if rep.status().is_success() => {}
+    Ok(rep) => {
+      log::warn!("Failed to post backup failure: {:?}, status: {:?}", notice, rep.status())
+    }
+    Err(err) => log::warn!("Failed to post backup failure: {:?}, err: {:?}", notice, err),
+  }
+}
+
+// Must be called before the managers start, as they read their tables once
+pub fn restore(path: &Path) -> Result<()> {
+  let bytes = fs::read(path).with_context(|| format!("Failed to read backup: {:?}", path))?;
+  let backup = decode(&bytes).with_context(|| format!("Invalid backup: {:?}", path))?;
+  encryption::check_canary(backup.canary.as_deref())
+    .with_context(|| format!("Failed to restore backup: {:?}", path))?;
+  log::info!(
+    "Restoring backup: path: {:?}, created_at: {:?}, tables: {:?}",
+    path,
+    backup.created_at,
+    backup.tables.len()
+  );
+  store::restore_tables(
+    backup
+      .tables
+      .into_iter()
+      .map(|table| {
+        let entries =
+          table.entries.into_iter().map(|(key, value)| (Bytes::from(key), Bytes::from(value)));
+        (table.name, entries.collect())
+      })
+      .collect(),
+  )
+}
+
+fn decode(bytes: &[u8]) -> Result<Backup> {
+  if bytes.len() < 4 {
+    bail!("Too short: size: {}", bytes.len());
+  }
+  let (payload, checksum) = bytes.split_at(bytes.len() - 4);
+  if crc32fast::hash(payload).to_le_bytes() != checksum {
+    bail!("The checksum does not match");
+  }
+  let backup: Backup = bincode::deserialize(payload)?;
+  if backup.version != BACKUP_VERSION {
+    bail!("Unsupported version: {}, expected: {}", backup.version, BACKUP_VERSION);
+  }
+  Ok(backup)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_decode() {
+    let backup = Backup {
+      version: BACKUP_VERSION,
+      created_at: 1,
+      canary: None,
+      tables: vec![TableBackup { name: "a".to_owned(), entries: vec![(vec![1], vec![2])] }],
+    };
+    let mut bytes = bincode::serialize(&backup).unwrap();
+    bytes.extend_from_slice(&crc32fast::hash(&bytes).to_le_bytes());
+    assert_eq!(decode(&bytes).unwrap().tables[0].entries, vec![(vec![1], vec![2])]);
+
+    bytes[0] ^= 1;
+    assert!(decode(&bytes).is_err());
+    assert!(decode(&[0, 1]).is_err());
+  }
+
+  #[test]
+  fn test_prune() {
+    let dir =
+      std::env::temp_dir().join(format!("maxwell-master-test-prune-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for created_at in [3, 1, 2] {
+      fs::write(dir.join(file_name(created_at)), b"").unwrap();
+    }
+    fs::write(dir.join("other.backup"), b"").unwrap();
+
+    let removed = prune(&dir, 2).unwrap();
+    assert_eq!(removed, vec![dir.join(file_name(1))]);
+    assert!(dir.join(file_name(3)).exists());
+    assert!(dir.join("other.backup").exists());
+    assert!(prune(&dir, 0).unwrap().is_empty());
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,312 @@
+use std::time::{Duration, Instant};
+
+use actix_codec::Framed;
+use anyhow::{anyhow, bail, Result};
+use awc::{ws, BoxedSocket};
+use clap::Args;
+use futures::{stream, SinkExt, StreamExt};
+use maxwell_protocol::{self, *};
+
+use crate::config::CONFIG;
+
+// The master is driven over the ws protocol, as the real nodes do, so the
+// numbers include the framing and the scheduling of the connections. The
+// services registered are left in the data dir, so point the master at a
+// throwaway one.
+#[derive(Debug, Clone, PartialEq, Args)]
+pub struct BenchArgs {
+  /// Defaults to the ws endpoint of server.http_port on localhost
+  #[arg(long)]
+  url: Option<String>,
+  /// Services registering, pinging and setting routes, one connection each
+  #[arg(long, default_value_t = 1000)]
+  services: u32,
+  /// Get paths set by each service, shared by every tenth service
+  #[arg(long, default_value_t = 10)]
+  paths: u32,
+  /// Pings sent by each service
+  #[arg(long, default_value_t = 10)]
+  pings: u32,
+  /// Topics located, across the connections of the services
+  #[arg(long, default_value_t = 10000)]
+  topics: u32,
+  /// Connections sending reqs at the same time
+  #[arg(long, default_value_t = 100)]
+  concurrency: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BenchReport {
+  pub phases: Vec<PhaseReport>,
+}
+
+// Latencies in microseconds
+#[derive(Debug, PartialEq, Serialize)]
+pub struct PhaseReport {
+  pub name: &'static str,
+  pub count: usize,
+  pub errors: usize,
+  pub throughput: f64,
+  pub p50: u64,
+  pub p90: u64,
+  pub p99: u64,
+  pub max: u64,
+}
+
+struct Phase {
+  name: &'static str,
+  latencies: Vec<Duration>,
+  errors: usize,
+  started_at: Instant,
+}
+
+impl Phase {
+  #[inline]
+  fn new(name: &'static str) -> Self {
+    Phase { name, latencies: vec![], errors: 0, started_at: Instant::now() }
+  }
+
+  #[inline]
+  fn record(&mut self, result: Result<Duration>) {
+    match result {
+      Ok(latency) => self.latencies.push(latency),
+      Err(err) => {
+        log::warn!("Bench req failed: phase: {:?}, err: {:?}", self.name, err);
+        self.errors += 1;
+      }
+    }
+  }
+
+  fn report(mut self, elapsed: Duration) -> PhaseReport {
+    self.latencies.sort();
+    let count = self.latencies.len() + self.errors;
+    PhaseReport {
+      name: self.name,
+      count,
+      errors: self.errors,
+      throughput: if elapsed.is_zero() { 0.0 } else { count as f64 / elapsed.as_secs_f64() },
+      p50: percentile(&self.latencies, 50),
+      p90: percentile(&self.latencies, 90),
+      p99: percentile(&self.latencies, 99),
+      max: percentile(&self.latencies, 100),
+    }
+  }
+
+  #[inline]
+  fn finish(self) -> PhaseReport {
+    let elapsed = self.started_at.elapsed();
+    self.report(elapsed)
+  }
+}
+
+// Of the sorted latencies, by the nearest rank
+fn percentile(latencies: &[Duration], p: usize) -> u64 {
+  if latencies.is_empty() {
+    return 0;
+  }
+  let rank = (latencies.len() * p).div_ceil(100).max(1);
+  latencies[rank - 1].as_micros() as u64
+}
+
+struct BenchConnection {
+  framed: Framed<BoxedSocket, ws::Codec>,
+  next_ref: u32,
+}
+
+impl BenchConnection {
+  async fn connect(url: &str) -> Result<Self> {
+    let (_, framed) = awc::Client::new()
+      .ws(url)
+      .max_frame_size(CONFIG.load().server.max_frame_size)
+      .connect()
+      .await
+      .map_err(|err| anyhow!("Failed to connect: url: {:?}, err: {:?}", url, err))?;
+    Ok(BenchConnection { framed, next_ref: 1 })
+  }
+
+  #[inline]
+  fn next_ref(&mut self) -> u32 {
+    self.next_ref += 1;
+    self.next_ref
+  }
+
+  // Sends the req and waits for its rep, the error reps count as failures
+  async fn call(&mut self, req: ProtocolMsg) -> Result<Duration> {
+    let started_at = Instant::now();
+    self.framed.send(ws::Message::Binary(maxwell_protocol::encode(&req).into())).await?;
+    loop {
+      match self.framed.next().await {
+        Some(Ok(ws::Frame::Binary(bytes))) => {
+          let rep = maxwell_protocol::decode(&bytes.into())
+            .map_err(|err| anyhow!("Failed to decode rep: {:?}", err))?;
+          return match rep {
+            ProtocolMsg::ErrorRep(rep) => bail!("Got error rep: {:?}", rep),
+            _ => Ok(started_at.elapsed()),
+          };
+        }
+        Some(Ok(ws::Frame::Ping(bytes))) => self.framed.send(ws::Message::Pong(bytes)).await?,
+        Some(Ok(ws::Frame::Close(reason))) => bail!("Connection closed: {:?}", reason),
+        Some(Ok(_)) => continue,
+        Some(Err(err)) => bail!("Failed to receive rep: {:?}", err),
+        None => bail!("Connection closed"),
+      }
+    }
+  }
+}
+
+#[actix_web::main]
+pub async fn run(args: BenchArgs) -> Result<()> {
+  let url = args
+    .url
+    .clone()
+    .unwrap_or_else(|| format!("ws://127.0.0.1:{}/$ws", CONFIG.load().server.http_port));
+  let mut phases = vec![];
+
+  let mut phase = Phase::new("connect");
+  let mut connections = vec![];
+  let mut results = stream::iter(0..args.services)
+    .map(|_| timed(BenchConnection::connect(&url)))
+    .buffered(args.concurrency);
+  while let Some(result) = results.next().await {
+    match result {
+      Ok((connection, latency)) => {
+        connections.push(connection);
+        phase.record(Ok(latency));
+      }
+      Err(err) => phase.record(Err(err)),
+    }
+  }
+  phases.push(phase.finish());
+  if connections.is_empty() {
+    bail!("No connection to the master: url: {:?}", url);
+  }
+
+  let mut phase = Phase::new("register_service");
+  for_each_connection(&mut connections, args.concurrency, &mut phase, |i, connection| {
+    let r#ref = connection.next_ref();
+    vec![RegisterServiceReq {
+      id: format!("bench-service-{}", i),
+      http_port: 10000 + i as u32,
+      r#ref,
+      ..Default::default()
+    }
+    .into_enum()]
+  })
+  .await;
+  phases.push(phase.finish());
+
+  let mut phase = Phase::new("set_routes");
+  for_each_connection(&mut connections, args.concurrency, &mut phase, |i, connection| {
+    let r#ref = connection.next_ref();
+    let get_paths =
+      (0..args.paths).map(|j| format!("/bench/{}/{}", i / 10, j)).collect::<Vec<String>>();
+    vec![SetRoutesReq { get_paths, r#ref, ..Default::default() }.into_enum()]
+  })
+  .await;
+  phases.push(phase.finish());
+
+  let mut phase = Phase::new("ping");
+  for_each_connection(&mut connections, args.concurrency, &mut phase, |_, connection| {
+    (0..args.pings)
+      .map(|_| PingReq { r#ref: connection.next_ref(), ..Default::default() }.into_enum())
+      .collect()
+  })
+  .await;
+  phases.push(phase.finish());
+
+  let mut phase = Phase::new("get_routes");
+  for_each_connection(&mut connections, args.concurrency, &mut phase, |_, connection| {
+    vec![GetRoutesReq { r#ref: connection.next_ref(), ..Default::default() }.into_enum()]
+  })
+  .await;
+  phases.push(phase.finish());
+
+  let mut phase = Phase::new("locate_topic");
+  let connection_count = connections.len() as u32;
+  for_each_connection(&mut connections, args.concurrency, &mut phase, |i, connection| {
+    (i as u32..args.topics)
+      .step_by(connection_count as usize)
+      .map(|topic| {
+        LocateTopicReq {
+          topic: format!("bench-topic-{}", topic),
+          r#ref: connection.next_ref(),
+          ..Default::default()
+        }
+        .into_enum()
+      })
+      .collect()
+  })
+  .await;
+  phases.push(phase.finish());
+
+  println!("{}", serde_json::to_string_pretty(&BenchReport { phases })?);
+  Ok(())
+}
+
+#[inline]
+async fn timed<T>(future: impl std::future::Future<Output = Result<T>>) -> Result<(T, Duration)> {
+  let started_at = Instant::now();
+  let value = future.await?;
+  Ok((value, started_at.elapsed()))
+}
+
+// Each connection sends its reqs one after another, while up to concurrency
+// connections do so at the same time
+async fn for_each_connection(
+  connections: &mut [BenchConnection], concurrency: usize, phase: &mut Phase,
+  mut build_reqs: impl FnMut(usize, &mut BenchConnection) -> Vec<ProtocolMsg>,
+) {
+  let jobs: Vec<(&mut BenchConnection, Vec<ProtocolMsg>)> = connections
+    .iter_mut()
+    .enumerate()
+    .map(|(i, connection)| {
+      let reqs = build_reqs(i, connection);
+      (connection, reqs)
+    })
+    .collect();
+  let mut results = stream::iter(jobs)
+    .map(|(connection, reqs)| async move {
+      let mut results = Vec::with_capacity(reqs.len());
+      for req in reqs {
+        results.push(connection.call(req).await);
+      }
+      results
+    })
+    .buffer_unordered(concurrency.max(1));
+  while let Some(results) = results.next().await {
+    for result in results {
+      phase.record(result);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_report() {
+    let mut phase = Phase::new("ping");
+    for millis in (1..=100).rev() {
+      phase.record(Ok(Duration::from_millis(millis)));
+    }
+    phase.record(Err(anyhow!("closed")));
+    let report = phase.report(Duration::from_secs(2));
+    assert_eq!(
+      report,
+      PhaseReport {
+        name: "ping",
+        count: 101,
+        errors: 1,
+        throughput: 50.5,
+        p50: 50000,
+        p90: 90000,
+        p99: 99000,
+        max: 100000,
+      }
+    );
+
+    assert_eq!(percentile(&[], 50), 0);
+    assert_eq!(percentile(&[Duration::from_micros(7)], 99), 7);
+  }
+}
@@ -0,0 +1,42 @@
+use crate::{
+  config::CONFIG,
+  handler::protocol_version::{MIN_PROTOCOL_VERSION, PROTOCOL_VERSION},
+  health,
+};
+
+// Set by build.rs, empty if not built from a git checkout
+const GIT_COMMIT: &str = env!("MAXWELL_GIT_COMMIT");
+// Seconds since the epoch
+const BUILD_TIME: &str = env!("MAXWELL_BUILD_TIME");
+
+// Which build is running where, for auditing a fleet of masters
+#[derive(Debug, Serialize)]
+pub struct BuildInfo {
+  name: &'static str,
+  version: &'static str,
+  #[serde(skip_serializing_if = "str::is_empty")]
+  git_commit: &'static str,
+  build_time: u64,
+  min_protocol_version: u32,
+  protocol_version: u32,
+  // Only of the enabled listeners
+  #[serde(skip_serializing_if = "Option::is_none")]
+  http_port: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  https_port: Option<u32>,
+  uptime: u32,
+}
+
+pub fn build_info() -> BuildInfo {
+  BuildInfo {
+    name: env!("CARGO_PKG_NAME"),
+    version: env!("CARGO_PKG_VERSION"),
+    git_commit: GIT_COMMIT,
+    build_time: BUILD_TIME.parse().unwrap_or(0),
+    min_protocol_version: MIN_PROTOCOL_VERSION,
+    protocol_version: PROTOCOL_VERSION,
+    http_port: CONFIG.load().server.enable_http.then_some(CONFIG.load().server.http_port),
+    https_port: CONFIG.load().server.enable_https.then_some(CONFIG.load().server.https_port),
+    uptime: health::uptime(),
+  }
+}
@@ -0,0 +1,263 @@
+use std::{env::current_exe, path::Path};
+
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
+use serde::Serialize;
+
+#[cfg(feature = "bench")]
+use crate::bench::BenchArgs;
+use crate::{
+  config::{self, ConfigSource, DbEngine, CONFIG, DEFAULT_CONFIG_PATH},
+  dump, fsck,
+  handler::admin_handler::{build_export_routes_rep, build_export_topics_rep},
+  migration, snapshot, standby, topic_mgr,
+};
+
+const DEFAULT_LOG_CONFIG_PATH: &str = "config/log4rs.yaml";
+
+#[derive(Debug, Parser)]
+#[command(name = "maxwell-master", version, about = "The master of maxwell")]
+pub struct Cli {
+  /// Relative paths are looked up in the working dir, then in the dir of the executable
+  #[arg(long, env = "MAXWELL_MASTER_CONFIG", default_value = DEFAULT_CONFIG_PATH)]
+  config: String,
+  /// Looked up like the config
+  #[arg(long, env = "MAXWELL_MASTER_LOG_CONFIG", default_value = DEFAULT_LOG_CONFIG_PATH)]
+  log_config: String,
+  /// Overrides server.http_port
+  #[arg(long)]
+  http_port: Option<u32>,
+  /// Overrides server.https_port
+  #[arg(long)]
+  https_port: Option<u32>,
+  /// Restores a backup of POST /$admin/backup before serving, replacing the
+  /// tables of the db it has
+  #[arg(long)]
+  restore: Option<String>,
+  /// Restores the newest backup in the bucket of db.offsite before serving, for
+  /// a master replacing the ones of a lost region
+  #[arg(long, conflicts_with = "restore")]
+  restore_offsite: bool,
+  #[command(subcommand)]
+  command: Option<Command>,
+}
+
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum Command {
+  /// Runs the server, the default
+  Serve,
+  /// Validates the config and the log config, then exits
+  CheckConfig,
+  /// Prints the routes in the data dir, as exported by the admin api
+  DumpRoutes,
+  /// Prints the topics in the data dir, as exported by the admin api
+  DumpTopics,
+  /// Writes every table in the data dir as json, to stdout if no output
+  Dump {
+    #[arg(long)]
+    output: Option<String>,
+  },
+  /// Loads a dump into the empty data dir, after checking that its entries
+  /// decode and refer to the services dumped and the backends configured
+  Load {
+    #[arg(long)]
+    input: String,
+  },
+  /// Checks that every entry of the tables in the data dir decodes and refers to
+  /// known services and backends, printing the problems found
+  Fsck {
+    /// Removes the entries with problems, instead of failing
+    #[arg(long)]
+    repair: bool,
+  },
+  /// Fetches the state of a peer into the data dir, for a new master to start
+  /// from, resuming the transfer interrupted last if the peer still has it
+  Bootstrap {
+    /// The snapshot url of the peer, e.g. http://10.0.0.1:8081/$peer/snapshot
+    #[arg(long)]
+    from: String,
+  },
+  /// Simulates nodes against a running master, and prints the throughput and
+  /// latencies of each kind of req
+  #[cfg(feature = "bench")]
+  Bench(BenchArgs),
+}
+
+impl Cli {
+  #[inline]
+  pub fn command(&self) -> Command {
+    self.command.clone().unwrap_or(Command::Serve)
+  }
+
+  #[inline]
+  pub fn config_path(&self) -> String {
+    resolve_path(&self.config)
+  }
+
+  #[inline]
+  pub fn log_config_path(&self) -> String {
+    resolve_path(&self.log_config)
+  }
+
+  #[inline]
+  pub fn restore_path(&self) -> Option<String> {
+    self.restore.as_deref().map(resolve_path)
+  }
+
+  #[inline]
+  pub fn restore_offsite(&self) -> bool {
+    self.restore_offsite
+  }
+
+  #[inline]
+  pub fn config_source(&self) -> ConfigSource {
+    ConfigSource {
+      path: self.config_path(),
+      http_port: self.http_port,
+      https_port: self.https_port,
+      config: None,
+    }
+  }
+}
+
+// The subcommands below work on the data dir without starting the server, so
+// they fail while a server holds the db
+pub fn check_config(cli: &Cli) -> Result<()> {
+  let config = config::load()?;
+  config.validate().with_context(|| format!("Invalid config: {:?}", cli.config_path()))?;
+  log4rs::config::load_config_file(cli.log_config_path(), Default::default())
+    .with_context(|| format!("Invalid log config: {:?}", cli.log_config_path()))?;
+  println!("The config is valid: {}", cli.config_path());
+  Ok(())
+}
+
+// The tables are brought to the schema of this master first, as on start
+pub fn dump_routes() -> Result<()> {
+  migration::run()?;
+  print_json(&build_export_routes_rep())
+}
+
+pub fn dump_topics() -> Result<()> {
+  migration::run()?;
+  print_json(&build_export_topics_rep(topic_mgr::read_topics()?))
+}
+
+pub fn dump(output: Option<&str>) -> Result<()> {
+  match output {
+    Some(output) => {
+      dump::dump_to(Path::new(output))?;
+      println!("Dumped to: {}", output);
+      Ok(())
+    }
+    None => print_json(&dump::dump()?),
+  }
+}
+
+pub fn load(input: &str) -> Result<()> {
+  dump::load_from(Path::new(input))?;
+  println!("Loaded from: {}", input);
+  Ok(())
+}
+
+pub fn fsck(repair: bool) -> Result<()> {
+  let report = fsck::run(repair)?;
+  print_json(&report)?;
+  if !report.problems.is_empty() && !repair {
+    bail!("Found {} problems, run with --repair to remove the entries", report.problems.len());
+  }
+  Ok(())
+}
+
+#[actix_web::main]
+pub async fn bootstrap(url: &str) -> Result<()> {
+  if CONFIG.load().db.engine == DbEngine::Memory {
+    bail!("Nothing to bootstrap with the memory engine");
+  }
+  migration::run()?;
+  let snapshot = snapshot::fetch(url, &snapshot::download_dir(), true).await?;
+  let (services, routes, topics) =
+    (snapshot.services.len(), snapshot.routes.len(), snapshot.topics.len());
+  standby::apply_snapshot(snapshot)?;
+  println!(
+    "Bootstrapped from: {}, services: {}, routes: {}, topics: {}",
+    url, services, routes, topics
+  );
+  Ok(())
+}
+
+#[inline]
+fn print_json<T: Serialize>(value: &T) -> Result<()> {
+  println!("{}", serde_json::to_string_pretty(value)?);
+  Ok(())
+}
+
+fn resolve_path(path: &str) -> String {
+  let relative_path = Path::new(path);
+  if relative_path.is_absolute() || relative_path.exists() {
+    return path.to_owned();
+  }
+  current_exe()
+    .ok()
+    .and_then(|exe| exe.parent().map(|dir| dir.join(relative_path)))
+    .filter(|path| path.exists())
+    .map_or_else(|| path.to_owned(), |path| path.display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse() {
+    let cli = Cli::try_parse_from(["maxwell-master"]).unwrap();
+    assert_eq!(cli.command(), Command::Serve);
+    assert_eq!(cli.config_source().http_port, None);
+    assert_eq!(cli.restore_path(), None);
+    assert!(!cli.restore_offsite());
+
+    let cli = Cli::try_parse_from([
+      "maxwell-master",
+      "--config",
+      "/etc/maxwell/master.toml",
+      "--http-port",
+      "9081",
+      "--restore",
+      "/var/backups/maxwell-master-1.backup",
+      "dump-topics",
+    ])
+    .unwrap();
+    assert_eq!(cli.command(), Command::DumpTopics);
+    assert_eq!(cli.config_path(), "/etc/maxwell/master.toml");
+    assert_eq!(cli.config_source().http_port, Some(9081));
+    assert_eq!(cli.restore_path().as_deref(), Some("/var/backups/maxwell-master-1.backup"));
+
+    let cli = Cli::try_parse_from([
+      "maxwell-master",
+      "bootstrap",
+      "--from",
+      "http://10.0.0.1:8081/$peer/snapshot",
+    ])
+    .unwrap();
+    assert_eq!(
+      cli.command(),
+      Command::Bootstrap { from: "http://10.0.0.1:8081/$peer/snapshot".to_owned() }
+    );
+
+    let cli = Cli::try_parse_from(["maxwell-master", "--restore-offsite"]).unwrap();
+    assert!(cli.restore_offsite());
+    assert!(Cli::try_parse_from(["maxwell-master", "--restore", "a.backup", "--restore-offsite"])
+      .is_err());
+
+    let cli = Cli::try_parse_from(["maxwell-master", "dump"]).unwrap();
+    assert_eq!(cli.command(), Command::Dump { output: None });
+    let cli =
+      Cli::try_parse_from(["maxwell-master", "load", "--input", "/tmp/master.json"]).unwrap();
+    assert_eq!(cli.command(), Command::Load { input: "/tmp/master.json".to_owned() });
+
+    let cli = Cli::try_parse_from(["maxwell-master", "fsck", "--repair"]).unwrap();
+    assert_eq!(cli.command(), Command::Fsck { repair: true });
+
+    assert!(Cli::try_parse_from(["maxwell-master", "unknown"]).is_err());
+    assert!(Cli::try_parse_from(["maxwell-master", "bootstrap"]).is_err());
+  }
+}
@@ -0,0 +1,114 @@
+use std::{
+  sync::{
+    atomic::{AtomicI64, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use once_cell::sync::OnceCell;
+
+// The time which the health, staleness, ttl and lease checks go by
+pub trait Clock: Send + Sync {
+  // Milliseconds since the epoch
+  fn now_millis(&self) -> i64;
+}
+
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  #[inline]
+  fn now_millis(&self) -> i64 {
+    Utc::now().timestamp_millis()
+  }
+}
+
+// Stands still until advanced
+#[derive(Debug)]
+pub struct MockClock {
+  millis: AtomicI64,
+}
+
+impl MockClock {
+  #[inline]
+  pub fn new(millis: i64) -> Self {
+    MockClock { millis: AtomicI64::new(millis) }
+  }
+
+  #[inline]
+  pub fn advance(&self, duration: Duration) {
+    self.millis.fetch_add(duration.as_millis() as i64, Ordering::Relaxed);
+  }
+
+  #[inline]
+  pub fn set(&self, millis: i64) {
+    self.millis.store(millis, Ordering::Relaxed);
+  }
+}
+
+impl Clock for MockClock {
+  #[inline]
+  fn now_millis(&self) -> i64 {
+    self.millis.load(Ordering::Relaxed)
+  }
+}
+
+static CLOCK: OnceCell<Arc<dyn Clock>> = OnceCell::new();
+
+// Must be set before the master starts, the times read before are of the system
+pub fn set_clock(clock: Arc<dyn Clock>) -> Result<()> {
+  CLOCK.set(clock).map_err(|_| anyhow!("The clock was already set"))
+}
+
+#[cfg(test)]
+thread_local! {
+  // The unit tests run in parallel, so each mocks the time of its own thread
+  static THREAD_CLOCK: std::cell::RefCell<Option<Arc<MockClock>>> =
+    const { std::cell::RefCell::new(None) };
+}
+
+// Mocks the time of the calling thread, starting from the current time
+#[cfg(test)]
+pub(crate) fn mock() -> Arc<MockClock> {
+  let clock = Arc::new(MockClock::new(Utc::now().timestamp_millis()));
+  THREAD_CLOCK.with(|thread_clock| *thread_clock.borrow_mut() = Some(Arc::clone(&clock)));
+  clock
+}
+
+#[inline]
+pub fn now_millis() -> i64 {
+  #[cfg(test)]
+  if let Some(millis) =
+    THREAD_CLOCK.with(|clock| clock.borrow().as_ref().map(|clock| clock.now_millis()))
+  {
+    return millis;
+  }
+  CLOCK.get().map_or_else(|| Utc::now().timestamp_millis(), |clock| clock.now_millis())
+}
+
+// Seconds since the epoch
+#[inline]
+pub fn now() -> u32 {
+  (now_millis() / 1000) as u32
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_mock() {
+    let clock = mock();
+    let millis = now_millis();
+    clock.advance(Duration::from_secs(90));
+    assert_eq!(now_millis(), millis + 90_000);
+    clock.set(5_999);
+    assert_eq!(now(), 5);
+
+    let other = std::thread::spawn(now).join().unwrap();
+    assert!(other > 5);
+  }
+}
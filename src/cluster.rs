@@ -0,0 +1,114 @@
+use std::{fmt, sync::RwLock};
+
+use once_cell::sync::Lazy;
+use subtle::ConstantTimeEq;
+
+use crate::{config::CONFIG, raft};
+
+// The masters configured as peers elect their leader by raft, which replicates
+// the nodes, routes and topics to the others, see raft. Without peers, every
+// master takes the writes to its own db, which a standby mirrors, see standby.
+
+// The master which peers elected to take the writes, as last known
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LeaderInfo {
+  pub master_id: String,
+  pub endpoint: String,
+  pub term: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidPeerToken;
+
+impl fmt::Display for InvalidPeerToken {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "The peer token does not match, or peers are disabled")
+  }
+}
+
+impl std::error::Error for InvalidPeerToken {}
+
+// Returned for the writes reaching a master which is not the leader, so that
+// the client can retry them at the leader, none while it is being elected
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotLeader {
+  pub leader: Option<LeaderInfo>,
+}
+
+impl fmt::Display for NotLeader {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match &self.leader {
+      Some(leader) => {
+        write!(f, "Not the leader: leader: {}, endpoint: {}", leader.master_id, leader.endpoint)
+      }
+      None => write!(f, "Not the leader: the leader is being elected"),
+    }
+  }
+}
+
+impl std::error::Error for NotLeader {}
+
+static LEADER: Lazy<RwLock<Option<LeaderInfo>>> = Lazy::new(|| RwLock::new(None));
+
+// Peers are disabled unless a token is configured, which is compared in
+// constant time, not to leak how much of it matched
+#[inline]
+pub fn authenticate_peer(token: &str) -> Result<(), InvalidPeerToken> {
+  let config = CONFIG.load();
+  let peer_token = config.cluster.peer_token.as_bytes();
+  if !peer_token.is_empty() && bool::from(peer_token.ct_eq(token.as_bytes())) {
+    Ok(())
+  } else {
+    Err(InvalidPeerToken)
+  }
+}
+
+#[inline]
+pub fn leader() -> Option<LeaderInfo> {
+  LEADER.read().unwrap().clone()
+}
+
+// Every master takes the writes unless its peers elect the leader by raft, then
+// only the leader does, the reads are always answered from its own state
+pub fn check_leader() -> Result<(), NotLeader> {
+  match leader() {
+    Some(leader) if leader.master_id == CONFIG.load().cluster.master_id => Ok(()),
+    None if !raft::is_enabled() => Ok(()),
+    leader => Err(NotLeader { leader }),
+  }
+}
+
+// By raft, which forgets the leader as well when a new term starts
+pub(crate) fn set_elected(elected: Option<LeaderInfo>) {
+  let mut leader = LEADER.write().unwrap();
+  if *leader != elected {
+    log::info!("Leader changed: from: {:?}, to: {:?}", leader, elected);
+    *leader = elected;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_check_leader() {
+    let leader_of = |master_id: &str, term| LeaderInfo {
+      master_id: master_id.to_owned(),
+      endpoint: format!("{}:8081", master_id),
+      term,
+    };
+    set_elected(Some(leader_of(&CONFIG.load().cluster.master_id, 2)));
+    assert_eq!(check_leader(), Ok(()));
+    set_elected(Some(leader_of("master-1", 3)));
+    assert_eq!(leader(), Some(leader_of("master-1", 3)));
+    assert_eq!(check_leader(), Err(NotLeader { leader: Some(leader_of("master-1", 3)) }));
+  }
+
+  #[test]
+  fn test_authenticate_peer() {
+    // Peers are disabled by the default config
+    assert_eq!(authenticate_peer(""), Err(InvalidPeerToken));
+    assert_eq!(authenticate_peer("token"), Err(InvalidPeerToken));
+  }
+}
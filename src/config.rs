@@ -1,33 +1,363 @@
-use std::{env::current_dir, net::IpAddr, path::PathBuf, time::Duration};
+use std::{
+  collections::{BTreeMap, HashSet},
+  env::current_dir,
+  net::IpAddr,
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex, RwLock},
+  time::Duration,
+};
 
-use anyhow::{Context, Result};
-use once_cell::sync::Lazy;
-use serde::de::{Deserialize, Deserializer};
+use anyhow::{anyhow, Context, Result};
+use arc_swap::ArcSwap;
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{
+  de::{Deserialize, Deserializer},
+  Serialize,
+};
 
-#[derive(Debug, Deserialize)]
+use crate::{cron::Schedule, event_bus::EventKind};
+
+// Every setting has a default, so that a minimal config file works
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct Config {
+  #[serde(default)]
   pub server: ServerConfig,
+  #[serde(default)]
   pub frontend_mgr: FrontendMgrConfig,
+  #[serde(default)]
   pub backend_mgr: BackendMgrConfig,
+  #[serde(default)]
   pub service_mgr: ServiceMgrConfig,
+  #[serde(default)]
+  pub discovery: DiscoveryConfig,
+  #[serde(default)]
+  pub health_policy: HealthPolicyConfig,
+  #[serde(default)]
+  pub route_mgr: RouteMgrConfig,
+  #[serde(default)]
+  pub topic_mgr: TopicMgrConfig,
+  #[serde(default)]
+  pub cluster: ClusterConfig,
+  #[serde(default)]
+  pub admin: AdminConfig,
+  #[serde(default)]
+  pub acme: AcmeConfig,
+  #[serde(default)]
+  pub tracing: TracingConfig,
+  #[serde(default)]
+  pub slow_log: SlowLogConfig,
+  #[serde(default)]
+  pub profiling: ProfilingConfig,
+  #[serde(default)]
+  pub statsd: StatsdConfig,
+  #[serde(default)]
+  pub event_bus: EventBusConfig,
+  #[serde(default)]
+  pub audit: AuditConfig,
+  #[serde(default)]
   pub db: DbConfig,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct ServerConfig {
+  #[serde(default = "default_enable_http")]
+  pub enable_http: bool,
+  #[serde(default = "default_enable_https")]
+  pub enable_https: bool,
+  #[serde(default = "default_http_port")]
   pub http_port: u32,
+  #[serde(default = "default_https_port")]
   pub https_port: u32,
-  #[serde(deserialize_with = "deserialize_path")]
+  // The same reqs as over ws, as grpc, in plaintext
+  #[serde(default)]
+  pub enable_grpc: bool,
+  #[serde(default = "default_grpc_port")]
+  pub grpc_port: u32,
+  // Only needed when https is enabled
+  #[serde(deserialize_with = "deserialize_path", default)]
   pub cert_file: String,
-  #[serde(deserialize_with = "deserialize_path")]
+  #[serde(deserialize_with = "deserialize_path", default)]
   pub key_file: String,
+  #[serde(default = "default_backlog")]
   pub backlog: u32,
   #[serde(deserialize_with = "deserialize_keep_alive", default)]
   pub keep_alive: Option<Duration>,
+  #[serde(default = "default_max_connection_rate")]
   pub max_connection_rate: usize,
+  #[serde(default = "default_max_connections")]
   pub max_connections: usize,
+  // 0 means one per cpu
+  #[serde(default)]
   pub workers: usize,
+  #[serde(default = "default_max_frame_size")]
   pub max_frame_size: usize,
+  #[serde(default = "default_ws_heartbeat_interval")]
+  pub ws_heartbeat_interval: u64,
+  #[serde(default = "default_ws_idle_timeout")]
+  pub ws_idle_timeout: u64,
+  #[serde(default)]
+  pub ws_msg_rate: f64,
+  #[serde(default)]
+  pub ws_msg_burst: f64,
+  #[serde(default)]
+  pub ws_ip_msg_rate: f64,
+  #[serde(default)]
+  pub ws_ip_msg_burst: f64,
+  #[serde(default = "default_ws_max_rate_limited")]
+  pub ws_max_rate_limited: u32,
+  #[serde(default = "default_ws_max_decode_failures")]
+  pub ws_max_decode_failures: u32,
+  #[serde(default = "default_ws_max_in_flight")]
+  pub ws_max_in_flight: u32,
+  #[serde(default = "default_ws_mailbox_capacity")]
+  pub ws_mailbox_capacity: usize,
+  #[serde(default)]
+  pub client_ca_file: String,
+  #[serde(default)]
+  pub require_client_cert: bool,
+  #[serde(default)]
+  pub client_identities: Vec<ClientIdentityConfig>,
+  #[serde(default)]
+  pub ready_requires_nodes: bool,
+  // Rejects the registrations of services, the routes set and the new topics
+  #[serde(default)]
+  pub read_only: bool,
+  #[serde(default)]
+  pub cors: CorsConfig,
+  #[serde(default)]
+  pub compression: CompressionConfig,
+  // Serves the health and admin endpoints locally, empty means disabled
+  #[serde(default)]
+  pub unix_socket_path: String,
+  #[serde(default = "default_unix_socket_mode")]
+  pub unix_socket_mode: u32,
+}
+
+// Empty lists mean anything is allowed
+#[derive(Debug, Clone, Deserialize)]
+pub struct CorsConfig {
+  #[serde(default)]
+  pub allowed_origins: Vec<String>,
+  #[serde(default)]
+  pub allowed_methods: Vec<String>,
+  #[serde(default)]
+  pub allowed_headers: Vec<String>,
+  // Seconds, 0 means preflight results are not cached
+  #[serde(default)]
+  pub max_age: usize,
+  #[serde(default)]
+  pub supports_credentials: bool,
+}
+
+impl CorsConfig {
+  #[inline]
+  pub fn allows_any_origin(&self) -> bool {
+    self.allowed_origins.is_empty() || self.allowed_origins.iter().any(|origin| origin == "*")
+  }
+}
+
+impl Default for CorsConfig {
+  fn default() -> Self {
+    CorsConfig {
+      allowed_origins: Vec::new(),
+      allowed_methods: Vec::new(),
+      allowed_headers: Vec::new(),
+      max_age: 0,
+      supports_credentials: false,
+    }
+  }
+}
+
+// Only applied when the client accepts one of the encodings
+#[derive(Debug, Clone, Deserialize)]
+pub struct CompressionConfig {
+  #[serde(default = "default_compression_enabled")]
+  pub enabled: bool,
+  // Bytes, smaller bodies are sent as they are
+  #[serde(default = "default_compression_min_size")]
+  pub min_size: usize,
+  // Empty means any of them
+  #[serde(default)]
+  pub encodings: Vec<CompressionEncoding>,
+}
+
+impl CompressionConfig {
+  #[inline]
+  pub fn allows(&self, encoding: &str) -> bool {
+    self.encodings.is_empty()
+      || self.encodings.iter().any(|allowed| allowed.as_str().eq_ignore_ascii_case(encoding))
+  }
+}
+
+impl Default for CompressionConfig {
+  fn default() -> Self {
+    CompressionConfig {
+      enabled: default_compression_enabled(),
+      min_size: default_compression_min_size(),
+      encodings: Vec::new(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionEncoding {
+  Br,
+  Gzip,
+  Zstd,
+}
+
+impl CompressionEncoding {
+  #[inline]
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      CompressionEncoding::Br => "br",
+      CompressionEncoding::Gzip => "gzip",
+      CompressionEncoding::Zstd => "zstd",
+    }
+  }
+}
+
+// Nodes presenting a client certificate with the common name may register as
+// the given node types, with any id if node_ids is empty
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClientIdentityConfig {
+  pub common_name: String,
+  pub node_types: Vec<ClientNodeType>,
+  #[serde(default)]
+  pub node_ids: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClientNodeType {
+  Frontend,
+  Backend,
+  Service,
+}
+
+impl Default for ServerConfig {
+  fn default() -> Self {
+    ServerConfig {
+      enable_http: default_enable_http(),
+      enable_https: default_enable_https(),
+      http_port: default_http_port(),
+      https_port: default_https_port(),
+      enable_grpc: false,
+      grpc_port: default_grpc_port(),
+      cert_file: String::new(),
+      key_file: String::new(),
+      backlog: default_backlog(),
+      keep_alive: None,
+      max_connection_rate: default_max_connection_rate(),
+      max_connections: default_max_connections(),
+      workers: 0,
+      max_frame_size: default_max_frame_size(),
+      ws_heartbeat_interval: default_ws_heartbeat_interval(),
+      ws_idle_timeout: default_ws_idle_timeout(),
+      ws_msg_rate: 0.0,
+      ws_msg_burst: 0.0,
+      ws_ip_msg_rate: 0.0,
+      ws_ip_msg_burst: 0.0,
+      ws_max_rate_limited: default_ws_max_rate_limited(),
+      ws_max_decode_failures: default_ws_max_decode_failures(),
+      ws_max_in_flight: default_ws_max_in_flight(),
+      ws_mailbox_capacity: default_ws_mailbox_capacity(),
+      client_ca_file: String::new(),
+      require_client_cert: false,
+      client_identities: Vec::new(),
+      ready_requires_nodes: false,
+      read_only: false,
+      cors: CorsConfig::default(),
+      compression: CompressionConfig::default(),
+      unix_socket_path: String::new(),
+      unix_socket_mode: default_unix_socket_mode(),
+    }
+  }
+}
+
+fn default_http_port() -> u32 {
+  8081
+}
+
+fn default_https_port() -> u32 {
+  1443
+}
+
+fn default_grpc_port() -> u32 {
+  9091
+}
+
+fn default_backlog() -> u32 {
+  10000
+}
+
+fn default_max_connection_rate() -> usize {
+  1000
+}
+
+fn default_max_connections() -> usize {
+  10000
+}
+
+fn default_max_frame_size() -> usize {
+  134217728
+}
+
+fn default_acme_directory_url() -> String {
+  "https://acme-v02.api.letsencrypt.org/directory".to_owned()
+}
+
+fn default_acme_renew_before() -> u64 {
+  30
+}
+
+fn default_acme_check_interval() -> u64 {
+  3600
+}
+
+fn default_unix_socket_mode() -> u32 {
+  0o600
+}
+
+fn default_enable_http() -> bool {
+  true
+}
+
+fn default_enable_https() -> bool {
+  true
+}
+
+fn default_compression_enabled() -> bool {
+  true
+}
+
+fn default_compression_min_size() -> usize {
+  1024
+}
+
+fn default_ws_heartbeat_interval() -> u64 {
+  10
+}
+
+fn default_ws_idle_timeout() -> u64 {
+  60
+}
+
+fn default_ws_max_rate_limited() -> u32 {
+  100
+}
+
+fn default_ws_max_decode_failures() -> u32 {
+  3
+}
+
+fn default_ws_max_in_flight() -> u32 {
+  256
+}
+
+fn default_ws_mailbox_capacity() -> usize {
+  16
 }
 
 fn deserialize_keep_alive<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
@@ -40,23 +370,708 @@ where D: Deserializer<'de> {
   }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Clone, Deserialize)]
 pub struct FrontendMgrConfig {
+  #[serde(default)]
   pub frontends: Vec<FrontendConfig>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Clone, Deserialize)]
 pub struct BackendMgrConfig {
+  #[serde(default)]
   pub backends: Vec<BackendConfig>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct ServiceMgrConfig {
+  #[serde(default = "default_stale_threshold")]
   pub stale_threshold: u32,
+  #[serde(default = "default_unhealthy_threshold")]
   pub unhealthy_threshold: u32,
+  #[serde(default = "default_sweep_interval")]
+  pub sweep_interval: u64,
+  // Seconds between writes of the activations of a service, 0 means each
+  // activation is written at once
+  #[serde(default = "default_persist_interval")]
+  pub persist_interval: u64,
+}
+
+fn default_stale_threshold() -> u32 {
+  1800
+}
+
+fn default_unhealthy_threshold() -> u32 {
+  30
+}
+
+fn default_sweep_interval() -> u64 {
+  60
+}
+
+fn default_persist_interval() -> u64 {
+  60
+}
+
+impl Default for ServiceMgrConfig {
+  fn default() -> Self {
+    ServiceMgrConfig {
+      stale_threshold: default_stale_threshold(),
+      unhealthy_threshold: default_unhealthy_threshold(),
+      sweep_interval: default_sweep_interval(),
+      persist_interval: default_persist_interval(),
+    }
+  }
+}
+
+// The nodes synced from a catalog, which keeps them the way the teams already
+// register them, instead of configuring them here too
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiscoveryConfig {
+  #[serde(default)]
+  pub source: DiscoverySource,
+  #[serde(default)]
+  pub url: String,
+  #[serde(default = "default_discovery_prefix")]
+  pub prefix: String,
+  #[serde(default)]
+  pub token: String,
+  // Seconds
+  #[serde(default = "default_discovery_poll_interval")]
+  pub poll_interval: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DiscoverySource {
+  #[default]
+  #[serde(rename = "")]
+  None,
+  Consul,
+  Etcd,
+}
+
+fn default_discovery_prefix() -> String {
+  "maxwell".to_owned()
+}
+
+fn default_discovery_poll_interval() -> u64 {
+  10
+}
+
+impl Default for DiscoveryConfig {
+  fn default() -> Self {
+    DiscoveryConfig {
+      source: DiscoverySource::None,
+      url: String::new(),
+      prefix: default_discovery_prefix(),
+      token: String::new(),
+      poll_interval: default_discovery_poll_interval(),
+    }
+  }
+}
+
+// How the health of each kind of nodes is decided from the signals, every
+// signal being stale after service_mgr.unhealthy_threshold
+#[derive(Debug, Clone, Deserialize)]
+pub struct HealthPolicyConfig {
+  #[serde(default)]
+  pub frontend: NodeHealthPolicy,
+  #[serde(default)]
+  pub backend: NodeHealthPolicy,
+  #[serde(default)]
+  pub service: NodeHealthPolicy,
+  // Seconds between the probes
+  #[serde(default = "default_probe_interval")]
+  pub probe_interval: u64,
+  // Milliseconds
+  #[serde(default = "default_probe_timeout")]
+  pub probe_timeout: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NodeHealthPolicy {
+  #[serde(default = "default_health_signals")]
+  pub signals: Vec<HealthSignal>,
+  #[serde(default)]
+  pub rule: HealthRule,
+  // Got over http at the private endpoint, for the probe signal
+  #[serde(default)]
+  pub probe_path: String,
+  // The highest load reported, from 0 to 1, for the load signal
+  #[serde(default = "default_max_load")]
+  pub max_load: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HealthSignal {
+  // Pinged or heartbeated recently
+  Ping,
+  // Probed successfully recently
+  Probe,
+  // Reported a load not above max_load recently
+  Load,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HealthRule {
+  // Every signal passes
+  #[default]
+  All,
+  // Any signal passes
+  Any,
+}
+
+fn default_probe_interval() -> u64 {
+  10
+}
+
+fn default_probe_timeout() -> u64 {
+  2000
+}
+
+fn default_health_signals() -> Vec<HealthSignal> {
+  vec![HealthSignal::Ping]
+}
+
+fn default_max_load() -> f64 {
+  1.0
+}
+
+impl Default for HealthPolicyConfig {
+  fn default() -> Self {
+    HealthPolicyConfig {
+      frontend: NodeHealthPolicy::default(),
+      backend: NodeHealthPolicy::default(),
+      service: NodeHealthPolicy::default(),
+      probe_interval: default_probe_interval(),
+      probe_timeout: default_probe_timeout(),
+    }
+  }
+}
+
+impl Default for NodeHealthPolicy {
+  fn default() -> Self {
+    NodeHealthPolicy {
+      signals: default_health_signals(),
+      rule: HealthRule::default(),
+      probe_path: String::new(),
+      max_load: default_max_load(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouteMgrConfig {
+  #[serde(default = "default_history_limit")]
+  pub history_limit: u32,
+  #[serde(default = "default_delta_window")]
+  pub delta_window: usize,
+  #[serde(default = "default_refresh_interval")]
+  pub refresh_interval: u64,
+  #[serde(default)]
+  pub strict: bool,
+  #[serde(default)]
+  pub tenants: Vec<TenantConfig>,
+  #[serde(default = "default_max_paths")]
+  pub max_paths: usize,
+  #[serde(default = "default_max_path_len")]
+  pub max_path_len: usize,
+  #[serde(default)]
+  pub alert_webhook: String,
+  #[serde(default)]
+  pub lease_grace: u32,
+}
+
+// Connections presenting the token as a bearer token belong to the tenant
+#[derive(Debug, Clone, Deserialize)]
+pub struct TenantConfig {
+  pub name: String,
+  pub token: String,
+}
+
+fn default_history_limit() -> u32 {
+  20
+}
+
+fn default_delta_window() -> usize {
+  16
+}
+
+fn default_refresh_interval() -> u64 {
+  1000
+}
+
+fn default_max_paths() -> usize {
+  1000
+}
+
+fn default_max_path_len() -> usize {
+  1024
+}
+
+impl Default for RouteMgrConfig {
+  fn default() -> Self {
+    RouteMgrConfig {
+      history_limit: default_history_limit(),
+      delta_window: default_delta_window(),
+      refresh_interval: default_refresh_interval(),
+      strict: false,
+      tenants: Vec::new(),
+      max_paths: default_max_paths(),
+      max_path_len: default_max_path_len(),
+      alert_webhook: String::new(),
+      lease_grace: 0,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TopicMgrConfig {
+  #[serde(default)]
+  pub assign_policy: AssignPolicyKind,
+  #[serde(default)]
+  pub pins: Vec<TopicPinConfig>,
+  #[serde(default)]
+  pub namespaces: Vec<TopicNamespaceConfig>,
+  #[serde(default)]
+  pub replication_factor: usize,
+  #[serde(default = "default_cache_max_items")]
+  pub cache_max_items: usize,
+  #[serde(default = "default_cache_max_weight")]
+  pub cache_max_weight: u64,
+  #[serde(default)]
+  pub cache_shards: usize,
+  #[serde(default)]
+  pub orphan_action: OrphanTopicAction,
+  #[serde(default)]
+  pub client_new_topic_rate: f64,
+  #[serde(default)]
+  pub client_new_topic_burst: f64,
+  #[serde(default)]
+  pub new_topic_rate: f64,
+  #[serde(default)]
+  pub new_topic_burst: f64,
+  #[serde(default)]
+  pub topic_ttl: u32,
+  #[serde(default = "default_gc_interval")]
+  pub gc_interval: u64,
+}
+
+fn default_gc_interval() -> u64 {
+  60
+}
+
+fn default_cache_max_items() -> usize {
+  10000
+}
+
+fn default_cache_max_weight() -> u64 {
+  10000 * 64
+}
+
+impl Default for TopicMgrConfig {
+  fn default() -> Self {
+    TopicMgrConfig {
+      assign_policy: AssignPolicyKind::default(),
+      pins: Vec::new(),
+      namespaces: Vec::new(),
+      replication_factor: 0,
+      cache_max_items: default_cache_max_items(),
+      cache_max_weight: default_cache_max_weight(),
+      cache_shards: 0,
+      orphan_action: OrphanTopicAction::default(),
+      client_new_topic_rate: 0.0,
+      client_new_topic_burst: 0.0,
+      new_topic_rate: 0.0,
+      new_topic_burst: 0.0,
+      topic_ttl: 0,
+      gc_interval: default_gc_interval(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TopicPinConfig {
+  pub topic: String,
+  pub backend_id: String,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AssignPolicyKind {
+  #[default]
+  Hash,
+  RoundRobin,
+  LeastTopics,
+  CapacityWeighted,
+}
+
+// What to do at startup with the topics assigned to backends no longer configured
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OrphanTopicAction {
+  Report,
+  #[default]
+  Reassign,
+  Delete,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TopicNamespaceConfig {
+  pub name: String,
+  pub backends: Vec<String>,
+  #[serde(default)]
+  pub assign_policy: Option<AssignPolicyKind>,
+  #[serde(default)]
+  pub max_topics: u64,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct AdminConfig {
+  // Empty means the admin endpoints are only served on the unix socket
+  #[serde(default)]
+  pub api_keys: Vec<ApiKeyConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiKeyConfig {
+  pub key: String,
+  pub role: AdminRole,
+}
+
+// Ordered, a role may do everything the lower ones may
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AdminRole {
+  ReadOnly,
+  Admin,
+}
+
+// Obtains and renews the certificate of the https listener, validated over the
+// http listener, which must be reachable on port 80 of the domain
+#[derive(Debug, Clone, Deserialize)]
+pub struct AcmeConfig {
+  #[serde(default)]
+  pub enabled: bool,
+  #[serde(default)]
+  pub domain: String,
+  #[serde(default)]
+  pub contact_email: String,
+  #[serde(default = "default_acme_directory_url")]
+  pub directory_url: String,
+  // Days before expiring
+  #[serde(default = "default_acme_renew_before")]
+  pub renew_before: u64,
+  // Seconds
+  #[serde(default = "default_acme_check_interval")]
+  pub check_interval: u64,
+}
+
+impl Default for AcmeConfig {
+  fn default() -> Self {
+    AcmeConfig {
+      enabled: false,
+      domain: String::new(),
+      contact_email: String::new(),
+      directory_url: default_acme_directory_url(),
+      renew_before: default_acme_renew_before(),
+      check_interval: default_acme_check_interval(),
+    }
+  }
+}
+
+// The changes of the nodes, routes and topics, as published by the managers
+#[derive(Debug, Clone, Deserialize)]
+pub struct EventBusConfig {
+  // Events buffered for the slowest subscriber, which misses the older ones
+  // once it lags further behind
+  #[serde(default = "default_event_bus_capacity")]
+  pub capacity: usize,
+  // Posted a json of each event, empty for none
+  #[serde(default)]
+  pub webhook: String,
+  // The kinds posted to the webhook, all kinds if empty
+  #[serde(default)]
+  pub webhook_kinds: Vec<EventKind>,
 }
 
-#[derive(Debug, Deserialize)]
+fn default_event_bus_capacity() -> usize {
+  1024
+}
+
+impl Default for EventBusConfig {
+  fn default() -> Self {
+    EventBusConfig {
+      capacity: default_event_bus_capacity(),
+      webhook: String::new(),
+      webhook_kinds: vec![],
+    }
+  }
+}
+
+// Records the mutations of the state, and who asked for them
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuditConfig {
+  #[serde(default = "default_audit_enabled")]
+  pub enabled: bool,
+  // Days the records are kept, 0 means forever
+  #[serde(default = "default_audit_retention")]
+  pub retention: u32,
+  // Seconds
+  #[serde(default = "default_audit_prune_interval")]
+  pub prune_interval: u64,
+}
+
+fn default_audit_enabled() -> bool {
+  true
+}
+
+fn default_audit_retention() -> u32 {
+  30
+}
+
+fn default_audit_prune_interval() -> u64 {
+  3600
+}
+
+impl Default for AuditConfig {
+  fn default() -> Self {
+    AuditConfig {
+      enabled: default_audit_enabled(),
+      retention: default_audit_retention(),
+      prune_interval: default_audit_prune_interval(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TracingConfig {
+  // e.g. "http://localhost:4317", empty means spans are not exported
+  #[serde(default)]
+  pub otlp_endpoint: String,
+  // Of the traces started here, the others follow their parents
+  #[serde(default = "default_sampling_ratio")]
+  pub sampling_ratio: f64,
+  #[serde(default = "default_service_name")]
+  pub service_name: String,
+}
+
+fn default_sampling_ratio() -> f64 {
+  0.1
+}
+
+fn default_service_name() -> String {
+  "maxwell-master".to_owned()
+}
+
+impl Default for TracingConfig {
+  fn default() -> Self {
+    TracingConfig {
+      otlp_endpoint: String::new(),
+      sampling_ratio: default_sampling_ratio(),
+      service_name: default_service_name(),
+    }
+  }
+}
+
+// Milliseconds, 0 means disabled
+#[derive(Debug, Clone, Deserialize)]
+pub struct SlowLogConfig {
+  #[serde(default = "default_slow_msg_threshold")]
+  pub msg_threshold: u64,
+  // By msg type, e.g. "get_routes_req", taking precedence over msg_threshold
+  #[serde(default)]
+  pub msg_thresholds: BTreeMap<String, u64>,
+  #[serde(default = "default_slow_http_threshold")]
+  pub http_threshold: u64,
+}
+
+fn default_slow_msg_threshold() -> u64 {
+  100
+}
+
+fn default_slow_http_threshold() -> u64 {
+  500
+}
+
+impl Default for SlowLogConfig {
+  fn default() -> Self {
+    SlowLogConfig {
+      msg_threshold: default_slow_msg_threshold(),
+      msg_thresholds: BTreeMap::new(),
+      http_threshold: default_slow_http_threshold(),
+    }
+  }
+}
+
+// Serves /$admin/profile/*, off by default as profiling slows the server down
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProfilingConfig {
+  #[serde(default)]
+  pub enabled: bool,
+  // Seconds a cpu profile may last
+  #[serde(default = "default_profiling_max_duration")]
+  pub max_duration: u64,
+}
+
+fn default_profiling_max_duration() -> u64 {
+  60
+}
+
+impl Default for ProfilingConfig {
+  fn default() -> Self {
+    ProfilingConfig { enabled: false, max_duration: default_profiling_max_duration() }
+  }
+}
+
+// Pushes the key metrics, in addition to the ones served at /$metrics
+#[derive(Debug, Clone, Deserialize)]
+pub struct StatsdConfig {
+  // e.g. "127.0.0.1:8125", empty means disabled
+  #[serde(default)]
+  pub address: String,
+  #[serde(default = "default_statsd_prefix")]
+  pub prefix: String,
+  // Seconds
+  #[serde(default = "default_statsd_interval")]
+  pub interval: u64,
+}
+
+fn default_statsd_prefix() -> String {
+  "maxwell_master".to_owned()
+}
+
+fn default_statsd_interval() -> u64 {
+  10
+}
+
+impl Default for StatsdConfig {
+  fn default() -> Self {
+    StatsdConfig {
+      address: String::new(),
+      prefix: default_statsd_prefix(),
+      interval: default_statsd_interval(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClusterConfig {
+  // Identifies this master to its peers
+  #[serde(default)]
+  pub master_id: String,
+  // Shared by all masters, empty means no peer may connect
+  #[serde(default)]
+  pub peer_token: String,
+  // The ws url of the primary, which makes this master a standby streaming the
+  // state of the primary until promoted, empty for none
+  #[serde(default)]
+  pub primary_url: String,
+  // Seconds between the attempts to reconnect to the primary
+  #[serde(default = "default_standby_retry_interval")]
+  pub standby_retry_interval: u64,
+  // The http url of the snapshots of the primary, which a standby then fetches
+  // in chunks instead of in a single ws msg, empty for none
+  #[serde(default)]
+  pub snapshot_url: String,
+  // Bytes per chunk of the snapshots served to peers
+  #[serde(default = "default_snapshot_chunk_size")]
+  pub snapshot_chunk_size: usize,
+  // Seconds a snapshot is kept for its transfer to be resumed
+  #[serde(default = "default_snapshot_ttl")]
+  pub snapshot_ttl: u32,
+  // The other masters replicating the state by raft with this one, empty means
+  // no replication, they are all expected to list each other
+  #[serde(default)]
+  pub peers: Vec<PeerConfig>,
+  // The ws endpoint of this master, which the clients are redirected to while
+  // it is the leader, e.g. "10.0.0.1:8081"
+  #[serde(default)]
+  pub endpoint: String,
+  // Milliseconds without a heartbeat of the leader before a follower stands for
+  // election, randomized between the min and the max
+  #[serde(default = "default_election_timeout_min")]
+  pub election_timeout_min: u64,
+  #[serde(default = "default_election_timeout_max")]
+  pub election_timeout_max: u64,
+  // Milliseconds between the appends the leader sends, empty or not
+  #[serde(default = "default_heartbeat_interval")]
+  pub heartbeat_interval: u64,
+  // Milliseconds a write waits for its entry to be committed and applied by the
+  // leader, before it fails with the outcome unknown
+  #[serde(default = "default_commit_timeout")]
+  pub commit_timeout: u64,
+  // Entries of the raft log since its last snapshot entry before the leader
+  // appends another one, and the entries before that are dropped
+  #[serde(default = "default_max_log_entries")]
+  pub max_log_entries: u64,
+}
+
+impl Default for ClusterConfig {
+  fn default() -> Self {
+    ClusterConfig {
+      master_id: String::new(),
+      peer_token: String::new(),
+      primary_url: String::new(),
+      standby_retry_interval: default_standby_retry_interval(),
+      snapshot_url: String::new(),
+      snapshot_chunk_size: default_snapshot_chunk_size(),
+      snapshot_ttl: default_snapshot_ttl(),
+      peers: vec![],
+      endpoint: String::new(),
+      election_timeout_min: default_election_timeout_min(),
+      election_timeout_max: default_election_timeout_max(),
+      heartbeat_interval: default_heartbeat_interval(),
+      commit_timeout: default_commit_timeout(),
+      max_log_entries: default_max_log_entries(),
+    }
+  }
+}
+
+fn default_standby_retry_interval() -> u64 {
+  5
+}
+
+fn default_snapshot_chunk_size() -> usize {
+  1024 * 1024
+}
+
+fn default_snapshot_ttl() -> u32 {
+  300
+}
+
+fn default_election_timeout_min() -> u64 {
+  1500
+}
+
+fn default_election_timeout_max() -> u64 {
+  3000
+}
+
+fn default_heartbeat_interval() -> u64 {
+  500
+}
+
+fn default_commit_timeout() -> u64 {
+  5000
+}
+
+fn default_max_log_entries() -> u64 {
+  10000
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PeerConfig {
+  pub master_id: String,
+  // The http url the raft reqs are posted to, e.g. "http://10.0.0.2:8081"
+  pub url: String,
+  // The ws endpoint the clients are redirected to while it is the leader
+  pub endpoint: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct FrontendConfig {
   pub id: String,
   pub domain: String,
@@ -66,21 +1081,215 @@ pub struct FrontendConfig {
   pub private_ip: IpAddr,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct BackendConfig {
   pub id: String,
   pub http_port: u32,
   pub private_ip: IpAddr,
+  #[serde(default = "default_capacity")]
+  pub capacity: u32,
 }
 
-#[derive(Debug, Deserialize)]
+pub(crate) fn default_capacity() -> u32 {
+  1
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct DbConfig {
-  #[serde(deserialize_with = "deserialize_path")]
+  #[serde(default)]
+  pub engine: DbEngine,
+  // Not used by the memory engine
+  #[serde(deserialize_with = "deserialize_path", default = "default_db_path")]
   pub path: String,
+  #[serde(default)]
+  pub backup: BackupConfig,
+  #[serde(default)]
+  pub encryption: EncryptionConfig,
+  #[serde(default)]
+  pub offsite: OffsiteConfig,
+  #[serde(default)]
   pub seriesdb: SeriesdbConfig,
+  #[serde(default)]
+  pub pool: DbPoolConfig,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DbEngine {
+  #[default]
+  Seriesdb,
+  // Nothing is kept across restarts, the nodes register again anyway
+  Memory,
+}
+
+// Relative to the working dir, as the paths configured
+fn default_db_path() -> String {
+  current_dir().map_or_else(|_| "data".to_owned(), |dir| dir.join("data").display().to_string())
 }
 
-#[derive(Debug, Deserialize)]
+impl Default for DbConfig {
+  fn default() -> Self {
+    DbConfig {
+      engine: DbEngine::default(),
+      path: default_db_path(),
+      backup: BackupConfig::default(),
+      encryption: EncryptionConfig::default(),
+      offsite: OffsiteConfig::default(),
+      seriesdb: SeriesdbConfig::default(),
+      pool: DbPoolConfig::default(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BackupConfig {
+  // Where the backups are written, unless POST /$admin/backup is given another
+  #[serde(deserialize_with = "deserialize_path", default = "default_backup_dir")]
+  pub dir: String,
+  // A cron expression in utc, e.g. "0 3 * * *", empty for no scheduled backups
+  #[serde(default)]
+  pub schedule: String,
+  // The backups in dir beyond the newest ones are deleted, 0 keeps all
+  #[serde(default = "default_backup_keep")]
+  pub keep: usize,
+  // Each scheduled backup is put there as well, with {name} replaced by its
+  // file name, e.g. an s3 compatible bucket accepting the puts, empty for none
+  #[serde(default)]
+  pub upload_url: String,
+  // Posted a json of the error when a scheduled backup fails, empty for none
+  #[serde(default)]
+  pub failure_webhook: String,
+}
+
+// Relative to the working dir, as the paths configured
+fn default_backup_dir() -> String {
+  current_dir()
+    .map_or_else(|_| "backups".to_owned(), |dir| dir.join("backups").display().to_string())
+}
+
+fn default_backup_keep() -> usize {
+  7
+}
+
+impl Default for BackupConfig {
+  fn default() -> Self {
+    BackupConfig {
+      dir: default_backup_dir(),
+      schedule: String::new(),
+      keep: default_backup_keep(),
+      upload_url: String::new(),
+      failure_webhook: String::new(),
+    }
+  }
+}
+
+// Where the key encrypting the values of the tables is read from, at most one
+// of them, as the base64 of 32 bytes, none for no encryption
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EncryptionConfig {
+  #[serde(default)]
+  pub key_env: String,
+  #[serde(default)]
+  pub key_file: String,
+  // Run by sh, printing the key, e.g. the cli of a kms decrypting it
+  #[serde(default)]
+  pub key_command: String,
+}
+
+// An s3 compatible bucket the backup and the dump of the db are put in every
+// interval, for a master in another region to start from the newest of them
+#[derive(Debug, Clone, Deserialize)]
+pub struct OffsiteConfig {
+  // E.g. "https://s3.us-east-1.amazonaws.com", empty for no syncs
+  #[serde(default)]
+  pub endpoint: String,
+  #[serde(default)]
+  pub bucket: String,
+  #[serde(default = "default_offsite_region")]
+  pub region: String,
+  // Of the names of the objects
+  #[serde(default = "default_offsite_prefix")]
+  pub prefix: String,
+  #[serde(default)]
+  pub access_key_id: String,
+  #[serde(default = "default_secret_access_key_env")]
+  pub secret_access_key_env: String,
+  // Seconds between the syncs
+  #[serde(default = "default_offsite_interval")]
+  pub interval: u64,
+  // The syncs in the bucket beyond the newest ones are deleted, 0 keeps all
+  #[serde(default = "default_backup_keep")]
+  pub keep: usize,
+}
+
+fn default_offsite_region() -> String {
+  "us-east-1".to_owned()
+}
+
+fn default_offsite_prefix() -> String {
+  "maxwell-master/".to_owned()
+}
+
+fn default_secret_access_key_env() -> String {
+  "AWS_SECRET_ACCESS_KEY".to_owned()
+}
+
+fn default_offsite_interval() -> u64 {
+  3600
+}
+
+impl Default for OffsiteConfig {
+  fn default() -> Self {
+    OffsiteConfig {
+      endpoint: String::new(),
+      bucket: String::new(),
+      region: default_offsite_region(),
+      prefix: default_offsite_prefix(),
+      access_key_id: String::new(),
+      secret_access_key_env: default_secret_access_key_env(),
+      interval: default_offsite_interval(),
+      keep: default_backup_keep(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DbPoolConfig {
+  #[serde(default = "default_db_pool_threads")]
+  pub threads: usize,
+  // Calls queued or running, beyond which the new ones fail at once, 0 means
+  // unbounded
+  #[serde(default = "default_max_queue_depth")]
+  pub max_queue_depth: usize,
+  // Milliseconds a handler waits for a call, 0 means forever
+  #[serde(default = "default_db_pool_timeout")]
+  pub timeout: u64,
+}
+
+fn default_db_pool_threads() -> usize {
+  4
+}
+
+fn default_max_queue_depth() -> usize {
+  1024
+}
+
+fn default_db_pool_timeout() -> u64 {
+  5000
+}
+
+impl Default for DbPoolConfig {
+  fn default() -> Self {
+    DbPoolConfig {
+      threads: default_db_pool_threads(),
+      max_queue_depth: default_max_queue_depth(),
+      timeout: default_db_pool_timeout(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
 pub struct SeriesdbConfig {
   pub table_cache_num_shard_bits: i32,
   pub write_buffer_size: usize,
@@ -94,6 +1303,23 @@ pub struct SeriesdbConfig {
   pub max_background_jobs: i32,
 }
 
+impl Default for SeriesdbConfig {
+  fn default() -> Self {
+    SeriesdbConfig {
+      table_cache_num_shard_bits: 4,
+      write_buffer_size: 134217728,
+      max_write_buffer_number: 4,
+      min_write_buffer_number_to_merge: 2,
+      max_bytes_for_level_base: 1073741824,
+      max_bytes_for_level_multiplier: 8.0,
+      target_file_size_base: 134217728,
+      target_file_size_multiplier: 8,
+      level_zero_file_num_compaction_trigger: 4,
+      max_background_jobs: 4,
+    }
+  }
+}
+
 fn deserialize_path<'de, D>(deserializer: D) -> Result<String, D::Error>
 where D: Deserializer<'de> {
   let path: String = Deserialize::deserialize(deserializer)?;
@@ -113,16 +1339,358 @@ where D: Deserializer<'de> {
 }
 
 impl Config {
-  pub(crate) fn new(path: &str) -> Result<Self> {
+  // The overrides are dotted keys to values, parsed as the settings they replace
+  pub(crate) fn new(path: &str, overrides: &BTreeMap<String, String>) -> Result<Self> {
+    let mut builder = config::Config::builder().add_source(config::File::with_name(path));
+    for (key, value) in overrides {
+      builder = builder
+        .set_override(key.as_str(), value.as_str())
+        .with_context(|| format!("Failed to override setting: {:?}", key))?;
+    }
     Ok(
-      config::Config::builder()
-        .add_source(config::File::with_name(path))
+      builder
         .build()
         .with_context(|| format!("Failed to read config from: {:?}", path))?
         .try_deserialize()
         .with_context(|| format!("Failed to deserialize config from: {:?}", path))?,
     )
   }
+
+  // The checks that need no more than the config itself
+  pub(crate) fn validate(&self) -> Result<()> {
+    let server = &self.server;
+    let has_unix_socket = cfg!(unix) && !server.unix_socket_path.is_empty();
+    if !server.enable_http && !server.enable_https && !has_unix_socket {
+      return Err(anyhow!("None of enable_http, enable_https and unix_socket_path is set"));
+    }
+    if self.acme.enabled {
+      if !server.enable_http || !server.enable_https || self.acme.domain.is_empty() {
+        return Err(anyhow!("acme needs a domain, enable_http and enable_https"));
+      }
+    } else if server.enable_https {
+      for file in [&server.cert_file, &server.key_file] {
+        if file.is_empty() {
+          return Err(anyhow!("enable_https needs a cert_file and a key_file"));
+        }
+        if !Path::new(file).is_file() {
+          return Err(anyhow!("File not found: {:?}", file));
+        }
+      }
+    }
+    if server.require_client_cert && server.client_ca_file.is_empty() {
+      return Err(anyhow!("require_client_cert needs a client_ca_file"));
+    }
+    if !self.cluster.primary_url.is_empty() && self.cluster.peer_token.is_empty() {
+      return Err(anyhow!("primary_url needs a peer_token"));
+    }
+    if !self.cluster.snapshot_url.is_empty() && self.cluster.primary_url.is_empty() {
+      return Err(anyhow!("snapshot_url needs a primary_url"));
+    }
+    let cluster = &self.cluster;
+    if !cluster.peers.is_empty() {
+      if cluster.master_id.is_empty() || cluster.peer_token.is_empty() {
+        return Err(anyhow!("cluster.peers needs a master_id and a peer_token"));
+      }
+      if cluster.endpoint.is_empty() {
+        return Err(anyhow!("cluster.peers needs an endpoint"));
+      }
+      if !cluster.primary_url.is_empty() {
+        return Err(anyhow!("cluster.peers can not be set along with a primary_url"));
+      }
+      let mut master_ids = HashSet::from([&cluster.master_id]);
+      for peer in &cluster.peers {
+        if !master_ids.insert(&peer.master_id) {
+          return Err(anyhow!("Duplicate master id: {:?}", peer.master_id));
+        }
+      }
+      if !(0 < cluster.heartbeat_interval
+        && cluster.heartbeat_interval < cluster.election_timeout_min
+        && cluster.election_timeout_min <= cluster.election_timeout_max)
+      {
+        return Err(anyhow!(
+          "cluster.heartbeat_interval must be positive and less than election_timeout_min, \
+           which must not be more than election_timeout_max"
+        ));
+      }
+      if cluster.max_log_entries == 0 {
+        return Err(anyhow!("cluster.max_log_entries must be positive"));
+      }
+      if cluster.commit_timeout <= cluster.heartbeat_interval {
+        return Err(anyhow!("cluster.commit_timeout must be more than heartbeat_interval"));
+      }
+    }
+    if !self.db.backup.schedule.is_empty() {
+      self
+        .db
+        .backup
+        .schedule
+        .parse::<Schedule>()
+        .map_err(|err| anyhow!("Invalid db.backup.schedule: {}", err))?;
+    }
+    let encryption = &self.db.encryption;
+    let key_sources = [&encryption.key_env, &encryption.key_file, &encryption.key_command];
+    if key_sources.iter().filter(|source| !source.is_empty()).count() > 1 {
+      return Err(anyhow!("Only one of key_env, key_file and key_command can be set"));
+    }
+    let offsite = &self.db.offsite;
+    if !offsite.endpoint.is_empty() {
+      if offsite.bucket.is_empty() || offsite.access_key_id.is_empty() {
+        return Err(anyhow!("db.offsite.endpoint needs a bucket and an access_key_id"));
+      }
+      if self.db.engine != DbEngine::Seriesdb {
+        return Err(anyhow!("db.offsite needs the seriesdb engine"));
+      }
+      if offsite.interval == 0 {
+        return Err(anyhow!("db.offsite.interval must be positive"));
+      }
+    }
+    for (node_type, policy) in [
+      ("frontend", &self.health_policy.frontend),
+      ("backend", &self.health_policy.backend),
+      ("service", &self.health_policy.service),
+    ] {
+      if policy.signals.is_empty() {
+        return Err(anyhow!("health_policy.{}.signals must not be empty", node_type));
+      }
+      if policy.signals.contains(&HealthSignal::Probe) && !policy.probe_path.starts_with('/') {
+        return Err(anyhow!("health_policy.{}.probe_path must start with /", node_type));
+      }
+      if !(policy.max_load > 0.0 && policy.max_load <= 1.0) {
+        return Err(anyhow!("health_policy.{}.max_load must be in (0, 1]", node_type));
+      }
+    }
+    if self.health_policy.probe_interval == 0 {
+      return Err(anyhow!("health_policy.probe_interval must be positive"));
+    }
+    if self.event_bus.capacity == 0 {
+      return Err(anyhow!("event_bus.capacity must be positive"));
+    }
+    if self.discovery.source != DiscoverySource::None && self.discovery.url.is_empty() {
+      return Err(anyhow!("discovery.source needs a discovery.url"));
+    }
+    if self.cluster.snapshot_chunk_size == 0 {
+      return Err(anyhow!("snapshot_chunk_size must be positive"));
+    }
+    let mut node_ids = HashSet::new();
+    for id in self.frontend_mgr.frontends.iter().map(|frontend| &frontend.id) {
+      if !node_ids.insert(id) {
+        return Err(anyhow!("Duplicate frontend id: {:?}", id));
+      }
+    }
+    node_ids.clear();
+    for id in self.backend_mgr.backends.iter().map(|backend| &backend.id) {
+      if !node_ids.insert(id) {
+        return Err(anyhow!("Duplicate backend id: {:?}", id));
+      }
+    }
+    Ok(())
+  }
+}
+
+pub const DEFAULT_CONFIG_PATH: &str = "config/config.toml";
+
+// Where the config is read from, and the settings overridden from the command line
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSource {
+  pub path: String,
+  pub http_port: Option<u32>,
+  pub https_port: Option<u32>,
+  // Given by an embedder instead of the file, neither the file nor the
+  // settings in the db apply then
+  pub config: Option<Config>,
+}
+
+static CONFIG_SOURCE: OnceCell<ConfigSource> = OnceCell::new();
+
+// The settings stored in the db by the config mgr
+static OVERRIDES: Lazy<RwLock<BTreeMap<String, String>>> =
+  Lazy::new(|| RwLock::new(BTreeMap::new()));
+
+// Must be called before the config is first accessed
+pub fn init(source: ConfigSource) {
+  if CONFIG_SOURCE.set(source).is_err() {
+    panic!("The config source was already initialized");
+  }
 }
 
-pub static CONFIG: Lazy<Config> = Lazy::new(|| Config::new("config/config.toml").unwrap());
+// From the lowest precedence to the highest: the defaults, the config file, the
+// settings in the db, the command line
+pub(crate) fn load() -> Result<Config> {
+  load_with(&OVERRIDES.read().unwrap())
+}
+
+pub(crate) fn load_with(overrides: &BTreeMap<String, String>) -> Result<Config> {
+  let source = CONFIG_SOURCE
+    .get_or_init(|| ConfigSource { path: DEFAULT_CONFIG_PATH.to_owned(), ..Default::default() });
+  let mut config = match &source.config {
+    Some(config) => config.clone(),
+    None => Config::new(&source.path, overrides)?,
+  };
+  if let Some(http_port) = source.http_port {
+    config.server.http_port = http_port;
+  }
+  if let Some(https_port) = source.https_port {
+    config.server.https_port = https_port;
+  }
+  Ok(config)
+}
+
+// Takes effect on the next load
+pub(crate) fn set_overrides(overrides: BTreeMap<String, String>) {
+  *OVERRIDES.write().unwrap() = overrides;
+}
+
+// Keeps the latest config read, what was loaded stays valid while it is held,
+// and a reload frees the config it replaces once nobody holds it
+pub struct ConfigCell {
+  config: ArcSwap<Config>,
+}
+
+impl ConfigCell {
+  #[inline]
+  fn new(config: Config) -> Self {
+    ConfigCell { config: ArcSwap::from_pointee(config) }
+  }
+
+  // Held only as long as needed, so that the settings reloaded are seen
+  #[inline]
+  pub fn load(&self) -> Arc<Config> {
+    self.config.load_full()
+  }
+
+  #[inline]
+  fn set(&self, config: Config) {
+    self.config.store(Arc::new(config));
+  }
+}
+
+pub static CONFIG: Lazy<ConfigCell> = Lazy::new(|| {
+  ConfigCell::new(load().unwrap_or_else(|err| panic!("Failed to load config: {:#}", err)))
+});
+
+static RELOAD_LOCK: Mutex<()> = Mutex::new(());
+
+// Restores the setting of the current config when the new one differs, the
+// name of the setting is returned with the others ignored
+macro_rules! keep_setting {
+  ($curr:expr, $new:expr, $ignored:expr, $($field:ident).+) => {
+    if format!("{:?}", $curr.$($field).+) != format!("{:?}", $new.$($field).+) {
+      $ignored.push(stringify!($($field).+));
+      $new.$($field).+ = $curr.$($field).+.clone();
+    }
+  };
+}
+
+// Re-reads the config file, the settings only read at startup keep their
+// current values until restarted, and are returned as ignored
+pub(crate) fn reload() -> Result<Vec<&'static str>> {
+  let _guard = RELOAD_LOCK.lock().unwrap();
+  let curr = CONFIG.load();
+  let mut new = load()?;
+  let mut ignored = vec![];
+  keep_setting!(curr, new, ignored, server.enable_http);
+  keep_setting!(curr, new, ignored, server.enable_https);
+  keep_setting!(curr, new, ignored, server.http_port);
+  keep_setting!(curr, new, ignored, server.https_port);
+  keep_setting!(curr, new, ignored, server.enable_grpc);
+  keep_setting!(curr, new, ignored, server.grpc_port);
+  keep_setting!(curr, new, ignored, server.cert_file);
+  keep_setting!(curr, new, ignored, server.key_file);
+  keep_setting!(curr, new, ignored, server.backlog);
+  keep_setting!(curr, new, ignored, server.keep_alive);
+  keep_setting!(curr, new, ignored, server.max_connection_rate);
+  keep_setting!(curr, new, ignored, server.max_connections);
+  keep_setting!(curr, new, ignored, server.workers);
+  keep_setting!(curr, new, ignored, server.max_frame_size);
+  keep_setting!(curr, new, ignored, server.client_ca_file);
+  keep_setting!(curr, new, ignored, server.require_client_cert);
+  keep_setting!(curr, new, ignored, server.unix_socket_path);
+  keep_setting!(curr, new, ignored, server.unix_socket_mode);
+  keep_setting!(curr, new, ignored, server.compression.enabled);
+  keep_setting!(curr, new, ignored, server.cors.allowed_methods);
+  keep_setting!(curr, new, ignored, server.cors.allowed_headers);
+  keep_setting!(curr, new, ignored, server.cors.max_age);
+  keep_setting!(curr, new, ignored, server.cors.supports_credentials);
+  // Only the origins listed may change, not whether any origin is allowed
+  if curr.server.cors.allows_any_origin() != new.server.cors.allows_any_origin() {
+    ignored.push("server.cors.allowed_origins");
+    new.server.cors.allowed_origins = curr.server.cors.allowed_origins.clone();
+  }
+  keep_setting!(curr, new, ignored, service_mgr.sweep_interval);
+  keep_setting!(curr, new, ignored, service_mgr.persist_interval);
+  keep_setting!(curr, new, ignored, discovery.source);
+  keep_setting!(curr, new, ignored, health_policy.probe_interval);
+  keep_setting!(curr, new, ignored, route_mgr.refresh_interval);
+  keep_setting!(curr, new, ignored, route_mgr.alert_webhook);
+  keep_setting!(curr, new, ignored, topic_mgr.assign_policy);
+  keep_setting!(curr, new, ignored, topic_mgr.pins);
+  keep_setting!(curr, new, ignored, topic_mgr.namespaces);
+  keep_setting!(curr, new, ignored, topic_mgr.cache_max_items);
+  keep_setting!(curr, new, ignored, topic_mgr.cache_max_weight);
+  keep_setting!(curr, new, ignored, topic_mgr.cache_shards);
+  keep_setting!(curr, new, ignored, topic_mgr.topic_ttl);
+  keep_setting!(curr, new, ignored, topic_mgr.gc_interval);
+  keep_setting!(curr, new, ignored, cluster.master_id);
+  keep_setting!(curr, new, ignored, cluster.primary_url);
+  keep_setting!(curr, new, ignored, cluster.peers);
+  keep_setting!(curr, new, ignored, cluster.endpoint);
+  keep_setting!(curr, new, ignored, acme);
+  keep_setting!(curr, new, ignored, tracing);
+  keep_setting!(curr, new, ignored, statsd);
+  keep_setting!(curr, new, ignored, event_bus.capacity);
+  keep_setting!(curr, new, ignored, event_bus.webhook);
+  keep_setting!(curr, new, ignored, audit.enabled);
+  keep_setting!(curr, new, ignored, audit.prune_interval);
+  keep_setting!(curr, new, ignored, db);
+  CONFIG.set(new);
+  Ok(ignored)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse(toml: &str) -> Config {
+    config::Config::builder()
+      .add_source(config::File::from_str(toml, config::FileFormat::Toml))
+      .build()
+      .unwrap()
+      .try_deserialize()
+      .unwrap()
+  }
+
+  #[test]
+  fn test_defaults() {
+    let config = parse("");
+    assert_eq!(config.server.http_port, 8081);
+    assert_eq!(config.server.workers, 0);
+    assert!(config.frontend_mgr.frontends.is_empty());
+    assert!(config.backend_mgr.backends.is_empty());
+    assert_eq!(config.service_mgr.unhealthy_threshold, 30);
+    assert!(config.db.path.ends_with("data"));
+    assert_eq!(config.db.seriesdb.max_background_jobs, 4);
+    assert_eq!(config.discovery.source, DiscoverySource::None);
+
+    let config = parse("[server]\nhttp_port = 9081\n[db.seriesdb]\nmax_background_jobs = 2\n");
+    assert_eq!(config.server.http_port, 9081);
+    assert_eq!(config.server.https_port, 1443);
+    assert_eq!(config.db.seriesdb.max_background_jobs, 2);
+    assert_eq!(config.db.seriesdb.write_buffer_size, 134217728);
+
+    let config = parse("[discovery]\nsource = \"consul\"\nurl = \"http://127.0.0.1:8500\"\n");
+    assert_eq!(config.discovery.source, DiscoverySource::Consul);
+    assert_eq!(config.discovery.prefix, "maxwell");
+
+    let config = parse("[db.offsite]\nendpoint = \"http://127.0.0.1:9000\"\nbucket = \"master\"\n");
+    assert_eq!(config.db.offsite.region, "us-east-1");
+    assert_eq!(config.db.offsite.interval, 3600);
+    assert!(config.validate().is_err());
+
+    let config =
+      parse("[health_policy.service]\nsignals = [\"ping\", \"probe\"]\nrule = \"any\"\n");
+    assert_eq!(config.health_policy.service.signals, vec![HealthSignal::Ping, HealthSignal::Probe]);
+    assert_eq!(config.health_policy.service.rule, HealthRule::Any);
+    assert_eq!(config.health_policy.frontend.signals, vec![HealthSignal::Ping]);
+    assert!(config.validate().is_err());
+  }
+}
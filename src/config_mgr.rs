@@ -0,0 +1,189 @@
+use std::{borrow::Borrow, collections::BTreeMap, fmt, sync::Mutex};
+
+use anyhow::Result;
+use bytes::{Bytes, BytesMut};
+use once_cell::sync::Lazy;
+use seriesdb::coder::Coder;
+
+use crate::{
+  config, hot_reload,
+  store::{open_store, Store},
+};
+
+// The settings read on each use, so that a change applies at once, the
+// others are only read at startup and are left to the config file
+pub const TUNABLE_SETTINGS: &[&str] = &[
+  "server.ws_idle_timeout",
+  "server.ws_msg_rate",
+  "server.ws_msg_burst",
+  "server.ws_ip_msg_rate",
+  "server.ws_ip_msg_burst",
+  "server.ws_max_rate_limited",
+  "server.ready_requires_nodes",
+  "server.read_only",
+  "service_mgr.stale_threshold",
+  "service_mgr.unhealthy_threshold",
+  "route_mgr.strict",
+  "route_mgr.history_limit",
+  "route_mgr.max_paths",
+  "route_mgr.lease_grace",
+  "topic_mgr.replication_factor",
+  "topic_mgr.orphan_action",
+  "topic_mgr.client_new_topic_rate",
+  "topic_mgr.client_new_topic_burst",
+  "topic_mgr.new_topic_rate",
+  "topic_mgr.new_topic_burst",
+  "slow_log.msg_threshold",
+  "slow_log.http_threshold",
+];
+
+type SettingKey = String;
+type SettingValue = String;
+type SettingStore = dyn Store<SettingKey, SettingValue>;
+
+pub(crate) struct SettingCoder;
+
+impl Coder<SettingKey, SettingValue> for SettingCoder {
+  type EncodedKey = Bytes;
+  type EncodedValue = Bytes;
+
+  #[inline(always)]
+  fn encode_key<K: Borrow<SettingKey>>(key: K) -> Self::EncodedKey {
+    BytesMut::from(key.borrow().as_bytes()).freeze()
+  }
+
+  #[inline(always)]
+  fn decode_key(key: &[u8]) -> SettingKey {
+    std::str::from_utf8(key).unwrap().to_string()
+  }
+
+  #[inline(always)]
+  fn encode_value<V: Borrow<SettingValue>>(value: V) -> Self::EncodedValue {
+    BytesMut::from(value.borrow().as_bytes()).freeze()
+  }
+
+  #[inline(always)]
+  fn decode_value(value: &[u8]) -> SettingValue {
+    std::str::from_utf8(value).unwrap().to_string()
+  }
+}
+
+#[derive(Debug)]
+pub enum SettingError {
+  Untunable { key: String },
+  InvalidValue { key: String, value: String, err: String },
+  Failed(anyhow::Error),
+}
+
+impl fmt::Display for SettingError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SettingError::Untunable { key } => write!(f, "The setting is not tunable: {}", key),
+      SettingError::InvalidValue { key, value, err } => {
+        write!(f, "Invalid value of setting: {}, value: {}, err: {}", key, value, err)
+      }
+      SettingError::Failed(err) => write!(f, "Failed to apply settings: {}", err),
+    }
+  }
+}
+
+impl std::error::Error for SettingError {}
+
+// Keeps the settings tuned at runtime, which take precedence over the config
+// file, so that they survive reloads and restarts
+pub struct ConfigMgr {
+  setting_store: Box<SettingStore>,
+  settings: Mutex<BTreeMap<SettingKey, SettingValue>>,
+}
+
+impl ConfigMgr {
+  #[inline]
+  fn new(setting_store: Box<SettingStore>) -> Self {
+    let config_mgr = ConfigMgr { setting_store, settings: Mutex::new(BTreeMap::new()) };
+    config_mgr.recover();
+    config_mgr
+  }
+
+  // Applies the stored settings, called once at startup
+  pub fn apply(&self) -> Result<()> {
+    let settings = self.settings.lock().unwrap();
+    if settings.is_empty() {
+      return Ok(());
+    }
+    log::info!("Applying settings: {:?}", settings);
+    config::set_overrides(settings.clone());
+    hot_reload::reload()?;
+    Ok(())
+  }
+
+  #[inline]
+  pub fn settings(&self) -> BTreeMap<SettingKey, SettingValue> {
+    self.settings.lock().unwrap().clone()
+  }
+
+  // The value is checked by loading the config with it before being stored
+  pub fn set(&self, key: SettingKey, value: SettingValue) -> Result<(), SettingError> {
+    if !is_tunable(&key) {
+      return Err(SettingError::Untunable { key });
+    }
+    let mut settings = self.settings.lock().unwrap();
+    let mut new_settings = settings.clone();
+    new_settings.insert(key.clone(), value.clone());
+    if let Err(err) = config::load_with(&new_settings) {
+      return Err(SettingError::InvalidValue { key, value, err: format!("{:#}", err) });
+    }
+    log::info!("Setting: {:?}, to: {:?}", key, value);
+    self.setting_store.put(&key, &value).map_err(|err| SettingError::Failed(err.into()))?;
+    *settings = new_settings;
+    Self::apply_settings(&settings)
+  }
+
+  // Falls back to the config file
+  pub fn remove(&self, key: &str) -> Result<bool, SettingError> {
+    let mut settings = self.settings.lock().unwrap();
+    if settings.remove(key).is_none() {
+      return Ok(false);
+    }
+    log::info!("Removing setting: {:?}", key);
+    self.setting_store.delete(&key.to_owned()).map_err(|err| SettingError::Failed(err.into()))?;
+    Self::apply_settings(&settings)?;
+    Ok(true)
+  }
+
+  #[inline]
+  fn apply_settings(settings: &BTreeMap<SettingKey, SettingValue>) -> Result<(), SettingError> {
+    config::set_overrides(settings.clone());
+    hot_reload::reload().map(|_| ()).map_err(SettingError::Failed)
+  }
+
+  // The settings no longer tunable, e.g. after an upgrade, are dropped
+  fn recover(&self) {
+    let mut settings = self.settings.lock().unwrap();
+    let mut untunable_keys = vec![];
+    self.setting_store.scan(None, &mut |key, value| {
+      if is_tunable(&key) {
+        settings.insert(key, value);
+      } else {
+        untunable_keys.push(key);
+      }
+      true
+    });
+    for key in untunable_keys {
+      log::warn!("Dropping untunable setting: {:?}", key);
+      self.setting_store.delete(&key).unwrap_or_else(|err| {
+        log::warn!("Failed to drop setting: {:?}, err: {:?}", key, err);
+      });
+    }
+  }
+}
+
+#[inline]
+pub fn is_tunable(key: &str) -> bool {
+  TUNABLE_SETTINGS.contains(&key)
+}
+
+pub static CONFIG_MGR: Lazy<ConfigMgr> = Lazy::new(|| {
+  ConfigMgr::new(
+    open_store::<SettingKey, SettingValue, SettingCoder>("config_mgr.settings").unwrap(),
+  )
+});
@@ -0,0 +1,171 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Datelike, Duration, DurationRound, Timelike, Utc};
+
+// The matches are looked for up to this far ahead, e.g. for "0 0 29 2 *"
+const MAX_LOOKAHEAD_DAYS: i64 = 366 * 4;
+
+// A cron expression of five fields, minute hour day-of-month month
+// day-of-week, each being *, a value, a range a-b, a list of those, with an
+// optional step /n, in utc. As with cron, a day matches either of day-of-month
+// and day-of-week if both are restricted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+  expr: String,
+  minutes: u64,
+  hours: u64,
+  days_of_month: u64,
+  months: u64,
+  days_of_week: u64,
+  is_day_of_month_any: bool,
+  is_day_of_week_any: bool,
+}
+
+impl Schedule {
+  // The first time matching after from, to the minute
+  pub fn next_after(&self, from: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let mut time = from.duration_trunc(Duration::minutes(1)).ok()? + Duration::minutes(1);
+    let until = time + Duration::days(MAX_LOOKAHEAD_DAYS);
+    while time < until {
+      if !self.matches_day(&time) {
+        time = time.duration_trunc(Duration::days(1)).ok()? + Duration::days(1);
+        continue;
+      }
+      if !has(self.hours, time.hour()) {
+        time = time.duration_trunc(Duration::hours(1)).ok()? + Duration::hours(1);
+        continue;
+      }
+      if has(self.minutes, time.minute()) {
+        return Some(time);
+      }
+      time += Duration::minutes(1);
+    }
+    None
+  }
+
+  fn matches_day(&self, time: &DateTime<Utc>) -> bool {
+    if !has(self.months, time.month()) {
+      return false;
+    }
+    let day_of_month = has(self.days_of_month, time.day());
+    let day_of_week = has(self.days_of_week, time.weekday().num_days_from_sunday());
+    match (self.is_day_of_month_any, self.is_day_of_week_any) {
+      (true, true) => true,
+      (false, true) => day_of_month,
+      (true, false) => day_of_week,
+      (false, false) => day_of_month || day_of_week,
+    }
+  }
+}
+
+impl FromStr for Schedule {
+  type Err = anyhow::Error;
+
+  fn from_str(expr: &str) -> Result<Self> {
+    let fields: Vec<&str> = expr.split_whitespace().collect();
+    let [minutes, hours, days_of_month, months, days_of_week] = fields[..] else {
+      bail!("Expected 5 fields: {:?}", expr);
+    };
+    Ok(Schedule {
+      expr: expr.to_owned(),
+      minutes: parse_field(minutes, 0, 59)?,
+      hours: parse_field(hours, 0, 23)?,
+      days_of_month: parse_field(days_of_month, 1, 31)?,
+      months: parse_field(months, 1, 12)?,
+      // 7 is sunday as well
+      days_of_week: {
+        let days = parse_field(days_of_week, 0, 7)?;
+        (days | days >> 7) & 0x7f
+      },
+      is_day_of_month_any: days_of_month == "*",
+      is_day_of_week_any: days_of_week == "*",
+    })
+  }
+}
+
+impl fmt::Display for Schedule {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.expr)
+  }
+}
+
+#[inline]
+fn has(bits: u64, value: u32) -> bool {
+  bits & (1 << value) != 0
+}
+
+// As a bit per value
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64> {
+  let mut bits = 0;
+  for part in field.split(',') {
+    let (range, step) = match part.split_once('/') {
+      Some((range, step)) => (range, parse_value(step, 1, max.max(1))?),
+      None => (part, 1),
+    };
+    let (from, to) = if range == "*" {
+      (min, max)
+    } else if let Some((from, to)) = range.split_once('-') {
+      (parse_value(from, min, max)?, parse_value(to, min, max)?)
+    } else {
+      let value = parse_value(range, min, max)?;
+      // a/n means from a to the max, as in most crons
+      (value, if step > 1 { max } else { value })
+    };
+    if from > to {
+      bail!("Invalid range: {:?}", part);
+    }
+    for value in (from..=to).step_by(step as usize) {
+      bits |= 1 << value;
+    }
+  }
+  Ok(bits)
+}
+
+#[inline]
+fn parse_value(value: &str, min: u32, max: u32) -> Result<u32> {
+  let parsed: u32 = value.parse().map_err(|_| anyhow!("Invalid value: {:?}", value))?;
+  if parsed < min || parsed > max {
+    bail!("Out of range: {:?}, min: {}, max: {}", value, min, max);
+  }
+  Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::TimeZone;
+
+  use super::*;
+
+  #[test]
+  fn test_next_after() {
+    let at = |y, mo, d, h, mi| Utc.with_ymd_and_hms(y, mo, d, h, mi, 0).unwrap();
+
+    let schedule: Schedule = "30 3 * * *".parse().unwrap();
+    assert_eq!(schedule.next_after(at(2024, 1, 1, 0, 0)), Some(at(2024, 1, 1, 3, 30)));
+    assert_eq!(schedule.next_after(at(2024, 1, 1, 3, 30)), Some(at(2024, 1, 2, 3, 30)));
+
+    let schedule: Schedule = "*/15 * * * *".parse().unwrap();
+    assert_eq!(schedule.next_after(at(2024, 1, 1, 0, 7)), Some(at(2024, 1, 1, 0, 15)));
+
+    // Sundays, 2024-01-07 was one
+    let schedule: Schedule = "0 0 * * 7".parse().unwrap();
+    assert_eq!(schedule.next_after(at(2024, 1, 1, 0, 0)), Some(at(2024, 1, 7, 0, 0)));
+
+    let schedule: Schedule = "0 0 29 2 *".parse().unwrap();
+    assert_eq!(schedule.next_after(at(2024, 3, 1, 0, 0)), Some(at(2028, 2, 29, 0, 0)));
+
+    // Either the 1st or a monday
+    let schedule: Schedule = "0 12 1 * 1".parse().unwrap();
+    assert_eq!(schedule.next_after(at(2024, 1, 2, 0, 0)), Some(at(2024, 1, 8, 12, 0)));
+  }
+
+  #[test]
+  fn test_parse() {
+    assert!("0 0 * *".parse::<Schedule>().is_err());
+    assert!("60 0 * * *".parse::<Schedule>().is_err());
+    assert!("5-1 0 * * *".parse::<Schedule>().is_err());
+    assert!("a 0 * * *".parse::<Schedule>().is_err());
+    assert!("0,30 1-5/2 * 1-12 1-5".parse::<Schedule>().is_ok());
+  }
+}
@@ -1,6 +1,6 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use once_cell::sync::Lazy;
-use seriesdb::prelude::{NormalDb, Options};
+use seriesdb::prelude::{Db, NormalDb, Options};
 
 use crate::config::*;
 
@@ -28,4 +28,67 @@ fn build_options(seriesdb_config: &SeriesdbConfig) -> Options {
   options
 }
 
-pub static DB: Lazy<NormalDb> = Lazy::new(|| open_db(&CONFIG.db).unwrap());
+pub static DB: Lazy<NormalDb> = Lazy::new(|| open_db(&CONFIG.load().db).unwrap());
+
+// As rocksdb has by default
+const LEVELS: u32 = 7;
+
+// Of the rocksdb under all the tables, which share it. Once the pending
+// compaction bytes or the files at level 0 pile up, rocksdb delays the writes,
+// then stops them, and every write of the managers blocks meanwhile.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct DbStats {
+  pub live_data_size: u64,
+  pub sst_files_size: u64,
+  pub sst_files: u64,
+  pub level_zero_files: u64,
+  pub pending_compaction_bytes: u64,
+  pub running_compactions: u64,
+  // Bytes per second the writes are delayed to, 0 if they are not
+  pub delayed_write_rate: u64,
+  pub is_write_stopped: bool,
+}
+
+// None with the memory engine, which has no db
+pub fn stats() -> Option<DbStats> {
+  if CONFIG.load().db.engine != DbEngine::Seriesdb {
+    return None;
+  }
+  let level_files: Vec<u64> = (0..LEVELS)
+    .map(|level| {
+      let name = format!("rocksdb.num-files-at-level{}", level);
+      DB.inner()
+        .property_value(&name)
+        .ok()
+        .flatten()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(0)
+    })
+    .collect();
+  Some(DbStats {
+    live_data_size: property_int("rocksdb.estimate-live-data-size"),
+    sst_files_size: property_int("rocksdb.live-sst-files-size"),
+    sst_files: level_files.iter().sum(),
+    level_zero_files: level_files[0],
+    pending_compaction_bytes: property_int("rocksdb.estimate-pending-compaction-bytes"),
+    running_compactions: property_int("rocksdb.num-running-compactions"),
+    delayed_write_rate: property_int("rocksdb.actual-delayed-write-rate"),
+    is_write_stopped: property_int("rocksdb.is-write-stopped") != 0,
+  })
+}
+
+#[inline]
+fn property_int(name: &str) -> u64 {
+  DB.inner().property_int_value(name).ok().flatten().unwrap_or(0)
+}
+
+// Of the whole key space, blocks until done, which may take long on a large db
+pub fn compact() -> Result<()> {
+  if CONFIG.load().db.engine != DbEngine::Seriesdb {
+    bail!("The memory engine has no db to compact");
+  }
+  log::info!("Compacting db: stats: {:?}", stats());
+  DB.inner().compact_range::<&[u8], &[u8]>(None, None);
+  log::info!("Compacted db: stats: {:?}", stats());
+  Ok(())
+}
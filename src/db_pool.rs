@@ -0,0 +1,173 @@
+use std::{
+  fmt,
+  sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    mpsc, Arc, Mutex,
+  },
+  thread,
+  time::Duration,
+};
+
+use once_cell::sync::Lazy;
+use tokio::sync::oneshot;
+
+use crate::config::{DbPoolConfig, CONFIG};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DbPoolError {
+  Overloaded { queue_depth: usize },
+  Timeout { timeout: u64 },
+  Closed,
+}
+
+impl fmt::Display for DbPoolError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      DbPoolError::Overloaded { queue_depth } => {
+        write!(f, "The db is overloaded: queue_depth: {}", queue_depth)
+      }
+      DbPoolError::Timeout { timeout } => write!(f, "The db timed out: timeout_ms: {}", timeout),
+      DbPoolError::Closed => write!(f, "The db pool is closed."),
+    }
+  }
+}
+
+impl std::error::Error for DbPoolError {}
+
+#[derive(Debug, Serialize)]
+pub struct DbPoolStats {
+  pub threads: usize,
+  pub queue_depth: usize,
+  pub completed: u64,
+  pub rejected: u64,
+  pub timed_out: u64,
+}
+
+// The db calls block, for long during compactions, so the handlers hand them
+// to these threads instead of running them on the actix workers, which would
+// stall every connection of the worker meanwhile
+pub struct DbPool {
+  sender: Mutex<mpsc::Sender<Job>>,
+  threads: usize,
+  max_queue_depth: usize,
+  timeout: u64,
+  counters: Arc<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+  // Queued or running
+  queue_depth: AtomicUsize,
+  completed: AtomicU64,
+  rejected: AtomicU64,
+  timed_out: AtomicU64,
+}
+
+impl DbPool {
+  pub(crate) fn new(config: &DbPoolConfig) -> Self {
+    let (sender, receiver) = mpsc::channel::<Job>();
+    let receiver = Arc::new(Mutex::new(receiver));
+    let counters = Arc::new(Counters::default());
+    let threads = config.threads.max(1);
+    for i in 0..threads {
+      let receiver = Arc::clone(&receiver);
+      let counters = Arc::clone(&counters);
+      thread::Builder::new()
+        .name(format!("db-pool-{}", i))
+        .spawn(move || loop {
+          let job = match receiver.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => break,
+          };
+          job();
+          counters.queue_depth.fetch_sub(1, Ordering::Relaxed);
+          counters.completed.fetch_add(1, Ordering::Relaxed);
+        })
+        .unwrap_or_else(|err| panic!("Failed to spawn db pool thread: {:?}", err));
+    }
+    DbPool {
+      sender: Mutex::new(sender),
+      threads,
+      max_queue_depth: config.max_queue_depth,
+      timeout: config.timeout,
+      counters,
+    }
+  }
+
+  // A call timed out still runs to the end, only its result is dropped, so
+  // that a write is never half done
+  pub async fn run<F, T>(&self, f: F) -> Result<T, DbPoolError>
+  where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static, {
+    let (result_sender, result_receiver) = oneshot::channel();
+    self.submit(Box::new(move || {
+      let _ = result_sender.send(f());
+    }))?;
+    if self.timeout == 0 {
+      return result_receiver.await.map_err(|_| DbPoolError::Closed);
+    }
+    match tokio::time::timeout(Duration::from_millis(self.timeout), result_receiver).await {
+      Ok(result) => result.map_err(|_| DbPoolError::Closed),
+      Err(_) => {
+        self.counters.timed_out.fetch_add(1, Ordering::Relaxed);
+        Err(DbPoolError::Timeout { timeout: self.timeout })
+      }
+    }
+  }
+
+  fn submit(&self, job: Job) -> Result<(), DbPoolError> {
+    let queue_depth = self.counters.queue_depth.fetch_add(1, Ordering::Relaxed);
+    if self.max_queue_depth > 0 && queue_depth >= self.max_queue_depth {
+      self.counters.queue_depth.fetch_sub(1, Ordering::Relaxed);
+      self.counters.rejected.fetch_add(1, Ordering::Relaxed);
+      return Err(DbPoolError::Overloaded { queue_depth });
+    }
+    self.sender.lock().unwrap().send(job).map_err(|_| {
+      self.counters.queue_depth.fetch_sub(1, Ordering::Relaxed);
+      DbPoolError::Closed
+    })
+  }
+
+  #[inline]
+  pub fn stats(&self) -> DbPoolStats {
+    DbPoolStats {
+      threads: self.threads,
+      queue_depth: self.counters.queue_depth.load(Ordering::Relaxed),
+      completed: self.counters.completed.load(Ordering::Relaxed),
+      rejected: self.counters.rejected.load(Ordering::Relaxed),
+      timed_out: self.counters.timed_out.load(Ordering::Relaxed),
+    }
+  }
+}
+
+pub static DB_POOL: Lazy<DbPool> = Lazy::new(|| DbPool::new(&CONFIG.load().db.pool));
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Barrier;
+
+  use super::*;
+
+  #[actix_web::test]
+  async fn test_run() {
+    let pool = DbPool::new(&DbPoolConfig { threads: 1, max_queue_depth: 1, timeout: 50 });
+    assert_eq!(pool.run(|| 1 + 1).await, Ok(2));
+
+    let barrier = Arc::new(Barrier::new(2));
+    let blocked = {
+      let barrier = Arc::clone(&barrier);
+      pool.run(move || {
+        barrier.wait();
+      })
+    };
+    assert_eq!(blocked.await, Err(DbPoolError::Timeout { timeout: 50 }));
+    assert_eq!(pool.run(|| ()).await, Err(DbPoolError::Overloaded { queue_depth: 1 }));
+    barrier.wait();
+
+    let stats = pool.stats();
+    assert_eq!((stats.rejected, stats.timed_out), (1, 1));
+  }
+}
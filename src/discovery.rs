@@ -0,0 +1,375 @@
+use std::{
+  collections::HashMap,
+  net::IpAddr,
+  sync::{Arc, RwLock},
+  time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use once_cell::sync::Lazy;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::{
+  config::{default_capacity, BackendConfig, DiscoverySource, FrontendConfig, CONFIG},
+  db_pool::DB_POOL,
+  maintenance,
+  node_mgr::{NodeId, Service, BACKEND_MGR, FRONTEND_MGR, SERVICE_MGR},
+  raft,
+  route_mgr::ROUTE_MGR,
+  standby,
+  topic_mgr::TOPIC_MGR,
+};
+
+// A service as kept in the catalog, which registers with the master otherwise
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub(crate) struct ServiceConfig {
+  pub private_ip: IpAddr,
+  pub http_port: u32,
+  #[serde(default)]
+  pub tenant: String,
+}
+
+// The nodes found in the catalog at the last poll
+#[derive(Debug, Default)]
+pub(crate) struct Catalog {
+  pub frontends: Vec<FrontendConfig>,
+  pub backends: Vec<BackendConfig>,
+  pub services: Vec<(NodeId, ServiceConfig)>,
+}
+
+static CATALOG: Lazy<RwLock<Arc<Catalog>>> =
+  Lazy::new(|| RwLock::new(Arc::new(Catalog::default())));
+
+// The frontends configured, then the ones discovered with other ids, which is
+// what the frontend mgr keeps
+pub(crate) fn frontend_configs() -> Vec<FrontendConfig> {
+  let catalog = CATALOG.read().unwrap().clone();
+  merge(&CONFIG.load().frontend_mgr.frontends, &catalog.frontends, |frontend| &frontend.id)
+}
+
+pub(crate) fn backend_configs() -> Vec<BackendConfig> {
+  let catalog = CATALOG.read().unwrap().clone();
+  merge(&CONFIG.load().backend_mgr.backends, &catalog.backends, |backend| &backend.id)
+}
+
+#[inline]
+fn merge<T: Clone>(configured: &[T], discovered: &[T], id_of: impl Fn(&T) -> &NodeId) -> Vec<T> {
+  let mut merged = configured.to_vec();
+  for node in discovered {
+    if !configured.iter().any(|configured| id_of(configured) == id_of(node)) {
+      merged.push(node.clone());
+    }
+  }
+  merged
+}
+
+// Polls the catalog, a failed poll keeps the nodes of the last one. The
+// services found are registered, or kept active, as if they pinged, and the
+// ones gone are swept once stale, as any other.
+pub fn spawn_poll_task() {
+  if CONFIG.load().discovery.source == DiscoverySource::None {
+    return;
+  }
+  actix_web::rt::spawn(async {
+    let client = awc::Client::builder().timeout(Duration::from_secs(10)).finish();
+    loop {
+      // On the db pool, as registering waits for raft to commit the services
+      match poll(&client).await {
+        Ok(catalog) => {
+          if let Err(err) = DB_POOL.run(move || apply(catalog)).await {
+            log::warn!("Failed to apply catalog: err: {:?}", err);
+          }
+        }
+        Err(err) => log::warn!("Failed to poll catalog: err: {:?}", err),
+      }
+      actix_web::rt::time::sleep(Duration::from_secs(CONFIG.load().discovery.poll_interval.max(1)))
+        .await;
+    }
+  });
+}
+
+fn apply(catalog: Catalog) {
+  log::debug!(
+    "Polled catalog: frontends: {:?}, backends: {:?}, services: {:?}",
+    catalog.frontends.len(),
+    catalog.backends.len(),
+    catalog.services.len()
+  );
+  let catalog = Arc::new(catalog);
+  *CATALOG.write().unwrap() = Arc::clone(&catalog);
+  FRONTEND_MGR.reload();
+  if BACKEND_MGR.reload() {
+    log::warn!("The backends discovered changed, the topics will be reassigned");
+    TOPIC_MGR.on_backends_changed();
+  }
+  // As the services could not register then either
+  if standby::is_standby() || raft::is_follower() || maintenance::is_read_only() {
+    return;
+  }
+  for (id, config) in &catalog.services {
+    let unchanged = SERVICE_MGR.get(id).map_or(false, |service| {
+      service.private_ip == config.private_ip && service.http_port == config.http_port
+    });
+    if unchanged && ROUTE_MGR.tenant_of(id) == config.tenant {
+      SERVICE_MGR.activate(id);
+      continue;
+    }
+    if let Err(err) = ROUTE_MGR.set_tenant(id, &config.tenant) {
+      log::warn!("Failed to set tenant: id: {:?}, err: {:?}", id, err);
+      continue;
+    }
+    log::info!("Registering service discovered: id: {:?}, config: {:?}", id, config);
+    if let Err(err) = SERVICE_MGR.add(Service::new(id.clone(), config.private_ip, config.http_port))
+    {
+      log::warn!("Failed to add service: id: {:?}, err: {:?}", id, err);
+    }
+  }
+}
+
+async fn poll(client: &awc::Client) -> Result<Catalog> {
+  match CONFIG.load().discovery.source {
+    DiscoverySource::Consul => poll_consul(client).await,
+    DiscoverySource::Etcd => poll_etcd(client).await,
+    DiscoverySource::None => Ok(Catalog::default()),
+  }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ConsulEntry {
+  node: ConsulNode,
+  service: ConsulService,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ConsulNode {
+  address: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ConsulService {
+  #[serde(rename = "ID")]
+  id: String,
+  #[serde(default)]
+  address: String,
+  port: u32,
+  #[serde(default)]
+  meta: Option<HashMap<String, String>>,
+}
+
+impl ConsulEntry {
+  // Of the node if the service has none
+  #[inline]
+  fn private_ip(&self) -> Result<IpAddr> {
+    let address =
+      if self.service.address.is_empty() { &self.node.address } else { &self.service.address };
+    address.parse().with_context(|| format!("Invalid address: {:?}", address))
+  }
+
+  #[inline]
+  fn meta(&self, key: &str) -> Option<&str> {
+    self.service.meta.as_ref().and_then(|meta| meta.get(key)).map(String::as_str)
+  }
+
+  fn parse_meta<T: std::str::FromStr>(&self, key: &str) -> Result<Option<T>> {
+    self
+      .meta(key)
+      .map(|value| value.parse().map_err(|_| anyhow!("Invalid meta: {}: {:?}", key, value)))
+      .transpose()
+  }
+
+  fn to_frontend(&self) -> Result<FrontendConfig> {
+    let private_ip = self.private_ip()?;
+    Ok(FrontendConfig {
+      id: self.service.id.clone(),
+      domain: self.meta("domain").unwrap_or_default().to_owned(),
+      http_port: self.service.port,
+      https_port: self.parse_meta("https_port")?.unwrap_or(0),
+      public_ip: self.parse_meta("public_ip")?.unwrap_or(private_ip),
+      private_ip,
+    })
+  }
+
+  fn to_backend(&self) -> Result<BackendConfig> {
+    Ok(BackendConfig {
+      id: self.service.id.clone(),
+      http_port: self.service.port,
+      private_ip: self.private_ip()?,
+      capacity: self.parse_meta("capacity")?.unwrap_or_else(default_capacity),
+    })
+  }
+
+  fn to_service(&self) -> Result<(NodeId, ServiceConfig)> {
+    Ok((
+      self.service.id.clone(),
+      ServiceConfig {
+        private_ip: self.private_ip()?,
+        http_port: self.service.port,
+        tenant: self.meta("tenant").unwrap_or_default().to_owned(),
+      },
+    ))
+  }
+}
+
+// The instances passing their checks of the services carrying the tags
+async fn poll_consul(client: &awc::Client) -> Result<Catalog> {
+  let config = CONFIG.load().discovery.clone();
+  let url = config.url.trim_end_matches('/');
+  let get = |path: String| {
+    let req = client.get(format!("{}{}", url, path));
+    if config.token.is_empty() {
+      req
+    } else {
+      req.insert_header(("X-Consul-Token", config.token.as_str()))
+    }
+  };
+  let services: HashMap<String, Vec<String>> =
+    send(get("/v1/catalog/services".to_owned()), None).await?;
+  let mut catalog = Catalog::default();
+  for kind in ["frontend", "backend", "service"] {
+    let tag = format!("{}-{}", config.prefix, kind);
+    for (name, _) in services.iter().filter(|(_, tags)| tags.contains(&tag)) {
+      let req = get(format!("/v1/health/service/{}", name))
+        .query(&[("passing", "true"), ("tag", tag.as_str())])?;
+      let entries: Vec<ConsulEntry> = send(req, None).await?;
+      for entry in entries {
+        let result = match kind {
+          "frontend" => entry.to_frontend().map(|frontend| catalog.frontends.push(frontend)),
+          "backend" => entry.to_backend().map(|backend| catalog.backends.push(backend)),
+          _ => entry.to_service().map(|service| catalog.services.push(service)),
+        };
+        if let Err(err) = result {
+          log::warn!("Skipped {}: id: {:?}, err: {:?}", kind, entry.service.id, err);
+        }
+      }
+    }
+  }
+  Ok(catalog)
+}
+
+#[derive(Debug, Deserialize)]
+struct EtcdRangeRep {
+  #[serde(default)]
+  kvs: Vec<EtcdKv>,
+}
+
+// Base64 encoded, as by the json gateway of etcd
+#[derive(Debug, Deserialize)]
+struct EtcdKv {
+  key: String,
+  #[serde(default)]
+  value: String,
+}
+
+// The keys under the prefix, through the json gateway of etcd v3
+async fn poll_etcd(client: &awc::Client) -> Result<Catalog> {
+  let config = CONFIG.load().discovery.clone();
+  let prefix = format!("/{}/", config.prefix.trim_matches('/'));
+  let mut range_end = prefix.clone().into_bytes();
+  *range_end.last_mut().unwrap() += 1;
+  let mut req = client.post(format!("{}/v3/kv/range", config.url.trim_end_matches('/')));
+  if !config.token.is_empty() {
+    req = req.insert_header(("Authorization", config.token.as_str()));
+  }
+  let body = json!({
+    "key": STANDARD.encode(&prefix),
+    "range_end": STANDARD.encode(&range_end),
+  });
+  let rep: EtcdRangeRep = send(req, Some(&body)).await?;
+  let mut kvs = Vec::with_capacity(rep.kvs.len());
+  for kv in rep.kvs {
+    kvs.push((STANDARD.decode(&kv.key)?, STANDARD.decode(&kv.value)?));
+  }
+  Ok(parse_etcd_kvs(&prefix, kvs))
+}
+
+// Keyed by "{prefix}{kind}s/{id}", the id is taken from the key if the value
+// has none
+fn parse_etcd_kvs(prefix: &str, kvs: Vec<(Vec<u8>, Vec<u8>)>) -> Catalog {
+  let mut catalog = Catalog::default();
+  for (key, value) in kvs {
+    let key = String::from_utf8_lossy(&key);
+    let Some((kind, id)) = key.strip_prefix(prefix).and_then(|rest| rest.split_once('/')) else {
+      continue;
+    };
+    let result =
+      serde_json::from_slice::<Value>(&value).map_err(anyhow::Error::from).and_then(|mut value| {
+        if let Some(object) = value.as_object_mut() {
+          object.entry("id").or_insert_with(|| Value::from(id));
+        }
+        match kind {
+          "frontends" => catalog.frontends.push(serde_json::from_value(value)?),
+          "backends" => catalog.backends.push(serde_json::from_value(value)?),
+          "services" => catalog.services.push((id.to_owned(), serde_json::from_value(value)?)),
+          _ => bail!("Unknown kind: {}", kind),
+        }
+        Ok(())
+      });
+    if let Err(err) = result {
+      log::warn!("Skipped key: {:?}, err: {:?}", key, err);
+    }
+  }
+  catalog
+}
+
+// Large enough for the catalogs of a few thousand nodes
+const MAX_REP_SIZE: usize = 16 * 1024 * 1024;
+
+async fn send<T: DeserializeOwned>(req: awc::ClientRequest, body: Option<&Value>) -> Result<T> {
+  let url = req.get_uri().to_string();
+  let result = match body {
+    Some(body) => req.send_json(body).await,
+    None => req.send().await,
+  };
+  let mut rep = result.map_err(|err| anyhow!("Failed to request: url: {}, err: {:?}", url, err))?;
+  if !rep.status().is_success() {
+    bail!("Failed to request: url: {}, status: {}", url, rep.status());
+  }
+  rep
+    .json::<T>()
+    .limit(MAX_REP_SIZE)
+    .await
+    .map_err(|err| anyhow!("Invalid rep: url: {}, err: {:?}", url, err))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_etcd_kvs() {
+    let kvs = vec![
+      (
+        b"/maxwell/frontends/frontend-0".to_vec(),
+        br#"{"domain": "a.example.com", "http_port": 10000, "https_port": 10443, "public_ip": "1.1.1.1", "private_ip": "10.0.0.1"}"#.to_vec(),
+      ),
+      (b"/maxwell/backends/backend-0".to_vec(), br#"{"private_ip": "10.0.0.2", "http_port": 20000}"#.to_vec()),
+      (
+        b"/maxwell/services/service-0".to_vec(),
+        br#"{"private_ip": "10.0.0.3", "http_port": 30000, "tenant": "acme"}"#.to_vec(),
+      ),
+      (b"/maxwell/backends/backend-1".to_vec(), b"not json".to_vec()),
+      (b"/maxwell/others/other-0".to_vec(), b"{}".to_vec()),
+    ];
+    let catalog = parse_etcd_kvs("/maxwell/", kvs);
+    assert_eq!(catalog.frontends.len(), 1);
+    assert_eq!(catalog.frontends[0].id, "frontend-0");
+    assert_eq!(catalog.backends.len(), 1);
+    assert_eq!(catalog.backends[0].capacity, 1);
+    assert_eq!(
+      catalog.services,
+      vec![(
+        "service-0".to_owned(),
+        ServiceConfig {
+          private_ip: "10.0.0.3".parse().unwrap(),
+          http_port: 30000,
+          tenant: "acme".to_owned()
+        }
+      )]
+    );
+  }
+}
@@ -0,0 +1,273 @@
+use std::{
+  collections::{BTreeMap, HashSet},
+  fs,
+  path::Path,
+};
+
+use anyhow::{bail, Context, Result};
+use bytes::Bytes;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use seriesdb::coder::Coder;
+
+use crate::{
+  audit::{AuditCoder, AuditKey, AuditRecord},
+  config::{DbEngine, DiscoverySource, CONFIG},
+  config_mgr::SettingCoder,
+  encryption::{self, EncryptedCoder},
+  health::ProbeCoder,
+  migration,
+  node_mgr::{service_mgr, NodeId, Service, ServiceCoder},
+  route_mgr::{
+    self, HistoryCoder, OwnerCoder, PathBundle, PathRateLimit, RateLimitCoder, Revision,
+    RevisionKey, RouteCoder, TenantCoder, WeightCoder,
+  },
+  store::open_raw_table,
+  topic_mgr::{self, LocatedAtCoder, Namespace, NamespaceCoder, Topic, TopicCoder},
+};
+
+// The state of the master as json, each table as its entries decoded, for
+// seeding a db, or for reading and editing the state by hand, unlike a backup.
+// The schema version is of the db dumped, and the one loaded into must be at
+// it, as the entries are decoded by the structs of that version.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Dump {
+  pub schema_version: u32,
+  pub tables: BTreeMap<String, Vec<Entry>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+  pub key: Value,
+  pub value: Value,
+}
+
+// Run fsck first if the tables may have entries failing to decode, which fail
+// the dump
+pub fn dump() -> Result<Dump> {
+  prepare("dump")?;
+  current()
+}
+
+// Of a running master, whose tables are at the latest schema already
+pub(crate) fn current() -> Result<Dump> {
+  let mut dumper =
+    Dumper { dump: Dump { schema_version: migration::latest_version(), ..Default::default() } };
+  for problem in visit_tables(&mut dumper)? {
+    log::warn!("Found problem: {}", problem);
+  }
+  Ok(dumper.dump)
+}
+
+pub fn dump_to(path: &Path) -> Result<()> {
+  let dump = dump()?;
+  fs::write(path, serde_json::to_vec_pretty(&dump)?)
+    .with_context(|| format!("Failed to write dump: {:?}", path))
+}
+
+// Into an empty db only, after every entry decodes and the routes and tenants
+// are of the services dumped and the topics of configured backends, so that
+// nothing is written if any fails
+pub fn load(mut dump: Dump) -> Result<()> {
+  prepare("load")?;
+  if dump.schema_version != migration::latest_version() {
+    bail!(
+      "The dump is at schema version {}, but the db is at {}",
+      dump.schema_version,
+      migration::latest_version()
+    );
+  }
+  let mut loader = Loader { tables: std::mem::take(&mut dump.tables), loads: vec![] };
+  let problems = visit_tables(&mut loader)?;
+  if !problems.is_empty() {
+    bail!("Found {} problems in the dump: {}", problems.len(), problems.join(", "));
+  }
+  if let Some(name) = loader.tables.keys().next() {
+    bail!("Unknown table: {}", name);
+  }
+  let mut tables = Vec::with_capacity(loader.loads.len());
+  for (name, _) in &loader.loads {
+    let table = open_raw_table(name)?;
+    if !table.dump().is_empty() {
+      bail!("The db is not empty, table: {}", name);
+    }
+    tables.push(table);
+  }
+  for (table, (name, entries)) in tables.iter().zip(&loader.loads) {
+    log::info!("Loading table: name: {:?}, entries: {:?}", name, entries.len());
+    table.load(entries)?;
+  }
+  Ok(())
+}
+
+pub fn load_from(path: &Path) -> Result<()> {
+  let bytes = fs::read(path).with_context(|| format!("Failed to read dump: {:?}", path))?;
+  load(serde_json::from_slice(&bytes).with_context(|| format!("Invalid dump: {:?}", path))?)
+}
+
+#[inline]
+fn prepare(action: &str) -> Result<()> {
+  if CONFIG.load().db.engine != DbEngine::Seriesdb {
+    bail!("Nothing to {} with the memory engine", action);
+  }
+  encryption::init()?;
+  migration::run()
+}
+
+trait Visitor {
+  // Returns the entries of the table decoded
+  fn visit<K, V, C>(&mut self, name: &'static str) -> Result<Vec<(K, V)>>
+  where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+    C: Coder<K, V, EncodedKey = Bytes, EncodedValue = Bytes>;
+}
+
+// All the tables but migration.infos, as the schema version is of the dump.
+// Returns the problems found, as in fsck.
+fn visit_tables(visitor: &mut impl Visitor) -> Result<Vec<String>> {
+  let mut problems = vec![];
+  let services = visitor.visit::<NodeId, Service, ServiceCoder>("node_mgr.service_mgr.services")?;
+  visitor.visit::<String, String, service_mgr::InfoCoder>("node_mgr.service_mgr.infos")?;
+  let service_ids: HashSet<NodeId> =
+    services.into_iter().map(|(service_id, _)| service_id).collect();
+  let routes = visitor.visit::<NodeId, PathBundle, RouteCoder>("route_mgr.routes")?;
+  let tenants = visitor.visit::<NodeId, String, TenantCoder>("route_mgr.tenants")?;
+  let rate_limits =
+    visitor.visit::<NodeId, Vec<PathRateLimit>, RateLimitCoder>("route_mgr.rate_limits")?;
+  for (table, service_id) in routes
+    .iter()
+    .map(|(service_id, _)| ("route_mgr.routes", service_id))
+    .chain(tenants.iter().map(|(service_id, _)| ("route_mgr.tenants", service_id)))
+    .chain(rate_limits.iter().map(|(service_id, _)| ("route_mgr.rate_limits", service_id)))
+  {
+    if !service_ids.contains(service_id) {
+      problems.push(format!("{}: Unknown service: {}", table, service_id));
+    }
+  }
+  visitor.visit::<RevisionKey, Revision, HistoryCoder>("route_mgr.history")?;
+  visitor.visit::<String, String, OwnerCoder>("route_mgr.owners")?;
+  visitor.visit::<NodeId, u32, WeightCoder>("route_mgr.weights")?;
+  visitor.visit::<String, String, route_mgr::InfoCoder>("route_mgr.infos")?;
+
+  let config = CONFIG.load();
+  let backend_ids: HashSet<&NodeId> =
+    config.backend_mgr.backends.iter().map(|backend| &backend.id).collect();
+  // The backends discovered are not known without polling the catalog
+  let discovers_backends = config.discovery.source != DiscoverySource::None;
+  for table in ["topic_mgr.topics", "topic_mgr.pins"] {
+    for (topic, backend_id) in visitor.visit::<Topic, NodeId, TopicCoder>(table)? {
+      if !discovers_backends && !backend_ids.contains(&backend_id) {
+        problems.push(format!("{}: Unknown backend: {}, topic: {}", table, backend_id, topic));
+      }
+    }
+  }
+  visitor.visit::<Topic, u32, LocatedAtCoder>("topic_mgr.located_ats")?;
+  visitor.visit::<String, Namespace, NamespaceCoder>("topic_mgr.namespaces")?;
+  visitor.visit::<String, String, topic_mgr::InfoCoder>("topic_mgr.infos")?;
+
+  visitor.visit::<String, String, SettingCoder>("config_mgr.settings")?;
+  visitor.visit::<String, u32, ProbeCoder>("health.probes")?;
+  visitor.visit::<AuditKey, AuditRecord, AuditCoder>("audit.records")?;
+  Ok(problems)
+}
+
+struct Dumper {
+  dump: Dump,
+}
+
+impl Visitor for Dumper {
+  fn visit<K, V, C>(&mut self, name: &'static str) -> Result<Vec<(K, V)>>
+  where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+    C: Coder<K, V, EncodedKey = Bytes, EncodedValue = Bytes>, {
+    let decoded = decode_entries::<K, V, EncryptedCoder<C>>(open_raw_table(name)?.dump());
+    let entries = decoded
+      .iter()
+      .map(|(key, value)| {
+        Ok(Entry { key: serde_json::to_value(key)?, value: serde_json::to_value(value)? })
+      })
+      .collect::<Result<_>>()?;
+    self.dump.tables.insert(name.to_owned(), entries);
+    Ok(decoded)
+  }
+}
+
+struct Loader {
+  // Taken as visited, so that the ones left are unknown
+  tables: BTreeMap<String, Vec<Entry>>,
+  loads: Vec<(&'static str, Vec<(Bytes, Bytes)>)>,
+}
+
+impl Visitor for Loader {
+  fn visit<K, V, C>(&mut self, name: &'static str) -> Result<Vec<(K, V)>>
+  where
+    K: Serialize + DeserializeOwned,
+    V: Serialize + DeserializeOwned,
+    C: Coder<K, V, EncodedKey = Bytes, EncodedValue = Bytes>, {
+    let entries = self.tables.remove(name).unwrap_or_default();
+    let decoded = decode_json_entries::<K, V>(name, entries)?;
+    let encoded = encode_entries::<K, V, EncryptedCoder<C>>(name, &decoded)?;
+    self.loads.push((name, encoded));
+    Ok(decoded)
+  }
+}
+
+#[inline]
+fn decode_entries<K, V, C: Coder<K, V>>(entries: Vec<(Bytes, Bytes)>) -> Vec<(K, V)> {
+  entries.iter().map(|(key, value)| (C::decode_key(key), C::decode_value(value))).collect()
+}
+
+fn decode_json_entries<K: DeserializeOwned, V: DeserializeOwned>(
+  name: &str, entries: Vec<Entry>,
+) -> Result<Vec<(K, V)>> {
+  entries
+    .into_iter()
+    .map(|entry| {
+      let key = serde_json::from_value(entry.key.clone())
+        .with_context(|| format!("Invalid key: table: {}, key: {}", name, entry.key))?;
+      let value = serde_json::from_value(entry.value)
+        .with_context(|| format!("Invalid value: table: {}, key: {}", name, entry.key))?;
+      Ok((key, value))
+    })
+    .collect()
+}
+
+// Sorted by the encoded keys, as the table keeps them
+fn encode_entries<K, V, C>(name: &str, entries: &[(K, V)]) -> Result<Vec<(Bytes, Bytes)>>
+where C: Coder<K, V, EncodedKey = Bytes, EncodedValue = Bytes> {
+  let mut encoded: Vec<(Bytes, Bytes)> =
+    entries.iter().map(|(key, value)| (C::encode_key(key), C::encode_value(value))).collect();
+  encoded.sort_by(|(a, _), (b, _)| a.cmp(b));
+  if let Some(pair) = encoded.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+    bail!("Duplicate key: table: {}, key: {}", name, pair[0].0.escape_ascii());
+  }
+  Ok(encoded)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_encode_and_decode_entries() {
+    let entries = vec![
+      Entry { key: Value::from("b"), value: Value::from(2) },
+      Entry { key: Value::from("a"), value: Value::from(1) },
+    ];
+    let decoded = decode_json_entries::<NodeId, u32>("weights", entries).unwrap();
+    let encoded = encode_entries::<_, _, WeightCoder>("weights", &decoded).unwrap();
+    assert_eq!(encoded[0].0, Bytes::from("a"));
+    assert_eq!(
+      decode_entries::<NodeId, u32, WeightCoder>(encoded),
+      vec![("a".to_owned(), 1), ("b".to_owned(), 2)]
+    );
+
+    let duplicated = vec![("a".to_owned(), 1), ("a".to_owned(), 2)];
+    assert!(encode_entries::<_, _, WeightCoder>("weights", &duplicated).is_err());
+
+    let invalid = vec![Entry { key: Value::from("a"), value: Value::from("heavy") }];
+    assert!(decode_json_entries::<NodeId, u32>("weights", invalid).is_err());
+  }
+}
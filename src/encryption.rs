@@ -0,0 +1,340 @@
+use std::{borrow::Borrow, env, fs, marker::PhantomData, process::Command};
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::{BufMut, Bytes, BytesMut};
+use once_cell::sync::OnceCell;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use seriesdb::coder::Coder;
+
+use crate::{
+  config::{DbEngine, EncryptionConfig, CONFIG},
+  store::{DbStore, RawCoder, Store, TABLE_NAMES},
+};
+
+// Of the layout of the encrypted values, which all start with it once the db
+// is encrypted, followed by the nonce, then the value encrypted and its tag
+const FORMAT_VERSION: u8 = 1;
+
+// Prefixed the encrypted values before the format was versioned, when the
+// values written before the encryption was enabled were told apart by it. Only
+// read by the rewrite, which leaves none.
+const LEGACY_MAGIC: &[u8] = b"\xffMXE1";
+
+// The canary is kept encrypted once every table is, so that a wrong key fails
+// the start rather than the reads, and a db encrypted is never read without
+// a key. Not in TABLE_NAMES, so neither backed up nor rewritten.
+const INFOS_TABLE: &str = "encryption.infos";
+const CANARY_KEY: &[u8] = b"canary";
+const CANARY: &[u8] = b"maxwell-master";
+
+// None if no key is configured
+static KEY: OnceCell<Option<LessSafeKey>> = OnceCell::new();
+
+// Loads the key and checks it by the canary before the tables are opened, so
+// that a missing or wrong key fails the start, rather than the first read or
+// write. Once the key is configured, the tables are rewritten encrypted on the
+// first start.
+pub fn init() -> Result<()> {
+  let key = load_key(&CONFIG.load().db.encryption)?;
+  if CONFIG.load().db.engine == DbEngine::Seriesdb {
+    check_db(key.as_ref())?;
+  }
+  if key.is_some() {
+    log::info!("Encrypting the values of the db");
+  }
+  let _ = KEY.set(key);
+  Ok(())
+}
+
+fn check_db(key: Option<&LessSafeKey>) -> Result<()> {
+  let infos = DbStore::<Bytes, Bytes, RawCoder>::open(INFOS_TABLE)?;
+  let canary_key = Bytes::from_static(CANARY_KEY);
+  match (key, infos.get(&canary_key)?) {
+    (None, None) => Ok(()),
+    (None, Some(_)) => bail!("The db is encrypted, but no key is configured"),
+    (Some(key), Some(canary)) => check_canary_with(key, &canary),
+    (Some(key), None) => {
+      let tables = TABLE_NAMES
+        .iter()
+        .map(|name| DbStore::<Bytes, Bytes, RawCoder>::open(name))
+        .collect::<Result<Vec<_>>>()?;
+      let tables: Vec<&dyn Store<Bytes, Bytes>> =
+        tables.iter().map(|table| table as &dyn Store<Bytes, Bytes>).collect();
+      rewrite_tables(key, &tables)?;
+      infos.put(&canary_key, &seal_with(key, CANARY))
+    }
+  }
+}
+
+#[inline]
+fn check_canary_with(key: &LessSafeKey, canary: &[u8]) -> Result<()> {
+  match open_with(key, canary) {
+    Ok(opened) if opened == CANARY => Ok(()),
+    _ => bail!("The key is not the one the db was encrypted with"),
+  }
+}
+
+// Of the db, for the backups, which can only be restored into a db encrypted
+// alike, None if no key is configured
+pub(crate) fn canary() -> Option<Vec<u8>> {
+  db_key().map(|key| seal_with(key, CANARY).to_vec())
+}
+
+pub(crate) fn check_canary(canary: Option<&[u8]>) -> Result<()> {
+  match (db_key(), canary) {
+    (None, None) => Ok(()),
+    (Some(key), Some(canary)) => check_canary_with(key, canary),
+    (Some(_), None) => bail!("The key is configured, but the backup is not encrypted"),
+    (None, Some(_)) => bail!("The backup is encrypted, but no key is configured"),
+  }
+}
+
+// Every value is read as it was written before, plain, or encrypted under the
+// legacy magic, then written encrypted. All of them are read first, so that a
+// wrong key fails before anything is written. The values encrypted already
+// are skipped, as a rewrite stopped halfway leaves them, which the tag tells
+// apart from the others for sure.
+fn rewrite_tables(key: &LessSafeKey, tables: &[&dyn Store<Bytes, Bytes>]) -> Result<()> {
+  let mut rewrites = vec![];
+  for table in tables {
+    let mut entries = vec![];
+    table.scan(None, &mut |entry_key, value| {
+      entries.push((entry_key, value));
+      true
+    });
+    let mut values = Vec::with_capacity(entries.len());
+    for (entry_key, value) in entries {
+      if open_with(key, &value).is_err() {
+        values.push((entry_key, open_legacy(key, &value)?));
+      }
+    }
+    rewrites.push(values);
+  }
+  let count: usize = rewrites.iter().map(Vec::len).sum();
+  log::info!("Encrypting the values of the db once: count: {:?}", count);
+  for (table, values) in tables.iter().zip(rewrites) {
+    for (entry_key, value) in values {
+      table.put(&entry_key, &seal_with(key, &value))?;
+    }
+  }
+  Ok(())
+}
+
+// The memory engine keeps the values plain
+#[inline]
+fn db_key() -> Option<&'static LessSafeKey> {
+  key().filter(|_| CONFIG.load().db.engine == DbEngine::Seriesdb)
+}
+
+#[inline]
+fn key() -> Option<&'static LessSafeKey> {
+  KEY
+    .get_or_init(|| {
+      load_key(&CONFIG.load().db.encryption)
+        .unwrap_or_else(|err| panic!("Failed to load db key: {:#}", err))
+    })
+    .as_ref()
+}
+
+fn load_key(config: &EncryptionConfig) -> Result<Option<LessSafeKey>> {
+  let encoded = if !config.key_env.is_empty() {
+    env::var(&config.key_env).with_context(|| format!("Failed to read env: {}", config.key_env))?
+  } else if !config.key_file.is_empty() {
+    fs::read_to_string(&config.key_file)
+      .with_context(|| format!("Failed to read key file: {:?}", config.key_file))?
+  } else if !config.key_command.is_empty() {
+    let output = Command::new("sh")
+      .arg("-c")
+      .arg(&config.key_command)
+      .output()
+      .with_context(|| format!("Failed to run key command: {:?}", config.key_command))?;
+    if !output.status.success() {
+      bail!(
+        "The key command failed: status: {}, stderr: {}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr)
+      );
+    }
+    String::from_utf8(output.stdout).context("The key command printed invalid utf8")?
+  } else {
+    return Ok(None);
+  };
+  Ok(Some(build_key(&STANDARD.decode(encoded.trim()).context("The key is not base64")?)?))
+}
+
+#[inline]
+fn build_key(key: &[u8]) -> Result<LessSafeKey> {
+  let key = UnboundKey::new(&AES_256_GCM, key)
+    .map_err(|_| anyhow!("The key must be 32 bytes, got: {}", key.len()))?;
+  Ok(LessSafeKey::new(key))
+}
+
+// The format version, the nonce, then the value encrypted, followed by its tag
+fn seal_with(key: &LessSafeKey, value: &[u8]) -> Bytes {
+  let header = [FORMAT_VERSION];
+  let nonce_bytes: [u8; NONCE_LEN] = rand::random();
+  let mut sealed = value.to_vec();
+  key
+    .seal_in_place_append_tag(
+      Nonce::assume_unique_for_key(nonce_bytes),
+      Aad::from(header),
+      &mut sealed,
+    )
+    .expect("The value is too long to encrypt");
+  let mut buf = BytesMut::with_capacity(header.len() + NONCE_LEN + sealed.len());
+  buf.put_slice(&header);
+  buf.put_slice(&nonce_bytes);
+  buf.put_slice(&sealed);
+  buf.freeze()
+}
+
+// Every value must be encrypted, in the format of this master
+fn open_with(key: &LessSafeKey, value: &[u8]) -> Result<Bytes> {
+  match value.first() {
+    Some(&FORMAT_VERSION) => open_sealed(key, &value[..1], &value[1..]),
+    Some(version) => bail!("Unknown format version of the encrypted value: {}", version),
+    None => bail!("The encrypted value is empty"),
+  }
+}
+
+// The values not prefixed by the legacy magic were written plain
+fn open_legacy(key: &LessSafeKey, value: &[u8]) -> Result<Bytes> {
+  match value.strip_prefix(LEGACY_MAGIC) {
+    Some(sealed) => open_sealed(key, LEGACY_MAGIC, sealed),
+    None => Ok(Bytes::copy_from_slice(value)),
+  }
+}
+
+fn open_sealed(key: &LessSafeKey, aad: &[u8], sealed: &[u8]) -> Result<Bytes> {
+  if sealed.len() < NONCE_LEN {
+    bail!("The encrypted value is too short: size: {}", sealed.len());
+  }
+  let (nonce_bytes, sealed) = sealed.split_at(NONCE_LEN);
+  let nonce = Nonce::try_assume_unique_for_key(nonce_bytes).unwrap();
+  let mut opened = sealed.to_vec();
+  let len = key
+    .open_in_place(nonce, Aad::from(aad), &mut opened)
+    .map_err(|_| anyhow!("Failed to decrypt the value, the key may be wrong"))?
+    .len();
+  opened.truncate(len);
+  Ok(Bytes::from(opened))
+}
+
+#[inline]
+pub(crate) fn seal(value: &[u8]) -> Bytes {
+  match key() {
+    Some(key) => seal_with(key, value),
+    None => Bytes::copy_from_slice(value),
+  }
+}
+
+// Panics if it fails, as the coders do with the values they fail to decode.
+// The key was checked by the canary on start, so only a corrupted value does.
+#[inline]
+pub(crate) fn open(value: &[u8]) -> Bytes {
+  match key() {
+    Some(key) => {
+      open_with(key, value).unwrap_or_else(|err| panic!("Failed to decrypt the value: {:#}", err))
+    }
+    None => Bytes::copy_from_slice(value),
+  }
+}
+
+// Encrypts the values encoded by C when a key is configured, the keys are not,
+// as the tables are ordered and sought by them
+pub(crate) struct EncryptedCoder<C> {
+  _marker: PhantomData<fn() -> C>,
+}
+
+impl<K, V, C> Coder<K, V> for EncryptedCoder<C>
+where C: Coder<K, V, EncodedKey = Bytes, EncodedValue = Bytes>
+{
+  type EncodedKey = Bytes;
+  type EncodedValue = Bytes;
+
+  #[inline(always)]
+  fn encode_key<Q: Borrow<K>>(key: Q) -> Self::EncodedKey {
+    C::encode_key(key)
+  }
+
+  #[inline(always)]
+  fn decode_key(key: &[u8]) -> K {
+    C::decode_key(key)
+  }
+
+  #[inline(always)]
+  fn encode_value<Q: Borrow<V>>(value: Q) -> Self::EncodedValue {
+    seal(&C::encode_value(value))
+  }
+
+  #[inline(always)]
+  fn decode_value(value: &[u8]) -> V {
+    C::decode_value(&open(value))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::store::MemoryStore;
+
+  #[test]
+  fn test_seal_and_open() {
+    let key = build_key(&[7; 32]).unwrap();
+    let sealed = seal_with(&key, b"backend-0.internal:8081");
+    assert_eq!(sealed[0], FORMAT_VERSION);
+    assert!(!sealed.windows(8).any(|window| window == b"internal"));
+    assert_eq!(open_with(&key, &sealed).unwrap(), Bytes::from("backend-0.internal:8081"));
+    // A new nonce each time
+    assert_ne!(seal_with(&key, b"a"), seal_with(&key, b"a"));
+
+    // No value is taken as plain once the db is encrypted
+    assert!(open_with(&key, b"plain").is_err());
+    assert!(open_with(&key, b"").is_err());
+
+    let other_key = build_key(&[8; 32]).unwrap();
+    assert!(open_with(&other_key, &sealed).is_err());
+    assert!(check_canary_with(&key, &seal_with(&key, CANARY)).is_ok());
+    assert!(check_canary_with(&other_key, &seal_with(&key, CANARY)).is_err());
+    let mut tampered = sealed.to_vec();
+    *tampered.last_mut().unwrap() ^= 1;
+    assert!(open_with(&key, &tampered).is_err());
+
+    assert!(build_key(&[7; 16]).is_err());
+  }
+
+  #[test]
+  fn test_rewrite_tables() {
+    let key = build_key(&[7; 32]).unwrap();
+    let legacy_sealed = {
+      let nonce_bytes = [1; NONCE_LEN];
+      let mut sealed = b"legacy".to_vec();
+      key
+        .seal_in_place_append_tag(
+          Nonce::assume_unique_for_key(nonce_bytes),
+          Aad::from(LEGACY_MAGIC),
+          &mut sealed,
+        )
+        .unwrap();
+      [LEGACY_MAGIC, &nonce_bytes[..], &sealed[..]].concat()
+    };
+    let table = MemoryStore::<Bytes, Bytes, RawCoder>::new();
+    table.put(&Bytes::from("a"), &Bytes::from("plain")).unwrap();
+    table.put(&Bytes::from("b"), &Bytes::from(legacy_sealed)).unwrap();
+    // As a rewrite stopped halfway leaves it
+    table.put(&Bytes::from("c"), &seal_with(&key, b"sealed")).unwrap();
+    let tables: [&dyn Store<Bytes, Bytes>; 1] = [&table];
+
+    // Nothing is written with a wrong key
+    let other_key = build_key(&[8; 32]).unwrap();
+    assert!(rewrite_tables(&other_key, &tables).is_err());
+    assert_eq!(table.get(&Bytes::from("a")).unwrap(), Some(Bytes::from("plain")));
+
+    rewrite_tables(&key, &tables).unwrap();
+    for (entry_key, value) in [("a", "plain"), ("b", "legacy"), ("c", "sealed")] {
+      let sealed = table.get(&Bytes::from(entry_key)).unwrap().unwrap();
+      assert_eq!(open_with(&key, &sealed).unwrap(), Bytes::from(value));
+    }
+  }
+}
@@ -0,0 +1,27 @@
+// Error codes which maxwell-protocol does not define yet, numbered far above
+// its own codes so that they never collide.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum ExtErrorCode {
+  TopicQuotaExceeded = 1000,
+  RouteConflict = 1001,
+  InvalidRoutes = 1002,
+  ClientCertRejected = 1003,
+  RateLimited = 1004,
+  UnsupportedVersion = 1005,
+  DecodeError = 1006,
+  Busy = 1007,
+  NotPeer = 1008,
+  AdminAuthFailed = 1009,
+  InvalidQuery = 1010,
+  NotFound = 1011,
+  UnknownCredentials = 1012,
+  AdminForbidden = 1013,
+  NotStandby = 1014,
+  // Carries the leader, see cluster::NotLeader
+  NotLeader = 1015,
+  // See maintenance::Maintenance
+  Maintenance = 1016,
+  // The write may still take effect, see raft::NotCommitted
+  NotCommitted = 1017,
+}
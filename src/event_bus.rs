@@ -0,0 +1,237 @@
+use std::{
+  sync::atomic::{AtomicU64, Ordering},
+  time::Duration,
+};
+
+use ahash::HashMap;
+use bytes::Bytes;
+use futures::Stream;
+use once_cell::sync::Lazy;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+  config::CONFIG,
+  node_mgr::{NodeId, NodeType, BACKEND_MGR, FRONTEND_MGR, SERVICE_MGR},
+  route_mgr::ROUTE_DIST_CHECKSUM,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EventKind {
+  NodeAdded,
+  NodeRemoved,
+  NodeHealthChanged,
+  RoutesChanged,
+  TopicReassigned,
+}
+
+pub const ALL_EVENT_KINDS: [EventKind; 5] = [
+  EventKind::NodeAdded,
+  EventKind::NodeRemoved,
+  EventKind::NodeHealthChanged,
+  EventKind::RoutesChanged,
+  EventKind::TopicReassigned,
+];
+
+impl EventKind {
+  #[inline]
+  pub fn as_str(self) -> &'static str {
+    match self {
+      EventKind::NodeAdded => "node-added",
+      EventKind::NodeRemoved => "node-removed",
+      EventKind::NodeHealthChanged => "node-health-changed",
+      EventKind::RoutesChanged => "routes-changed",
+      EventKind::TopicReassigned => "topic-reassigned",
+    }
+  }
+
+  #[inline]
+  pub fn from_name(name: &str) -> Option<Self> {
+    ALL_EVENT_KINDS.into_iter().find(|kind| kind.as_str() == name)
+  }
+}
+
+// Changes of the cluster topology, published by the managers as they happen,
+// so that the watching connections, /$admin/events, the webhook, the audit log
+// and the metrics all follow them without being wired into the managers
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Event {
+  NodeAdded { node_type: &'static str, node_id: NodeId },
+  NodeRemoved { node_type: &'static str, node_id: NodeId },
+  NodeHealthChanged { node_type: &'static str, node_id: NodeId, is_healthy: bool },
+  RoutesChanged { tenant: String, checksum: u32 },
+  // backend_id is none if the topic was deleted
+  TopicReassigned { topic: String, backend_id: Option<NodeId> },
+}
+
+impl Event {
+  #[inline]
+  pub fn kind(&self) -> EventKind {
+    match self {
+      Event::NodeAdded { .. } => EventKind::NodeAdded,
+      Event::NodeRemoved { .. } => EventKind::NodeRemoved,
+      Event::NodeHealthChanged { .. } => EventKind::NodeHealthChanged,
+      Event::RoutesChanged { .. } => EventKind::RoutesChanged,
+      Event::TopicReassigned { .. } => EventKind::TopicReassigned,
+    }
+  }
+}
+
+static EVENT_SENDER: Lazy<broadcast::Sender<Event>> =
+  Lazy::new(|| broadcast::channel(CONFIG.load().event_bus.capacity).0);
+
+// By kind, whether anybody subscribed or not
+static PUBLISHED_COUNTS: [AtomicU64; ALL_EVENT_KINDS.len()] =
+  [const { AtomicU64::new(0) }; ALL_EVENT_KINDS.len()];
+
+#[inline]
+pub fn publish(event: Event) {
+  log::debug!("Publishing event: {:?}", event);
+  PUBLISHED_COUNTS[event.kind() as usize].fetch_add(1, Ordering::Relaxed);
+  // Failing only means nobody is subscribing
+  let _ = EVENT_SENDER.send(event);
+}
+
+// The nodes added and removed by a reload of the configured or discovered ones
+pub fn publish_node_changes(node_type: NodeType, added_ids: Vec<NodeId>, removed_ids: Vec<NodeId>) {
+  let node_type = node_type.as_str();
+  for node_id in added_ids {
+    publish(Event::NodeAdded { node_type, node_id });
+  }
+  for node_id in removed_ids {
+    publish(Event::NodeRemoved { node_type, node_id });
+  }
+}
+
+#[inline]
+pub fn subscribe() -> broadcast::Receiver<Event> {
+  EVENT_SENDER.subscribe()
+}
+
+#[inline]
+pub fn published_count(kind: EventKind) -> u64 {
+  PUBLISHED_COUNTS[kind as usize].load(Ordering::Relaxed)
+}
+
+// The health of nodes changes as time passes rather than on any call, so it is
+// checked periodically.
+pub fn spawn_health_watch_task() {
+  actix_web::rt::spawn(async {
+    let mut healths: HashMap<(&'static str, NodeId), bool> = HashMap::default();
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+      interval.tick().await;
+      let mut curr_healths = HashMap::default();
+      for frontend in FRONTEND_MGR.iter() {
+        curr_healths
+          .insert((NodeType::Frontend.as_str(), frontend.key().clone()), frontend.is_healthy());
+      }
+      for backend in BACKEND_MGR.iter() {
+        curr_healths
+          .insert((NodeType::Backend.as_str(), backend.key().clone()), backend.is_healthy());
+      }
+      for service in SERVICE_MGR.iter() {
+        curr_healths
+          .insert((NodeType::Service.as_str(), service.key().clone()), service.is_healthy());
+      }
+      for ((node_type, node_id), is_healthy) in &curr_healths {
+        let was_healthy = healths.get(&(*node_type, node_id.clone()));
+        if was_healthy.is_none_or(|was_healthy| was_healthy == is_healthy) {
+          continue;
+        }
+        if *node_type == NodeType::Service.as_str() {
+          ROUTE_DIST_CHECKSUM.invalidate();
+        }
+        publish(Event::NodeHealthChanged {
+          node_type: *node_type,
+          node_id: node_id.clone(),
+          is_healthy: *is_healthy,
+        });
+      }
+      healths = curr_healths;
+    }
+  });
+}
+
+// Posts the events of the kinds configured to the webhook one by one, a slow
+// webhook misses the events beyond the capacity of the bus
+pub fn spawn_webhook_task() {
+  if CONFIG.load().event_bus.webhook.is_empty() {
+    return;
+  }
+  actix_web::rt::spawn(async {
+    let client = awc::Client::default();
+    let mut receiver = subscribe();
+    loop {
+      match receiver.recv().await {
+        Ok(event) => {
+          let config = CONFIG.load();
+          let kinds = &config.event_bus.webhook_kinds;
+          if !kinds.is_empty() && !kinds.contains(&event.kind()) {
+            continue;
+          }
+          match client.post(&config.event_bus.webhook).send_json(&event).await {
+            Ok(rep) if rep.status().is_success() => {}
+            Ok(rep) => log::warn!("Failed to post event: {:?}, status: {:?}", event, rep.status()),
+            Err(err) => log::warn!("Failed to post event: {:?}, err: {:?}", event, err),
+          }
+        }
+        Err(RecvError::Lagged(count)) => log::warn!("Missed events to post: count: {:?}", count),
+        Err(RecvError::Closed) => break,
+      }
+    }
+  });
+}
+
+// The events of the kinds, all kinds if empty, as server-sent events, the
+// events missed by lagging behind are told by a comment
+pub fn sse_stream(kinds: Vec<EventKind>) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+  futures::stream::unfold(subscribe(), move |mut receiver| {
+    let kinds = kinds.clone();
+    async move {
+      loop {
+        match receiver.recv().await {
+          Ok(event) if kinds.is_empty() || kinds.contains(&event.kind()) => {
+            return Some((Ok(encode_sse(&event)), receiver));
+          }
+          Ok(_) => continue,
+          Err(RecvError::Lagged(count)) => {
+            return Some((Ok(Bytes::from(format!(": missed {} events\n\n", count))), receiver));
+          }
+          Err(RecvError::Closed) => return None,
+        }
+      }
+    }
+  })
+}
+
+#[inline]
+fn encode_sse(event: &Event) -> Bytes {
+  let data = serde_json::to_string(event).unwrap_or_default();
+  Bytes::from(format!("event: {}\ndata: {}\n\n", event.kind().as_str(), data))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_encode_sse() {
+    let event = Event::NodeHealthChanged {
+      node_type: "backend",
+      node_id: "backend-0".to_owned(),
+      is_healthy: false,
+    };
+    assert_eq!(
+      encode_sse(&event),
+      Bytes::from_static(
+        b"event: node-health-changed\ndata: {\"kind\":\"node-health-changed\",\"node_type\":\"backend\",\"node_id\":\"backend-0\",\"is_healthy\":false}\n\n"
+      )
+    );
+    for kind in ALL_EVENT_KINDS {
+      assert_eq!(EventKind::from_name(kind.as_str()), Some(kind));
+    }
+    assert_eq!(EventKind::from_name("node_added"), None);
+  }
+}
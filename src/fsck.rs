@@ -0,0 +1,202 @@
+use std::{
+  collections::HashSet,
+  panic::{self, AssertUnwindSafe},
+};
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use seriesdb::coder::Coder;
+
+use crate::{
+  audit::{AuditCoder, AuditKey, AuditRecord},
+  config::{DbEngine, DiscoverySource, CONFIG},
+  config_mgr::SettingCoder,
+  encryption::{self, EncryptedCoder},
+  health::ProbeCoder,
+  migration,
+  node_mgr::{service_mgr, NodeId, Service, ServiceCoder},
+  route_mgr::{
+    self, HistoryCoder, OwnerCoder, PathBundle, PathRateLimit, RateLimitCoder, Revision,
+    RevisionKey, RouteCoder, TenantCoder, WeightCoder,
+  },
+  store::{open_raw_table, RawTable},
+  topic_mgr::{self, LocatedAtCoder, Namespace, NamespaceCoder, Topic, TopicCoder},
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Problem {
+  pub table: &'static str,
+  // Escaped, as the keys of some tables are binary
+  pub key: String,
+  pub desc: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct FsckReport {
+  pub tables: usize,
+  pub entries: usize,
+  pub problems: Vec<Problem>,
+  // Whether the entries with problems were removed
+  pub repaired: bool,
+}
+
+// Decodes every entry of every table, and checks that the routes and tenants
+// are of known services and the topics are assigned or pinned to configured
+// backends, as the managers would otherwise panic on the first entry failing
+// to decode when they recover. With repair, the entries with problems are
+// removed, which the managers rebuild as the nodes register again.
+pub fn run(repair: bool) -> Result<FsckReport> {
+  if CONFIG.load().db.engine != DbEngine::Seriesdb {
+    bail!("Nothing to check with the memory engine");
+  }
+  // Rather than reporting, and repairing, every encrypted entry as failing to
+  // decode when the key fails to load
+  encryption::init()?;
+  migration::run()?;
+  // The coders panic on the entries failing to decode, which are reported
+  let hook = panic::take_hook();
+  panic::set_hook(Box::new(|_| {}));
+  let mut fsck = Fsck { repair, report: FsckReport { repaired: repair, ..Default::default() } };
+  let result = fsck.check_tables();
+  panic::set_hook(hook);
+  result.map(|()| fsck.report)
+}
+
+struct Fsck {
+  repair: bool,
+  report: FsckReport,
+}
+
+impl Fsck {
+  fn check_tables(&mut self) -> Result<()> {
+    let service_ids: HashSet<NodeId> = self
+      .check::<NodeId, Service, ServiceCoder>("node_mgr.service_mgr.services", no_check)?
+      .into_iter()
+      .map(|(service_id, _)| service_id)
+      .collect();
+    self.check::<String, String, service_mgr::InfoCoder>("node_mgr.service_mgr.infos", no_check)?;
+    let check_service = |service_id: &NodeId| {
+      (!service_ids.contains(service_id)).then(|| format!("Unknown service: {}", service_id))
+    };
+    self.check::<NodeId, PathBundle, RouteCoder>("route_mgr.routes", |service_id, _| {
+      check_service(service_id)
+    })?;
+    self.check::<NodeId, String, TenantCoder>("route_mgr.tenants", |service_id, _| {
+      check_service(service_id)
+    })?;
+    self.check::<RevisionKey, Revision, HistoryCoder>("route_mgr.history", no_check)?;
+    self.check::<String, String, OwnerCoder>("route_mgr.owners", no_check)?;
+    self.check::<NodeId, u32, WeightCoder>("route_mgr.weights", no_check)?;
+    self.check::<NodeId, Vec<PathRateLimit>, RateLimitCoder>(
+      "route_mgr.rate_limits",
+      |service_id, _| check_service(service_id),
+    )?;
+    self.check::<String, String, route_mgr::InfoCoder>("route_mgr.infos", no_check)?;
+
+    let config = CONFIG.load();
+    let backend_ids: HashSet<&NodeId> =
+      config.backend_mgr.backends.iter().map(|backend| &backend.id).collect();
+    // The backends discovered are not known without polling the catalog
+    let discovers_backends = config.discovery.source != DiscoverySource::None;
+    let check_backend = |backend_id: &NodeId| {
+      (!discovers_backends && !backend_ids.contains(backend_id))
+        .then(|| format!("Unknown backend: {}", backend_id))
+    };
+    self.check::<Topic, NodeId, TopicCoder>("topic_mgr.topics", |_, backend_id| {
+      check_backend(backend_id)
+    })?;
+    self.check::<Topic, NodeId, TopicCoder>("topic_mgr.pins", |_, backend_id| {
+      check_backend(backend_id)
+    })?;
+    self.check::<Topic, u32, LocatedAtCoder>("topic_mgr.located_ats", no_check)?;
+    self.check::<String, Namespace, NamespaceCoder>("topic_mgr.namespaces", no_check)?;
+    self.check::<String, String, topic_mgr::InfoCoder>("topic_mgr.infos", no_check)?;
+
+    self.check::<String, String, SettingCoder>("config_mgr.settings", no_check)?;
+    self.check::<String, u32, ProbeCoder>("health.probes", no_check)?;
+    self.check::<AuditKey, AuditRecord, AuditCoder>("audit.records", no_check)?;
+    self.check::<String, String, migration::InfoCoder>("migration.infos", no_check)?;
+    Ok(())
+  }
+
+  // As stored, the values are encrypted if a key is configured
+  #[inline]
+  fn check<K, V, C: Coder<K, V, EncodedKey = Bytes, EncodedValue = Bytes>>(
+    &mut self, name: &'static str, validate: impl FnMut(&K, &V) -> Option<String>,
+  ) -> Result<Vec<(K, V)>> {
+    self.check_entries::<K, V, EncryptedCoder<C>>(name, &*open_raw_table(name)?, validate)
+  }
+
+  // Returns the entries decoded and valid
+  fn check_entries<K, V, C: Coder<K, V>>(
+    &mut self, name: &'static str, table: &dyn RawTable,
+    mut validate: impl FnMut(&K, &V) -> Option<String>,
+  ) -> Result<Vec<(K, V)>> {
+    let entries = table.dump();
+    let count = entries.len();
+    self.report.tables += 1;
+    self.report.entries += count;
+    let mut valid = Vec::with_capacity(count);
+    let mut kept: Vec<(Bytes, Bytes)> = Vec::with_capacity(count);
+    for (key, value) in entries {
+      let decoded =
+        panic::catch_unwind(AssertUnwindSafe(|| (C::decode_key(&key), C::decode_value(&value))));
+      let desc = match decoded {
+        Ok((decoded_key, decoded_value)) => match validate(&decoded_key, &decoded_value) {
+          None => {
+            valid.push((decoded_key, decoded_value));
+            kept.push((key, value));
+            continue;
+          }
+          Some(desc) => desc,
+        },
+        Err(_) => "Failed to decode".to_owned(),
+      };
+      log::warn!("Found problem: table: {:?}, key: {:?}, desc: {:?}", name, key, desc);
+      self.report.problems.push(Problem { table: name, key: key.escape_ascii().to_string(), desc });
+    }
+    if self.repair && kept.len() < count {
+      log::info!("Repairing table: name: {:?}, kept: {:?}", name, kept.len());
+      table.load(&kept)?;
+    }
+    Ok(valid)
+  }
+}
+
+#[inline]
+fn no_check<K, V>(_: &K, _: &V) -> Option<String> {
+  None
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::store::{MemoryStore, RawCoder, Store};
+
+  #[test]
+  fn test_check_entries() {
+    let table = MemoryStore::<Bytes, Bytes, RawCoder>::new();
+    table.put(&Bytes::from("a"), &Bytes::copy_from_slice(&1u32.to_be_bytes())).unwrap();
+    table.put(&Bytes::from("b"), &Bytes::copy_from_slice(&2u32.to_be_bytes())).unwrap();
+    table.put(&Bytes::from("c"), &Bytes::from_static(&[3])).unwrap();
+
+    let mut fsck = Fsck { repair: false, report: FsckReport::default() };
+    let valid = fsck
+      .check_entries::<NodeId, u32, WeightCoder>("weights", &table, |_, weight| {
+        (*weight > 1).then(|| "Too heavy".to_owned())
+      })
+      .unwrap();
+    assert_eq!(valid, vec![("a".to_owned(), 1)]);
+    assert_eq!((fsck.report.tables, fsck.report.entries), (1, 3));
+    assert_eq!(
+      fsck.report.problems.iter().map(|problem| problem.key.as_str()).collect::<Vec<_>>(),
+      vec!["b", "c"]
+    );
+    assert_eq!(table.dump().len(), 3);
+
+    let mut fsck = Fsck { repair: true, report: FsckReport::default() };
+    fsck.check_entries::<NodeId, u32, WeightCoder>("weights", &table, no_check).unwrap();
+    assert_eq!(fsck.report.problems.len(), 1);
+    assert_eq!(table.dump().len(), 2);
+  }
+}
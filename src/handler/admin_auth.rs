@@ -0,0 +1,132 @@
+use std::fmt;
+
+use actix_web::{
+  http::{header, Method},
+  HttpRequest, HttpResponse,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use subtle::ConstantTimeEq;
+
+use super::error_rep::ErrorRep;
+use crate::{
+  config::{AdminRole, CONFIG},
+  error_code::ExtErrorCode,
+};
+
+// Lowercase, as header names are case insensitive
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+// Makes browsers prompt for the key, with any user name, e.g. to open the dashboard
+const BASIC_CHALLENGE: &str = "Basic realm=\"maxwell-master admin\"";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdminAuthError {
+  NoKeyConfigured,
+  MissingKey,
+  UnknownKey,
+  Forbidden { role: AdminRole, required: AdminRole },
+}
+
+impl fmt::Display for AdminAuthError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      AdminAuthError::NoKeyConfigured => {
+        write!(
+          f,
+          "No api key is configured, the admin endpoints are only served on the unix socket"
+        )
+      }
+      AdminAuthError::MissingKey => {
+        write!(f, "Neither the {} header nor basic credentials are present", API_KEY_HEADER)
+      }
+      AdminAuthError::UnknownKey => write!(f, "The api key does not match any configured"),
+      AdminAuthError::Forbidden { role, required } => {
+        write!(f, "The api key is not allowed to: role: {:?}, required: {:?}", role, required)
+      }
+    }
+  }
+}
+
+impl std::error::Error for AdminAuthError {}
+
+// Reads are open to every role, anything else changes the state
+#[inline]
+fn required_role(method: &Method) -> AdminRole {
+  if method == Method::GET || method == Method::HEAD {
+    AdminRole::ReadOnly
+  } else {
+    AdminRole::Admin
+  }
+}
+
+pub fn authorize(req: &HttpRequest) -> Result<(), AdminAuthError> {
+  // Looked up on each request, so that reloaded keys apply at once
+  let config = CONFIG.load();
+  let api_keys = &config.admin.api_keys;
+  // Closed rather than open to anyone who can reach them
+  if api_keys.is_empty() {
+    return Err(AdminAuthError::NoKeyConfigured);
+  }
+  let key = api_key_of(req).ok_or(AdminAuthError::MissingKey)?;
+  let role = api_keys
+    .iter()
+    .find(|api_key| bool::from(api_key.key.as_bytes().ct_eq(key.as_bytes())))
+    .map(|api_key| api_key.role)
+    .ok_or(AdminAuthError::UnknownKey)?;
+  let required = required_role(req.method());
+  if role >= required {
+    Ok(())
+  } else {
+    Err(AdminAuthError::Forbidden { role, required })
+  }
+}
+
+// From the header, or else the password of basic credentials
+fn api_key_of(req: &HttpRequest) -> Option<String> {
+  if let Some(key) = req.headers().get(API_KEY_HEADER).and_then(|value| value.to_str().ok()) {
+    return Some(key.to_owned());
+  }
+  let credentials = req
+    .headers()
+    .get(header::AUTHORIZATION)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.strip_prefix("Basic "))?;
+  let credentials = String::from_utf8(STANDARD.decode(credentials.trim()).ok()?).ok()?;
+  credentials.split_once(':').map(|(_, key)| key.to_owned())
+}
+
+pub fn build_rejection(err: &AdminAuthError) -> HttpResponse {
+  match err {
+    AdminAuthError::MissingKey | AdminAuthError::UnknownKey => {
+      let mut rep =
+        ErrorRep::new(ExtErrorCode::AdminAuthFailed as i32, format!("{}", err)).to_response();
+      rep
+        .headers_mut()
+        .insert(header::WWW_AUTHENTICATE, header::HeaderValue::from_static(BASIC_CHALLENGE));
+      rep
+    }
+    AdminAuthError::NoKeyConfigured | AdminAuthError::Forbidden { .. } => {
+      ErrorRep::new(ExtErrorCode::AdminForbidden as i32, format!("{}", err)).to_response()
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_required_role() {
+    assert_eq!(required_role(&Method::GET), AdminRole::ReadOnly);
+    assert_eq!(required_role(&Method::DELETE), AdminRole::Admin);
+    assert!(AdminRole::Admin >= required_role(&Method::POST));
+    assert!(AdminRole::ReadOnly < required_role(&Method::POST));
+  }
+
+  #[test]
+  fn test_authorize_without_keys() {
+    // No api key is configured by default
+    let req = actix_web::test::TestRequest::default().to_http_request();
+    assert_eq!(authorize(&req), Err(AdminAuthError::NoKeyConfigured));
+  }
+}
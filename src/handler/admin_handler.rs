@@ -0,0 +1,1455 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+use std::path::Path;
+
+use actix_web::HttpRequest;
+use maxwell_protocol::ErrorCode;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::{
+  connection_registry::CONNECTION_REGISTRY,
+  error_rep::ErrorRep,
+  http_handler::{not_leader_rep, ListQuery, PageInfo, Sortable, DEFAULT_PER_PAGE, MAX_PER_PAGE},
+};
+use crate::{
+  audit::{AuditRecord, AUDIT_LOG},
+  backup::{self, BackupInfo},
+  cluster,
+  config::CONFIG,
+  config_mgr::{SettingError, CONFIG_MGR, TUNABLE_SETTINGS},
+  db::{self, DbStats},
+  error_code::ExtErrorCode,
+  event_bus::EventKind,
+  hot_reload, maintenance,
+  node_mgr::{Node, NodeId, NodeType, BACKEND_MGR, FRONTEND_MGR, SERVICE_MGR},
+  profiling::{self, HeapStats, ProfileFormat, ProfilingError},
+  route_mgr::{
+    is_lease_expired, tenant_names, PathBundle, Revision, RouteHealth, DEFAULT_WEIGHT, METHODS,
+    ROUTE_MGR,
+  },
+  standby::{self, StandbyStatus},
+  store::{self, TableStats},
+  topic_mgr::{Namespace, Topic, TOPIC_MGR},
+  topology::{Topology, TopologyFormat},
+};
+
+const DEFAULT_AUDIT_LIMIT: usize = 100;
+const MAX_AUDIT_LIMIT: usize = 1000;
+const DEFAULT_CPU_PROFILE_SECONDS: u64 = 10;
+
+#[derive(Debug, Serialize)]
+pub struct AdminRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+}
+
+impl AdminRep {
+  #[inline]
+  pub fn ok() -> Self {
+    AdminRep { code: ErrorCode::Ok as i32, desc: None }
+  }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReassignTopicReq {
+  topic: String,
+  backend_id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReassignTopicRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  prev_backend_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PinTopicReq {
+  topic: String,
+  backend_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnpinTopicReq {
+  topic: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TopicPin {
+  topic: String,
+  backend_id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetTopicPinsRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  pins: Vec<TopicPin>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BackendTopicStats {
+  backend_id: String,
+  topic_count: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetTopicStatsRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  total_count: u64,
+  backends: Vec<BackendTopicStats>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NamespaceStats {
+  #[serde(flatten)]
+  namespace: Namespace,
+  topic_count: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetTopicNamespacesRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  namespaces: Vec<NamespaceStats>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RemoveTopicNamespaceReq {
+  name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TopicAssignment {
+  topic: String,
+  backend_id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportTopicsRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  topics: Vec<TopicAssignment>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportTopicsReq {
+  topics: Vec<TopicAssignment>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportTopicsRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  imported_count: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MatchRouteReq {
+  method: String,
+  path: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MatchedRoute {
+  service_id: String,
+  pattern: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MatchRouteRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  routes: Vec<MatchedRoute>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetRouteHistoryReq {
+  service_id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetRouteHistoryRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  revisions: Vec<Revision>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RollbackRoutesReq {
+  service_id: String,
+  revision: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RouteOwner {
+  #[serde(default, skip_serializing_if = "String::is_empty")]
+  tenant: String,
+  method: String,
+  path: String,
+  owner: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetRouteOwnersRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  owners: Vec<RouteOwner>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransferRouteReq {
+  #[serde(default)]
+  tenant: String,
+  method: String,
+  path: String,
+  owner: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetServiceWeightReq {
+  service_id: String,
+  weight: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ServiceRoutes {
+  service_id: String,
+  #[serde(skip_serializing_if = "String::is_empty")]
+  tenant: String,
+  // None if the service is stale or unknown, its routes are not served then
+  #[serde(skip_serializing_if = "Option::is_none")]
+  endpoint: Option<String>,
+  is_healthy: bool,
+  // Routes of expired services are not served until the services are active again
+  is_expired: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  active_at: Option<u32>,
+  weight: u32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  revision: Option<u32>,
+  paths: PathBundle,
+}
+
+impl Sortable for ServiceRoutes {
+  const SORT_FIELDS: &'static [&'static str] =
+    &["service_id", "tenant", "active_at", "weight", "revision"];
+
+  fn cmp_by(&self, other: &Self, field: &str) -> Ordering {
+    match field {
+      "tenant" => self.tenant.cmp(&other.tenant),
+      "active_at" => self.active_at.cmp(&other.active_at),
+      "weight" => self.weight.cmp(&other.weight),
+      "revision" => self.revision.cmp(&other.revision),
+      _ => self.service_id.cmp(&other.service_id),
+    }
+  }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListRoutesReq {
+  #[serde(default)]
+  healthy: Option<bool>,
+  #[serde(default)]
+  tenant: Option<String>,
+  // Only the services having a path of any method starting with it
+  #[serde(default)]
+  path_prefix: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetRoutesRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  route_version: u32,
+  service_version: u32,
+  routes: Vec<ServiceRoutes>,
+  #[serde(flatten)]
+  page: PageInfo,
+}
+
+// Paths are sorted and grouped by method, so that exports can be diffed
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedRoutes {
+  service_id: String,
+  #[serde(default, skip_serializing_if = "String::is_empty")]
+  tenant: String,
+  #[serde(default = "default_weight")]
+  weight: u32,
+  paths: BTreeMap<String, BTreeSet<String>>,
+}
+
+fn default_weight() -> u32 {
+  DEFAULT_WEIGHT
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportRoutesRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  routes: Vec<ExportedRoutes>,
+  owners: Vec<RouteOwner>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportRoutesReq {
+  routes: Vec<ExportedRoutes>,
+  #[serde(default)]
+  owners: Vec<RouteOwner>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportRoutesRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  imported_count: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetRouteHealthReq {
+  #[serde(default)]
+  down_only: bool,
+  #[serde(default)]
+  tenant: Option<String>,
+  #[serde(default)]
+  path_prefix: Option<String>,
+}
+
+impl Sortable for RouteHealth {
+  const SORT_FIELDS: &'static [&'static str] =
+    &["tenant", "method", "path", "healthy_count", "unhealthy_count", "down_since"];
+
+  fn cmp_by(&self, other: &Self, field: &str) -> Ordering {
+    match field {
+      "tenant" => self.tenant.cmp(&other.tenant),
+      "method" => self.method.cmp(other.method),
+      "healthy_count" => self.healthy_count.cmp(&other.healthy_count),
+      "unhealthy_count" => self.unhealthy_count.cmp(&other.unhealthy_count),
+      "down_since" => self.down_since.cmp(&other.down_since),
+      _ => self.path.cmp(&other.path),
+    }
+  }
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetRouteHealthRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  // Of all the routes, whatever the filters
+  down_count: usize,
+  routes: Vec<RouteHealth>,
+  #[serde(flatten)]
+  page: PageInfo,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConnectionInfo {
+  id: u32,
+  peer_addr: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  node_type: Option<&'static str>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  node_id: Option<String>,
+  connected_at: u32,
+  received_binary_count: u64,
+  received_text_count: u64,
+}
+
+impl Sortable for ConnectionInfo {
+  const SORT_FIELDS: &'static [&'static str] =
+    &["id", "peer_addr", "node_id", "connected_at", "received_binary_count", "received_text_count"];
+
+  fn cmp_by(&self, other: &Self, field: &str) -> Ordering {
+    match field {
+      "peer_addr" => self.peer_addr.cmp(&other.peer_addr),
+      "node_id" => self.node_id.cmp(&other.node_id),
+      "connected_at" => self.connected_at.cmp(&other.connected_at),
+      "received_binary_count" => self.received_binary_count.cmp(&other.received_binary_count),
+      "received_text_count" => self.received_text_count.cmp(&other.received_text_count),
+      _ => self.id.cmp(&other.id),
+    }
+  }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListConnectionsReq {
+  // unknown, frontend, backend, service or peer
+  #[serde(default)]
+  node_type: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetConnectionsRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  connections: Vec<ConnectionInfo>,
+  #[serde(flatten)]
+  page: PageInfo,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetAuditRecordsReq {
+  // Seconds since the epoch, both inclusive
+  #[serde(default)]
+  since: u32,
+  #[serde(default)]
+  until: Option<u32>,
+  // Matching any part of the actor, e.g. an ip
+  #[serde(default)]
+  actor: Option<String>,
+  #[serde(default)]
+  action: Option<String>,
+  #[serde(default)]
+  limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetAuditRecordsRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  records: Vec<AuditRecord>,
+  // More records matched, to be queried since the last one
+  truncated: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetSettingReq {
+  key: String,
+  // Numbers and bools are taken as they are, strings unquoted
+  value: Value,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RemoveSettingReq {
+  key: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetSettingsRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  // Only the settings overriding the config file
+  settings: BTreeMap<String, String>,
+  tunable: &'static [&'static str],
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetTopologyReq {
+  #[serde(default)]
+  format: Option<TopologyFormat>,
+}
+
+// kinds is comma separated, e.g. node-added,topic-reassigned, all kinds if
+// left out
+#[derive(Debug, Deserialize)]
+pub struct GetEventsReq {
+  #[serde(default)]
+  kinds: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetCpuProfileReq {
+  #[serde(default)]
+  seconds: Option<u64>,
+  #[serde(default)]
+  format: Option<ProfileFormat>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetHeapStatsRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  stats: HeapStats,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StandbyRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  status: StandbyStatus,
+}
+
+// dir defaults to db.backup.dir
+#[derive(Debug, Deserialize)]
+pub struct BackupReq {
+  dir: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BackupRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  backup: BackupInfo,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetDbStatsRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  // None with the memory engine
+  db: Option<DbStats>,
+  tables: BTreeMap<String, TableStats>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReloadConfigRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  ignored: Vec<&'static str>,
+  backends_changed: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NodeInfo {
+  node_type: &'static str,
+  id: String,
+  endpoint: String,
+  active_at: u32,
+  is_healthy: bool,
+}
+
+impl Sortable for NodeInfo {
+  const SORT_FIELDS: &'static [&'static str] = &["node_type", "id", "active_at"];
+
+  fn cmp_by(&self, other: &Self, field: &str) -> Ordering {
+    match field {
+      "node_type" => self.node_type.cmp(other.node_type),
+      "active_at" => self.active_at.cmp(&other.active_at),
+      _ => self.id.cmp(&other.id),
+    }
+  }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListNodesReq {
+  // frontend, backend or service
+  #[serde(default)]
+  node_type: Option<String>,
+  #[serde(default)]
+  healthy: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetNodesRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  nodes: Vec<NodeInfo>,
+  #[serde(flatten)]
+  page: PageInfo,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TopicInfo {
+  topic: String,
+  backend_id: String,
+}
+
+// Paged by cursor instead of by page, as there may be too many topics to load
+#[derive(Debug, Deserialize)]
+pub struct ListTopicsReq {
+  #[serde(default)]
+  prefix: Option<String>,
+  #[serde(default)]
+  backend_id: Option<String>,
+  // The next_cursor of the last page, none for the first page
+  #[serde(default)]
+  cursor: Option<Topic>,
+  #[serde(default)]
+  limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetTopicsRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  topics: Vec<TopicInfo>,
+  // None once there are no more topics
+  next_cursor: Option<Topic>,
+}
+
+pub struct AdminHandler {
+  peer_addr: Option<SocketAddr>,
+}
+
+impl AdminHandler {
+  #[inline]
+  pub fn new(req: &HttpRequest) -> Self {
+    Self { peer_addr: req.peer_addr() }
+  }
+
+  #[inline]
+  pub fn reassign_topic(&self, req: ReassignTopicReq) -> Result<ReassignTopicRep, ErrorRep> {
+    log::info!("Reassigning topic: from: {:?}, req: {:?}", self.peer_addr, req);
+    cluster::check_leader().map_err(not_leader_rep)?;
+    maintenance::check_writable()?;
+
+    if BACKEND_MGR.get(&req.backend_id).is_none() {
+      log::error!("Backend not found in config: id: {:?}", req.backend_id);
+
+      return Err(ErrorRep::new(
+        ExtErrorCode::NotFound as i32,
+        format!("Backend not found in config: id: {}", req.backend_id),
+      ));
+    }
+
+    match TOPIC_MGR.reassign(req.topic.clone(), req.backend_id) {
+      Ok(prev_backend_id) => {
+        Ok(ReassignTopicRep { code: ErrorCode::Ok as i32, desc: None, prev_backend_id })
+      }
+      Err(err) => {
+        log::error!("Failed to reassign topic: {:?}, err: {:?}", req.topic, err);
+
+        Err(ErrorRep::new(
+          ErrorCode::MasterError as i32,
+          format!("Failed to reassign topic: {}, err: {}", req.topic, err),
+        ))
+      }
+    }
+  }
+
+  #[inline]
+  pub fn get_topic_pins(&self) -> GetTopicPinsRep {
+    let pins = TOPIC_MGR
+      .pins()
+      .into_iter()
+      .map(|(topic, backend_id)| TopicPin { topic, backend_id })
+      .collect();
+    GetTopicPinsRep { code: ErrorCode::Ok as i32, desc: None, pins }
+  }
+
+  #[inline]
+  pub fn pin_topic(&self, req: PinTopicReq) -> Result<AdminRep, ErrorRep> {
+    log::info!("Pinning topic: from: {:?}, req: {:?}", self.peer_addr, req);
+    cluster::check_leader().map_err(not_leader_rep)?;
+    maintenance::check_writable()?;
+
+    if BACKEND_MGR.get(&req.backend_id).is_none() {
+      log::error!("Backend not found in config: id: {:?}", req.backend_id);
+
+      return Err(ErrorRep::new(
+        ExtErrorCode::NotFound as i32,
+        format!("Backend not found in config: id: {}", req.backend_id),
+      ));
+    }
+
+    match TOPIC_MGR.pin(req.topic.clone(), req.backend_id) {
+      Ok(()) => Ok(AdminRep::ok()),
+      Err(err) => {
+        log::error!("Failed to pin topic: {:?}, err: {:?}", req.topic, err);
+
+        Err(ErrorRep::new(
+          ErrorCode::MasterError as i32,
+          format!("Failed to pin topic: {}, err: {}", req.topic, err),
+        ))
+      }
+    }
+  }
+
+  #[inline]
+  pub fn unpin_topic(&self, req: UnpinTopicReq) -> Result<AdminRep, ErrorRep> {
+    log::info!("Unpinning topic: from: {:?}, req: {:?}", self.peer_addr, req);
+
+    match TOPIC_MGR.unpin(&req.topic) {
+      Ok(true) => Ok(AdminRep::ok()),
+      Ok(false) => Err(ErrorRep::new(
+        ExtErrorCode::NotFound as i32,
+        format!("The topic was not pinned: {}", req.topic),
+      )),
+      Err(err) => {
+        log::error!("Failed to unpin topic: {:?}, err: {:?}", req.topic, err);
+
+        Err(ErrorRep::new(
+          ErrorCode::MasterError as i32,
+          format!("Failed to unpin topic: {}, err: {}", req.topic, err),
+        ))
+      }
+    }
+  }
+
+  #[inline]
+  pub fn get_topic_stats(&self) -> GetTopicStatsRep {
+    let backends: Vec<BackendTopicStats> = BACKEND_MGR
+      .ids()
+      .iter()
+      .map(|backend_id| BackendTopicStats {
+        backend_id: backend_id.clone(),
+        topic_count: TOPIC_MGR.topic_count(backend_id),
+      })
+      .collect();
+    let total_count = backends.iter().map(|stats| stats.topic_count).sum();
+    GetTopicStatsRep { code: ErrorCode::Ok as i32, desc: None, total_count, backends }
+  }
+
+  #[inline]
+  pub fn get_topic_namespaces(&self) -> GetTopicNamespacesRep {
+    let namespaces = TOPIC_MGR
+      .namespaces()
+      .into_iter()
+      .map(|namespace| {
+        let topic_count = TOPIC_MGR.namespace_topic_count(&namespace.name);
+        NamespaceStats { namespace, topic_count }
+      })
+      .collect();
+    GetTopicNamespacesRep { code: ErrorCode::Ok as i32, desc: None, namespaces }
+  }
+
+  #[inline]
+  pub fn set_topic_namespace(&self, namespace: Namespace) -> Result<AdminRep, ErrorRep> {
+    log::info!("Setting topic namespace: from: {:?}, req: {:?}", self.peer_addr, namespace);
+
+    if namespace.name.is_empty() || namespace.name.contains('/') {
+      return Err(ErrorRep::new(
+        ExtErrorCode::InvalidQuery as i32,
+        format!("Invalid namespace name: {:?}", namespace.name),
+      ));
+    }
+    if let Some(backend_id) =
+      namespace.backends.iter().find(|backend_id| BACKEND_MGR.get(backend_id).is_none())
+    {
+      log::error!("Backend not found in config: id: {:?}", backend_id);
+
+      return Err(ErrorRep::new(
+        ExtErrorCode::NotFound as i32,
+        format!("Backend not found in config: id: {}", backend_id),
+      ));
+    }
+
+    let name = namespace.name.clone();
+    match TOPIC_MGR.set_namespace(namespace) {
+      Ok(()) => Ok(AdminRep::ok()),
+      Err(err) => {
+        log::error!("Failed to set namespace: {:?}, err: {:?}", name, err);
+
+        Err(ErrorRep::new(
+          ErrorCode::MasterError as i32,
+          format!("Failed to set namespace: {}, err: {}", name, err),
+        ))
+      }
+    }
+  }
+
+  #[inline]
+  pub fn remove_topic_namespace(&self, req: RemoveTopicNamespaceReq) -> Result<AdminRep, ErrorRep> {
+    log::info!("Removing topic namespace: from: {:?}, req: {:?}", self.peer_addr, req);
+
+    match TOPIC_MGR.remove_namespace(&req.name) {
+      Ok(true) => Ok(AdminRep::ok()),
+      Ok(false) => Err(ErrorRep::new(
+        ExtErrorCode::NotFound as i32,
+        format!("Namespace not found: {}", req.name),
+      )),
+      Err(err) => {
+        log::error!("Failed to remove namespace: {:?}, err: {:?}", req.name, err);
+
+        Err(ErrorRep::new(
+          ErrorCode::MasterError as i32,
+          format!("Failed to remove namespace: {}, err: {}", req.name, err),
+        ))
+      }
+    }
+  }
+
+  #[inline]
+  pub fn export_topics(&self) -> ExportTopicsRep {
+    log::info!("Exporting topics: from: {:?}", self.peer_addr);
+
+    build_export_topics_rep(TOPIC_MGR.dump())
+  }
+
+  // Validates all backend ids before importing anything, so that a bad
+  // export is rejected as a whole instead of being half imported.
+  pub fn import_topics(&self, req: ImportTopicsReq) -> Result<ImportTopicsRep, ErrorRep> {
+    log::info!("Importing topics: from: {:?}, count: {:?}", self.peer_addr, req.topics.len());
+    cluster::check_leader().map_err(not_leader_rep)?;
+    maintenance::check_writable()?;
+
+    if let Some(assignment) =
+      req.topics.iter().find(|assignment| BACKEND_MGR.get(&assignment.backend_id).is_none())
+    {
+      log::error!("Backend not found in config: id: {:?}", assignment.backend_id);
+
+      return Err(
+        ErrorRep::new(
+          ExtErrorCode::NotFound as i32,
+          format!(
+            "Backend not found in config: id: {}, topic: {}",
+            assignment.backend_id, assignment.topic
+          ),
+        )
+        .with_details(json!({ "imported_count": 0 })),
+      );
+    }
+
+    let mut imported_count = 0;
+    for assignment in req.topics {
+      if let Err(err) = TOPIC_MGR.reassign(assignment.topic.clone(), assignment.backend_id) {
+        log::error!("Failed to import topic: {:?}, err: {:?}", assignment.topic, err);
+
+        return Err(
+          ErrorRep::new(
+            ErrorCode::MasterError as i32,
+            format!("Failed to import topic: {}, err: {}", assignment.topic, err),
+          )
+          .with_details(json!({ "imported_count": imported_count })),
+        );
+      }
+      imported_count += 1;
+    }
+    Ok(ImportTopicsRep { code: ErrorCode::Ok as i32, desc: None, imported_count })
+  }
+
+  #[inline]
+  pub fn match_route(&self, req: MatchRouteReq) -> MatchRouteRep {
+    let routes = ROUTE_MGR
+      .match_path(&req.method.to_ascii_lowercase(), &req.path)
+      .into_iter()
+      .map(|(service_id, pattern)| MatchedRoute { service_id, pattern })
+      .collect();
+    MatchRouteRep { code: ErrorCode::Ok as i32, desc: None, routes }
+  }
+
+  #[inline]
+  pub fn get_route_history(&self, req: GetRouteHistoryReq) -> GetRouteHistoryRep {
+    let revisions = ROUTE_MGR.history(&req.service_id);
+    GetRouteHistoryRep { code: ErrorCode::Ok as i32, desc: None, revisions }
+  }
+
+  #[inline]
+  pub fn rollback_routes(&self, req: RollbackRoutesReq) -> Result<AdminRep, ErrorRep> {
+    log::info!("Rolling back routes: from: {:?}, req: {:?}", self.peer_addr, req);
+
+    match ROUTE_MGR.rollback(&req.service_id, req.revision) {
+      Ok(true) => Ok(AdminRep::ok()),
+      Ok(false) => Err(ErrorRep::new(
+        ExtErrorCode::NotFound as i32,
+        format!("Revision not found: service_id: {}, revision: {}", req.service_id, req.revision),
+      )),
+      Err(err) => {
+        log::error!("Failed to roll back routes: {:?}, err: {:?}", req, err);
+
+        Err(ErrorRep::new(
+          ErrorCode::MasterError as i32,
+          format!("Failed to roll back routes: service_id: {}, err: {}", req.service_id, err),
+        ))
+      }
+    }
+  }
+
+  #[inline]
+  pub fn get_route_owners(&self) -> GetRouteOwnersRep {
+    let owners = ROUTE_MGR
+      .owners()
+      .into_iter()
+      .map(|(tenant, method, path, owner)| RouteOwner { tenant, method, path, owner })
+      .collect();
+    GetRouteOwnersRep { code: ErrorCode::Ok as i32, desc: None, owners }
+  }
+
+  #[inline]
+  pub fn transfer_route(&self, req: TransferRouteReq) -> Result<AdminRep, ErrorRep> {
+    log::info!("Transferring route: from: {:?}, req: {:?}", self.peer_addr, req);
+
+    let method = req.method.to_ascii_lowercase();
+    if !METHODS.contains(&method.as_str()) {
+      return Err(ErrorRep::new(
+        ExtErrorCode::InvalidQuery as i32,
+        format!("Unknown method: {}", req.method),
+      ));
+    }
+    match ROUTE_MGR.transfer_path(&req.tenant, &method, &req.path, &req.owner) {
+      Ok(()) => Ok(AdminRep::ok()),
+      Err(err) => {
+        log::error!("Failed to transfer route: {:?}, err: {:?}", req, err);
+
+        Err(ErrorRep::new(
+          ErrorCode::MasterError as i32,
+          format!("Failed to transfer route: {} {}, err: {}", method, req.path, err),
+        ))
+      }
+    }
+  }
+
+  #[inline]
+  pub fn set_service_weight(&self, req: SetServiceWeightReq) -> Result<AdminRep, ErrorRep> {
+    log::info!("Setting service weight: from: {:?}, req: {:?}", self.peer_addr, req);
+
+    match ROUTE_MGR.set_weight(&req.service_id, req.weight) {
+      Ok(()) => Ok(AdminRep::ok()),
+      Err(err) => {
+        log::error!("Failed to set service weight: {:?}, err: {:?}", req, err);
+
+        Err(ErrorRep::new(
+          ErrorCode::MasterError as i32,
+          format!("Failed to set service weight: service_id: {}, err: {}", req.service_id, err),
+        ))
+      }
+    }
+  }
+
+  #[inline]
+  pub fn get_routes(&self, list: &ListQuery, req: ListRoutesReq) -> Result<GetRoutesRep, ErrorRep> {
+    let mut routes: Vec<ServiceRoutes> = ROUTE_MGR
+      .reverse_route_group_iter()
+      .map(|reverse_route_group| {
+        let service_id = reverse_route_group.key();
+        let service = SERVICE_MGR.get(service_id);
+        ServiceRoutes {
+          service_id: service_id.clone(),
+          tenant: ROUTE_MGR.tenant_of(service_id),
+          endpoint: service.as_ref().map(|service| service.private_endpoint()),
+          is_healthy: service.as_ref().is_some_and(|service| service.is_healthy()),
+          is_expired: !matches!(&service, Some(service) if !is_lease_expired(service)),
+          active_at: service.as_ref().map(|service| service.active_at),
+          weight: ROUTE_MGR.weight(service_id),
+          revision: ROUTE_MGR.latest_revision(service_id),
+          paths: reverse_route_group.value().clone(),
+        }
+      })
+      .filter(|service: &ServiceRoutes| {
+        req.healthy.is_none_or(|healthy| service.is_healthy == healthy)
+          && req.tenant.as_ref().is_none_or(|tenant| &service.tenant == tenant)
+          && req.path_prefix.as_ref().is_none_or(|prefix| {
+            service
+              .paths
+              .path_sets()
+              .iter()
+              .any(|(_, paths)| paths.iter().any(|path| path.starts_with(prefix.as_str())))
+          })
+      })
+      .collect();
+    routes.sort_by(|a, b| a.service_id.cmp(&b.service_id));
+    let (routes, page) = list.apply(routes)?;
+    Ok(GetRoutesRep {
+      code: ErrorCode::Ok as i32,
+      desc: None,
+      route_version: ROUTE_MGR.version(),
+      service_version: SERVICE_MGR.version(),
+      routes,
+      page,
+    })
+  }
+
+  #[inline]
+  pub fn remove_routes(&self, service_id: String) -> Result<AdminRep, ErrorRep> {
+    log::info!("Removing routes: from: {:?}, service_id: {:?}", self.peer_addr, service_id);
+
+    match ROUTE_MGR.remove_reverse_route_group(&service_id) {
+      Ok(true) => Ok(AdminRep::ok()),
+      Ok(false) => Err(ErrorRep::new(
+        ExtErrorCode::NotFound as i32,
+        format!("Routes not found: service_id: {}", service_id),
+      )),
+      Err(err) => {
+        log::error!("Failed to remove routes: service_id: {:?}, err: {:?}", service_id, err);
+
+        Err(ErrorRep::new(
+          ErrorCode::MasterError as i32,
+          format!("Failed to remove routes: service_id: {}, err: {}", service_id, err),
+        ))
+      }
+    }
+  }
+
+  pub fn export_routes(&self) -> ExportRoutesRep {
+    log::info!("Exporting routes: from: {:?}", self.peer_addr);
+
+    build_export_routes_rep()
+  }
+
+  // Like import_topics, everything is validated before anything is imported,
+  // the routes of the services not in the document are left untouched.
+  pub fn import_routes(&self, req: ImportRoutesReq) -> Result<ImportRoutesRep, ErrorRep> {
+    log::info!("Importing routes: from: {:?}, count: {:?}", self.peer_addr, req.routes.len());
+
+    let tenants = tenant_names();
+    let mut bundles = Vec::with_capacity(req.routes.len());
+    for routes in &req.routes {
+      match Self::validate_exported_routes(routes, &tenants) {
+        Ok(pb) => bundles.push(pb),
+        Err(desc) => {
+          log::error!("Failed to import routes: {:?}, err: {:?}", routes.service_id, desc);
+
+          return Err(
+            ErrorRep::new(ExtErrorCode::InvalidRoutes as i32, desc)
+              .with_details(json!({ "imported_count": 0 })),
+          );
+        }
+      }
+    }
+    if let Some(owner) = req
+      .owners
+      .iter()
+      .find(|owner| !METHODS.contains(&owner.method.as_str()) || !tenants.contains(&owner.tenant))
+    {
+      return Err(
+        ErrorRep::new(
+          ExtErrorCode::InvalidRoutes as i32,
+          format!(
+            "Unknown method or tenant of owner: {} {}, tenant: {:?}",
+            owner.method, owner.path, owner.tenant
+          ),
+        )
+        .with_details(json!({ "imported_count": 0 })),
+      );
+    }
+
+    let mut imported_count = 0;
+    for (routes, pb) in req.routes.into_iter().zip(bundles) {
+      let result = ROUTE_MGR
+        .set_tenant(&routes.service_id, &routes.tenant)
+        .and_then(|_| ROUTE_MGR.set_weight(&routes.service_id, routes.weight))
+        .and_then(|_| ROUTE_MGR.set_reverse_route_group(routes.service_id.clone(), pb));
+      if let Err(err) = result {
+        log::error!("Failed to import routes: {:?}, err: {:?}", routes.service_id, err);
+
+        return Err(
+          ErrorRep::new(
+            ErrorCode::MasterError as i32,
+            format!("Failed to import routes: service_id: {}, err: {}", routes.service_id, err),
+          )
+          .with_details(json!({ "imported_count": imported_count })),
+        );
+      }
+      imported_count += 1;
+    }
+    for owner in req.owners {
+      if let Err(err) = ROUTE_MGR.set_owner(&owner.tenant, &owner.method, &owner.path, &owner.owner)
+      {
+        log::error!("Failed to import route owner: {:?}, err: {:?}", owner, err);
+
+        return Err(
+          ErrorRep::new(
+            ErrorCode::MasterError as i32,
+            format!("Failed to import route owner: {} {}, err: {}", owner.method, owner.path, err),
+          )
+          .with_details(json!({ "imported_count": imported_count })),
+        );
+      }
+    }
+    Ok(ImportRoutesRep { code: ErrorCode::Ok as i32, desc: None, imported_count })
+  }
+
+  fn validate_exported_routes(
+    routes: &ExportedRoutes, tenants: &[String],
+  ) -> Result<PathBundle, String> {
+    if SERVICE_MGR.get(&routes.service_id).is_none() {
+      return Err(format!("Service not registered: id: {}", routes.service_id));
+    }
+    if !tenants.contains(&routes.tenant) {
+      return Err(format!(
+        "Tenant not found in config: {}, service_id: {}",
+        routes.tenant, routes.service_id
+      ));
+    }
+    let mut pb = PathBundle::default();
+    for (method, paths) in &routes.paths {
+      match pb.path_set_mut(method) {
+        Some(path_set) => path_set.extend(paths.iter().cloned()),
+        None => {
+          return Err(format!("Unknown method: {}, service_id: {}", method, routes.service_id))
+        }
+      }
+    }
+    pb.normalize().map_err(|err| format!("{}, service_id: {}", err, routes.service_id))
+  }
+
+  #[inline]
+  pub fn get_route_health(
+    &self, list: &ListQuery, req: GetRouteHealthReq,
+  ) -> Result<GetRouteHealthRep, ErrorRep> {
+    let mut routes = ROUTE_MGR.health();
+    let down_count = routes.iter().filter(|route| route.down_since.is_some()).count();
+    routes.retain(|route| {
+      (!req.down_only || route.down_since.is_some())
+        && req.tenant.as_ref().is_none_or(|tenant| &route.tenant == tenant)
+        && req.path_prefix.as_ref().is_none_or(|prefix| route.path.starts_with(prefix.as_str()))
+    });
+    let (routes, page) = list.apply(routes)?;
+    Ok(GetRouteHealthRep { code: ErrorCode::Ok as i32, desc: None, down_count, routes, page })
+  }
+
+  #[inline]
+  pub fn get_connections(
+    &self, list: &ListQuery, req: ListConnectionsReq,
+  ) -> Result<GetConnectionsRep, ErrorRep> {
+    let connections = CONNECTION_REGISTRY
+      .connections()
+      .into_iter()
+      .map(|connection| {
+        let node = connection.node();
+        ConnectionInfo {
+          id: connection.id,
+          peer_addr: connection.peer_addr.to_string(),
+          node_type: node.as_ref().map(|(node_type, _)| node_type.as_str()),
+          node_id: node.map(|(_, node_id)| node_id),
+          connected_at: connection.connected_at,
+          received_binary_count: connection.received_binary_count(),
+          received_text_count: connection.received_text_count(),
+        }
+      })
+      .filter(|connection: &ConnectionInfo| {
+        req
+          .node_type
+          .as_deref()
+          .is_none_or(|node_type| connection.node_type.unwrap_or("unknown") == node_type)
+      })
+      .collect();
+    let (connections, page) = list.apply(connections)?;
+    Ok(GetConnectionsRep { code: ErrorCode::Ok as i32, desc: None, connections, page })
+  }
+
+  pub fn get_nodes(&self, list: &ListQuery, req: ListNodesReq) -> Result<GetNodesRep, ErrorRep> {
+    let is_listed = |node_type: &str| req.node_type.as_deref().is_none_or(|t| t == node_type);
+    let mut nodes = vec![];
+    if is_listed("frontend") {
+      nodes.extend(FRONTEND_MGR.iter().map(|frontend| NodeInfo {
+        node_type: "frontend",
+        id: frontend.id.clone(),
+        endpoint: format!("{}:{}", frontend.private_ip, frontend.http_port),
+        active_at: frontend.active_at(),
+        is_healthy: frontend.is_healthy(),
+      }));
+    }
+    if is_listed("backend") {
+      nodes.extend(BACKEND_MGR.iter().map(|backend| NodeInfo {
+        node_type: "backend",
+        id: backend.id.clone(),
+        endpoint: backend.private_endpoint(),
+        active_at: backend.active_at(),
+        is_healthy: backend.is_healthy(),
+      }));
+    }
+    if is_listed("service") {
+      nodes.extend(SERVICE_MGR.iter().map(|service| NodeInfo {
+        node_type: "service",
+        id: service.id.clone(),
+        endpoint: service.private_endpoint(),
+        active_at: service.active_at,
+        is_healthy: service.is_healthy(),
+      }));
+    }
+    nodes.retain(|node| req.healthy.is_none_or(|healthy| node.is_healthy == healthy));
+    nodes.sort_by(|a, b| a.node_type.cmp(b.node_type).then_with(|| a.id.cmp(&b.id)));
+    let (nodes, page) = list.apply(nodes)?;
+    Ok(GetNodesRep { code: ErrorCode::Ok as i32, desc: None, nodes, page })
+  }
+
+  // Sorted by topic, as scanned from the db, up to the limit only
+  pub fn get_topics(&self, req: ListTopicsReq) -> GetTopicsRep {
+    let limit = req.limit.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE);
+    let prefix = req.prefix.unwrap_or_default();
+    // The topics having the prefix follow it in the db
+    let from = match req.cursor {
+      Some(cursor) if cursor > prefix => cursor,
+      _ => prefix.clone(),
+    };
+    let mut topics = Vec::with_capacity(limit);
+    let mut next_cursor = None;
+    TOPIC_MGR.scan(Some(&from), &mut |topic, backend_id| {
+      if !topic.starts_with(prefix.as_str()) {
+        return false;
+      }
+      if req.backend_id.as_ref().is_some_and(|id| &backend_id != id) {
+        return true;
+      }
+      if topics.len() == limit {
+        next_cursor = Some(topic);
+        return false;
+      }
+      topics.push(TopicInfo { topic, backend_id });
+      true
+    });
+    GetTopicsRep { code: ErrorCode::Ok as i32, desc: None, topics, next_cursor }
+  }
+
+  #[inline]
+  pub fn close_connection(&self, id: u32) -> Result<AdminRep, ErrorRep> {
+    log::info!("Closing connection: from: {:?}, id: {:?}", self.peer_addr, id);
+
+    if CONNECTION_REGISTRY.close(id) {
+      Ok(AdminRep::ok())
+    } else {
+      Err(ErrorRep::new(ExtErrorCode::NotFound as i32, format!("Connection not found: id: {}", id)))
+    }
+  }
+
+  #[inline]
+  pub fn get_audit_records(&self, req: GetAuditRecordsReq) -> GetAuditRecordsRep {
+    let limit = req.limit.unwrap_or(DEFAULT_AUDIT_LIMIT).clamp(1, MAX_AUDIT_LIMIT);
+    let (records, truncated) =
+      AUDIT_LOG.query(req.since, req.until, req.actor.as_deref(), req.action.as_deref(), limit);
+    GetAuditRecordsRep { code: ErrorCode::Ok as i32, desc: None, records, truncated }
+  }
+
+  // Returns the profile in the format asked for, pprof by default
+  pub async fn get_cpu_profile(
+    &self, req: GetCpuProfileReq,
+  ) -> Result<(Vec<u8>, ProfileFormat), ErrorRep> {
+    log::info!("Getting cpu profile: from: {:?}, req: {:?}", self.peer_addr, req);
+
+    let format = req.format.unwrap_or(ProfileFormat::Pprof);
+    let profile =
+      profiling::profile_cpu(req.seconds.unwrap_or(DEFAULT_CPU_PROFILE_SECONDS), format).await?;
+    Ok((profile, format))
+  }
+
+  // The kinds of the events to stream, which the server streams itself
+  pub fn get_events(&self, req: GetEventsReq) -> Result<Vec<EventKind>, ErrorRep> {
+    let Some(kinds) = req.kinds.filter(|kinds| !kinds.is_empty()) else {
+      return Ok(vec![]);
+    };
+    kinds
+      .split(',')
+      .map(|name| {
+        EventKind::from_name(name.trim()).ok_or_else(|| {
+          ErrorRep::new(
+            ExtErrorCode::InvalidQuery as i32,
+            format!("Unknown event kind: {:?}", name),
+          )
+        })
+      })
+      .collect()
+  }
+
+  // Json by default, as the other admin endpoints
+  pub fn get_topology(&self, req: GetTopologyReq) -> Result<(String, TopologyFormat), ErrorRep> {
+    let format = req.format.unwrap_or(TopologyFormat::Json);
+    let topology = Topology::current();
+    let body = match format {
+      TopologyFormat::Json => serde_json::to_string(&topology).map_err(|err| {
+        ErrorRep::new(ErrorCode::MasterError as i32, format!("Failed to encode topology: {}", err))
+      })?,
+      TopologyFormat::Dot => topology.to_dot(),
+    };
+    Ok((body, format))
+  }
+
+  #[inline]
+  pub fn get_heap_stats(&self) -> Result<GetHeapStatsRep, ErrorRep> {
+    let stats = profiling::heap_stats()?;
+    Ok(GetHeapStatsRep { code: ErrorCode::Ok as i32, desc: None, stats })
+  }
+
+  #[inline]
+  pub fn get_settings(&self) -> GetSettingsRep {
+    GetSettingsRep {
+      code: ErrorCode::Ok as i32,
+      desc: None,
+      settings: CONFIG_MGR.settings(),
+      tunable: TUNABLE_SETTINGS,
+    }
+  }
+
+  #[inline]
+  pub fn set_setting(&self, req: SetSettingReq) -> Result<AdminRep, ErrorRep> {
+    log::info!("Setting: from: {:?}, req: {:?}", self.peer_addr, req);
+
+    let value = match req.value {
+      Value::String(value) => value,
+      Value::Number(_) | Value::Bool(_) => req.value.to_string(),
+      _ => {
+        return Err(ErrorRep::new(
+          ExtErrorCode::InvalidQuery as i32,
+          format!("The value must be a number, a bool or a string: key: {}", req.key),
+        ))
+      }
+    };
+    CONFIG_MGR.set(req.key, value)?;
+    Ok(AdminRep::ok())
+  }
+
+  #[inline]
+  pub fn remove_setting(&self, req: RemoveSettingReq) -> Result<AdminRep, ErrorRep> {
+    log::info!("Removing setting: from: {:?}, req: {:?}", self.peer_addr, req);
+
+    if CONFIG_MGR.remove(&req.key)? {
+      Ok(AdminRep::ok())
+    } else {
+      Err(ErrorRep::new(
+        ExtErrorCode::NotFound as i32,
+        format!("The setting was not set: {}", req.key),
+      ))
+    }
+  }
+
+  #[inline]
+  pub fn get_standby(&self) -> StandbyRep {
+    StandbyRep { code: ErrorCode::Ok as i32, desc: None, status: standby::status() }
+  }
+
+  // Called by the operator, or by a script checking the health of the primary
+  pub fn promote(&self) -> Result<StandbyRep, ErrorRep> {
+    log::info!("Promoting: from: {:?}", self.peer_addr);
+
+    if !standby::promote() {
+      return Err(ErrorRep::new(
+        ExtErrorCode::NotStandby as i32,
+        "This master is not a standby".to_owned(),
+      ));
+    }
+    Ok(self.get_standby())
+  }
+
+  pub fn backup(&self, req: BackupReq) -> Result<BackupRep, ErrorRep> {
+    log::info!("Backing up: from: {:?}, req: {:?}", self.peer_addr, req);
+
+    let dir = req.dir.unwrap_or_else(|| CONFIG.load().db.backup.dir.clone());
+    match backup::create_file(Path::new(&dir)) {
+      Ok(backup) => Ok(BackupRep { code: ErrorCode::Ok as i32, desc: None, backup }),
+      Err(err) => {
+        log::error!("Failed to back up: dir: {:?}, err: {:?}", dir, err);
+        Err(ErrorRep::new(
+          ErrorCode::MasterError as i32,
+          format!("Failed to back up: dir: {}, err: {}", dir, err),
+        ))
+      }
+    }
+  }
+
+  // The same as backup, but streamed to the caller instead of written
+  pub fn download_backup(&self) -> Result<(Vec<u8>, BackupInfo), ErrorRep> {
+    log::info!("Downloading backup: from: {:?}", self.peer_addr);
+
+    backup::create().map_err(|err| {
+      log::error!("Failed to back up: err: {:?}", err);
+      ErrorRep::new(ErrorCode::MasterError as i32, format!("Failed to back up: err: {}", err))
+    })
+  }
+
+  // Scans every table, so it is not for the metrics
+  pub fn get_db_stats(&self) -> GetDbStatsRep {
+    GetDbStatsRep {
+      code: ErrorCode::Ok as i32,
+      desc: None,
+      db: db::stats(),
+      tables: store::table_stats().into_iter().collect(),
+    }
+  }
+
+  // Blocks a thread of the db pool until done
+  pub fn compact_db(&self) -> Result<GetDbStatsRep, ErrorRep> {
+    log::info!("Compacting db: from: {:?}", self.peer_addr);
+
+    match db::compact() {
+      Ok(()) => Ok(self.get_db_stats()),
+      Err(err) => {
+        log::error!("Failed to compact db: err: {:?}", err);
+        Err(ErrorRep::new(
+          ErrorCode::MasterError as i32,
+          format!("Failed to compact db: err: {}", err),
+        ))
+      }
+    }
+  }
+
+  #[inline]
+  pub fn reload_config(&self) -> Result<ReloadConfigRep, ErrorRep> {
+    log::info!("Reloading config: from: {:?}", self.peer_addr);
+
+    match hot_reload::reload() {
+      Ok(report) => Ok(ReloadConfigRep {
+        code: ErrorCode::Ok as i32,
+        desc: None,
+        ignored: report.ignored,
+        backends_changed: report.backends_changed,
+      }),
+      Err(err) => Err(ErrorRep::new(
+        ErrorCode::MasterError as i32,
+        format!("Failed to reload config: {:?}", err),
+      )),
+    }
+  }
+}
+
+impl From<ProfilingError> for ErrorRep {
+  #[inline]
+  fn from(err: ProfilingError) -> Self {
+    let code = match err {
+      // As if the endpoints did not exist
+      ProfilingError::Disabled => ExtErrorCode::NotFound as i32,
+      ProfilingError::InvalidDuration { .. } => ExtErrorCode::InvalidQuery as i32,
+      ProfilingError::AlreadyRunning => ExtErrorCode::Busy as i32,
+      ProfilingError::Unsupported | ProfilingError::Failed(_) => ErrorCode::MasterError as i32,
+    };
+    ErrorRep::new(code, format!("{}", err))
+  }
+}
+
+impl From<SettingError> for ErrorRep {
+  #[inline]
+  fn from(err: SettingError) -> Self {
+    let code = match err {
+      SettingError::Untunable { .. } | SettingError::InvalidValue { .. } => {
+        ExtErrorCode::InvalidQuery as i32
+      }
+      SettingError::Failed(_) => ErrorCode::MasterError as i32,
+    };
+    ErrorRep::new(code, format!("{}", err))
+  }
+}
+
+// Shared with the dump-topics command
+pub(crate) fn build_export_topics_rep(assignments: Vec<(Topic, NodeId)>) -> ExportTopicsRep {
+  let topics = assignments
+    .into_iter()
+    .map(|(topic, backend_id)| TopicAssignment { topic, backend_id })
+    .collect();
+  ExportTopicsRep { code: ErrorCode::Ok as i32, desc: None, topics }
+}
+
+// Shared with the dump-routes command
+pub(crate) fn build_export_routes_rep() -> ExportRoutesRep {
+  let mut routes: Vec<ExportedRoutes> = ROUTE_MGR
+    .reverse_route_group_iter()
+    .map(|reverse_route_group| {
+      let service_id = reverse_route_group.key();
+      let mut paths = BTreeMap::new();
+      for (method, path_set) in reverse_route_group.value().path_sets() {
+        if !path_set.is_empty() {
+          paths.insert(method.to_owned(), path_set.iter().cloned().collect());
+        }
+      }
+      ExportedRoutes {
+        service_id: service_id.clone(),
+        tenant: ROUTE_MGR.tenant_of(service_id),
+        weight: ROUTE_MGR.weight(service_id),
+        paths,
+      }
+    })
+    .collect();
+  routes.sort_by(|a, b| a.service_id.cmp(&b.service_id));
+  let owners = ROUTE_MGR
+    .owners()
+    .into_iter()
+    .map(|(tenant, method, path, owner)| RouteOwner { tenant, method, path, owner })
+    .collect();
+  ExportRoutesRep { code: ErrorCode::Ok as i32, desc: None, routes, owners }
+}
@@ -0,0 +1,66 @@
+use std::any::Any;
+
+use actix_tls::accept::rustls_0_23::TlsStream;
+use actix_web::{dev::Extensions, rt::net::TcpStream, HttpRequest};
+use x509_parser::prelude::*;
+
+use crate::{
+  config::{ClientNodeType, CONFIG},
+  node_mgr::NodeType,
+};
+
+// The common name of the verified certificate presented by the client
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientIdentity(pub String);
+
+// Records the identity of the client certificate, if any, as connection data,
+// rustls has already verified the certificate against the ca bundle by then
+pub fn on_connect(conn: &dyn Any, ext: &mut Extensions) {
+  let Some(tls_stream) = conn.downcast_ref::<TlsStream<TcpStream>>() else {
+    return;
+  };
+  let (_, session) = tls_stream.get_ref();
+  let Some(cert) = session.peer_certificates().and_then(|certs| certs.first()) else {
+    return;
+  };
+  match parse_x509_certificate(cert.as_ref()) {
+    Ok((_, cert)) => {
+      if let Some(common_name) =
+        cert.subject().iter_common_name().next().and_then(|common_name| common_name.as_str().ok())
+      {
+        ext.insert(ClientIdentity(common_name.to_owned()));
+      }
+    }
+    Err(err) => log::warn!("Failed to parse client certificate: err: {:?}", err),
+  }
+}
+
+#[inline]
+pub fn client_identity_of(req: &HttpRequest) -> Option<ClientIdentity> {
+  req.conn_data::<ClientIdentity>().cloned()
+}
+
+// Without require_client_cert anyone may register, otherwise only the nodes
+// granted to the identity in client_identities
+pub fn is_allowed_to_register(
+  identity: Option<&ClientIdentity>, node_type: NodeType, node_id: &str,
+) -> bool {
+  if !CONFIG.load().server.require_client_cert {
+    return true;
+  }
+  let Some(identity) = identity else {
+    return false;
+  };
+  CONFIG.load().server.client_identities.iter().any(|config| {
+    config.common_name == identity.0
+      && config.node_types.iter().any(|client_node_type| {
+        matches!(
+          (client_node_type, node_type),
+          (ClientNodeType::Frontend, NodeType::Frontend)
+            | (ClientNodeType::Backend, NodeType::Backend)
+            | (ClientNodeType::Service, NodeType::Service)
+        )
+      })
+      && (config.node_ids.is_empty() || config.node_ids.iter().any(|id| id == node_id))
+  })
+}
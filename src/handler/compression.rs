@@ -0,0 +1,70 @@
+use actix_web::{
+  body::{BodySize, MessageBody},
+  dev::{ServiceRequest, ServiceResponse},
+  http::header::{self, HeaderValue},
+};
+
+use crate::config::CONFIG;
+
+// Leaves only the configured encodings in the accept-encoding header, before
+// the Compress middleware negotiates one
+pub fn filter_accept_encoding(req: &mut ServiceRequest) {
+  let config = CONFIG.load();
+  let compression = &config.server.compression;
+  if !compression.enabled || compression.encodings.is_empty() {
+    return;
+  }
+  let Some(accept_encoding) =
+    req.headers().get(header::ACCEPT_ENCODING).and_then(|value| value.to_str().ok())
+  else {
+    return;
+  };
+  let filtered = filter_encodings(accept_encoding, |encoding| compression.allows(encoding));
+  match HeaderValue::from_str(&filtered) {
+    Ok(value) if !filtered.is_empty() => {
+      req.headers_mut().insert(header::ACCEPT_ENCODING, value);
+    }
+    _ => {
+      req.headers_mut().remove(header::ACCEPT_ENCODING);
+    }
+  }
+}
+
+// Marks the small bodies as identity encoded, which the Compress middleware
+// then leaves alone, as compressing them costs more than it saves
+pub fn skip_small_body<B: MessageBody>(res: &mut ServiceResponse<B>) {
+  if res.headers().contains_key(header::CONTENT_ENCODING) {
+    return;
+  }
+  if let BodySize::Sized(size) = res.response().body().size() {
+    if size < CONFIG.load().server.compression.min_size as u64 {
+      res.headers_mut().insert(header::CONTENT_ENCODING, HeaderValue::from_static("identity"));
+    }
+  }
+}
+
+// Keeps identity and the allowed codings, with their q values
+fn filter_encodings<F: Fn(&str) -> bool>(accept_encoding: &str, allows: F) -> String {
+  accept_encoding
+    .split(',')
+    .map(str::trim)
+    .filter(|item| {
+      let coding = item.split(';').next().unwrap_or("").trim();
+      coding.eq_ignore_ascii_case("identity") || allows(coding)
+    })
+    .collect::<Vec<_>>()
+    .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_filter_encodings() {
+    let allows = |coding: &str| coding == "gzip";
+    assert_eq!(filter_encodings("br, gzip;q=0.8, identity", allows), "gzip;q=0.8, identity");
+    assert_eq!(filter_encodings("br, zstd", allows), "");
+    assert_eq!(filter_encodings("*", allows), "");
+  }
+}
@@ -0,0 +1,132 @@
+use std::{
+  net::SocketAddr,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+  },
+};
+
+use actix::{Addr, Message};
+use ahash::RandomState as AHasher;
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+
+use super::ws_handler::Handler;
+use crate::{
+  clock,
+  node_mgr::{NodeId, NodeType},
+};
+
+// What is known about a live ws connection, shared by its handler and the registry
+#[derive(Debug)]
+pub struct Connection {
+  pub id: u32,
+  pub peer_addr: SocketAddr,
+  pub connected_at: u32,
+  node: Mutex<Option<(NodeType, NodeId)>>,
+  received_binary_count: AtomicU64,
+  received_text_count: AtomicU64,
+}
+
+impl Connection {
+  #[inline]
+  pub fn new(id: u32, peer_addr: SocketAddr) -> Self {
+    Connection {
+      id,
+      peer_addr,
+      connected_at: clock::now(),
+      node: Mutex::new(None),
+      received_binary_count: AtomicU64::new(0),
+      received_text_count: AtomicU64::new(0),
+    }
+  }
+
+  #[inline]
+  pub fn set_node(&self, node_type: NodeType, node_id: NodeId) {
+    *self.node.lock().unwrap() = Some((node_type, node_id));
+  }
+
+  #[inline]
+  pub fn node(&self) -> Option<(NodeType, NodeId)> {
+    self.node.lock().unwrap().clone()
+  }
+
+  #[inline]
+  pub fn on_binary_received(&self) {
+    self.received_binary_count.fetch_add(1, Ordering::Relaxed);
+  }
+
+  #[inline]
+  pub fn on_text_received(&self) {
+    self.received_text_count.fetch_add(1, Ordering::Relaxed);
+  }
+
+  #[inline]
+  pub fn received_binary_count(&self) -> u64 {
+    self.received_binary_count.load(Ordering::Relaxed)
+  }
+
+  #[inline]
+  pub fn received_text_count(&self) -> u64 {
+    self.received_text_count.load(Ordering::Relaxed)
+  }
+}
+
+// Asks the handler to close its connection
+#[derive(Debug, Message)]
+#[rtype(result = "()")]
+pub struct CloseConnection;
+
+struct Entry {
+  connection: Arc<Connection>,
+  addr: Addr<Handler>,
+}
+
+// All the live ws connections of this master, registered by the handler actors
+// once started and unregistered once stopped
+pub struct ConnectionRegistry {
+  entries: DashMap<u32, Entry, AHasher>,
+}
+
+impl ConnectionRegistry {
+  #[inline]
+  fn new() -> Self {
+    ConnectionRegistry { entries: DashMap::with_capacity_and_hasher(1024, AHasher::default()) }
+  }
+
+  #[inline]
+  pub fn register(&self, connection: Arc<Connection>, addr: Addr<Handler>) {
+    self.entries.insert(connection.id, Entry { connection, addr });
+  }
+
+  #[inline]
+  pub fn unregister(&self, id: u32) {
+    self.entries.remove(&id);
+  }
+
+  #[inline]
+  pub fn count(&self) -> usize {
+    self.entries.len()
+  }
+
+  // Sorted by id, i.e. the oldest connection first
+  pub fn connections(&self) -> Vec<Arc<Connection>> {
+    let mut connections: Vec<Arc<Connection>> =
+      self.entries.iter().map(|entry| entry.connection.clone()).collect();
+    connections.sort_by_key(|connection| connection.id);
+    connections
+  }
+
+  // Returns false if there is no such connection
+  pub fn close(&self, id: u32) -> bool {
+    match self.entries.get(&id) {
+      Some(entry) => {
+        entry.addr.do_send(CloseConnection);
+        true
+      }
+      None => false,
+    }
+  }
+}
+
+pub static CONNECTION_REGISTRY: Lazy<ConnectionRegistry> = Lazy::new(ConnectionRegistry::new);
@@ -0,0 +1,138 @@
+use std::fmt;
+
+use actix_web::{
+  http::{header::ContentType, StatusCode},
+  HttpResponse, ResponseError,
+};
+use maxwell_protocol::ErrorCode;
+use serde_json::Value;
+
+use crate::{error_code::ExtErrorCode, trace::current_trace_id};
+
+// The envelope of every http error, carrying the same codes as the ws ErrorRep
+// so that clients can handle both alike
+#[derive(Debug, Serialize)]
+pub struct ErrorRep {
+  code: i32,
+  desc: String,
+  // Whatever else helps, e.g. how much of a batch was done before failing
+  #[serde(skip_serializing_if = "Option::is_none")]
+  details: Option<Value>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  trace_id: Option<String>,
+}
+
+impl ErrorRep {
+  // Tagged with the trace id of the req being handled
+  #[inline]
+  pub fn new(code: i32, desc: String) -> Self {
+    ErrorRep { code, desc, details: None, trace_id: current_trace_id() }
+  }
+
+  #[inline]
+  pub fn with_details(mut self, details: Value) -> Self {
+    self.details = Some(details);
+    self
+  }
+
+  #[inline]
+  pub fn code(&self) -> i32 {
+    self.code
+  }
+
+  #[inline]
+  pub fn desc(&self) -> &str {
+    &self.desc
+  }
+
+  #[inline]
+  pub fn to_response(&self) -> HttpResponse {
+    HttpResponse::build(status_of(self.code))
+      .content_type(ContentType::json())
+      .force_close()
+      .json(self)
+  }
+}
+
+impl fmt::Display for ErrorRep {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{} (code: {})", self.desc, self.code)
+  }
+}
+
+// So that extractors and handlers can fail with it as an actix error
+impl ResponseError for ErrorRep {
+  #[inline]
+  fn status_code(&self) -> StatusCode {
+    status_of(self.code)
+  }
+
+  #[inline]
+  fn error_response(&self) -> HttpResponse {
+    self.to_response()
+  }
+}
+
+// The one place the codes are mapped to http statuses
+pub fn status_of(code: i32) -> StatusCode {
+  const OK: i32 = ErrorCode::Ok as i32;
+  const NOT_ALLOWED_TO_REGISTER_FRONTEND: i32 = ErrorCode::NotAllowedToRegisterFrontend as i32;
+  const NOT_ALLOWED_TO_REGISTER_BACKEND: i32 = ErrorCode::NotAllowedToRegisterBackend as i32;
+  const FAILED_TO_PICK_FRONTEND: i32 = ErrorCode::FailedToPickFrontend as i32;
+  const FAILED_TO_LOCATE_TOPIC: i32 = ErrorCode::FailedToLocateTopic as i32;
+  const UNKNOWN_MSG: i32 = ErrorCode::UnknownMsg as i32;
+  const TOPIC_QUOTA_EXCEEDED: i32 = ExtErrorCode::TopicQuotaExceeded as i32;
+  const ROUTE_CONFLICT: i32 = ExtErrorCode::RouteConflict as i32;
+  const INVALID_ROUTES: i32 = ExtErrorCode::InvalidRoutes as i32;
+  const CLIENT_CERT_REJECTED: i32 = ExtErrorCode::ClientCertRejected as i32;
+  const RATE_LIMITED: i32 = ExtErrorCode::RateLimited as i32;
+  const UNSUPPORTED_VERSION: i32 = ExtErrorCode::UnsupportedVersion as i32;
+  const DECODE_ERROR: i32 = ExtErrorCode::DecodeError as i32;
+  const BUSY: i32 = ExtErrorCode::Busy as i32;
+  const NOT_PEER: i32 = ExtErrorCode::NotPeer as i32;
+  const ADMIN_AUTH_FAILED: i32 = ExtErrorCode::AdminAuthFailed as i32;
+  const INVALID_QUERY: i32 = ExtErrorCode::InvalidQuery as i32;
+  const NOT_FOUND: i32 = ExtErrorCode::NotFound as i32;
+  const UNKNOWN_CREDENTIALS: i32 = ExtErrorCode::UnknownCredentials as i32;
+  const ADMIN_FORBIDDEN: i32 = ExtErrorCode::AdminForbidden as i32;
+  const NOT_STANDBY: i32 = ExtErrorCode::NotStandby as i32;
+  const NOT_LEADER: i32 = ExtErrorCode::NotLeader as i32;
+  const MAINTENANCE: i32 = ExtErrorCode::Maintenance as i32;
+  const NOT_COMMITTED: i32 = ExtErrorCode::NotCommitted as i32;
+
+  match code {
+    OK => StatusCode::OK,
+    INVALID_QUERY | INVALID_ROUTES | DECODE_ERROR | UNSUPPORTED_VERSION | UNKNOWN_MSG => {
+      StatusCode::BAD_REQUEST
+    }
+    ADMIN_AUTH_FAILED | UNKNOWN_CREDENTIALS => StatusCode::UNAUTHORIZED,
+    ADMIN_FORBIDDEN
+    | CLIENT_CERT_REJECTED
+    | NOT_PEER
+    | NOT_ALLOWED_TO_REGISTER_FRONTEND
+    | NOT_ALLOWED_TO_REGISTER_BACKEND => StatusCode::FORBIDDEN,
+    NOT_FOUND => StatusCode::NOT_FOUND,
+    NOT_LEADER => StatusCode::MISDIRECTED_REQUEST,
+    ROUTE_CONFLICT | NOT_STANDBY => StatusCode::CONFLICT,
+    TOPIC_QUOTA_EXCEEDED | RATE_LIMITED => StatusCode::TOO_MANY_REQUESTS,
+    BUSY | MAINTENANCE | NOT_COMMITTED | FAILED_TO_PICK_FRONTEND | FAILED_TO_LOCATE_TOPIC => {
+      StatusCode::SERVICE_UNAVAILABLE
+    }
+    _ => StatusCode::INTERNAL_SERVER_ERROR,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_status_of() {
+    assert_eq!(status_of(ErrorCode::Ok as i32), StatusCode::OK);
+    assert_eq!(status_of(ExtErrorCode::NotFound as i32), StatusCode::NOT_FOUND);
+    assert_eq!(status_of(ExtErrorCode::RouteConflict as i32), StatusCode::CONFLICT);
+    assert_eq!(status_of(ExtErrorCode::Maintenance as i32), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(status_of(ExtErrorCode::NotCommitted as i32), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(status_of(ErrorCode::MasterError as i32), StatusCode::INTERNAL_SERVER_ERROR);
+  }
+}
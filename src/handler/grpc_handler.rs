@@ -0,0 +1,275 @@
+use std::net::SocketAddr;
+
+use actix_web::http::StatusCode;
+use maxwell_protocol::{self, ErrorCode};
+use serde_json::json;
+use tonic::{metadata::MetadataMap, Code, Request, Response, Status};
+
+use super::{
+  client_cert::is_allowed_to_register,
+  error_rep::{status_of, ErrorRep},
+  http_handler::{
+    locate_topic_error_code, HttpHandler, PickFrontendReq, RegisterServiceReq, SetRoutesReq,
+  },
+};
+use crate::{
+  audit,
+  db_pool::DB_POOL,
+  error_code::ExtErrorCode,
+  node_mgr::{NodeType, BACKEND_MGR, FRONTEND_MGR},
+  route_mgr::{resolve_tenant, ROUTE_MGR},
+  topic_mgr::TOPIC_MGR,
+};
+
+mod generated {
+  include!(concat!(env!("OUT_DIR"), "/maxwell.master.Master.rs"));
+}
+
+pub use generated::master_server::{Master, MasterServer as MasterGrpcServer};
+
+// The service setting its routes, as no connection is registered as it
+pub const SERVICE_ID_KEY: &str = "x-service-id";
+// The code of the ErrorRep, finer than the status code of grpc
+pub const ERROR_CODE_KEY: &str = "x-error-code";
+// "true" for the domain:https_port form of the frontend picked
+pub const SECURE_KEY: &str = "x-secure";
+
+// The reqs of the protocol as unary calls, for the clients which have grpc
+// stubs rather than the ws framing. Without a connection, a node registering
+// again is what keeps it active, and the tenant is decided by the bearer token
+// in the authorization metadata, as over http.
+#[derive(Debug, Default)]
+pub struct GrpcHandler;
+
+#[tonic::async_trait]
+impl Master for GrpcHandler {
+  async fn pick_frontend(
+    &self, req: Request<maxwell_protocol::PickFrontendReq>,
+  ) -> Result<Response<maxwell_protocol::PickFrontendRep>, Status> {
+    // Plaintext, the clients wanting the https endpoints ask for it
+    let secure = metadata_str(req.metadata(), SECURE_KEY).map(|value| value == "true");
+    let rep = HttpHandler::of_peer(req.remote_addr())
+      .pick_frontend(PickFrontendReq::new(secure))
+      .map_err(to_status)?;
+    Ok(Response::new(maxwell_protocol::PickFrontendRep {
+      endpoint: rep.endpoint().to_owned(),
+      r#ref: req.get_ref().r#ref,
+    }))
+  }
+
+  async fn get_routes(
+    &self, req: Request<maxwell_protocol::GetRoutesReq>,
+  ) -> Result<Response<maxwell_protocol::GetRoutesRep>, Status> {
+    let table = ROUTE_MGR.snapshot(&tenant_of(req.metadata())?);
+    Ok(Response::new(maxwell_protocol::GetRoutesRep {
+      ws_route_groups: table.proto_route_groups("ws"),
+      get_route_groups: table.proto_route_groups("get"),
+      post_route_groups: table.proto_route_groups("post"),
+      put_route_groups: table.proto_route_groups("put"),
+      patch_route_groups: table.proto_route_groups("patch"),
+      delete_route_groups: table.proto_route_groups("delete"),
+      head_route_groups: table.proto_route_groups("head"),
+      options_route_groups: table.proto_route_groups("options"),
+      trace_route_groups: table.proto_route_groups("trace"),
+      r#ref: req.get_ref().r#ref,
+    }))
+  }
+
+  async fn locate_topic(
+    &self, req: Request<maxwell_protocol::LocateTopicReq>,
+  ) -> Result<Response<maxwell_protocol::LocateTopicRep>, Status> {
+    let client = req.remote_addr().map(|peer_addr| peer_addr.ip());
+    let req = req.into_inner();
+    let result = DB_POOL
+      .run({
+        let topic = req.topic.clone();
+        move || TOPIC_MGR.locate_or_assign(&topic, client)
+      })
+      .await
+      .map_err(|err| to_status(err.into()))?;
+    let backend_id = result.map_err(|err| {
+      log::error!("Failed to locate topic: {:?}, err: {:?}", req.topic, err);
+      to_status(ErrorRep::new(
+        locate_topic_error_code(&err),
+        format!("Failed to locate topic: {}, err: {}", req.topic, err),
+      ))
+    })?;
+    let Some(backend) = BACKEND_MGR.get(&backend_id) else {
+      return Err(to_status(ErrorRep::new(
+        ErrorCode::FailedToLocateTopic as i32,
+        format!("Failed to find the backend: topic: {}, backend_id: {}", req.topic, backend_id),
+      )));
+    };
+    Ok(Response::new(maxwell_protocol::LocateTopicRep {
+      endpoint: backend.private_endpoint(),
+      r#ref: req.r#ref,
+    }))
+  }
+
+  async fn set_routes(
+    &self, req: Request<maxwell_protocol::SetRoutesReq>,
+  ) -> Result<Response<maxwell_protocol::SetRoutesRep>, Status> {
+    let handler = HttpHandler::of_peer(req.remote_addr());
+    let tenant = tenant_of(req.metadata())?;
+    let Some(service_id) = metadata_str(req.metadata(), SERVICE_ID_KEY) else {
+      return Err(Status::invalid_argument(format!("Missing metadata: {}", SERVICE_ID_KEY)));
+    };
+    let service_id = service_id.to_owned();
+    let req = req.into_inner();
+    let r#ref = req.r#ref;
+    run_on_db_pool(move || handler.set_routes(&tenant, SetRoutesReq::from_proto(service_id, req)))
+      .await?;
+    Ok(Response::new(maxwell_protocol::SetRoutesRep { r#ref }))
+  }
+
+  async fn register_frontend(
+    &self, req: Request<maxwell_protocol::RegisterFrontendReq>,
+  ) -> Result<Response<maxwell_protocol::RegisterFrontendRep>, Status> {
+    let peer_addr = req.remote_addr();
+    let req = req.into_inner();
+    check_allowed(NodeType::Frontend, &req.id)?;
+    let http_port = FRONTEND_MGR.get(&req.id).map(|frontend| frontend.http_port);
+    check_registered(NodeType::Frontend, &req.id, http_port, req.http_port)?;
+    FRONTEND_MGR.activate(&req.id);
+    audit::record(
+      actor(NodeType::Frontend, &req.id, peer_addr),
+      "register-frontend",
+      json!({
+        "node_id": req.id
+      }),
+    );
+    Ok(Response::new(maxwell_protocol::RegisterFrontendRep { r#ref: req.r#ref }))
+  }
+
+  async fn register_backend(
+    &self, req: Request<maxwell_protocol::RegisterBackendReq>,
+  ) -> Result<Response<maxwell_protocol::RegisterBackendRep>, Status> {
+    let peer_addr = req.remote_addr();
+    let req = req.into_inner();
+    check_allowed(NodeType::Backend, &req.id)?;
+    let http_port = BACKEND_MGR.get(&req.id).map(|backend| backend.http_port);
+    check_registered(NodeType::Backend, &req.id, http_port, req.http_port)?;
+    BACKEND_MGR.activate(&req.id);
+    audit::record(
+      actor(NodeType::Backend, &req.id, peer_addr),
+      "register-backend",
+      json!({
+        "node_id": req.id
+      }),
+    );
+    Ok(Response::new(maxwell_protocol::RegisterBackendRep { r#ref: req.r#ref }))
+  }
+
+  // The same as over http, the id defaults to ip:http_port
+  async fn register_service(
+    &self, req: Request<maxwell_protocol::RegisterServiceReq>,
+  ) -> Result<Response<maxwell_protocol::RegisterServiceRep>, Status> {
+    let handler = HttpHandler::of_peer(req.remote_addr());
+    let tenant = tenant_of(req.metadata())?;
+    let req = req.into_inner();
+    let r#ref = req.r#ref;
+    run_on_db_pool(move || {
+      handler.register_service(&tenant, RegisterServiceReq::new(req.id, req.http_port))
+    })
+    .await?;
+    Ok(Response::new(maxwell_protocol::RegisterServiceRep { r#ref }))
+  }
+}
+
+async fn run_on_db_pool<T, F>(f: F) -> Result<T, Status>
+where
+  F: FnOnce() -> Result<T, ErrorRep> + Send + 'static,
+  T: Send + 'static, {
+  DB_POOL.run(f).await.map_err(ErrorRep::from).and_then(|result| result).map_err(to_status)
+}
+
+#[inline]
+fn metadata_str<'a>(metadata: &'a MetadataMap, key: &str) -> Option<&'a str> {
+  metadata.get(key).and_then(|value| value.to_str().ok())
+}
+
+#[inline]
+fn tenant_of(metadata: &MetadataMap) -> Result<String, Status> {
+  let token =
+    metadata_str(metadata, "authorization").and_then(|value| value.strip_prefix("Bearer "));
+  resolve_tenant(token).map_err(|err| to_status(err.into()))
+}
+
+// No client certificate is presented over grpc, so nothing may register if one
+// is required
+#[inline]
+fn check_allowed(node_type: NodeType, node_id: &str) -> Result<(), Status> {
+  if is_allowed_to_register(None, node_type, node_id) {
+    return Ok(());
+  }
+  Err(to_status(ErrorRep::new(
+    ExtErrorCode::ClientCertRejected as i32,
+    format!(
+      "Client certificate does not allow registering: node_type: {:?}, node_id: {}",
+      node_type, node_id
+    ),
+  )))
+}
+
+// The frontends and backends are configured, or discovered, not registered
+fn check_registered(
+  node_type: NodeType, node_id: &str, http_port: Option<u32>, req_http_port: u32,
+) -> Result<(), Status> {
+  let code = match node_type {
+    NodeType::Frontend => ErrorCode::NotAllowedToRegisterFrontend,
+    _ => ErrorCode::NotAllowedToRegisterBackend,
+  };
+  let desc = match http_port {
+    Some(http_port) if http_port == req_http_port => return Ok(()),
+    Some(http_port) => format!(
+      "The {} http port does not match: config: {}, request: {}",
+      node_type.as_str(),
+      http_port,
+      req_http_port
+    ),
+    None => format!("The {} is not found in config: id: {}", node_type.as_str(), node_id),
+  };
+  log::error!("Rejected registration: {}", desc);
+  Err(to_status(ErrorRep::new(code as i32, desc)))
+}
+
+#[inline]
+fn actor(node_type: NodeType, node_id: &str, peer_addr: Option<SocketAddr>) -> String {
+  let peer_ip = peer_addr.map_or_else(String::new, |peer_addr| peer_addr.ip().to_string());
+  format!("{}:{}@{}", node_type.as_str(), node_id, peer_ip)
+}
+
+// By the http status the code maps to, the code itself is in the metadata
+fn to_status(err: ErrorRep) -> Status {
+  let code = match status_of(err.code()) {
+    StatusCode::BAD_REQUEST => Code::InvalidArgument,
+    StatusCode::UNAUTHORIZED => Code::Unauthenticated,
+    StatusCode::FORBIDDEN => Code::PermissionDenied,
+    StatusCode::NOT_FOUND => Code::NotFound,
+    StatusCode::CONFLICT | StatusCode::MISDIRECTED_REQUEST => Code::FailedPrecondition,
+    StatusCode::TOO_MANY_REQUESTS => Code::ResourceExhausted,
+    StatusCode::SERVICE_UNAVAILABLE => Code::Unavailable,
+    _ => Code::Internal,
+  };
+  let mut status = Status::new(code, err.desc());
+  status.metadata_mut().insert(ERROR_CODE_KEY, err.code().into());
+  status
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_to_status() {
+    let status = to_status(ErrorRep::new(ExtErrorCode::Maintenance as i32, "Read only".to_owned()));
+    assert_eq!(status.code(), Code::Unavailable);
+    assert_eq!(status.message(), "Read only");
+    assert_eq!(
+      metadata_str(status.metadata(), ERROR_CODE_KEY),
+      Some((ExtErrorCode::Maintenance as i32).to_string().as_str())
+    );
+    let status = to_status(ErrorRep::new(ExtErrorCode::NotLeader as i32, "Not leader".to_owned()));
+    assert_eq!(status.code(), Code::FailedPrecondition);
+  }
+}
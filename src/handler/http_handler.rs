@@ -1,22 +1,211 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
 
-use actix_web::HttpRequest;
+use actix_web::{http::header, HttpRequest};
 use ahash::HashMap;
 use maxwell_protocol::{self, *};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 
+use super::{
+  client_cert::{client_identity_of, is_allowed_to_register, ClientIdentity},
+  error_rep::ErrorRep,
+};
 use crate::{
+  audit,
+  cluster::{self, NotLeader},
+  db_pool::DbPoolError,
+  error_code::ExtErrorCode,
+  health_policy,
+  maintenance::{self, Maintenance},
   node_mgr::*,
-  route_mgr::{PathSet, ROUTE_MGR},
+  raft::NotCommitted,
+  route_mgr::{
+    normalize_rate_limits, resolve_tenant, Path, PathBundle, PathRateLimit, RateLimits,
+    RouteConflict, RouteDelta, SharedRouteGroup, UnknownCredentials, ROUTE_DIST_CHECKSUM,
+    ROUTE_MGR,
+  },
+  snapshot::SnapshotError,
+  topic_mgr::{QuotaExceeded, TOPIC_MGR},
 };
 
+// Pages of the listing endpoints, when not asked for
+pub const DEFAULT_PER_PAGE: usize = 100;
+pub const MAX_PER_PAGE: usize = 1000;
+
+// Shared by the listing endpoints: ?page=2&per_page=50&sort=-active_at, the
+// filters are left to each endpoint
+#[derive(Debug, Default, Deserialize)]
+pub struct ListQuery {
+  // Starts from 1
+  #[serde(default)]
+  page: Option<usize>,
+  #[serde(default)]
+  per_page: Option<usize>,
+  // A field name, descending if prefixed with '-'
+  #[serde(default)]
+  sort: Option<String>,
+}
+
 #[derive(Debug, Serialize)]
-pub struct AssignFrontendRep {
+pub struct PageInfo {
+  page: usize,
+  per_page: usize,
+  // Of all the items matching the filters
+  total: usize,
+}
+
+// Items of the listing endpoints which can be sorted by the fields named
+pub trait Sortable {
+  const SORT_FIELDS: &'static [&'static str];
+
+  // Only called with one of SORT_FIELDS
+  fn cmp_by(&self, other: &Self, field: &str) -> Ordering;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownSortField {
+  pub field: String,
+  pub allowed: &'static [&'static str],
+}
+
+impl fmt::Display for UnknownSortField {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "Unknown sort field: {}, allowed: {}", self.field, self.allowed.join(", "))
+  }
+}
+
+impl std::error::Error for UnknownSortField {}
+
+impl From<UnknownCredentials> for ErrorRep {
+  #[inline]
+  fn from(err: UnknownCredentials) -> Self {
+    ErrorRep::new(ExtErrorCode::UnknownCredentials as i32, format!("{}", err))
+  }
+}
+
+impl From<DbPoolError> for ErrorRep {
+  #[inline]
+  fn from(err: DbPoolError) -> Self {
+    ErrorRep::new(ExtErrorCode::Busy as i32, format!("{}", err))
+  }
+}
+
+impl From<UnknownSortField> for ErrorRep {
+  #[inline]
+  fn from(err: UnknownSortField) -> Self {
+    ErrorRep::new(ExtErrorCode::InvalidQuery as i32, format!("{}", err))
+  }
+}
+
+impl From<SnapshotError> for ErrorRep {
+  #[inline]
+  fn from(err: SnapshotError) -> Self {
+    ErrorRep::new(ExtErrorCode::NotFound as i32, format!("{}", err))
+  }
+}
+
+impl From<Maintenance> for ErrorRep {
+  #[inline]
+  fn from(err: Maintenance) -> Self {
+    log::warn!("Rejected write: err: {:?}", err);
+    ErrorRep::new(ExtErrorCode::Maintenance as i32, format!("{}", err))
+  }
+}
+
+impl ListQuery {
+  // Sorts the filtered items, stably, so that an already sorted list keeps its
+  // order among equals, then takes the page asked for
+  pub fn apply<T: Sortable>(
+    &self, mut items: Vec<T>,
+  ) -> Result<(Vec<T>, PageInfo), UnknownSortField> {
+    if let Some(sort) = self.sort.as_deref().filter(|sort| !sort.is_empty()) {
+      let (field, is_desc) = match sort.strip_prefix('-') {
+        Some(field) => (field, true),
+        None => (sort, false),
+      };
+      if !T::SORT_FIELDS.contains(&field) {
+        return Err(UnknownSortField { field: field.to_owned(), allowed: T::SORT_FIELDS });
+      }
+      items.sort_by(|a, b| {
+        let ordering = a.cmp_by(b, field);
+        if is_desc {
+          ordering.reverse()
+        } else {
+          ordering
+        }
+      });
+    }
+
+    let page = self.page.unwrap_or(1).max(1);
+    let per_page = self.per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE);
+    let total = items.len();
+    let items =
+      items.into_iter().skip((page - 1).saturating_mul(per_page)).take(per_page).collect();
+    Ok((items, PageInfo { page, per_page, total }))
+  }
+}
+
+// secure asks for the domain:https_port form where the scheme decides it,
+// rather than that of the req
+#[derive(Debug, Default, Deserialize)]
+pub struct PickFrontendReq {
+  #[serde(default)]
+  secure: Option<bool>,
+}
+
+impl PickFrontendReq {
+  #[inline]
+  pub(crate) fn new(secure: Option<bool>) -> Self {
+    PickFrontendReq { secure }
+  }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetRoutesReq {
+  // Only the routes whose paths start with it
+  #[serde(default)]
+  prefix: Option<String>,
+  #[serde(default)]
+  pretty: bool,
+}
+
+impl GetRoutesReq {
+  #[inline]
+  pub fn pretty(&self) -> bool {
+    self.pretty
+  }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetRoutesDeltaReq {
+  since: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetRoutesDeltaRep {
   code: i32,
   #[serde(skip_serializing_if = "Option::is_none")]
   desc: Option<String>,
+  checksum: u32,
+  full: bool,
+  updated: BTreeMap<&'static str, Vec<Arc<SharedRouteGroup>>>,
+  removed: BTreeMap<&'static str, Vec<Arc<str>>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  weights: Option<BTreeMap<String, u32>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  rate_limits: Option<RateLimits>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AssignFrontendRep {
+  code: i32,
   #[serde(skip_serializing_if = "Option::is_none")]
-  endpoint: Option<String>,
+  desc: Option<String>,
+  endpoint: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -33,129 +222,592 @@ pub struct GetRoutesRep {
   code: i32,
   #[serde(skip_serializing_if = "Option::is_none")]
   desc: Option<String>,
-  ws_route_groups: Vec<RouteGroup>,
-  get_route_groups: Vec<RouteGroup>,
-  post_route_groups: Vec<RouteGroup>,
-  put_route_groups: Vec<RouteGroup>,
-  patch_route_groups: Vec<RouteGroup>,
-  delete_route_groups: Vec<RouteGroup>,
-  head_route_groups: Vec<RouteGroup>,
-  options_route_groups: Vec<RouteGroup>,
-  trace_route_groups: Vec<RouteGroup>,
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum AddrType {
+  checksum: u32,
+  ws_route_groups: Vec<Arc<SharedRouteGroup>>,
+  get_route_groups: Vec<Arc<SharedRouteGroup>>,
+  post_route_groups: Vec<Arc<SharedRouteGroup>>,
+  put_route_groups: Vec<Arc<SharedRouteGroup>>,
+  patch_route_groups: Vec<Arc<SharedRouteGroup>>,
+  delete_route_groups: Vec<Arc<SharedRouteGroup>>,
+  head_route_groups: Vec<Arc<SharedRouteGroup>>,
+  options_route_groups: Vec<Arc<SharedRouteGroup>>,
+  trace_route_groups: Vec<Arc<SharedRouteGroup>>,
+  // Endpoint to weight, for the endpoints not having the default weight
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  weights: BTreeMap<String, u32>,
+  // Method to path to limit, for the paths having a limit declared
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  rate_limits: RateLimits,
+}
+
+// For the services which can not hold a ws connection
+#[derive(Debug, Deserialize)]
+pub struct RegisterServiceReq {
+  // Defaults to ip:http_port, like over ws
+  #[serde(default)]
+  id: String,
+  http_port: u32,
+}
+
+impl RegisterServiceReq {
+  #[inline]
+  pub(crate) fn new(id: String, http_port: u32) -> Self {
+    RegisterServiceReq { id, http_port }
+  }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetRoutesReq {
+  id: String,
+  #[serde(default)]
+  ws_paths: Vec<Path>,
+  #[serde(default)]
+  get_paths: Vec<Path>,
+  #[serde(default)]
+  post_paths: Vec<Path>,
+  #[serde(default)]
+  put_paths: Vec<Path>,
+  #[serde(default)]
+  patch_paths: Vec<Path>,
+  #[serde(default)]
+  delete_paths: Vec<Path>,
+  #[serde(default)]
+  head_paths: Vec<Path>,
+  #[serde(default)]
+  options_paths: Vec<Path>,
+  #[serde(default)]
+  trace_paths: Vec<Path>,
+  // Replace the ones declared before, which are kept if left out
+  #[serde(default)]
+  rate_limits: Option<Vec<PathRateLimit>>,
+}
+
+impl SetRoutesReq {
+  // Of the service, which the protocol req leaves to the connection
+  pub(crate) fn from_proto(id: String, req: maxwell_protocol::SetRoutesReq) -> Self {
+    SetRoutesReq {
+      id,
+      ws_paths: req.ws_paths,
+      get_paths: req.get_paths,
+      post_paths: req.post_paths,
+      put_paths: req.put_paths,
+      patch_paths: req.patch_paths,
+      delete_paths: req.delete_paths,
+      head_paths: req.head_paths,
+      options_paths: req.options_paths,
+      trace_paths: req.trace_paths,
+      rate_limits: None,
+    }
+  }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HeartbeatReq {
+  id: String,
+  // In [0, 1], for the health policies having the load signal
+  #[serde(default)]
+  load: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ServiceRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  id: Option<String>,
+}
+
+impl ServiceRep {
+  #[inline]
+  fn ok(id: Option<String>) -> Self {
+    ServiceRep { code: ErrorCode::Ok as i32, desc: None, id }
+  }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LocateTopicReq {
+  topic: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LocateTopicRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  endpoint: String,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  standby_endpoints: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LocateTopicsReq {
+  topics: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LocateTopicsRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  endpoints: HashMap<String, String>,
+  #[serde(skip_serializing_if = "HashMap::is_empty")]
+  standby_endpoints: HashMap<String, Vec<String>>,
+  #[serde(skip_serializing_if = "HashMap::is_empty")]
+  errors: HashMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetTopicDistRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  checksum: u32,
+  topics: HashMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResolveIpRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  ip: String,
+  port: u16,
+  addr_type: AddrType,
+  is_tls: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetChecksumRep {
+  code: i32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+  checksum: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum AddrType {
   Loopback,
   Private,
   Public,
 }
 
 pub struct HttpHandler {
+  peer_ip: Option<IpAddr>,
+  peer_port: Option<u16>,
   addr_type: AddrType,
   is_https: bool,
+  client_identity: Option<ClientIdentity>,
+}
+
+impl GetRoutesRep {
+  #[inline]
+  pub fn checksum(&self) -> u32 {
+    self.checksum
+  }
+}
+
+impl AssignFrontendRep {
+  #[inline]
+  pub fn endpoint(&self) -> &str {
+    &self.endpoint
+  }
 }
 
 impl HttpHandler {
   #[inline]
   pub fn new(req: &HttpRequest) -> Self {
     Self {
+      peer_ip: req.peer_addr().map(|peer_addr| peer_addr.ip()),
+      peer_port: req.peer_addr().map(|peer_addr| peer_addr.port()),
       addr_type: if let Some(peer_addr) = &req.peer_addr() {
         Self::detect_addr_type(peer_addr)
       } else {
         AddrType::Public
       },
       is_https: req.connection_info().scheme() == "https",
+      client_identity: client_identity_of(req),
     }
   }
 
+  // For the reqs not over http, e.g. over grpc, which carry no client certificate
   #[inline]
-  pub fn pick_frontend(&self) -> AssignFrontendRep {
+  pub(crate) fn of_peer(peer_addr: Option<SocketAddr>) -> Self {
+    Self {
+      peer_ip: peer_addr.map(|peer_addr| peer_addr.ip()),
+      peer_port: peer_addr.map(|peer_addr| peer_addr.port()),
+      addr_type: peer_addr.as_ref().map_or(AddrType::Public, Self::detect_addr_type),
+      is_https: false,
+      client_identity: None,
+    }
+  }
+
+  #[inline]
+  pub fn pick_frontend(&self, req: PickFrontendReq) -> Result<AssignFrontendRep, ErrorRep> {
     if let Some(frontend) = FRONTEND_MGR.pick() {
-      AssignFrontendRep {
+      Ok(AssignFrontendRep {
         code: ErrorCode::Ok as i32,
         desc: None,
-        endpoint: Some(self.build_endpoint(&frontend)),
-      }
+        endpoint: self.build_endpoint(&frontend, req.secure),
+      })
     } else {
       log::error!("Failed to pick an available frontend.");
 
-      AssignFrontendRep {
-        code: ErrorCode::FailedToPickFrontend as i32,
-        desc: Some(format!("Failed to pick an available frontend.")),
-        endpoint: None,
-      }
+      Err(ErrorRep::new(
+        ErrorCode::FailedToPickFrontend as i32,
+        "Failed to pick an available frontend.".to_owned(),
+      ))
     }
   }
 
   #[inline]
-  pub fn pick_frontends(&self) -> GetFrontendsRep {
+  pub fn pick_frontends(&self, req: PickFrontendReq) -> GetFrontendsRep {
     let mut endpoints = vec![];
     for frontend in FRONTEND_MGR.iter() {
-      endpoints.push(self.build_endpoint(&frontend));
+      endpoints.push(self.build_endpoint(&frontend, req.secure));
     }
     GetFrontendsRep { code: ErrorCode::Ok as i32, desc: None, endpoints }
   }
 
   #[inline]
-  pub fn get_routes(&self) -> GetRoutesRep {
-    let mut stale_services = vec![];
-
-    let mut ws_route_groups = HashMap::default();
-    let mut get_route_groups = HashMap::default();
-    let mut post_route_groups = HashMap::default();
-    let mut put_route_groups = HashMap::default();
-    let mut patch_route_groups = HashMap::default();
-    let mut delete_route_groups = HashMap::default();
-    let mut head_route_groups = HashMap::default();
-    let mut options_route_groups = HashMap::default();
-    let mut trace_route_groups = HashMap::default();
-
-    for reverse_route_group in ROUTE_MGR.reverse_route_group_iter() {
-      let pb = reverse_route_group.value();
-      let service_id = reverse_route_group.key();
-      let (endpoint, is_healthy) = match SERVICE_MGR.get(service_id) {
-        Some(service) => (service.private_endpoint(), service.is_healthy()),
-        None => {
-          log::warn!("Found a stale service: id: {:?}", service_id);
-          stale_services.push(service_id.clone());
-          continue;
+  pub fn get_routes(&self, tenant: &str, req: &GetRoutesReq) -> GetRoutesRep {
+    let table = ROUTE_MGR.snapshot(tenant);
+    let route_groups = |method| {
+      let mut route_groups = table.route_groups(method);
+      if let Some(prefix) = &req.prefix {
+        route_groups.retain(|route_group| route_group.path.starts_with(prefix.as_str()));
+      }
+      route_groups
+    };
+    GetRoutesRep {
+      code: ErrorCode::Ok as i32,
+      desc: None,
+      checksum: table.checksum(),
+      ws_route_groups: route_groups("ws"),
+      get_route_groups: route_groups("get"),
+      post_route_groups: route_groups("post"),
+      put_route_groups: route_groups("put"),
+      patch_route_groups: route_groups("patch"),
+      delete_route_groups: route_groups("delete"),
+      head_route_groups: route_groups("head"),
+      options_route_groups: route_groups("options"),
+      trace_route_groups: route_groups("trace"),
+      weights: table.weights().clone(),
+      rate_limits: table.rate_limits().clone(),
+    }
+  }
+
+  // Also the etag of /$get-routes
+  #[inline]
+  pub fn routes_checksum(&self, tenant: &str) -> u32 {
+    ROUTE_MGR.snapshot(tenant).checksum()
+  }
+
+  #[inline]
+  pub fn get_routes_delta(&self, tenant: &str, req: GetRoutesDeltaReq) -> GetRoutesDeltaRep {
+    let (checksum, full, delta) = build_routes_delta(tenant, req.since);
+    GetRoutesDeltaRep {
+      code: ErrorCode::Ok as i32,
+      desc: None,
+      checksum,
+      full,
+      updated: delta.updated,
+      removed: delta.removed,
+      weights: delta.weights,
+      rate_limits: delta.rate_limits,
+    }
+  }
+
+  #[inline]
+  // The same as the ws RegisterServiceReq, the service then keeps itself alive
+  // with heartbeats instead of pings
+  pub fn register_service(
+    &self, tenant: &str, req: RegisterServiceReq,
+  ) -> Result<ServiceRep, ErrorRep> {
+    let Some(peer_ip) = self.peer_ip else {
+      return Err(ErrorRep::new(
+        ErrorCode::MasterError as i32,
+        "Failed to resolve the peer ip.".to_owned(),
+      ));
+    };
+    let id =
+      if !req.id.is_empty() { req.id.clone() } else { format!("{}:{}", peer_ip, req.http_port) };
+
+    if !is_allowed_to_register(self.client_identity.as_ref(), NodeType::Service, &id) {
+      log::error!(
+        "Client certificate rejected: from: {:?}, identity: {:?}, node_id: {:?}",
+        peer_ip,
+        self.client_identity,
+        id
+      );
+      return Err(ErrorRep::new(
+        ExtErrorCode::ClientCertRejected as i32,
+        format!("Client certificate does not allow registering: node_id: {}", id),
+      ));
+    }
+
+    cluster::check_leader().map_err(not_leader_rep)?;
+    maintenance::check_writable()?;
+
+    log::info!("Registering service: from: {:?}, req: {:?}", peer_ip, req);
+
+    if let Err(err) = ROUTE_MGR
+      .set_tenant(&id, tenant)
+      .and_then(|()| SERVICE_MGR.add(Service::new(id.clone(), peer_ip, req.http_port)))
+    {
+      log::error!("Failed to register service: id: {:?}, err: {:?}", id, err);
+      return Err(ErrorRep::new(
+        ErrorCode::MasterError as i32,
+        format!("Failed to register service: id: {}, err: {}", id, err),
+      ));
+    }
+    audit::record(
+      format!("service:{}@{}", id, peer_ip),
+      "register-service",
+      json!({ "node_id": id, "http_port": req.http_port, "tenant": tenant }),
+    );
+    Ok(ServiceRep::ok(Some(id)))
+  }
+
+  // The same as the ws SetRoutesReq
+  pub fn set_routes(&self, tenant: &str, req: SetRoutesReq) -> Result<ServiceRep, ErrorRep> {
+    self.check_service(tenant, &req.id)?;
+    cluster::check_leader().map_err(not_leader_rep)?;
+    maintenance::check_writable()?;
+
+    log::info!("Setting routes: id: {:?}, req : {:?}", req.id, req);
+    let pb = PathBundle {
+      ws_paths: req.ws_paths.into_iter().collect(),
+      get_paths: req.get_paths.into_iter().collect(),
+      post_paths: req.post_paths.into_iter().collect(),
+      put_paths: req.put_paths.into_iter().collect(),
+      patch_paths: req.patch_paths.into_iter().collect(),
+      delete_paths: req.delete_paths.into_iter().collect(),
+      head_paths: req.head_paths.into_iter().collect(),
+      options_paths: req.options_paths.into_iter().collect(),
+      trace_paths: req.trace_paths.into_iter().collect(),
+    };
+    let pb = match pb.normalize() {
+      Ok(pb) => pb,
+      Err(err) => {
+        log::error!("Failed to set routes: id: {:?}, err: {:?}", req.id, err);
+        return Err(ErrorRep::new(
+          ExtErrorCode::InvalidRoutes as i32,
+          format!("Failed to set routes: id: {}, err: {}", req.id, err),
+        ));
+      }
+    };
+    let rate_limits =
+      match req.rate_limits.map(|limits| normalize_rate_limits(limits, &pb)).transpose() {
+        Ok(rate_limits) => rate_limits,
+        Err(err) => {
+          log::error!("Failed to set routes: id: {:?}, err: {:?}", req.id, err);
+          return Err(ErrorRep::new(
+            ExtErrorCode::InvalidRoutes as i32,
+            format!("Failed to set routes: id: {}, err: {}", req.id, err),
+          ));
         }
       };
+    // Recorded only once applied
+    let details = json!({ "service_id": req.id, "paths": pb, "rate_limits": rate_limits });
+    if let Err(err) = ROUTE_MGR.claim_and_set_routes(req.id.clone(), pb) {
+      log::error!("Failed to set routes: id: {:?}, err: {:?}", req.id, err);
+      return Err(ErrorRep::new(
+        set_routes_error_code(&err),
+        format!("Failed to set routes: id: {}, err: {}", req.id, err),
+      ));
+    }
+    if let Some(limits) = rate_limits {
+      if let Err(err) = ROUTE_MGR.set_rate_limits(&req.id, limits) {
+        log::error!("Failed to set rate limits: id: {:?}, err: {:?}", req.id, err);
+        return Err(ErrorRep::new(
+          ErrorCode::MasterError as i32,
+          format!("Failed to set rate limits: id: {}, err: {}", req.id, err),
+        ));
+      }
+    }
+    audit::record(
+      format!("service:{}@{}", req.id, self.peer_ip.map_or_else(String::new, |ip| ip.to_string())),
+      "set-routes",
+      details,
+    );
+    Ok(ServiceRep::ok(None))
+  }
 
-      Self::build_route_groups(&mut ws_route_groups, &pb.ws_paths, &endpoint, is_healthy);
-      Self::build_route_groups(&mut get_route_groups, &pb.get_paths, &endpoint, is_healthy);
-      Self::build_route_groups(&mut post_route_groups, &pb.post_paths, &endpoint, is_healthy);
-      Self::build_route_groups(&mut put_route_groups, &pb.put_paths, &endpoint, is_healthy);
-      Self::build_route_groups(&mut patch_route_groups, &pb.patch_paths, &endpoint, is_healthy);
-      Self::build_route_groups(&mut delete_route_groups, &pb.delete_paths, &endpoint, is_healthy);
-      Self::build_route_groups(&mut head_route_groups, &pb.head_paths, &endpoint, is_healthy);
-      Self::build_route_groups(&mut options_route_groups, &pb.options_paths, &endpoint, is_healthy);
-      Self::build_route_groups(&mut trace_route_groups, &pb.trace_paths, &endpoint, is_healthy);
+  // The same as a ws PingReq of a registered service
+  pub fn heartbeat(&self, tenant: &str, req: HeartbeatReq) -> Result<ServiceRep, ErrorRep> {
+    self.check_service(tenant, &req.id)?;
+    if let Some(load) = req.load {
+      if !health_policy::is_valid_load(load) {
+        return Err(ErrorRep::new(
+          ExtErrorCode::InvalidQuery as i32,
+          format!("The load must be in [0, 1]: load: {}", load),
+        ));
+      }
+      health_policy::report_load(NodeType::Service, &req.id, load);
     }
+    SERVICE_MGR.activate(&req.id);
+    Ok(ServiceRep::ok(None))
+  }
 
-    for service_id in &stale_services {
-      SERVICE_MGR.remove(service_id);
-      ROUTE_MGR.remove_reverse_route_group(service_id);
+  // Only the registered services may act, and only as themselves
+  fn check_service(&self, tenant: &str, id: &NodeId) -> Result<(), ErrorRep> {
+    if SERVICE_MGR.get(id).is_none() {
+      log::error!("The related service has not registered: ip: {:?}, id: {:?}", self.peer_ip, id);
+      return Err(ErrorRep::new(
+        ExtErrorCode::NotFound as i32,
+        format!("The related service has not registered: id: {}", id),
+      ));
+    }
+    if ROUTE_MGR.tenant_of(id) != tenant
+      || !is_allowed_to_register(self.client_identity.as_ref(), NodeType::Service, id)
+    {
+      log::error!(
+        "Not allowed to act as the service: ip: {:?}, identity: {:?}, id: {:?}",
+        self.peer_ip,
+        self.client_identity,
+        id
+      );
+      return Err(ErrorRep::new(
+        ExtErrorCode::ClientCertRejected as i32,
+        format!("Not allowed to act as the service: id: {}", id),
+      ));
     }
+    Ok(())
+  }
 
-    GetRoutesRep {
-      code: ErrorCode::Ok as i32,
-      desc: None,
-      ws_route_groups: ws_route_groups.values().cloned().collect(),
-      get_route_groups: get_route_groups.values().cloned().collect(),
-      post_route_groups: post_route_groups.values().cloned().collect(),
-      put_route_groups: put_route_groups.values().cloned().collect(),
-      patch_route_groups: patch_route_groups.values().cloned().collect(),
-      delete_route_groups: delete_route_groups.values().cloned().collect(),
-      head_route_groups: head_route_groups.values().cloned().collect(),
-      options_route_groups: options_route_groups.values().cloned().collect(),
-      trace_route_groups: trace_route_groups.values().cloned().collect(),
+  // The same as the ws LocateTopicReq, a new topic gets assigned
+  pub fn locate_topic(&self, req: LocateTopicReq) -> Result<LocateTopicRep, ErrorRep> {
+    match TOPIC_MGR.locate_or_assign(&req.topic, self.peer_ip) {
+      Ok(backend_id) => {
+        log::debug!("Found the backend: topic: {:?}, backend_id: {:?}", req.topic, backend_id);
+
+        if let Some(backend) = BACKEND_MGR.get(&backend_id) {
+          Ok(LocateTopicRep {
+            code: ErrorCode::Ok as i32,
+            desc: None,
+            endpoint: backend.private_endpoint(),
+            standby_endpoints: build_standby_endpoints(&req.topic, &backend_id),
+          })
+        } else {
+          log::error!(
+            "Failed to find the backend: topic: {:?}, backend_id: {:?}",
+            req.topic,
+            backend_id
+          );
+          Err(ErrorRep::new(
+            ErrorCode::FailedToLocateTopic as i32,
+            format!("Failed to find the backend: topic: {}, backend_id: {}", req.topic, backend_id),
+          ))
+        }
+      }
+      Err(err) => {
+        log::error!("Failed to locate topic: {:?}, err: {:?}", req.topic, err);
+        let rep = ErrorRep::new(
+          locate_topic_error_code(&err),
+          format!("Failed to locate topic: {}, err: {}", req.topic, err),
+        );
+        match err.downcast_ref::<NotLeader>() {
+          Some(not_leader) => Err(rep.with_details(json!({ "leader": not_leader.leader }))),
+          None => Err(rep),
+        }
+      }
+    }
+  }
+
+  pub fn locate_topics(&self, req: LocateTopicsReq) -> LocateTopicsRep {
+    let mut endpoints = HashMap::default();
+    let mut standby_endpoints = HashMap::default();
+    let mut errors = HashMap::default();
+    let mut code = ErrorCode::FailedToLocateTopic as i32;
+    for (topic, result) in TOPIC_MGR.locate_or_assign_all(&req.topics, self.peer_ip) {
+      match result {
+        Ok(backend_id) => {
+          if let Some(backend) = BACKEND_MGR.get(&backend_id) {
+            let standbys = build_standby_endpoints(&topic, &backend_id);
+            if !standbys.is_empty() {
+              standby_endpoints.insert(topic.clone(), standbys);
+            }
+            endpoints.insert(topic, backend.private_endpoint());
+          } else {
+            log::error!(
+              "Failed to find the backend: topic: {:?}, backend_id: {:?}",
+              topic,
+              backend_id
+            );
+            errors.insert(topic, format!("Failed to find the backend: backend_id: {}", backend_id));
+          }
+        }
+        Err(err) => {
+          log::error!("Failed to locate topic: {:?}, err: {:?}", topic, err);
+          if err.is::<QuotaExceeded>() || err.is::<NotLeader>() || err.is::<Maintenance>() {
+            code = locate_topic_error_code(&err);
+          }
+          errors.insert(topic, format!("Failed to locate topic: err: {}", err));
+        }
+      }
+    }
+
+    if errors.is_empty() {
+      LocateTopicsRep {
+        code: ErrorCode::Ok as i32,
+        desc: None,
+        endpoints,
+        standby_endpoints,
+        errors,
+      }
+    } else {
+      LocateTopicsRep {
+        code,
+        desc: Some(format!("Failed to locate {} of {} topics.", errors.len(), req.topics.len())),
+        endpoints,
+        standby_endpoints,
+        errors,
+      }
     }
   }
 
   #[inline]
-  fn detect_addr_type(addr: &SocketAddr) -> AddrType {
+  pub fn get_topic_dist(&self) -> GetTopicDistRep {
+    let (checksum, topics) = build_topic_dist();
+    GetTopicDistRep { code: ErrorCode::Ok as i32, desc: None, checksum, topics }
+  }
+
+  // The same as the ws GetRouteDistChecksumReq
+  #[inline]
+  pub fn get_route_dist_checksum(&self) -> GetChecksumRep {
+    GetChecksumRep { code: ErrorCode::Ok as i32, desc: None, checksum: build_route_dist_checksum() }
+  }
+
+  // The same as the ws GetTopicDistChecksumReq
+  #[inline]
+  pub fn get_topic_dist_checksum(&self) -> GetChecksumRep {
+    GetChecksumRep { code: ErrorCode::Ok as i32, desc: None, checksum: TOPIC_MGR.checksum() }
+  }
+
+  // The same as the ws ResolveIpReq, plus what build_endpoint decides the
+  // frontend endpoints by
+  #[inline]
+  pub fn resolve_ip(&self) -> Result<ResolveIpRep, ErrorRep> {
+    match (self.peer_ip, self.peer_port) {
+      (Some(ip), Some(port)) => Ok(ResolveIpRep {
+        code: ErrorCode::Ok as i32,
+        desc: None,
+        ip: ip.to_string(),
+        port,
+        addr_type: self.addr_type,
+        is_tls: self.is_https,
+      }),
+      _ => Err(ErrorRep::new(
+        ErrorCode::MasterError as i32,
+        "Failed to resolve the peer ip.".to_owned(),
+      )),
+    }
+  }
+
+  #[inline]
+  pub(crate) fn detect_addr_type(addr: &SocketAddr) -> AddrType {
     match addr.ip() {
       IpAddr::V4(ip) => {
         if ip.is_loopback() {
@@ -171,40 +823,167 @@ impl HttpHandler {
   }
 
   #[inline]
-  fn build_endpoint(&self, frontend: &Frontend) -> String {
-    if self.addr_type == AddrType::Loopback {
-      if self.is_https {
-        format!("{}:{}", frontend.domain, frontend.https_port)
-      } else {
-        format!("{}:{}", frontend.private_ip, frontend.http_port)
-      }
-    } else if self.addr_type == AddrType::Private {
+  fn build_endpoint(&self, frontend: &Frontend, secure: Option<bool>) -> String {
+    build_frontend_endpoint(frontend, self.addr_type, secure.unwrap_or(self.is_https))
+  }
+}
+
+// The private endpoint for the private peers, otherwise the domain one if
+// secure, as the certificates are of the domain
+pub(crate) fn build_frontend_endpoint(
+  frontend: &Frontend, addr_type: AddrType, secure: bool,
+) -> String {
+  if addr_type == AddrType::Loopback {
+    if secure {
+      format!("{}:{}", frontend.domain, frontend.https_port)
+    } else {
       format!("{}:{}", frontend.private_ip, frontend.http_port)
+    }
+  } else if addr_type == AddrType::Private {
+    format!("{}:{}", frontend.private_ip, frontend.http_port)
+  } else {
+    if secure {
+      format!("{}:{}", frontend.domain, frontend.https_port)
     } else {
-      if self.is_https {
-        format!("{}:{}", frontend.domain, frontend.https_port)
-      } else {
-        format!("{}:{}", frontend.public_ip, frontend.http_port)
-      }
+      format!("{}:{}", frontend.public_ip, frontend.http_port)
     }
   }
+}
 
-  #[inline(always)]
-  fn build_route_groups(
-    route_groups_map: &mut HashMap<String, RouteGroup>, paths: &PathSet, endpoint: &String,
-    is_healthy: bool,
-  ) {
-    for path in paths {
-      let ws_route_group = route_groups_map.entry(path.to_owned()).or_insert_with(|| RouteGroup {
-        path: path.clone(),
-        healthy_endpoints: Vec::new(),
-        unhealthy_endpoints: Vec::new(),
-      });
-      if is_healthy {
-        ws_route_group.healthy_endpoints.push(endpoint.clone());
-      } else {
-        ws_route_group.unhealthy_endpoints.push(endpoint.clone());
-      }
+// The tenant is decided by the bearer token, no token means the default tenant
+pub(crate) fn tenant_of(req: &HttpRequest) -> Result<String, UnknownCredentials> {
+  let token = req
+    .headers()
+    .get(header::AUTHORIZATION)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.strip_prefix("Bearer "));
+  resolve_tenant(token)
+}
+
+// Falls back to a full snapshot when the checksum the client has seen is unknown
+pub(crate) fn build_routes_delta(tenant: &str, since: Option<u32>) -> (u32, bool, RouteDelta) {
+  let (table, delta) = ROUTE_MGR.route_delta(tenant, since);
+  match delta {
+    Some(delta) => (table.checksum(), false, delta),
+    None => (table.checksum(), true, table.full()),
+  }
+}
+
+// Changes whenever the services, the routes or the health of any service
+// change, so that clients refresh until an unhealthy service recovers.
+#[inline]
+pub(crate) fn build_route_dist_checksum() -> u32 {
+  ROUTE_DIST_CHECKSUM.get()
+}
+
+// Maps every topic to its backend's endpoint, the checksum is taken before the
+// scan so that changes during the scan will be noticed by the next poll.
+pub(crate) fn build_topic_dist() -> (u32, HashMap<String, String>) {
+  let checksum = TOPIC_MGR.checksum();
+  let mut endpoints: HashMap<NodeId, String> = HashMap::default();
+  let mut topics = HashMap::default();
+  for (topic, backend_id) in TOPIC_MGR.dump() {
+    if let Some(endpoint) = endpoints.get(&backend_id) {
+      topics.insert(topic, endpoint.clone());
+    } else if let Some(backend) = BACKEND_MGR.get(&backend_id) {
+      let endpoint = backend.private_endpoint();
+      endpoints.insert(backend_id, endpoint.clone());
+      topics.insert(topic, endpoint);
+    } else {
+      log::warn!("Failed to find the backend: topic: {:?}, backend_id: {:?}", topic, backend_id);
+    }
+  }
+  (checksum, topics)
+}
+
+#[inline]
+pub(crate) fn build_standby_endpoints(topic: &String, primary: &NodeId) -> Vec<String> {
+  TOPIC_MGR
+    .standbys(topic, primary)
+    .iter()
+    .filter_map(|backend_id| BACKEND_MGR.get(backend_id).map(|backend| backend.private_endpoint()))
+    .collect()
+}
+
+// The leader is in the details, for the client to retry there
+pub(crate) fn not_leader_rep(err: NotLeader) -> ErrorRep {
+  log::warn!("Rejected write: err: {:?}", err);
+  ErrorRep::new(ExtErrorCode::NotLeader as i32, err.to_string())
+    .with_details(json!({ "leader": err.leader }))
+}
+
+#[inline]
+pub(crate) fn set_routes_error_code(err: &anyhow::Error) -> i32 {
+  if err.is::<RouteConflict>() {
+    ExtErrorCode::RouteConflict as i32
+  } else if err.is::<NotCommitted>() {
+    ExtErrorCode::NotCommitted as i32
+  } else {
+    ErrorCode::MasterError as i32
+  }
+}
+
+#[inline]
+pub(crate) fn locate_topic_error_code(err: &anyhow::Error) -> i32 {
+  if err.is::<QuotaExceeded>() {
+    ExtErrorCode::TopicQuotaExceeded as i32
+  } else if err.is::<NotLeader>() {
+    ExtErrorCode::NotLeader as i32
+  } else if err.is::<Maintenance>() {
+    ExtErrorCode::Maintenance as i32
+  } else if err.is::<DbPoolError>() {
+    ExtErrorCode::Busy as i32
+  } else if err.is::<NotCommitted>() {
+    ExtErrorCode::NotCommitted as i32
+  } else {
+    ErrorCode::FailedToLocateTopic as i32
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[derive(Debug, PartialEq)]
+  struct Item(u32);
+
+  impl Sortable for Item {
+    const SORT_FIELDS: &'static [&'static str] = &["value"];
+
+    fn cmp_by(&self, other: &Self, _field: &str) -> Ordering {
+      self.0.cmp(&other.0)
     }
   }
+
+  #[test]
+  fn test_list_query() {
+    let items = || (0..5).map(Item).collect::<Vec<_>>();
+
+    let query = ListQuery { page: Some(2), per_page: Some(2), sort: Some("-value".to_owned()) };
+    let (page, info) = query.apply(items()).unwrap();
+    assert_eq!(page, vec![Item(2), Item(1)]);
+    assert_eq!((info.page, info.per_page, info.total), (2, 2, 5));
+
+    let query = ListQuery { page: Some(4), per_page: Some(2), sort: None };
+    assert!(query.apply(items()).unwrap().0.is_empty());
+
+    let query = ListQuery { page: None, per_page: None, sort: Some("unknown".to_owned()) };
+    assert!(query.apply(items()).is_err());
+  }
+
+  #[test]
+  fn test_build_frontend_endpoint() {
+    let frontend = Frontend::new(
+      "frontend-0".to_owned(),
+      "f0.example.com".to_owned(),
+      "1.2.3.4".parse().unwrap(),
+      "10.0.0.1".parse().unwrap(),
+      10000,
+      10443,
+    );
+    assert_eq!(build_frontend_endpoint(&frontend, AddrType::Public, false), "1.2.3.4:10000");
+    assert_eq!(build_frontend_endpoint(&frontend, AddrType::Public, true), "f0.example.com:10443");
+    assert_eq!(build_frontend_endpoint(&frontend, AddrType::Private, true), "10.0.0.1:10000");
+    assert_eq!(build_frontend_endpoint(&frontend, AddrType::Loopback, false), "10.0.0.1:10000");
+  }
 }
@@ -1,2 +1,12 @@
+pub mod admin_auth;
+pub mod admin_handler;
+pub mod client_cert;
+pub mod compression;
+pub mod connection_registry;
+pub mod error_rep;
+pub mod grpc_handler;
 pub mod http_handler;
+pub mod protocol_version;
+pub mod rate_limit;
+pub mod text_msg;
 pub mod ws_handler;
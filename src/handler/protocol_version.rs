@@ -0,0 +1,182 @@
+use std::fmt;
+
+use ahash::HashSet;
+
+// Version 1 is plain maxwell-protocol, the later ones add the features below,
+// by the json msgs of text_msg, whose wire format is in proto/text_protocol.md
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+pub const PROTOCOL_VERSION: u32 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+  TopicDist,
+  DeltaSync,
+  RouteOptions,
+  RouteWatch,
+  EventWatch,
+  ConditionalRoutes,
+  Stats,
+  ResolveIp,
+  FrontendScheme,
+  LoadReport,
+  StateSync,
+}
+
+pub const ALL_FEATURES: [Feature; 11] = [
+  Feature::TopicDist,
+  Feature::DeltaSync,
+  Feature::RouteOptions,
+  Feature::RouteWatch,
+  Feature::EventWatch,
+  Feature::ConditionalRoutes,
+  Feature::Stats,
+  Feature::ResolveIp,
+  Feature::FrontendScheme,
+  Feature::LoadReport,
+  Feature::StateSync,
+];
+
+impl Feature {
+  #[inline]
+  pub fn since_version(self) -> u32 {
+    match self {
+      Feature::TopicDist | Feature::DeltaSync | Feature::RouteOptions => 2,
+      Feature::RouteWatch | Feature::EventWatch => 3,
+      Feature::ConditionalRoutes => 4,
+      Feature::Stats => 5,
+      Feature::ResolveIp | Feature::FrontendScheme | Feature::LoadReport => 6,
+      Feature::StateSync => 7,
+    }
+  }
+
+  #[inline]
+  pub fn name(self) -> &'static str {
+    match self {
+      Feature::TopicDist => "topic_dist",
+      Feature::DeltaSync => "delta_sync",
+      Feature::RouteOptions => "route_options",
+      Feature::RouteWatch => "route_watch",
+      Feature::EventWatch => "event_watch",
+      Feature::ConditionalRoutes => "conditional_routes",
+      Feature::Stats => "stats",
+      Feature::ResolveIp => "resolve_ip",
+      Feature::FrontendScheme => "frontend_scheme",
+      Feature::LoadReport => "load_report",
+      Feature::StateSync => "state_sync",
+    }
+  }
+
+  #[inline]
+  fn from_name(name: &str) -> Option<Self> {
+    ALL_FEATURES.into_iter().find(|feature| feature.name() == name)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnsupportedVersion {
+  TooOld { version: u32 },
+  FeatureNotNegotiated { feature: Feature, version: u32 },
+}
+
+impl fmt::Display for UnsupportedVersion {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      UnsupportedVersion::TooOld { version } => write!(
+        f,
+        "Unsupported protocol version: {}, supported: {}..={}",
+        version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+      ),
+      UnsupportedVersion::FeatureNotNegotiated { feature, version } => write!(
+        f,
+        "The feature was not negotiated: feature: {}, version: {}, required version: {}",
+        feature.name(),
+        version,
+        feature.since_version()
+      ),
+    }
+  }
+}
+
+impl std::error::Error for UnsupportedVersion {}
+
+// What a connection agreed on, plain maxwell-protocol until negotiated
+#[derive(Debug, Clone)]
+pub struct NegotiatedProtocol {
+  pub version: u32,
+  pub features: HashSet<Feature>,
+}
+
+impl Default for NegotiatedProtocol {
+  fn default() -> Self {
+    NegotiatedProtocol { version: MIN_PROTOCOL_VERSION, features: HashSet::default() }
+  }
+}
+
+impl NegotiatedProtocol {
+  // The lower of both versions, and the features both sides know of within it,
+  // all of them if the client lists none. Unknown feature names are ignored.
+  pub fn negotiate(version: u32, features: &[String]) -> Result<Self, UnsupportedVersion> {
+    if version < MIN_PROTOCOL_VERSION {
+      return Err(UnsupportedVersion::TooOld { version });
+    }
+    let version = version.min(PROTOCOL_VERSION);
+    let features = if features.is_empty() {
+      ALL_FEATURES.into_iter().collect()
+    } else {
+      features.iter().filter_map(|name| Feature::from_name(name)).collect::<HashSet<_>>()
+    };
+    let features =
+      features.into_iter().filter(|feature| feature.since_version() <= version).collect();
+    Ok(NegotiatedProtocol { version, features })
+  }
+
+  #[inline]
+  pub fn check(&self, feature: Feature) -> Result<(), UnsupportedVersion> {
+    if self.features.contains(&feature) {
+      Ok(())
+    } else {
+      Err(UnsupportedVersion::FeatureNotNegotiated { feature, version: self.version })
+    }
+  }
+
+  // Sorted, so that replies are stable
+  pub fn feature_list(&self) -> Vec<Feature> {
+    ALL_FEATURES.into_iter().filter(|feature| self.features.contains(feature)).collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_negotiate() {
+    let protocol = NegotiatedProtocol::default();
+    assert!(protocol.check(Feature::DeltaSync).is_err());
+
+    let protocol = NegotiatedProtocol::negotiate(2, &[]).unwrap();
+    assert_eq!(protocol.version, 2);
+    assert!(protocol.check(Feature::DeltaSync).is_ok());
+    assert!(protocol.check(Feature::RouteWatch).is_err());
+
+    let protocol = NegotiatedProtocol::negotiate(5, &[]).unwrap();
+    assert!(protocol.check(Feature::Stats).is_ok());
+    assert!(protocol.check(Feature::ResolveIp).is_err());
+
+    let protocol = NegotiatedProtocol::negotiate(6, &[]).unwrap();
+    assert!(protocol.check(Feature::LoadReport).is_ok());
+    assert!(protocol.check(Feature::StateSync).is_err());
+
+    let protocol =
+      NegotiatedProtocol::negotiate(100, &["route_watch".to_owned(), "unknown".to_owned()])
+        .unwrap();
+    assert_eq!(protocol.version, PROTOCOL_VERSION);
+    assert_eq!(protocol.feature_list(), vec![Feature::RouteWatch]);
+
+    assert_eq!(
+      NegotiatedProtocol::negotiate(0, &[]).unwrap_err(),
+      UnsupportedVersion::TooOld { version: 0 }
+    );
+  }
+}
@@ -0,0 +1,110 @@
+use std::{
+  cell::{Cell, RefCell},
+  fmt,
+  net::IpAddr,
+};
+
+use ahash::{HashMap, RandomState as AHasher};
+use dashmap::DashMap;
+use maxwell_protocol::ProtocolMsg;
+use once_cell::sync::Lazy;
+
+use crate::{
+  config::CONFIG,
+  topic_mgr::{Limit, TokenBucket},
+};
+
+// Idle buckets are purged once there are more ips and msg types than this
+const MAX_TRACKED_IP_BUCKETS: usize = 65536;
+
+// Shared by all the connections from the same ip
+static IP_BUCKETS: Lazy<DashMap<(IpAddr, &'static str), TokenBucket, AHasher>> =
+  Lazy::new(|| DashMap::with_capacity_and_hasher(1024, AHasher::default()));
+
+#[derive(Debug)]
+pub struct RateLimited {
+  pub msg_type: &'static str,
+  pub per_ip: bool,
+}
+
+impl fmt::Display for RateLimited {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.per_ip {
+      write!(f, "Too many msgs from the ip: msg_type: {}", self.msg_type)
+    } else {
+      write!(f, "Too many msgs from the connection: msg_type: {}", self.msg_type)
+    }
+  }
+}
+
+impl std::error::Error for RateLimited {}
+
+// Limits how fast each type of msgs can be sent, per connection and per ip
+pub struct MsgRateLimiter {
+  conn_buckets: RefCell<HashMap<&'static str, TokenBucket>>,
+  rejected_count: Cell<u32>,
+}
+
+impl MsgRateLimiter {
+  #[inline]
+  pub fn new() -> Self {
+    MsgRateLimiter { conn_buckets: RefCell::new(HashMap::default()), rejected_count: Cell::new(0) }
+  }
+
+  // The limits are read from the config on each msg, so that reloaded ones apply at once
+  pub fn acquire(&self, ip: IpAddr, msg_type: &'static str) -> Result<(), RateLimited> {
+    let config = CONFIG.load();
+    let server_config = &config.server;
+    if let Some(limit) = Limit::new(server_config.ws_msg_rate, server_config.ws_msg_burst) {
+      let mut conn_buckets = self.conn_buckets.borrow_mut();
+      let bucket = conn_buckets.entry(msg_type).or_insert_with(|| TokenBucket::new(limit.burst));
+      if !bucket.try_take(limit.rate, limit.burst) {
+        return Err(self.reject(msg_type, false));
+      }
+    }
+    if let Some(limit) = Limit::new(server_config.ws_ip_msg_rate, server_config.ws_ip_msg_burst) {
+      if IP_BUCKETS.len() > MAX_TRACKED_IP_BUCKETS {
+        IP_BUCKETS.retain(|_, bucket| {
+          bucket.refill(limit.rate, limit.burst);
+          !bucket.is_full(limit.burst)
+        });
+      }
+      let mut bucket =
+        IP_BUCKETS.entry((ip, msg_type)).or_insert_with(|| TokenBucket::new(limit.burst));
+      if !bucket.try_take(limit.rate, limit.burst) {
+        return Err(self.reject(msg_type, true));
+      }
+    }
+    Ok(())
+  }
+
+  // Whether the connection keeps sending after being told to slow down
+  #[inline]
+  pub fn is_abusive(&self) -> bool {
+    let max_rate_limited = CONFIG.load().server.ws_max_rate_limited;
+    max_rate_limited > 0 && self.rejected_count.get() >= max_rate_limited
+  }
+
+  #[inline]
+  fn reject(&self, msg_type: &'static str, per_ip: bool) -> RateLimited {
+    self.rejected_count.set(self.rejected_count.get().saturating_add(1));
+    RateLimited { msg_type, per_ip }
+  }
+}
+
+pub fn protocol_msg_type(protocol_msg: &ProtocolMsg) -> &'static str {
+  match protocol_msg {
+    ProtocolMsg::PingReq(_) => "ping_req",
+    ProtocolMsg::RegisterFrontendReq(_) => "register_frontend_req",
+    ProtocolMsg::RegisterBackendReq(_) => "register_backend_req",
+    ProtocolMsg::RegisterServiceReq(_) => "register_service_req",
+    ProtocolMsg::SetRoutesReq(_) => "set_routes_req",
+    ProtocolMsg::GetRoutesReq(_) => "get_routes_req",
+    ProtocolMsg::GetTopicDistChecksumReq(_) => "get_topic_dist_checksum_req",
+    ProtocolMsg::GetRouteDistChecksumReq(_) => "get_route_dist_checksum_req",
+    ProtocolMsg::PickFrontendReq(_) => "pick_frontend_req",
+    ProtocolMsg::LocateTopicReq(_) => "locate_topic_req",
+    ProtocolMsg::ResolveIpReq(_) => "resolve_ip_req",
+    _ => "unknown",
+  }
+}
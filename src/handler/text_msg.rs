@@ -0,0 +1,396 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use ahash::HashMap;
+use serde::{Deserialize, Serialize};
+
+use super::{http_handler::AddrType, protocol_version::Feature};
+use crate::{
+  cluster::LeaderInfo,
+  event_bus::{Event, EventKind},
+  route_mgr::{PathRateLimit, RateLimits, SharedRouteGroup},
+  standby::{StateDelta, StateSnapshot},
+  stats::Stats,
+};
+
+// Messages exchanged as json over ws text frames, for the features which
+// maxwell-protocol does not define yet. Their wire format is specified in
+// proto/text_protocol.md, which must change along with them: a msg or a
+// required field is added by a new protocol version and feature only, and the
+// fields are never renamed or removed.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TextReq {
+  // Should be sent first, the other msgs here are rejected unless negotiated
+  NegotiateReq {
+    version: u32,
+    features: Vec<String>,
+    r#ref: u32,
+  },
+  WatchTopicDistReq {
+    topics: Vec<String>,
+    r#ref: u32,
+  },
+  UnwatchTopicDistReq {
+    topics: Vec<String>,
+    r#ref: u32,
+  },
+  GetTopicDistReq {
+    r#ref: u32,
+  },
+  LocateTopicReq {
+    topic: String,
+    r#ref: u32,
+  },
+  // since is the checksum of the routes the client has, none for a full sync
+  GetRoutesDeltaReq {
+    since: Option<u32>,
+    r#ref: u32,
+  },
+  // Replies routes_not_modified_rep if checksum is still the current one,
+  // otherwise get_routes_rep with all the routes
+  GetRoutesReq {
+    checksum: Option<u32>,
+    r#ref: u32,
+  },
+  // Replies like get_routes_delta_req, then pushes routes_changed_msg
+  WatchRoutesReq {
+    since: Option<u32>,
+    r#ref: u32,
+  },
+  UnwatchRoutesReq {
+    r#ref: u32,
+  },
+  // Sent by a registered service, weight is its share of traffic, rate_limits
+  // replace the ones declared for its paths, kept if left out
+  SetRouteOptionsReq {
+    weight: u32,
+    #[serde(default)]
+    rate_limits: Option<Vec<PathRateLimit>>,
+    r#ref: u32,
+  },
+  // Pushes event_msg for the kinds, all kinds if empty
+  WatchEventsReq {
+    kinds: Vec<EventKind>,
+    r#ref: u32,
+  },
+  // Stops pushing the kinds, all kinds if empty
+  UnwatchEventsReq {
+    kinds: Vec<EventKind>,
+    r#ref: u32,
+  },
+  // Replies the sizes, versions and req rates of the master, for monitoring
+  GetStatsReq {
+    r#ref: u32,
+  },
+  // The resolve_ip_req of maxwell-protocol, plus what the master decides the
+  // endpoints it replies by
+  ResolveIpReq {
+    r#ref: u32,
+  },
+  // The pick_frontend_req of maxwell-protocol, the endpoint is the domain one if
+  // secure, which defaults to whether the connection is over tls
+  PickFrontendReq {
+    secure: Option<bool>,
+    r#ref: u32,
+  },
+  // Sent by a registered frontend, backend or service, load is in [0, 1], for
+  // the health policies having the load signal
+  ReportLoadReq {
+    load: f64,
+    r#ref: u32,
+  },
+  // Sent first by another master, the msgs below are only accepted from peers
+  PeerHelloReq {
+    master_id: String,
+    token: String,
+    r#ref: u32,
+  },
+  // Sent by a standby, replies the state, then pushes state_delta_msg, the state
+  // is left out with skip_snapshot, for the standby to fetch it in chunks
+  WatchStateReq {
+    skip_snapshot: Option<bool>,
+    r#ref: u32,
+  },
+}
+
+impl TextReq {
+  pub fn msg_type(&self) -> &'static str {
+    match self {
+      TextReq::NegotiateReq { .. } => "negotiate_req",
+      TextReq::WatchTopicDistReq { .. } => "watch_topic_dist_req",
+      TextReq::UnwatchTopicDistReq { .. } => "unwatch_topic_dist_req",
+      TextReq::GetTopicDistReq { .. } => "get_topic_dist_req",
+      TextReq::LocateTopicReq { .. } => "locate_topic_req",
+      TextReq::GetRoutesReq { .. } => "get_routes_req",
+      TextReq::GetRoutesDeltaReq { .. } => "get_routes_delta_req",
+      TextReq::WatchRoutesReq { .. } => "watch_routes_req",
+      TextReq::UnwatchRoutesReq { .. } => "unwatch_routes_req",
+      TextReq::SetRouteOptionsReq { .. } => "set_route_options_req",
+      TextReq::WatchEventsReq { .. } => "watch_events_req",
+      TextReq::UnwatchEventsReq { .. } => "unwatch_events_req",
+      TextReq::GetStatsReq { .. } => "get_stats_req",
+      TextReq::ResolveIpReq { .. } => "resolve_ip_req",
+      TextReq::PickFrontendReq { .. } => "pick_frontend_req",
+      TextReq::ReportLoadReq { .. } => "report_load_req",
+      TextReq::PeerHelloReq { .. } => "peer_hello_req",
+      TextReq::WatchStateReq { .. } => "watch_state_req",
+    }
+  }
+
+  pub fn r#ref(&self) -> u32 {
+    match self {
+      TextReq::NegotiateReq { r#ref, .. }
+      | TextReq::WatchTopicDistReq { r#ref, .. }
+      | TextReq::UnwatchTopicDistReq { r#ref, .. }
+      | TextReq::GetTopicDistReq { r#ref }
+      | TextReq::LocateTopicReq { r#ref, .. }
+      | TextReq::GetRoutesReq { r#ref, .. }
+      | TextReq::GetRoutesDeltaReq { r#ref, .. }
+      | TextReq::WatchRoutesReq { r#ref, .. }
+      | TextReq::UnwatchRoutesReq { r#ref }
+      | TextReq::SetRouteOptionsReq { r#ref, .. }
+      | TextReq::WatchEventsReq { r#ref, .. }
+      | TextReq::UnwatchEventsReq { r#ref, .. }
+      | TextReq::GetStatsReq { r#ref }
+      | TextReq::ResolveIpReq { r#ref }
+      | TextReq::PickFrontendReq { r#ref, .. }
+      | TextReq::ReportLoadReq { r#ref, .. }
+      | TextReq::PeerHelloReq { r#ref, .. }
+      | TextReq::WatchStateReq { r#ref, .. } => *r#ref,
+    }
+  }
+
+  #[inline]
+  pub fn is_peer_only(&self) -> bool {
+    matches!(self, TextReq::WatchStateReq { .. })
+  }
+
+  // The feature the connection must have negotiated to send the msg
+  pub fn feature(&self) -> Option<Feature> {
+    match self {
+      TextReq::NegotiateReq { .. } | TextReq::PeerHelloReq { .. } => None,
+      TextReq::WatchTopicDistReq { .. }
+      | TextReq::UnwatchTopicDistReq { .. }
+      | TextReq::GetTopicDistReq { .. }
+      | TextReq::LocateTopicReq { .. } => Some(Feature::TopicDist),
+      TextReq::GetRoutesReq { .. } => Some(Feature::ConditionalRoutes),
+      TextReq::GetRoutesDeltaReq { .. } => Some(Feature::DeltaSync),
+      TextReq::WatchRoutesReq { .. } | TextReq::UnwatchRoutesReq { .. } => {
+        Some(Feature::RouteWatch)
+      }
+      TextReq::SetRouteOptionsReq { .. } => Some(Feature::RouteOptions),
+      TextReq::WatchEventsReq { .. } | TextReq::UnwatchEventsReq { .. } => {
+        Some(Feature::EventWatch)
+      }
+      TextReq::GetStatsReq { .. } => Some(Feature::Stats),
+      TextReq::ResolveIpReq { .. } => Some(Feature::ResolveIp),
+      TextReq::PickFrontendReq { .. } => Some(Feature::FrontendScheme),
+      TextReq::ReportLoadReq { .. } => Some(Feature::LoadReport),
+      TextReq::WatchStateReq { .. } => Some(Feature::StateSync),
+    }
+  }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TextMsg {
+  NegotiateRep {
+    version: u32,
+    features: Vec<Feature>,
+    r#ref: u32,
+  },
+  WatchTopicDistRep {
+    r#ref: u32,
+  },
+  UnwatchTopicDistRep {
+    r#ref: u32,
+  },
+  // The primary endpoint first, then the standby ones
+  LocateTopicRep {
+    endpoints: Vec<String>,
+    r#ref: u32,
+  },
+  GetTopicDistRep {
+    checksum: u32,
+    topics: HashMap<String, String>,
+    r#ref: u32,
+  },
+  // Pushed when a watched topic was moved (endpoint is the new one) or deleted
+  TopicDistChangedMsg {
+    topic: String,
+    endpoint: Option<String>,
+    checksum: u32,
+  },
+  // Pushed when changes were missed, all cached locations should be dropped
+  TopicDistInvalidatedMsg {
+    checksum: u32,
+  },
+  GetRoutesRep {
+    checksum: u32,
+    route_groups: BTreeMap<&'static str, Vec<Arc<SharedRouteGroup>>>,
+    // Endpoint to weight, for the endpoints not having the default weight
+    weights: BTreeMap<String, u32>,
+    // Method to path to limit, for the paths having a limit declared
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    rate_limits: RateLimits,
+    r#ref: u32,
+  },
+  RoutesNotModifiedRep {
+    checksum: u32,
+    r#ref: u32,
+  },
+  GetRoutesDeltaRep {
+    checksum: u32,
+    full: bool,
+    updated: BTreeMap<&'static str, Vec<Arc<SharedRouteGroup>>>,
+    removed: BTreeMap<&'static str, Vec<Arc<str>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    weights: Option<BTreeMap<String, u32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rate_limits: Option<RateLimits>,
+    r#ref: u32,
+  },
+  WatchRoutesRep {
+    checksum: u32,
+    full: bool,
+    updated: BTreeMap<&'static str, Vec<Arc<SharedRouteGroup>>>,
+    removed: BTreeMap<&'static str, Vec<Arc<str>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    weights: Option<BTreeMap<String, u32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rate_limits: Option<RateLimits>,
+    r#ref: u32,
+  },
+  UnwatchRoutesRep {
+    r#ref: u32,
+  },
+  SetRouteOptionsRep {
+    r#ref: u32,
+  },
+  WatchEventsRep {
+    r#ref: u32,
+  },
+  UnwatchEventsRep {
+    r#ref: u32,
+  },
+  EventMsg {
+    event: Event,
+  },
+  // Pushed when events were dropped as the connection was too slow
+  EventsMissedMsg {
+    count: u64,
+  },
+  // Pushed to the watchers when the route table changed, relative to the last
+  // checksum they got
+  RoutesChangedMsg {
+    checksum: u32,
+    updated: BTreeMap<&'static str, Vec<Arc<SharedRouteGroup>>>,
+    removed: BTreeMap<&'static str, Vec<Arc<str>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    weights: Option<BTreeMap<String, u32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rate_limits: Option<RateLimits>,
+  },
+  GetStatsRep {
+    stats: Stats,
+    r#ref: u32,
+  },
+  PickFrontendRep {
+    endpoint: String,
+    r#ref: u32,
+  },
+  ReportLoadRep {
+    r#ref: u32,
+  },
+  ResolveIpRep {
+    ip: String,
+    port: u16,
+    addr_type: AddrType,
+    is_tls: bool,
+    r#ref: u32,
+  },
+  PeerHelloRep {
+    master_id: String,
+    r#ref: u32,
+  },
+  WatchStateRep {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    snapshot: Option<StateSnapshot>,
+    r#ref: u32,
+  },
+  StateDeltaMsg {
+    delta: StateDelta,
+  },
+  // Pushed instead of the deltas missed as the standby was too slow
+  StateSnapshotMsg {
+    snapshot: StateSnapshot,
+  },
+  // Replied to the writes reaching a master which is not the leader, instead of
+  // error_rep, they should be sent again to the endpoint of the leader, or
+  // later if none, as it is being elected
+  NotLeaderRep {
+    leader: Option<LeaderInfo>,
+    r#ref: u32,
+  },
+  ErrorRep {
+    code: i32,
+    desc: String,
+    r#ref: u32,
+  },
+}
+
+impl TextMsg {
+  #[inline]
+  pub fn encode(&self) -> String {
+    serde_json::to_string(self).unwrap()
+  }
+
+  // Echoes the trace id of the req replied to
+  pub fn encode_traced(&self, trace_id: &str) -> String {
+    let mut value = serde_json::to_value(self).unwrap();
+    if let serde_json::Value::Object(fields) = &mut value {
+      fields.insert("trace_id".to_owned(), trace_id.into());
+    }
+    value.to_string()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+
+  // As specified in proto/text_protocol.md
+  #[test]
+  fn test_wire_format() {
+    let req = json!({ "type": "negotiate_req", "version": 7, "features": [], "ref": 1 });
+    assert!(matches!(
+      serde_json::from_value::<TextReq>(req).unwrap(),
+      TextReq::NegotiateReq { version: 7, r#ref: 1, .. }
+    ));
+    let req = json!({ "type": "set_route_options_req", "weight": 2, "ref": 3 });
+    assert!(matches!(
+      serde_json::from_value::<TextReq>(req).unwrap(),
+      TextReq::SetRouteOptionsReq { weight: 2, rate_limits: None, r#ref: 3 }
+    ));
+    assert!(serde_json::from_value::<TextReq>(json!({ "type": "unknown_req", "ref": 1 })).is_err());
+
+    let rep = TextMsg::NegotiateRep { version: 7, features: vec![Feature::StateSync], r#ref: 1 };
+    assert_eq!(
+      serde_json::from_str::<serde_json::Value>(&rep.encode_traced("abc")).unwrap(),
+      json!({
+        "type": "negotiate_rep",
+        "version": 7,
+        "features": ["state_sync"],
+        "ref": 1,
+        "trace_id": "abc",
+      })
+    );
+    let rep = TextMsg::ErrorRep { code: 1005, desc: "too old".to_owned(), r#ref: 2 };
+    assert_eq!(
+      serde_json::from_str::<serde_json::Value>(&rep.encode()).unwrap(),
+      json!({ "type": "error_rep", "code": 1005, "desc": "too old", "ref": 2 })
+    );
+  }
+}
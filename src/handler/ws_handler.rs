@@ -1,20 +1,53 @@
 use std::{
   cell::{Cell, RefCell},
-  hash::Hasher,
-  net::{IpAddr, SocketAddr},
+  future::Future,
+  net::SocketAddr,
   rc::Rc,
-  sync::atomic::{AtomicU32, Ordering},
+  sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+  },
+  time::{Duration, Instant},
 };
 
 use actix::{prelude::*, Actor};
 use actix_web::HttpRequest;
 use actix_web_actors::ws;
-use ahash::{AHasher, HashMap};
-use chrono::Utc;
+use ahash::HashSet;
 use maxwell_protocol::{self, *};
+use opentelemetry::KeyValue;
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
 
+use super::{
+  client_cert::{client_identity_of, is_allowed_to_register, ClientIdentity},
+  connection_registry::{CloseConnection, Connection, CONNECTION_REGISTRY},
+  http_handler::{
+    build_frontend_endpoint, build_route_dist_checksum, build_routes_delta,
+    build_standby_endpoints, build_topic_dist, locate_topic_error_code, set_routes_error_code,
+    HttpHandler,
+  },
+  protocol_version::NegotiatedProtocol,
+  rate_limit::{protocol_msg_type, MsgRateLimiter, RateLimited},
+  text_msg::{TextMsg, TextReq},
+};
 use crate::route_mgr::*;
-use crate::{node_mgr::*, topic_mgr::TOPIC_MGR};
+use crate::{
+  audit, cluster,
+  config::CONFIG,
+  db_pool::{DbPoolError, DB_POOL},
+  error_code::ExtErrorCode,
+  event_bus::{self, Event, EventKind, ALL_EVENT_KINDS},
+  health_policy,
+  maintenance::{self, Maintenance},
+  node_mgr::*,
+  slow_log,
+  standby::{self, StateDelta, StateSnapshot},
+  stats::{Stats, REQ_RATES},
+  telemetry,
+  topic_mgr::{TopicChange, TOPIC_MGR},
+  trace::{new_trace_id, traced, TraceScope},
+};
 
 static ID_SEED: AtomicU32 = AtomicU32::new(1);
 
@@ -22,20 +55,88 @@ fn next_id() -> u32 {
   ID_SEED.fetch_add(1, Ordering::Relaxed)
 }
 
+// The client is told to retry later, as when too many reqs are in flight
+fn db_pool_error_rep(
+  action: &str, id: &str, err: DbPoolError, r#ref: u32,
+) -> maxwell_protocol::ProtocolMsg {
+  log::error!("{}: id: {:?}, err: {:?}", action, id, err);
+
+  maxwell_protocol::ErrorRep {
+    code: ExtErrorCode::Busy as i32,
+    desc: format!("{}: id: {}, err: {}", action, id, err),
+    r#ref,
+  }
+  .into_enum()
+}
+
+fn maintenance_rep(err: Maintenance, r#ref: u32) -> maxwell_protocol::ProtocolMsg {
+  log::warn!("Rejected write: err: {:?}", err);
+
+  maxwell_protocol::ErrorRep {
+    code: ExtErrorCode::Maintenance as i32,
+    desc: err.to_string(),
+    r#ref,
+  }
+  .into_enum()
+}
+
 struct HandlerInner {
   id: u32,
   peer_addr: SocketAddr,
   node_type: Cell<NodeType>,
   node_id: RefCell<Option<NodeId>>,
+  tenant: String,
+  connection: Arc<Connection>,
+  client_identity: Option<ClientIdentity>,
+  is_tls: bool,
+  rate_limiter: MsgRateLimiter,
+  protocol: RefCell<NegotiatedProtocol>,
+  watched_topics: RefCell<HashSet<String>>,
+  is_subscribing_topic_changes: Cell<bool>,
+  watched_route_table: RefCell<Option<Arc<RouteTable>>>,
+  is_subscribing_route_changes: Cell<bool>,
+  watched_event_kinds: RefCell<HashSet<EventKind>>,
+  is_subscribing_events: Cell<bool>,
+  is_subscribing_state_deltas: Cell<bool>,
+  // The state deltas are held back while a snapshot is being taken off the
+  // actor, so that none is sent before the snapshot it follows
+  taking_snapshot_count: Cell<u32>,
+  held_state_deltas: RefCell<Vec<StateDelta>>,
+  // When anything, pongs included, was last received from the peer
+  last_active_at: Cell<Instant>,
+  // Reset by every frame decoded successfully
+  decode_failure_count: Cell<u32>,
+  // Reqs spawned off the actor but not replied yet
+  in_flight_count: Cell<u32>,
 }
 
 impl HandlerInner {
-  fn new(req: &HttpRequest) -> Self {
+  fn new(req: &HttpRequest, tenant: String) -> Self {
+    let id = next_id();
+    let peer_addr = req.peer_addr().unwrap();
     HandlerInner {
-      id: next_id(),
-      peer_addr: req.peer_addr().unwrap(),
+      id,
+      peer_addr,
       node_type: Cell::new(NodeType::Unknown),
       node_id: RefCell::new(None),
+      tenant,
+      connection: Arc::new(Connection::new(id, peer_addr)),
+      client_identity: client_identity_of(req),
+      is_tls: req.connection_info().scheme() == "https",
+      rate_limiter: MsgRateLimiter::new(),
+      protocol: RefCell::new(NegotiatedProtocol::default()),
+      watched_topics: RefCell::new(HashSet::default()),
+      is_subscribing_topic_changes: Cell::new(false),
+      watched_route_table: RefCell::new(None),
+      is_subscribing_route_changes: Cell::new(false),
+      watched_event_kinds: RefCell::new(HashSet::default()),
+      is_subscribing_events: Cell::new(false),
+      is_subscribing_state_deltas: Cell::new(false),
+      taking_snapshot_count: Cell::new(0),
+      held_state_deltas: RefCell::new(Vec::new()),
+      last_active_at: Cell::new(Instant::now()),
+      decode_failure_count: Cell::new(0),
+      in_flight_count: Cell::new(0),
     }
   }
 
@@ -45,13 +146,13 @@ impl HandlerInner {
       ProtocolMsg::PingReq(req) => self.handle_ping_req(req),
       ProtocolMsg::RegisterFrontendReq(req) => self.handle_register_frontend_req(req),
       ProtocolMsg::RegisterBackendReq(req) => self.handle_register_backend_req(req),
-      ProtocolMsg::RegisterServiceReq(req) => self.handle_register_service_req(req),
-      ProtocolMsg::SetRoutesReq(req) => self.handle_set_routes_req(req),
+      ProtocolMsg::RegisterServiceReq(req) => self.handle_register_service_req(req).await,
+      ProtocolMsg::SetRoutesReq(req) => self.handle_set_routes_req(req).await,
       ProtocolMsg::GetRoutesReq(req) => self.handle_get_routes_req(req),
       ProtocolMsg::GetTopicDistChecksumReq(req) => self.handle_get_topic_dist_checksum_req(req),
       ProtocolMsg::GetRouteDistChecksumReq(req) => self.handle_get_route_dist_checksum_req(req),
       ProtocolMsg::PickFrontendReq(req) => self.handle_pick_frontend_req(req),
-      ProtocolMsg::LocateTopicReq(req) => self.handle_locate_topic_req(req),
+      ProtocolMsg::LocateTopicReq(req) => self.handle_locate_topic_req(req).await,
       ProtocolMsg::ResolveIpReq(req) => self.handle_resolve_ip_req(req),
       _ => {
         log::error!("Received unknown msg: {:?}", protocol_msg);
@@ -66,22 +167,6 @@ impl HandlerInner {
     }
   }
 
-  async fn handle_internal_msg(self: Rc<Self>, protocol_msg: ProtocolMsg) -> ProtocolMsg {
-    log::debug!("received internal msg: {:?}", protocol_msg);
-    match &protocol_msg {
-      _ => {
-        log::error!("Received unknown msg: {:?}", protocol_msg);
-
-        maxwell_protocol::ErrorRep {
-          code: ErrorCode::UnknownMsg as i32,
-          desc: format!("Received unknown msg: {:?}", protocol_msg),
-          r#ref: get_ref(&protocol_msg),
-        }
-        .into_enum()
-      }
-    }
-  }
-
   #[inline(always)]
   fn handle_ping_req(
     self: Rc<Self>, req: maxwell_protocol::PingReq,
@@ -94,13 +179,16 @@ impl HandlerInner {
   fn handle_register_frontend_req(
     self: Rc<Self>, req: maxwell_protocol::RegisterFrontendReq,
   ) -> maxwell_protocol::ProtocolMsg {
-    self.node_type.set(NodeType::Frontend);
-    *self.node_id.borrow_mut() = Some(req.id.clone());
+    if let Some(rep) = self.check_client_identity(NodeType::Frontend, &req.id, req.r#ref) {
+      return rep;
+    }
+    self.set_node(NodeType::Frontend, req.id.clone());
 
     log::info!("Registering frontend: from: {:?}, req: {:?}", self.peer_addr.ip(), req);
 
     if let Some(frontend) = FRONTEND_MGR.get(&req.id) {
       if req.http_port == frontend.http_port {
+        audit::record(self.actor(), "register-frontend", json!({ "node_id": req.id }));
         maxwell_protocol::RegisterFrontendRep { r#ref: req.r#ref }.into_enum()
       } else {
         log::error!(
@@ -135,13 +223,16 @@ impl HandlerInner {
   fn handle_register_backend_req(
     self: Rc<Self>, req: maxwell_protocol::RegisterBackendReq,
   ) -> maxwell_protocol::ProtocolMsg {
-    self.node_type.set(NodeType::Backend);
-    *self.node_id.borrow_mut() = Some(req.id.clone());
+    if let Some(rep) = self.check_client_identity(NodeType::Backend, &req.id, req.r#ref) {
+      return rep;
+    }
+    self.set_node(NodeType::Backend, req.id.clone());
 
     log::info!("Registering backend: from: {:?}, req: {:?}", self.peer_addr.ip(), req);
 
     if let Some(backend) = BACKEND_MGR.get(&req.id) {
       if req.http_port == backend.http_port {
+        audit::record(self.actor(), "register-backend", json!({ "node_id": req.id }));
         maxwell_protocol::RegisterBackendRep { r#ref: req.r#ref }.into_enum()
       } else {
         log::error!(
@@ -172,8 +263,7 @@ impl HandlerInner {
     }
   }
 
-  #[inline(always)]
-  fn handle_register_service_req(
+  async fn handle_register_service_req(
     self: Rc<Self>, req: maxwell_protocol::RegisterServiceReq,
   ) -> maxwell_protocol::ProtocolMsg {
     let id = if req.id != "" {
@@ -182,22 +272,123 @@ impl HandlerInner {
       format!("{}:{}", self.peer_addr.ip(), req.http_port)
     };
 
-    self.node_type.set(NodeType::Service);
-    *self.node_id.borrow_mut() = Some(id.clone());
+    if let Some(rep) = self.check_client_identity(NodeType::Service, &id, req.r#ref) {
+      return rep;
+    }
+    if let Err(not_leader) = cluster::check_leader() {
+      log::warn!("Rejected service: id: {:?}, err: {:?}", id, not_leader);
+
+      // maxwell-protocol has no field for the leader, so it is only in the desc
+      return maxwell_protocol::ErrorRep {
+        code: ExtErrorCode::NotLeader as i32,
+        desc: not_leader.to_string(),
+        r#ref: req.r#ref,
+      }
+      .into_enum();
+    }
+    if let Err(err) = maintenance::check_writable() {
+      return maintenance_rep(err, req.r#ref);
+    }
+    self.set_node(NodeType::Service, id.clone());
 
     log::info!("Registering service: from: {:?}, req: {:?}", self.peer_addr.ip(), req);
 
-    let new_service = Service::new(id, self.peer_addr.ip(), req.http_port);
-    SERVICE_MGR.add(new_service);
+    let new_service = Service::new(id.clone(), self.peer_addr.ip(), req.http_port);
+    let result = DB_POOL
+      .run({
+        let id = id.clone();
+        let tenant = self.tenant.clone();
+        move || {
+          ROUTE_MGR.set_tenant(&id, &tenant)?;
+          SERVICE_MGR.add(new_service)
+        }
+      })
+      .await;
+    match result {
+      Ok(Ok(())) => {}
+      Ok(Err(err)) => {
+        log::error!("Failed to register service: id: {:?}, err: {:?}", id, err);
+
+        return maxwell_protocol::ErrorRep {
+          code: ErrorCode::MasterError as i32,
+          desc: format!("Failed to register service: id: {}, err: {}", id, err),
+          r#ref: req.r#ref,
+        }
+        .into_enum();
+      }
+      Err(err) => return db_pool_error_rep("Failed to register service", &id, err, req.r#ref),
+    }
+    audit::record(
+      self.actor(),
+      "register-service",
+      json!({ "node_id": id, "http_port": req.http_port, "tenant": self.tenant }),
+    );
 
     maxwell_protocol::RegisterServiceRep { r#ref: req.r#ref }.into_enum()
   }
 
-  #[inline(always)]
-  fn handle_set_routes_req(
+  // Who the mutations asked for over the connection are audited as
+  fn actor(&self) -> String {
+    match self.node_id.borrow().as_ref() {
+      Some(node_id) => format!("{}:{}@{}", self.node_type.get().as_str(), node_id, self.peer_addr),
+      None => format!("ws@{}", self.peer_addr),
+    }
+  }
+
+  #[inline]
+  fn set_node(&self, node_type: NodeType, node_id: NodeId) {
+    self.node_type.set(node_type);
+    *self.node_id.borrow_mut() = Some(node_id.clone());
+    self.connection.set_node(node_type, node_id);
+  }
+
+  // Rejects the registration if the client certificate does not grant it
+  fn check_client_identity(
+    &self, node_type: NodeType, node_id: &str, r#ref: u32,
+  ) -> Option<maxwell_protocol::ProtocolMsg> {
+    if is_allowed_to_register(self.client_identity.as_ref(), node_type, node_id) {
+      return None;
+    }
+    log::error!(
+      "Client certificate rejected: from: {:?}, identity: {:?}, node_type: {:?}, node_id: {:?}",
+      self.peer_addr.ip(),
+      self.client_identity,
+      node_type,
+      node_id
+    );
+    Some(
+      maxwell_protocol::ErrorRep {
+        code: ExtErrorCode::ClientCertRejected as i32,
+        desc: format!(
+          "Client certificate does not allow registering: node_type: {:?}, node_id: {}",
+          node_type, node_id
+        ),
+        r#ref,
+      }
+      .into_enum(),
+    )
+  }
+
+  async fn handle_set_routes_req(
     self: Rc<Self>, req: maxwell_protocol::SetRoutesReq,
   ) -> maxwell_protocol::ProtocolMsg {
-    if let Some(service_id) = self.node_id.borrow().as_ref() {
+    // Not borrowed across the await
+    let service_id = self.node_id.borrow().clone();
+    if let Some(service_id) = service_id {
+      if let Err(not_leader) = cluster::check_leader() {
+        log::warn!("Rejected routes: id: {:?}, err: {:?}", service_id, not_leader);
+
+        // maxwell-protocol has no field for the leader, so it is only in the desc
+        return maxwell_protocol::ErrorRep {
+          code: ExtErrorCode::NotLeader as i32,
+          desc: not_leader.to_string(),
+          r#ref: req.r#ref,
+        }
+        .into_enum();
+      }
+      if let Err(err) = maintenance::check_writable() {
+        return maintenance_rep(err, req.r#ref);
+      }
       log::info!("Setting routes: id: {:?}, req : {:?}", service_id, req);
       let pb = PathBundle {
         ws_paths: req.ws_paths.into_iter().collect(),
@@ -210,7 +401,42 @@ impl HandlerInner {
         options_paths: req.options_paths.into_iter().collect(),
         trace_paths: req.trace_paths.into_iter().collect(),
       };
-      ROUTE_MGR.set_reverse_route_group(service_id.clone(), pb);
+      let pb = match pb.normalize() {
+        Ok(pb) => pb,
+        Err(err) => {
+          log::error!("Failed to set routes: id: {:?}, err: {:?}", service_id, err);
+
+          return maxwell_protocol::ErrorRep {
+            code: ExtErrorCode::InvalidRoutes as i32,
+            desc: format!("Failed to set routes: id: {}, err: {}", service_id, err),
+            r#ref: req.r#ref,
+          }
+          .into_enum();
+        }
+      };
+      // Recorded only once applied
+      let details = json!({ "service_id": service_id, "paths": pb });
+      let result = DB_POOL
+        .run({
+          let service_id = service_id.clone();
+          move || ROUTE_MGR.claim_and_set_routes(service_id, pb)
+        })
+        .await;
+      match result {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => {
+          log::error!("Failed to set routes: id: {:?}, err: {:?}", service_id, err);
+
+          return maxwell_protocol::ErrorRep {
+            code: set_routes_error_code(&err),
+            desc: format!("Failed to set routes: id: {}, err: {}", service_id, err),
+            r#ref: req.r#ref,
+          }
+          .into_enum();
+        }
+        Err(err) => return db_pool_error_rep("Failed to set routes", &service_id, err, req.r#ref),
+      }
+      audit::record(self.actor(), "set-routes", details);
       maxwell_protocol::SetRoutesRep { r#ref: req.r#ref }.into_enum()
     } else {
       log::error!(
@@ -236,56 +462,17 @@ impl HandlerInner {
   fn handle_get_routes_req(
     self: Rc<Self>, req: maxwell_protocol::GetRoutesReq,
   ) -> maxwell_protocol::ProtocolMsg {
-    let mut stale_services = vec![];
-
-    let mut ws_route_groups = HashMap::default();
-    let mut get_route_groups = HashMap::default();
-    let mut post_route_groups = HashMap::default();
-    let mut put_route_groups = HashMap::default();
-    let mut patch_route_groups = HashMap::default();
-    let mut delete_route_groups = HashMap::default();
-    let mut head_route_groups = HashMap::default();
-    let mut options_route_groups = HashMap::default();
-    let mut trace_route_groups = HashMap::default();
-
-    for reverse_route_group in ROUTE_MGR.reverse_route_group_iter() {
-      let pb = reverse_route_group.value();
-      let service_id = reverse_route_group.key();
-      let (endpoint, is_healthy) = match SERVICE_MGR.get(service_id) {
-        Some(service) => (service.private_endpoint(), service.is_healthy()),
-        None => {
-          log::warn!("Found a stale service: id: {:?}", service_id);
-          stale_services.push(service_id.clone());
-          continue;
-        }
-      };
-
-      Self::build_route_groups(&mut ws_route_groups, &pb.ws_paths, &endpoint, is_healthy);
-      Self::build_route_groups(&mut get_route_groups, &pb.get_paths, &endpoint, is_healthy);
-      Self::build_route_groups(&mut post_route_groups, &pb.post_paths, &endpoint, is_healthy);
-      Self::build_route_groups(&mut put_route_groups, &pb.put_paths, &endpoint, is_healthy);
-      Self::build_route_groups(&mut patch_route_groups, &pb.patch_paths, &endpoint, is_healthy);
-      Self::build_route_groups(&mut delete_route_groups, &pb.delete_paths, &endpoint, is_healthy);
-      Self::build_route_groups(&mut head_route_groups, &pb.head_paths, &endpoint, is_healthy);
-      Self::build_route_groups(&mut options_route_groups, &pb.options_paths, &endpoint, is_healthy);
-      Self::build_route_groups(&mut trace_route_groups, &pb.trace_paths, &endpoint, is_healthy);
-    }
-
-    for service_id in &stale_services {
-      SERVICE_MGR.remove(service_id);
-      ROUTE_MGR.remove_reverse_route_group(service_id);
-    }
-
+    let table = ROUTE_MGR.snapshot(&self.tenant);
     maxwell_protocol::GetRoutesRep {
-      ws_route_groups: ws_route_groups.values().cloned().collect(),
-      get_route_groups: get_route_groups.values().cloned().collect(),
-      post_route_groups: post_route_groups.values().cloned().collect(),
-      put_route_groups: put_route_groups.values().cloned().collect(),
-      patch_route_groups: patch_route_groups.values().cloned().collect(),
-      delete_route_groups: delete_route_groups.values().cloned().collect(),
-      head_route_groups: head_route_groups.values().cloned().collect(),
-      options_route_groups: options_route_groups.values().cloned().collect(),
-      trace_route_groups: trace_route_groups.values().cloned().collect(),
+      ws_route_groups: table.proto_route_groups("ws"),
+      get_route_groups: table.proto_route_groups("get"),
+      post_route_groups: table.proto_route_groups("post"),
+      put_route_groups: table.proto_route_groups("put"),
+      patch_route_groups: table.proto_route_groups("patch"),
+      delete_route_groups: table.proto_route_groups("delete"),
+      head_route_groups: table.proto_route_groups("head"),
+      options_route_groups: table.proto_route_groups("options"),
+      trace_route_groups: table.proto_route_groups("trace"),
       r#ref: req.r#ref,
     }
     .into_enum()
@@ -294,45 +481,14 @@ impl HandlerInner {
   fn handle_get_topic_dist_checksum_req(
     self: Rc<Self>, req: maxwell_protocol::GetTopicDistChecksumReq,
   ) -> maxwell_protocol::ProtocolMsg {
-    maxwell_protocol::GetTopicDistChecksumRep { checksum: BACKEND_MGR.checksum(), r#ref: req.r#ref }
+    maxwell_protocol::GetTopicDistChecksumRep { checksum: TOPIC_MGR.checksum(), r#ref: req.r#ref }
       .into_enum()
   }
 
   fn handle_get_route_dist_checksum_req(
     self: Rc<Self>, req: maxwell_protocol::GetRouteDistChecksumReq,
   ) -> maxwell_protocol::ProtocolMsg {
-    let mut stale_services = vec![];
-    let mut is_every_service_healthy = true;
-    for reverse_route_group in ROUTE_MGR.reverse_route_group_iter() {
-      if let Some(service) = SERVICE_MGR.get(reverse_route_group.key()) {
-        if !service.is_healthy() {
-          log::info!("Found an unhealthy service: id: {:?}", service.id());
-          is_every_service_healthy = false;
-          break;
-        }
-      } else {
-        log::info!("Found a stale service: id: {:?}", reverse_route_group.key());
-        stale_services.push(reverse_route_group.key().clone());
-        is_every_service_healthy = false;
-        break;
-      }
-    }
-
-    for service_id in &stale_services {
-      SERVICE_MGR.remove(service_id);
-      ROUTE_MGR.remove_reverse_route_group(&service_id);
-    }
-
-    let checksum = crc32fast::hash(
-      format!(
-        "{}|{}|{}",
-        SERVICE_MGR.version(),
-        ROUTE_MGR.version(),
-        if is_every_service_healthy { 1 } else { Utc::now().timestamp_millis() }
-      )
-      .as_bytes(),
-    );
-
+    let checksum = build_route_dist_checksum();
     maxwell_protocol::GetRouteDistChecksumRep { checksum, r#ref: req.r#ref }.into_enum()
   }
 
@@ -340,22 +496,8 @@ impl HandlerInner {
   fn handle_pick_frontend_req(
     self: Rc<Self>, req: maxwell_protocol::PickFrontendReq,
   ) -> maxwell_protocol::ProtocolMsg {
-    if let Some(frontend) = FRONTEND_MGR.pick() {
-      let ip = match self.peer_addr.ip() {
-        IpAddr::V4(ip) => {
-          if ip.is_private() {
-            frontend.private_ip
-          } else {
-            frontend.public_ip
-          }
-        }
-        IpAddr::V6(_) => frontend.public_ip,
-      };
-      maxwell_protocol::PickFrontendRep {
-        endpoint: format!("{}:{}", ip, frontend.http_port),
-        r#ref: req.r#ref,
-      }
-      .into_enum()
+    if let Some(endpoint) = self.pick_frontend_endpoint(None) {
+      maxwell_protocol::PickFrontendRep { endpoint, r#ref: req.r#ref }.into_enum()
     } else {
       log::error!("Failed to find an available frontend.");
 
@@ -368,17 +510,25 @@ impl HandlerInner {
     }
   }
 
-  #[inline(always)]
-  fn handle_locate_topic_req(
+  async fn handle_locate_topic_req(
     self: Rc<Self>, req: maxwell_protocol::LocateTopicReq,
   ) -> maxwell_protocol::ProtocolMsg {
-    match TOPIC_MGR.locate(&req.topic) {
-      Ok(Some(backend_id)) => {
+    let result = DB_POOL
+      .run({
+        let topic = req.topic.clone();
+        let client = self.peer_addr.ip();
+        move || TOPIC_MGR.locate_or_assign(&topic, Some(client))
+      })
+      .await
+      .map_err(anyhow::Error::from)
+      .and_then(|result| result);
+    match result {
+      Ok(backend_id) => {
         log::debug!("Found the backend: topic: {:?}, backend_id: {:?}", req.topic, backend_id);
 
         if let Some(backend) = BACKEND_MGR.get(&backend_id) {
           maxwell_protocol::LocateTopicRep {
-            endpoint: format!("{}:{}", backend.private_ip, backend.http_port),
+            endpoint: backend.private_endpoint(),
             r#ref: req.r#ref,
           }
           .into_enum()
@@ -400,49 +550,11 @@ impl HandlerInner {
           .into_enum()
         }
       }
-      Ok(None) => {
-        if let Some(backend) = BACKEND_MGR.pick_with(|_, ids| {
-          let mut hasher = AHasher::default();
-          hasher.write(req.topic.as_bytes());
-          let hash = hasher.finish();
-          let index = hash % ids.len() as u64;
-          ids.get(index as usize)
-        }) {
-          log::debug!("Picked the backend: topic: {:?}, backend_id: {:?}", req.topic, backend.id());
-
-          match TOPIC_MGR.assign(req.topic.clone(), backend.id().clone()) {
-            Ok(()) => maxwell_protocol::LocateTopicRep {
-              endpoint: format!("{}:{}", backend.private_ip, backend.http_port),
-              r#ref: req.r#ref,
-            }
-            .into_enum(),
-            Err(err) => {
-              log::error!("Failed to assign topic: {:?}, err: {:?}", req.topic, err);
-
-              return maxwell_protocol::ErrorRep {
-                code: ErrorCode::FailedToLocateTopic as i32,
-                desc: format!("Failed to assign topic: {}, err: {}", req.topic, err),
-                r#ref: req.r#ref,
-              }
-              .into_enum();
-            }
-          }
-        } else {
-          log::error!("Failed to find an available backend: topic: {:?}", req.topic);
-
-          maxwell_protocol::ErrorRep {
-            code: ErrorCode::FailedToLocateTopic as i32,
-            desc: format!("Failed to find an available backend: topic: {}", req.topic),
-            r#ref: req.r#ref,
-          }
-          .into_enum()
-        }
-      }
       Err(err) => {
         log::error!("Failed to locate topic: {:?}, err: {:?}", req.topic, err);
 
         maxwell_protocol::ErrorRep {
-          code: ErrorCode::FailedToLocateTopic as i32,
+          code: locate_topic_error_code(&err),
           desc: format!("Failed to locate topic: {}, err: {}", req.topic, err),
           r#ref: req.r#ref,
         }
@@ -451,6 +563,14 @@ impl HandlerInner {
     }
   }
 
+  // As over http, the domain endpoint for the connections over tls
+  #[inline]
+  fn pick_frontend_endpoint(&self, secure: Option<bool>) -> Option<String> {
+    let frontend = FRONTEND_MGR.pick()?;
+    let addr_type = HttpHandler::detect_addr_type(&self.peer_addr);
+    Some(build_frontend_endpoint(&frontend, addr_type, secure.unwrap_or(self.is_tls)))
+  }
+
   #[inline(always)]
   fn handle_resolve_ip_req(
     self: Rc<Self>, req: maxwell_protocol::ResolveIpReq,
@@ -459,6 +579,150 @@ impl HandlerInner {
       .into_enum()
   }
 
+  async fn locate_topic(self: Rc<Self>, topic: String, r#ref: u32) -> TextMsg {
+    let result = DB_POOL
+      .run({
+        let topic = topic.clone();
+        let client = self.peer_addr.ip();
+        move || TOPIC_MGR.locate_or_assign(&topic, Some(client))
+      })
+      .await
+      .map_err(anyhow::Error::from)
+      .and_then(|result| result);
+    match result {
+      Ok(backend_id) => match BACKEND_MGR.get(&backend_id) {
+        Some(backend) => {
+          let mut endpoints = vec![backend.private_endpoint()];
+          endpoints.extend(build_standby_endpoints(&topic, &backend_id));
+          TextMsg::LocateTopicRep { endpoints, r#ref }
+        }
+        None => TextMsg::ErrorRep {
+          code: ErrorCode::FailedToLocateTopic as i32,
+          desc: format!("Failed to find the backend: topic: {}, backend_id: {}", topic, backend_id),
+          r#ref,
+        },
+      },
+      Err(err) => {
+        log::error!("Failed to locate topic: {:?}, err: {:?}", topic, err);
+
+        match err.downcast::<cluster::NotLeader>() {
+          Ok(not_leader) => TextMsg::NotLeaderRep { leader: not_leader.leader, r#ref },
+          Err(err) => TextMsg::ErrorRep {
+            code: locate_topic_error_code(&err),
+            desc: format!("Failed to locate topic: {}, err: {}", topic, err),
+            r#ref,
+          },
+        }
+      }
+    }
+  }
+
+  async fn get_topic_dist(self: Rc<Self>, r#ref: u32) -> TextMsg {
+    match DB_POOL.run(build_topic_dist).await {
+      Ok((checksum, topics)) => TextMsg::GetTopicDistRep { checksum, topics, r#ref },
+      Err(err) => {
+        log::error!("Failed to get topic dist: id: {:?}, err: {:?}", self.id, err);
+
+        TextMsg::ErrorRep {
+          code: ExtErrorCode::Busy as i32,
+          desc: format!("Failed to get topic dist: err: {}", err),
+          r#ref,
+        }
+      }
+    }
+  }
+
+  async fn set_route_options(
+    self: Rc<Self>, weight: u32, rate_limits: Option<Vec<PathRateLimit>>, r#ref: u32,
+  ) -> TextMsg {
+    // Not borrowed across the await
+    let service_id = match self.node_id.borrow().as_ref() {
+      Some(service_id) if matches!(self.node_type.get(), NodeType::Service) => service_id.clone(),
+      _ => {
+        return TextMsg::ErrorRep {
+          code: ErrorCode::MasterError as i32,
+          desc: "Failed to set route options: the service is not registered.".to_owned(),
+          r#ref,
+        }
+      }
+    };
+    if let Err(not_leader) = cluster::check_leader() {
+      log::warn!("Rejected route options: id: {:?}, err: {:?}", service_id, not_leader);
+      return TextMsg::NotLeaderRep { leader: not_leader.leader, r#ref };
+    }
+    if let Err(err) = maintenance::check_writable() {
+      log::warn!("Rejected route options: id: {:?}, err: {:?}", service_id, err);
+      return TextMsg::ErrorRep {
+        code: ExtErrorCode::Maintenance as i32,
+        desc: err.to_string(),
+        r#ref,
+      };
+    }
+    // Against the routes already set
+    let pb = ROUTE_MGR.reverse_route_group(&service_id).unwrap_or_default();
+    let rate_limits = match rate_limits.map(|limits| normalize_rate_limits(limits, &pb)).transpose()
+    {
+      Ok(rate_limits) => rate_limits,
+      Err(err) => {
+        log::error!("Failed to set route options: id: {:?}, err: {:?}", service_id, err);
+        return TextMsg::ErrorRep {
+          code: ExtErrorCode::InvalidRoutes as i32,
+          desc: format!("Failed to set route options: id: {}, err: {}", service_id, err),
+          r#ref,
+        };
+      }
+    };
+    let result = DB_POOL
+      .run({
+        let service_id = service_id.clone();
+        move || -> anyhow::Result<()> {
+          ROUTE_MGR.set_weight(&service_id, weight)?;
+          match rate_limits {
+            Some(limits) => ROUTE_MGR.set_rate_limits(&service_id, limits),
+            None => Ok(()),
+          }
+        }
+      })
+      .await
+      .map_err(anyhow::Error::from)
+      .and_then(|result| result);
+    match result {
+      Ok(()) => TextMsg::SetRouteOptionsRep { r#ref },
+      Err(err) => {
+        log::error!("Failed to set route options: id: {:?}, err: {:?}", service_id, err);
+
+        TextMsg::ErrorRep {
+          code: ErrorCode::MasterError as i32,
+          desc: format!("Failed to set route options: id: {}, err: {}", service_id, err),
+          r#ref,
+        }
+      }
+    }
+  }
+
+  // Takes a slot for a req spawned off the actor, or returns the max if too
+  // many are in flight already
+  fn enter_in_flight(&self) -> Result<(), u32> {
+    let max_in_flight = CONFIG.load().server.ws_max_in_flight;
+    let in_flight_count = self.in_flight_count.get();
+    if max_in_flight > 0 && in_flight_count >= max_in_flight {
+      log::warn!(
+        "Too many reqs in flight: id: {:?}, peer_addr: {:?}, count: {:?}",
+        self.id,
+        self.peer_addr,
+        in_flight_count
+      );
+      return Err(max_in_flight);
+    }
+    self.in_flight_count.set(in_flight_count + 1);
+    Ok(())
+  }
+
+  #[inline]
+  fn exit_in_flight(&self) {
+    self.in_flight_count.set(self.in_flight_count.get() - 1);
+  }
+
   #[inline(always)]
   fn activate_node(self: Rc<Self>) {
     if let Some(node_id) = self.node_id.borrow().as_ref() {
@@ -471,25 +735,6 @@ impl HandlerInner {
       }
     }
   }
-
-  #[inline(always)]
-  fn build_route_groups(
-    route_groups_map: &mut HashMap<String, RouteGroup>, paths: &PathSet, endpoint: &String,
-    is_healthy: bool,
-  ) {
-    for path in paths {
-      let ws_route_group = route_groups_map.entry(path.to_owned()).or_insert_with(|| RouteGroup {
-        path: path.clone(),
-        healthy_endpoints: Vec::new(),
-        unhealthy_endpoints: Vec::new(),
-      });
-      if is_healthy {
-        ws_route_group.healthy_endpoints.push(endpoint.clone());
-      } else {
-        ws_route_group.unhealthy_endpoints.push(endpoint.clone());
-      }
-    }
-  }
 }
 
 pub struct Handler {
@@ -499,8 +744,11 @@ pub struct Handler {
 impl Actor for Handler {
   type Context = ws::WebsocketContext<Self>;
 
-  fn started(&mut self, _ctx: &mut Self::Context) {
+  fn started(&mut self, ctx: &mut Self::Context) {
     log::debug!("Handler actor started: id: {:?}", self.inner.id);
+    ctx.set_mailbox_capacity(CONFIG.load().server.ws_mailbox_capacity);
+    CONNECTION_REGISTRY.register(self.inner.connection.clone(), ctx.address());
+    self.start_heartbeat(ctx);
   }
 
   fn stopping(&mut self, _ctx: &mut Self::Context) -> Running {
@@ -510,63 +758,730 @@ impl Actor for Handler {
 
   fn stopped(&mut self, _ctx: &mut Self::Context) {
     log::debug!("Handler actor stopped: id: {:?}", self.inner.id);
+    CONNECTION_REGISTRY.unregister(self.inner.id);
   }
 }
 
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Handler {
   fn handle(&mut self, ws_msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+    self.inner.last_active_at.set(Instant::now());
     match ws_msg {
       Ok(ws::Message::Ping(ws_msg)) => {
         self.inner.clone().activate_node();
         ctx.pong(&ws_msg);
       }
       Ok(ws::Message::Pong(_)) => (),
-      Ok(ws::Message::Text(_)) => (),
+      Ok(ws::Message::Text(text)) => {
+        self.inner.connection.on_text_received();
+        self.handle_text_msg(&text, ctx)
+      }
       Ok(ws::Message::Binary(bin)) => {
-        let inner = self.inner.clone();
-        async move {
-          let res = maxwell_protocol::decode(&bin.into());
-          match res {
-            Ok(req) => Ok(inner.handle_external_msg(req).await),
-            Err(err) => Err(err),
+        self.inner.connection.on_binary_received();
+        let received_at = Instant::now();
+        // Binary replies can not carry it, but the log lines of the req do
+        let trace_id = new_trace_id();
+        let _span_guard = telemetry::enter_span_with(
+          "ws.msg",
+          vec![
+            KeyValue::new("trace_id", trace_id.clone()),
+            KeyValue::new("peer_addr", self.inner.peer_addr.to_string()),
+          ],
+        );
+        let decoded = {
+          let _decode_span_guard = telemetry::enter_span("ws.decode");
+          maxwell_protocol::decode(&bin.into())
+        };
+        let req = match decoded {
+          Ok(req) => req,
+          Err(err) => {
+            // The ref is inside the undecodable msg, so it can not be echoed
+            let rep = maxwell_protocol::ErrorRep {
+              code: ExtErrorCode::DecodeError as i32,
+              desc: format!("Failed to decode msg: err: {:?}", err),
+              r#ref: 0,
+            }
+            .into_enum();
+            ctx.binary(maxwell_protocol::encode(&rep));
+            self.on_decode_failed(&err, ctx);
+            return;
+          }
+        };
+        self.inner.decode_failure_count.set(0);
+        telemetry::set_attribute("msg_type", protocol_msg_type(&req));
+        REQ_RATES.record(protocol_msg_type(&req));
+        let _scope = TraceScope::enter(&trace_id);
+        if let Err(err) =
+          self.inner.rate_limiter.acquire(self.inner.peer_addr.ip(), protocol_msg_type(&req))
+        {
+          let rep = maxwell_protocol::ErrorRep {
+            code: ExtErrorCode::RateLimited as i32,
+            desc: err.to_string(),
+            r#ref: get_ref(&req),
+          }
+          .into_enum();
+          ctx.binary(maxwell_protocol::encode(&rep));
+          self.on_rate_limited(err, ctx);
+          return;
+        }
+        if let Err(max_in_flight) = self.inner.enter_in_flight() {
+          let rep = maxwell_protocol::ErrorRep {
+            code: ExtErrorCode::Busy as i32,
+            desc: format!("Too many reqs in flight: max: {}", max_in_flight),
+            r#ref: get_ref(&req),
           }
+          .into_enum();
+          ctx.binary(maxwell_protocol::encode(&rep));
+          return;
         }
-        .into_actor(self)
-        .map(move |res, _act, ctx| match res {
-          Ok(msg) => {
+        let msg_type = protocol_msg_type(&req);
+        let inner = self.inner.clone();
+        traced(trace_id, async move { inner.handle_external_msg(req).await })
+          .into_actor(self)
+          .map(move |msg, act, ctx| {
+            act.inner.exit_in_flight();
+            slow_log::check_msg(msg_type, act.inner.peer_addr, received_at.elapsed());
             if msg.is_some() {
               ctx.binary(maxwell_protocol::encode(&msg));
             }
-          }
-          Err(err) => log::error!("Failed to decode msg: {:?}", err),
-        })
-        .spawn(ctx);
+          })
+          .spawn(ctx);
       }
       Ok(ws::Message::Close(_)) => ctx.stop(),
+      Err(ws::ProtocolError::Overflow) => {
+        let rep = maxwell_protocol::ErrorRep {
+          code: ExtErrorCode::DecodeError as i32,
+          desc: format!("The frame exceeds the max size: {}", CONFIG.load().server.max_frame_size),
+          r#ref: 0,
+        }
+        .into_enum();
+        ctx.binary(maxwell_protocol::encode(&rep));
+        log::error!(
+          "Closing connection sending an oversized frame: id: {:?}, peer_addr: {:?}",
+          self.inner.id,
+          self.inner.peer_addr
+        );
+        ctx.close(Some(ws::CloseCode::Size.into()));
+        ctx.stop();
+      }
       _ => log::error!("Received unknown msg: {:?}", ws_msg),
     }
   }
 }
 
-impl actix::Handler<ProtocolMsg> for Handler {
-  type Result = Result<ProtocolMsg, HandleError<ProtocolMsg>>;
+// Lagged(n) means n changes were missed
+impl StreamHandler<Result<TopicChange, u64>> for Handler {
+  fn handle(&mut self, change: Result<TopicChange, u64>, ctx: &mut Self::Context) {
+    match change {
+      Ok(change) => {
+        if !self.inner.watched_topics.borrow().contains(&change.topic) {
+          return;
+        }
+        let endpoint = change
+          .backend_id
+          .and_then(|backend_id| BACKEND_MGR.get(&backend_id).map(|b| b.private_endpoint()));
+        log::debug!("Pushing topic change: topic: {:?}, endpoint: {:?}", change.topic, endpoint);
+        ctx.text(
+          TextMsg::TopicDistChangedMsg {
+            topic: change.topic,
+            endpoint,
+            checksum: TOPIC_MGR.checksum(),
+          }
+          .encode(),
+        );
+      }
+      Err(lagged_count) => {
+        log::warn!("Missed topic changes: id: {:?}, count: {:?}", self.inner.id, lagged_count);
+        ctx.text(TextMsg::TopicDistInvalidatedMsg { checksum: TOPIC_MGR.checksum() }.encode());
+      }
+    }
+  }
+
+  fn finished(&mut self, _ctx: &mut Self::Context) {
+    log::debug!("Topic change stream finished: id: {:?}", self.inner.id);
+  }
+}
+
+impl StreamHandler<Result<Event, u64>> for Handler {
+  fn handle(&mut self, event: Result<Event, u64>, ctx: &mut Self::Context) {
+    match event {
+      Ok(event) => {
+        if self.inner.watched_event_kinds.borrow().contains(&event.kind()) {
+          ctx.text(TextMsg::EventMsg { event }.encode());
+        }
+      }
+      Err(lagged_count) => {
+        log::warn!("Missed events: id: {:?}, count: {:?}", self.inner.id, lagged_count);
+        if !self.inner.watched_event_kinds.borrow().is_empty() {
+          ctx.text(TextMsg::EventsMissedMsg { count: lagged_count }.encode());
+        }
+      }
+    }
+  }
+
+  fn finished(&mut self, _ctx: &mut Self::Context) {
+    log::debug!("Event stream finished: id: {:?}", self.inner.id);
+  }
+}
+
+impl StreamHandler<Result<StateDelta, u64>> for Handler {
+  fn handle(&mut self, delta: Result<StateDelta, u64>, ctx: &mut Self::Context) {
+    match delta {
+      Ok(delta) if self.inner.taking_snapshot_count.get() > 0 => {
+        self.inner.held_state_deltas.borrow_mut().push(delta);
+      }
+      Ok(delta) => ctx.text(TextMsg::StateDeltaMsg { delta }.encode()),
+      Err(lagged_count) => {
+        log::warn!("Missed state deltas: id: {:?}, count: {:?}", self.inner.id, lagged_count);
+        self.spawn_state_snapshot(
+          |result| match result {
+            Ok(snapshot) => Some(TextMsg::StateSnapshotMsg { snapshot }),
+            Err(_) => None,
+          },
+          None,
+          ctx,
+        );
+      }
+    }
+  }
+
+  fn finished(&mut self, _ctx: &mut Self::Context) {
+    log::debug!("State delta stream finished: id: {:?}", self.inner.id);
+  }
+}
 
-  fn handle(&mut self, protocol_msg: ProtocolMsg, ctx: &mut Self::Context) -> Self::Result {
-    let inner = self.inner.clone();
-    async move { inner.handle_internal_msg(protocol_msg).await }
+impl StreamHandler<Arc<RouteTable>> for Handler {
+  fn handle(&mut self, table: Arc<RouteTable>, ctx: &mut Self::Context) {
+    let mut watched_route_table = self.inner.watched_route_table.borrow_mut();
+    let Some(last_table) = watched_route_table.as_ref() else {
+      return;
+    };
+    if last_table.checksum() == table.checksum() {
+      return;
+    }
+    let delta = table.diff(last_table);
+    log::debug!("Pushing route changes: id: {:?}, checksum: {:?}", self.inner.id, table.checksum());
+    ctx.text(
+      TextMsg::RoutesChangedMsg {
+        checksum: table.checksum(),
+        updated: delta.updated,
+        removed: delta.removed,
+        weights: delta.weights,
+        rate_limits: delta.rate_limits,
+      }
+      .encode(),
+    );
+    *watched_route_table = Some(table);
+  }
+
+  fn finished(&mut self, _ctx: &mut Self::Context) {
+    log::debug!("Route change stream finished: id: {:?}", self.inner.id);
+  }
+}
+
+impl actix::Handler<CloseConnection> for Handler {
+  type Result = ();
+
+  fn handle(&mut self, _msg: CloseConnection, ctx: &mut Self::Context) {
+    log::info!(
+      "Closing connection on request: id: {:?}, peer_addr: {:?}",
+      self.inner.id,
+      self.inner.peer_addr
+    );
+    ctx.close(Some(ws::CloseCode::Normal.into()));
+    ctx.stop();
+  }
+}
+
+impl Handler {
+  pub fn new(req: &HttpRequest, tenant: String) -> Self {
+    Self { inner: Rc::new(HandlerInner::new(req, tenant)) }
+  }
+
+  fn handle_text_msg(&mut self, text: &str, ctx: &mut <Self as Actor>::Context) {
+    let received_at = Instant::now();
+    let value = serde_json::from_str::<serde_json::Value>(text);
+    let trace_id = value
+      .as_ref()
+      .ok()
+      .and_then(|value| value.get("trace_id"))
+      .and_then(|trace_id| trace_id.as_str())
+      .map_or_else(new_trace_id, str::to_owned);
+    let _scope = TraceScope::enter(&trace_id);
+    let _span_guard = telemetry::enter_span_with(
+      "ws.text_msg",
+      vec![
+        KeyValue::new("trace_id", trace_id.clone()),
+        KeyValue::new("peer_addr", self.inner.peer_addr.to_string()),
+      ],
+    );
+    // Echoed even if the msg is not a valid req
+    let r#ref = value
+      .as_ref()
+      .ok()
+      .and_then(|value| value.get("ref"))
+      .and_then(|r#ref| r#ref.as_u64())
+      .and_then(|r#ref| u32::try_from(r#ref).ok())
+      .unwrap_or(0);
+    let req = match value.and_then(serde_json::from_value::<TextReq>) {
+      Ok(req) => req,
+      Err(err) => {
+        log::error!("Failed to decode text msg: {:?}, err: {:?}", text, err);
+        ctx.text(
+          TextMsg::ErrorRep {
+            code: ExtErrorCode::DecodeError as i32,
+            desc: format!("Failed to decode text msg: err: {}", err),
+            r#ref,
+          }
+          .encode_traced(&trace_id),
+        );
+        self.on_decode_failed(&err, ctx);
+        return;
+      }
+    };
+    self.inner.decode_failure_count.set(0);
+    let msg_type = req.msg_type();
+    telemetry::set_attribute("msg_type", msg_type);
+    REQ_RATES.record(msg_type);
+    log::debug!("received text msg: {:?}", req);
+    if let Err(err) = self.inner.rate_limiter.acquire(self.inner.peer_addr.ip(), req.msg_type()) {
+      ctx.text(
+        TextMsg::ErrorRep {
+          code: ExtErrorCode::RateLimited as i32,
+          desc: err.to_string(),
+          r#ref: req.r#ref(),
+        }
+        .encode_traced(&trace_id),
+      );
+      self.on_rate_limited(err, ctx);
+      return;
+    }
+    if let Some(feature) = req.feature() {
+      if let Err(err) = self.inner.protocol.borrow().check(feature) {
+        log::warn!("Rejected text msg: id: {:?}, err: {:?}", self.inner.id, err);
+        ctx.text(
+          TextMsg::ErrorRep {
+            code: ExtErrorCode::UnsupportedVersion as i32,
+            desc: err.to_string(),
+            r#ref: req.r#ref(),
+          }
+          .encode_traced(&trace_id),
+        );
+        return;
+      }
+    }
+    if req.is_peer_only() && !matches!(self.inner.node_type.get(), NodeType::Peer) {
+      log::warn!("Rejected peer msg from non peer: id: {:?}, req: {:?}", self.inner.id, req);
+      ctx.text(
+        TextMsg::ErrorRep {
+          code: ExtErrorCode::NotPeer as i32,
+          desc: "The connection has not been authenticated as a peer.".to_owned(),
+          r#ref: req.r#ref(),
+        }
+        .encode_traced(&trace_id),
+      );
+      return;
+    }
+    let rep = match req {
+      TextReq::PeerHelloReq { master_id, token, r#ref } => match cluster::authenticate_peer(&token)
+      {
+        Ok(()) => {
+          log::info!("Peer connected: id: {:?}, master_id: {:?}", self.inner.id, master_id);
+          self.inner.set_node(NodeType::Peer, master_id);
+          TextMsg::PeerHelloRep { master_id: CONFIG.load().cluster.master_id.clone(), r#ref }
+        }
+        Err(err) => {
+          log::error!("Rejected peer: id: {:?}, master_id: {:?}", self.inner.id, master_id);
+          TextMsg::ErrorRep { code: ExtErrorCode::NotPeer as i32, desc: err.to_string(), r#ref }
+        }
+      },
+      TextReq::WatchStateReq { skip_snapshot, r#ref } => {
+        // Before the snapshot, so that no change is missed in between
+        self.subscribe_state_deltas(ctx);
+        log::info!("Streaming state to standby: id: {:?}", self.inner.id);
+        if skip_snapshot.unwrap_or(false) {
+          TextMsg::WatchStateRep { snapshot: None, r#ref }
+        } else {
+          self.spawn_state_snapshot(
+            move |result| {
+              Some(match result {
+                Ok(snapshot) => TextMsg::WatchStateRep { snapshot: Some(snapshot), r#ref },
+                Err(err) => TextMsg::ErrorRep {
+                  code: ExtErrorCode::Busy as i32,
+                  desc: format!("Failed to take state snapshot: err: {}", err),
+                  r#ref,
+                },
+              })
+            },
+            Some(trace_id),
+            ctx,
+          );
+          return;
+        }
+      }
+      TextReq::NegotiateReq { version, features, r#ref } => {
+        match NegotiatedProtocol::negotiate(version, &features) {
+          Ok(protocol) => {
+            log::info!("Negotiated protocol: id: {:?}, protocol: {:?}", self.inner.id, protocol);
+            let rep = TextMsg::NegotiateRep {
+              version: protocol.version,
+              features: protocol.feature_list(),
+              r#ref,
+            };
+            *self.inner.protocol.borrow_mut() = protocol;
+            rep
+          }
+          Err(err) => TextMsg::ErrorRep {
+            code: ExtErrorCode::UnsupportedVersion as i32,
+            desc: err.to_string(),
+            r#ref,
+          },
+        }
+      }
+      TextReq::WatchTopicDistReq { topics, r#ref } => {
+        self.subscribe_topic_changes(ctx);
+        self.inner.watched_topics.borrow_mut().extend(topics);
+        TextMsg::WatchTopicDistRep { r#ref }
+      }
+      TextReq::UnwatchTopicDistReq { topics, r#ref } => {
+        let mut watched_topics = self.inner.watched_topics.borrow_mut();
+        for topic in &topics {
+          watched_topics.remove(topic);
+        }
+        TextMsg::UnwatchTopicDistRep { r#ref }
+      }
+      TextReq::LocateTopicReq { topic, r#ref } => {
+        let fut = self.inner.clone().locate_topic(topic, r#ref);
+        self.spawn_text_rep(fut, r#ref, msg_type, received_at, trace_id, ctx);
+        return;
+      }
+      TextReq::GetRoutesReq { checksum, r#ref } => {
+        let table = ROUTE_MGR.snapshot(&self.inner.tenant);
+        if checksum == Some(table.checksum()) {
+          TextMsg::RoutesNotModifiedRep { checksum: table.checksum(), r#ref }
+        } else {
+          TextMsg::GetRoutesRep {
+            checksum: table.checksum(),
+            route_groups: table.full().updated,
+            weights: table.weights().clone(),
+            rate_limits: table.rate_limits().clone(),
+            r#ref,
+          }
+        }
+      }
+      TextReq::GetRoutesDeltaReq { since, r#ref } => {
+        let (checksum, full, delta) = build_routes_delta(&self.inner.tenant, since);
+        TextMsg::GetRoutesDeltaRep {
+          checksum,
+          full,
+          updated: delta.updated,
+          removed: delta.removed,
+          weights: delta.weights,
+          rate_limits: delta.rate_limits,
+          r#ref,
+        }
+      }
+      TextReq::WatchRoutesReq { since, r#ref } => {
+        self.subscribe_route_changes(ctx);
+        let (table, delta) = ROUTE_MGR.route_delta(&self.inner.tenant, since);
+        let full = delta.is_none();
+        let delta = delta.unwrap_or_else(|| table.full());
+        let checksum = table.checksum();
+        *self.inner.watched_route_table.borrow_mut() = Some(table);
+        TextMsg::WatchRoutesRep {
+          checksum,
+          full,
+          updated: delta.updated,
+          removed: delta.removed,
+          weights: delta.weights,
+          rate_limits: delta.rate_limits,
+          r#ref,
+        }
+      }
+      TextReq::UnwatchRoutesReq { r#ref } => {
+        self.inner.watched_route_table.borrow_mut().take();
+        TextMsg::UnwatchRoutesRep { r#ref }
+      }
+      TextReq::SetRouteOptionsReq { weight, rate_limits, r#ref } => {
+        let fut = self.inner.clone().set_route_options(weight, rate_limits, r#ref);
+        self.spawn_text_rep(fut, r#ref, msg_type, received_at, trace_id, ctx);
+        return;
+      }
+      TextReq::WatchEventsReq { kinds, r#ref } => {
+        self.subscribe_events(ctx);
+        let mut watched_event_kinds = self.inner.watched_event_kinds.borrow_mut();
+        if kinds.is_empty() {
+          watched_event_kinds.extend(ALL_EVENT_KINDS);
+        } else {
+          watched_event_kinds.extend(kinds);
+        }
+        TextMsg::WatchEventsRep { r#ref }
+      }
+      TextReq::UnwatchEventsReq { kinds, r#ref } => {
+        let mut watched_event_kinds = self.inner.watched_event_kinds.borrow_mut();
+        if kinds.is_empty() {
+          watched_event_kinds.clear();
+        } else {
+          for kind in &kinds {
+            watched_event_kinds.remove(kind);
+          }
+        }
+        TextMsg::UnwatchEventsRep { r#ref }
+      }
+      TextReq::GetTopicDistReq { r#ref } => {
+        let fut = self.inner.clone().get_topic_dist(r#ref);
+        self.spawn_text_rep(fut, r#ref, msg_type, received_at, trace_id, ctx);
+        return;
+      }
+      TextReq::GetStatsReq { r#ref } => {
+        TextMsg::GetStatsRep { stats: Stats::current(&self.inner.tenant), r#ref }
+      }
+      TextReq::PickFrontendReq { secure, r#ref } => {
+        match self.inner.pick_frontend_endpoint(secure) {
+          Some(endpoint) => TextMsg::PickFrontendRep { endpoint, r#ref },
+          None => TextMsg::ErrorRep {
+            code: ErrorCode::FailedToPickFrontend as i32,
+            desc: "Failed to find an available frontend.".to_owned(),
+            r#ref,
+          },
+        }
+      }
+      TextReq::ResolveIpReq { r#ref } => TextMsg::ResolveIpRep {
+        ip: self.inner.peer_addr.ip().to_string(),
+        port: self.inner.peer_addr.port(),
+        addr_type: HttpHandler::detect_addr_type(&self.inner.peer_addr),
+        is_tls: self.inner.is_tls,
+        r#ref,
+      },
+      TextReq::ReportLoadReq { load, r#ref } => self.report_load(load, r#ref),
+    };
+    ctx.text(rep.encode_traced(&trace_id));
+    slow_log::check_msg(msg_type, self.inner.peer_addr, received_at.elapsed());
+  }
+
+  // Replies once the req is handled off the actor, counted in flight as the
+  // binary reqs are
+  fn spawn_text_rep<F: Future<Output = TextMsg> + 'static>(
+    &mut self, fut: F, r#ref: u32, msg_type: &'static str, received_at: Instant, trace_id: String,
+    ctx: &mut <Self as Actor>::Context,
+  ) {
+    if let Err(max_in_flight) = self.inner.enter_in_flight() {
+      let rep = TextMsg::ErrorRep {
+        code: ExtErrorCode::Busy as i32,
+        desc: format!("Too many reqs in flight: max: {}", max_in_flight),
+        r#ref,
+      };
+      ctx.text(rep.encode_traced(&trace_id));
+      return;
+    }
+    traced(trace_id.clone(), fut)
+      .into_actor(self)
+      .map(move |rep, act, ctx| {
+        act.inner.exit_in_flight();
+        ctx.text(rep.encode_traced(&trace_id));
+        slow_log::check_msg(msg_type, act.inner.peer_addr, received_at.elapsed());
+      })
+      .spawn(ctx);
+  }
+
+  // Takes the snapshot on the db pool, holding the state deltas back until it
+  // is sent. If no rep is built, the standby can't be kept in sync anymore, so
+  // it is disconnected to resync on reconnecting.
+  fn spawn_state_snapshot<
+    F: FnOnce(Result<StateSnapshot, DbPoolError>) -> Option<TextMsg> + 'static,
+  >(
+    &mut self, build_rep: F, trace_id: Option<String>, ctx: &mut <Self as Actor>::Context,
+  ) {
+    self.inner.taking_snapshot_count.set(self.inner.taking_snapshot_count.get() + 1);
+    DB_POOL
+      .run(standby::snapshot)
       .into_actor(self)
-      .map(move |res, _act, ctx| {
-        if res.is_some() {
-          ctx.binary(maxwell_protocol::encode(&res));
+      .map(move |result, act, ctx| {
+        if let Err(err) = &result {
+          log::error!("Failed to take state snapshot: id: {:?}, err: {:?}", act.inner.id, err);
+        }
+        match build_rep(result) {
+          Some(rep) => match &trace_id {
+            Some(trace_id) => ctx.text(rep.encode_traced(trace_id)),
+            None => ctx.text(rep.encode()),
+          },
+          None => ctx.stop(),
+        }
+        let taking_snapshot_count = act.inner.taking_snapshot_count.get() - 1;
+        act.inner.taking_snapshot_count.set(taking_snapshot_count);
+        if taking_snapshot_count == 0 {
+          for delta in act.inner.held_state_deltas.take() {
+            ctx.text(TextMsg::StateDeltaMsg { delta }.encode());
+          }
         }
       })
       .spawn(ctx);
-    Ok(ProtocolMsg::None)
   }
-}
 
-impl Handler {
-  pub fn new(req: &HttpRequest) -> Self {
-    Self { inner: Rc::new(HandlerInner::new(req)) }
+  fn report_load(&self, load: f64, r#ref: u32) -> TextMsg {
+    let node_type = self.inner.node_type.get();
+    let node_id = self.inner.node_id.borrow();
+    let node_id = match node_id.as_ref() {
+      Some(node_id)
+        if matches!(node_type, NodeType::Frontend | NodeType::Backend | NodeType::Service) =>
+      {
+        node_id
+      }
+      _ => {
+        return TextMsg::ErrorRep {
+          code: ErrorCode::MasterError as i32,
+          desc: "Failed to report load: the node is not registered.".to_owned(),
+          r#ref,
+        }
+      }
+    };
+    if !health_policy::is_valid_load(load) {
+      return TextMsg::ErrorRep {
+        code: ExtErrorCode::InvalidQuery as i32,
+        desc: format!("Failed to report load: the load must be in [0, 1]: load: {}", load),
+        r#ref,
+      };
+    }
+    health_policy::report_load(node_type, node_id, load);
+    TextMsg::ReportLoadRep { r#ref }
+  }
+
+  fn subscribe_route_changes(&mut self, ctx: &mut <Self as Actor>::Context) {
+    if self.inner.is_subscribing_route_changes.replace(true) {
+      return;
+    }
+    let receiver = ROUTE_MGR.watch(&self.inner.tenant);
+    ctx.add_stream(futures::stream::unfold(receiver, |mut receiver| async move {
+      match receiver.changed().await {
+        Ok(()) => {
+          let table = receiver.borrow_and_update().clone();
+          Some((table, receiver))
+        }
+        Err(_) => None,
+      }
+    }));
+  }
+
+  // Closes the connection once too many frames in a row failed to decode
+  fn on_decode_failed<E: std::fmt::Debug>(&mut self, err: &E, ctx: &mut <Self as Actor>::Context) {
+    let decode_failure_count = self.inner.decode_failure_count.get() + 1;
+    self.inner.decode_failure_count.set(decode_failure_count);
+    log::error!(
+      "Failed to decode msg: id: {:?}, peer_addr: {:?}, count: {:?}, err: {:?}",
+      self.inner.id,
+      self.inner.peer_addr,
+      decode_failure_count,
+      err
+    );
+    let max_decode_failures = CONFIG.load().server.ws_max_decode_failures;
+    if max_decode_failures > 0 && decode_failure_count >= max_decode_failures {
+      log::warn!(
+        "Closing connection sending undecodable frames: id: {:?}, peer_addr: {:?}",
+        self.inner.id,
+        self.inner.peer_addr
+      );
+      ctx.close(Some(ws::CloseCode::Invalid.into()));
+      ctx.stop();
+    }
+  }
+
+  // Closes the connection once it has been rate limited too many times
+  fn on_rate_limited(&mut self, err: RateLimited, ctx: &mut <Self as Actor>::Context) {
+    log::warn!(
+      "Rate limited: id: {:?}, peer_addr: {:?}, err: {:?}",
+      self.inner.id,
+      self.inner.peer_addr,
+      err
+    );
+    if self.inner.rate_limiter.is_abusive() {
+      log::warn!(
+        "Closing abusive connection: id: {:?}, peer_addr: {:?}",
+        self.inner.id,
+        self.inner.peer_addr
+      );
+      ctx.close(Some(ws::CloseCode::Policy.into()));
+      ctx.stop();
+    }
+  }
+
+  // Pings the peer periodically, and stops the actor if nothing was received
+  // within the idle timeout, e.g. the peer crashed leaving a half-open connection
+  fn start_heartbeat(&mut self, ctx: &mut <Self as Actor>::Context) {
+    let idle_timeout = CONFIG.load().server.ws_idle_timeout;
+    if idle_timeout == 0 {
+      return;
+    }
+    let idle_timeout = Duration::from_secs(idle_timeout);
+    let interval = Duration::from_secs(CONFIG.load().server.ws_heartbeat_interval.max(1));
+    ctx.run_interval(interval, move |act, ctx| {
+      let idle_time = act.inner.last_active_at.get().elapsed();
+      if idle_time > idle_timeout {
+        log::warn!(
+          "Closing idle connection: id: {:?}, peer_addr: {:?}, idle_time: {:?}",
+          act.inner.id,
+          act.inner.peer_addr,
+          idle_time
+        );
+        ctx.stop();
+      } else {
+        ctx.ping(b"");
+      }
+    });
+  }
+
+  fn subscribe_events(&mut self, ctx: &mut <Self as Actor>::Context) {
+    if self.inner.is_subscribing_events.replace(true) {
+      return;
+    }
+    let receiver = event_bus::subscribe();
+    ctx.add_stream(futures::stream::unfold(receiver, |mut receiver| async move {
+      match receiver.recv().await {
+        Ok(event) => Some((Ok(event), receiver)),
+        Err(RecvError::Lagged(lagged_count)) => Some((Err(lagged_count), receiver)),
+        Err(RecvError::Closed) => None,
+      }
+    }));
+  }
+
+  // The changes of the nodes and routes from the event bus, merged with those of
+  // the topics
+  fn subscribe_state_deltas(&mut self, ctx: &mut <Self as Actor>::Context) {
+    if self.inner.is_subscribing_state_deltas.replace(true) {
+      return;
+    }
+    let events = futures::stream::unfold(event_bus::subscribe(), |mut receiver| async move {
+      loop {
+        match receiver.recv().await {
+          Ok(event) => {
+            if let Some(delta) = StateDelta::of_event(&event) {
+              return Some((Ok(delta), receiver));
+            }
+          }
+          Err(RecvError::Lagged(lagged_count)) => return Some((Err(lagged_count), receiver)),
+          Err(RecvError::Closed) => return None,
+        }
+      }
+    });
+    let topic_changes = futures::stream::unfold(TOPIC_MGR.subscribe(), |mut receiver| async move {
+      match receiver.recv().await {
+        Ok(change) => Some((Ok(StateDelta::of_topic_change(change)), receiver)),
+        Err(RecvError::Lagged(lagged_count)) => Some((Err(lagged_count), receiver)),
+        Err(RecvError::Closed) => None,
+      }
+    });
+    ctx.add_stream(futures::stream::select(events, topic_changes));
+  }
+
+  fn subscribe_topic_changes(&mut self, ctx: &mut <Self as Actor>::Context) {
+    if self.inner.is_subscribing_topic_changes.replace(true) {
+      return;
+    }
+    let receiver = TOPIC_MGR.subscribe();
+    ctx.add_stream(futures::stream::unfold(receiver, |mut receiver| async move {
+      match receiver.recv().await {
+        Ok(change) => Some((Ok(change), receiver)),
+        Err(RecvError::Lagged(lagged_count)) => Some((Err(lagged_count), receiver)),
+        Err(RecvError::Closed) => None,
+      }
+    }));
   }
 }
@@ -0,0 +1,224 @@
+use std::{
+  borrow::Borrow,
+  sync::atomic::{AtomicBool, Ordering},
+};
+
+use anyhow::{anyhow, Result};
+use bytes::{Bytes, BytesMut};
+use once_cell::sync::Lazy;
+use seriesdb::coder::Coder;
+
+use crate::{
+  clock,
+  config::CONFIG,
+  node_mgr::{BACKEND_MGR, FRONTEND_MGR, SERVICE_MGR},
+  route_mgr::ROUTE_MGR,
+  store::{open_store, Store},
+  topic_mgr::TOPIC_MGR,
+};
+
+const PROBE_KEY: &str = "probe";
+
+type ProbeStore = dyn Store<String, u32>;
+
+pub(crate) struct ProbeCoder;
+
+impl Coder<String, u32> for ProbeCoder {
+  type EncodedKey = Bytes;
+  type EncodedValue = Bytes;
+
+  #[inline(always)]
+  fn encode_key<K: Borrow<String>>(key: K) -> Self::EncodedKey {
+    BytesMut::from(key.borrow().as_bytes()).freeze()
+  }
+
+  #[inline(always)]
+  fn decode_key(key: &[u8]) -> String {
+    std::str::from_utf8(key).unwrap().to_string()
+  }
+
+  #[inline(always)]
+  fn encode_value<V: Borrow<u32>>(value: V) -> Self::EncodedValue {
+    Bytes::copy_from_slice(&value.borrow().to_be_bytes())
+  }
+
+  #[inline(always)]
+  fn decode_value(value: &[u8]) -> u32 {
+    u32::from_be_bytes(value.try_into().unwrap())
+  }
+}
+
+static PROBE_STORE: Lazy<Box<ProbeStore>> =
+  Lazy::new(|| open_store::<String, u32, ProbeCoder>("health.probes").unwrap());
+
+static STARTED_AT: Lazy<u32> = Lazy::new(|| clock::now());
+
+static IS_READY: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+  Ok,
+  // Serving, but some kind of the configured nodes has none healthy
+  Degraded,
+  // The db can not be read or written
+  Unavailable,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DbHealth {
+  ok: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NodeHealth {
+  healthy: usize,
+  total: usize,
+}
+
+impl NodeHealth {
+  // A kind of nodes none is configured of is never considered down
+  #[inline]
+  fn is_down(&self) -> bool {
+    self.total > 0 && self.healthy == 0
+  }
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+  status: HealthStatus,
+  db: DbHealth,
+  frontends: NodeHealth,
+  backends: NodeHealth,
+  services: NodeHealth,
+  route_version: u32,
+  uptime: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+  ready: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  desc: Option<String>,
+}
+
+impl Readiness {
+  #[inline]
+  pub fn is_ready(&self) -> bool {
+    self.ready
+  }
+
+  #[inline]
+  fn not_ready(desc: String) -> Self {
+    Readiness { ready: false, desc: Some(desc) }
+  }
+}
+
+impl HealthReport {
+  #[inline]
+  pub fn status(&self) -> HealthStatus {
+    self.status
+  }
+}
+
+// Called once the master starts, so that the uptime counts from then
+#[inline]
+pub fn mark_started() {
+  Lazy::force(&STARTED_AT);
+}
+
+// Called right before serving, the managers recover their state from the db
+// once forced
+pub fn mark_ready() {
+  Lazy::force(&TOPIC_MGR);
+  Lazy::force(&ROUTE_MGR);
+  Lazy::force(&SERVICE_MGR);
+  IS_READY.store(true, Ordering::Release);
+}
+
+// Whether the master should be sent traffic, unlike being alive at all
+pub fn check_ready() -> Readiness {
+  if !IS_READY.load(Ordering::Acquire) {
+    return Readiness::not_ready("The state has not been recovered yet.".to_owned());
+  }
+  if let Err(err) = probe_db() {
+    log::error!("Failed to probe the db: err: {:?}", err);
+    return Readiness::not_ready(format!("The db is unavailable: {}", err));
+  }
+  if CONFIG.load().server.ready_requires_nodes {
+    if frontend_health().is_down() {
+      return Readiness::not_ready("None of the frontends is healthy.".to_owned());
+    }
+    if backend_health().is_down() {
+      return Readiness::not_ready("None of the backends is healthy.".to_owned());
+    }
+  }
+  Readiness { ready: true, desc: None }
+}
+
+pub fn check() -> HealthReport {
+  let db = match probe_db() {
+    Ok(()) => DbHealth { ok: true, desc: None },
+    Err(err) => {
+      log::error!("Failed to probe the db: err: {:?}", err);
+      DbHealth { ok: false, desc: Some(format!("{}", err)) }
+    }
+  };
+  let frontends = frontend_health();
+  let backends = backend_health();
+  let services = NodeHealth {
+    healthy: SERVICE_MGR.iter().filter(|service| service.is_healthy()).count(),
+    total: SERVICE_MGR.iter().count(),
+  };
+  let status = if !db.ok {
+    HealthStatus::Unavailable
+  } else if frontends.is_down() || backends.is_down() || services.is_down() {
+    HealthStatus::Degraded
+  } else {
+    HealthStatus::Ok
+  };
+  HealthReport {
+    status,
+    db,
+    frontends,
+    backends,
+    services,
+    route_version: ROUTE_MGR.version(),
+    uptime: uptime(),
+  }
+}
+
+// Seconds since the master started
+#[inline]
+pub fn uptime() -> u32 {
+  clock::now().saturating_sub(*STARTED_AT)
+}
+
+#[inline]
+fn frontend_health() -> NodeHealth {
+  NodeHealth {
+    healthy: FRONTEND_MGR.iter().filter(|frontend| frontend.is_healthy()).count(),
+    total: FRONTEND_MGR.iter().count(),
+  }
+}
+
+#[inline]
+fn backend_health() -> NodeHealth {
+  NodeHealth {
+    healthy: BACKEND_MGR.iter().filter(|backend| backend.is_healthy()).count(),
+    total: BACKEND_MGR.iter().count(),
+  }
+}
+
+// Writes the current time and reads it back, which fails on a wedged db
+fn probe_db() -> Result<()> {
+  let now = clock::now();
+  let key = PROBE_KEY.to_owned();
+  PROBE_STORE.put(&key, &now)?;
+  match PROBE_STORE.get(&key)? {
+    Some(value) if value == now => Ok(()),
+    value => Err(anyhow!("Read back a different value: expected: {}, actual: {:?}", now, value)),
+  }
+}
@@ -0,0 +1,180 @@
+use std::time::Duration;
+
+use ahash::RandomState as AHasher;
+use dashmap::DashMap;
+use futures::future;
+use once_cell::sync::Lazy;
+
+use crate::{
+  clock,
+  config::{Config, HealthRule, HealthSignal, NodeHealthPolicy, CONFIG},
+  node_mgr::{Node, NodeId, NodeType, BACKEND_MGR, FRONTEND_MGR, SERVICE_MGR},
+};
+
+// The signals other than the pings, which the nodes keep in active_at
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Signals {
+  // When the last probe succeeded
+  probed_at: Option<u32>,
+  // The last load reported, and when
+  load: Option<(f64, u32)>,
+}
+
+type SignalKey = (&'static str, NodeId);
+
+static SIGNALS: Lazy<DashMap<SignalKey, Signals, AHasher>> =
+  Lazy::new(|| DashMap::with_hasher(AHasher::default()));
+
+// Whether the node is healthy by the policy of its type, instead of by its
+// active_at alone, wherever the health of the nodes is decided
+pub(crate) fn is_healthy<N: Node>(node: &N) -> bool {
+  let signals = SIGNALS
+    .get(&(N::NODE_TYPE.as_str(), node.id().clone()))
+    .map(|signals| *signals)
+    .unwrap_or_default();
+  let config = CONFIG.load();
+  evaluate(
+    policy_of(&config, N::NODE_TYPE),
+    node.active_at(),
+    &signals,
+    clock::now(),
+    config.service_mgr.unhealthy_threshold,
+  )
+}
+
+// Loads out of [0, 1] are rejected by the callers
+pub(crate) fn report_load(node_type: NodeType, node_id: &NodeId, load: f64) {
+  SIGNALS.entry((node_type.as_str(), node_id.clone())).or_default().load =
+    Some((load, clock::now()));
+}
+
+#[inline]
+pub(crate) fn is_valid_load(load: f64) -> bool {
+  (0.0..=1.0).contains(&load)
+}
+
+#[inline]
+fn policy_of(config: &Config, node_type: NodeType) -> &NodeHealthPolicy {
+  match node_type {
+    NodeType::Frontend => &config.health_policy.frontend,
+    NodeType::Backend => &config.health_policy.backend,
+    // Only the frontends, the backends and the services are nodes
+    _ => &config.health_policy.service,
+  }
+}
+
+fn evaluate(
+  policy: &NodeHealthPolicy, active_at: u32, signals: &Signals, now: u32, threshold: u32,
+) -> bool {
+  let is_fresh = |at: u32| now.saturating_sub(at) <= threshold;
+  let mut results = policy.signals.iter().map(|signal| match signal {
+    HealthSignal::Ping => is_fresh(active_at),
+    HealthSignal::Probe => signals.probed_at.is_some_and(is_fresh),
+    HealthSignal::Load => signals
+      .load
+      .is_some_and(|(load, reported_at)| is_fresh(reported_at) && load <= policy.max_load),
+  });
+  match policy.rule {
+    HealthRule::All => results.all(|passed| passed),
+    HealthRule::Any => results.any(|passed| passed),
+  }
+}
+
+// Probes the nodes whose policy has the probe signal, as the policy may be
+// reloaded, and drops the signals of the nodes gone
+pub fn spawn_probe_task() {
+  actix_web::rt::spawn(async {
+    let client = awc::Client::builder()
+      .timeout(Duration::from_millis(CONFIG.load().health_policy.probe_timeout))
+      .finish();
+    let mut interval =
+      tokio::time::interval(Duration::from_secs(CONFIG.load().health_policy.probe_interval));
+    loop {
+      interval.tick().await;
+      sweep();
+      let mut targets = vec![];
+      let config = CONFIG.load();
+      let policy = &config.health_policy;
+      if policy.frontend.signals.contains(&HealthSignal::Probe) {
+        targets.extend(FRONTEND_MGR.iter().map(|frontend| {
+          let endpoint = format!("{}:{}", frontend.private_ip, frontend.http_port);
+          (NodeType::Frontend, frontend.id.clone(), endpoint, &policy.frontend.probe_path)
+        }));
+      }
+      if policy.backend.signals.contains(&HealthSignal::Probe) {
+        targets.extend(BACKEND_MGR.iter().map(|backend| {
+          (
+            NodeType::Backend,
+            backend.id.clone(),
+            backend.private_endpoint(),
+            &policy.backend.probe_path,
+          )
+        }));
+      }
+      if policy.service.signals.contains(&HealthSignal::Probe) {
+        targets.extend(SERVICE_MGR.iter().map(|service| {
+          (
+            NodeType::Service,
+            service.id.clone(),
+            service.private_endpoint(),
+            &policy.service.probe_path,
+          )
+        }));
+      }
+      future::join_all(targets.into_iter().map(|(node_type, node_id, endpoint, path)| {
+        let client = &client;
+        async move {
+          let url = format!("http://{}{}", endpoint, path);
+          match client.get(&url).send().await {
+            Ok(rep) if rep.status().is_success() => {
+              SIGNALS.entry((node_type.as_str(), node_id)).or_default().probed_at =
+                Some(clock::now());
+            }
+            Ok(rep) => log::warn!("Failed to probe: url: {}, status: {}", url, rep.status()),
+            Err(err) => log::warn!("Failed to probe: url: {}, err: {:?}", url, err),
+          }
+        }
+      }))
+      .await;
+    }
+  });
+}
+
+// Collected first, not to lock the managers while holding the signals
+fn sweep() {
+  let gone: Vec<SignalKey> = SIGNALS
+    .iter()
+    .map(|entry| entry.key().clone())
+    .filter(|(node_type, node_id)| match *node_type {
+      "frontend" => FRONTEND_MGR.get(node_id).is_none(),
+      "backend" => BACKEND_MGR.get(node_id).is_none(),
+      _ => SERVICE_MGR.get(node_id).is_none(),
+    })
+    .collect();
+  for key in gone {
+    SIGNALS.remove(&key);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_evaluate() {
+    let mut policy = NodeHealthPolicy::default();
+    let signals = Signals { probed_at: Some(90), load: Some((0.95, 100)) };
+    assert!(evaluate(&policy, 100, &signals, 100, 30));
+    assert!(!evaluate(&policy, 60, &signals, 100, 30));
+
+    policy.signals = vec![HealthSignal::Ping, HealthSignal::Probe, HealthSignal::Load];
+    assert!(evaluate(&policy, 100, &signals, 100, 30));
+    policy.max_load = 0.9;
+    assert!(!evaluate(&policy, 100, &signals, 100, 30));
+    policy.rule = HealthRule::Any;
+    assert!(evaluate(&policy, 60, &signals, 100, 30));
+    // Every signal stale
+    assert!(!evaluate(&policy, 60, &signals, 200, 30));
+    assert!(!evaluate(&policy, 60, &Signals::default(), 100, 30));
+  }
+}
@@ -0,0 +1,55 @@
+use anyhow::Result;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::{
+  config,
+  node_mgr::{BACKEND_MGR, FRONTEND_MGR},
+  topic_mgr::TOPIC_MGR,
+};
+
+#[derive(Debug)]
+pub struct ReloadReport {
+  // The settings changed in the file but only applied on restart
+  pub ignored: Vec<&'static str>,
+  pub backends_changed: bool,
+}
+
+// Re-reads the config file and applies what is safe to change at runtime
+pub fn reload() -> Result<ReloadReport> {
+  let ignored = config::reload()?;
+  for setting in &ignored {
+    log::warn!("The change of setting: {:?} needs a restart, ignored", setting);
+  }
+  FRONTEND_MGR.reload();
+  let backends_changed = BACKEND_MGR.reload();
+  if backends_changed {
+    log::warn!("The backends changed, the topics will be reassigned");
+    TOPIC_MGR.on_backends_changed();
+  }
+  TOPIC_MGR.reload_quota();
+  log::info!("Reloaded config: ignored: {:?}, backends_changed: {:?}", ignored, backends_changed);
+  Ok(ReloadReport { ignored, backends_changed })
+}
+
+#[cfg(unix)]
+pub fn spawn_signal_task() {
+  actix_web::rt::spawn(async {
+    let mut hangup = match signal(SignalKind::hangup()) {
+      Ok(hangup) => hangup,
+      Err(err) => {
+        log::error!("Failed to listen to SIGHUP: {:?}", err);
+        return;
+      }
+    };
+    while hangup.recv().await.is_some() {
+      log::info!("Received SIGHUP, reloading config");
+      if let Err(err) = reload() {
+        log::error!("Failed to reload config: {:?}", err);
+      }
+    }
+  });
+}
+
+#[cfg(not(unix))]
+pub fn spawn_signal_task() {}
@@ -0,0 +1,48 @@
+#[macro_use]
+extern crate serde_derive;
+
+mod acme;
+mod audit;
+mod backup;
+#[cfg(feature = "bench")]
+pub mod bench;
+mod build_info;
+pub mod cli;
+pub mod clock;
+mod cluster;
+pub mod config;
+mod config_mgr;
+mod cron;
+mod db;
+mod db_pool;
+mod discovery;
+mod dump;
+mod encryption;
+mod error_code;
+mod event_bus;
+mod fsck;
+mod handler;
+mod health;
+mod health_policy;
+mod hot_reload;
+mod maintenance;
+mod metrics;
+mod migration;
+pub mod node_mgr;
+mod offsite;
+mod profiling;
+mod raft;
+pub mod route_mgr;
+mod server;
+mod slow_log;
+mod snapshot;
+mod standby;
+mod stats;
+mod statsd;
+mod store;
+mod telemetry;
+pub mod topic_mgr;
+mod topology;
+mod trace;
+
+pub use crate::server::{MasterServer, MasterServerBuilder};
@@ -0,0 +1,33 @@
+use std::fmt;
+
+use crate::config::CONFIG;
+
+// Rejects the writes while server.read_only is set, e.g. while the db is
+// migrated or restored, which is tunable, so that it can be set through the
+// settings of the admin api without a restart. The frontends and backends
+// still register, being configured rather than written, and the topics
+// already assigned are still located.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Maintenance;
+
+impl fmt::Display for Maintenance {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "The master is read only for maintenance")
+  }
+}
+
+impl std::error::Error for Maintenance {}
+
+#[inline]
+pub fn is_read_only() -> bool {
+  CONFIG.load().server.read_only
+}
+
+#[inline]
+pub fn check_writable() -> Result<(), Maintenance> {
+  if is_read_only() {
+    Err(Maintenance)
+  } else {
+    Ok(())
+  }
+}
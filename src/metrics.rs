@@ -0,0 +1,305 @@
+use std::{
+  collections::BTreeMap,
+  fmt::{Display, Write},
+};
+
+use serde::Serialize;
+
+use crate::{
+  backup, clock, db,
+  db_pool::DB_POOL,
+  event_bus::{self, ALL_EVENT_KINDS},
+  maintenance,
+  node_mgr::{BACKEND_MGR, FRONTEND_MGR, SERVICE_MGR},
+  route_mgr::ROUTE_MGR,
+  topic_mgr::TOPIC_MGR,
+};
+
+// A target group of the prometheus http_sd format, one per node, as the labels
+// are of the node
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SdTargetGroup {
+  pub targets: Vec<String>,
+  pub labels: BTreeMap<&'static str, String>,
+}
+
+// The frontends, backends and services known, healthy or not, so that
+// prometheus scrapes the whole fleet with the master as its inventory, and the
+// health is a label to relabel or drop by
+pub fn sd_target_groups() -> Vec<SdTargetGroup> {
+  let mut groups = vec![];
+  groups.extend(FRONTEND_MGR.iter().map(|frontend| {
+    sd_target_group(
+      "frontend",
+      &frontend.id,
+      format!("{}:{}", frontend.private_ip, frontend.http_port),
+      frontend.is_healthy(),
+      None,
+    )
+  }));
+  groups.extend(BACKEND_MGR.iter().map(|backend| {
+    sd_target_group("backend", &backend.id, backend.private_endpoint(), backend.is_healthy(), None)
+  }));
+  groups.extend(SERVICE_MGR.iter().map(|service| {
+    sd_target_group(
+      "service",
+      &service.id,
+      service.private_endpoint(),
+      service.is_healthy(),
+      Some(ROUTE_MGR.tenant_of(&service.id)),
+    )
+  }));
+  groups
+}
+
+#[inline]
+fn sd_target_group(
+  node_type: &str, node_id: &str, endpoint: String, is_healthy: bool, tenant: Option<String>,
+) -> SdTargetGroup {
+  let mut labels = BTreeMap::from([
+    ("__meta_maxwell_node_type", node_type.to_owned()),
+    ("__meta_maxwell_node_id", node_id.to_owned()),
+    ("__meta_maxwell_healthy", is_healthy.to_string()),
+  ]);
+  if let Some(tenant) = tenant {
+    labels.insert("__meta_maxwell_tenant", tenant);
+  }
+  SdTargetGroup { targets: vec![endpoint], labels }
+}
+
+// Renders all metrics in the prometheus text exposition format
+pub fn render() -> String {
+  let mut writer = MetricWriter::new();
+
+  writer.header("maxwell_master_topics", "gauge", "Number of topics assigned to the backend.");
+  for backend_id in BACKEND_MGR.ids().iter() {
+    writer.sample(
+      "maxwell_master_topics",
+      &[("backend_id", backend_id.as_str())],
+      TOPIC_MGR.topic_count(backend_id),
+    );
+  }
+
+  writer.header(
+    "maxwell_master_events_total",
+    "counter",
+    "Events published on the event bus, by kind.",
+  );
+  for kind in ALL_EVENT_KINDS {
+    writer.sample(
+      "maxwell_master_events_total",
+      &[("kind", kind.as_str())],
+      event_bus::published_count(kind),
+    );
+  }
+
+  writer.header("maxwell_master_topic_dist_version", "gauge", "Version of the topic dist.");
+  writer.sample("maxwell_master_topic_dist_version", &[], TOPIC_MGR.version());
+
+  writer.header(
+    "maxwell_master_topic_cache_hits_total",
+    "counter",
+    "Topic lookups served by the cache.",
+  );
+  writer.sample("maxwell_master_topic_cache_hits_total", &[], TOPIC_MGR.cache_hits());
+  writer.header(
+    "maxwell_master_topic_cache_misses_total",
+    "counter",
+    "Topic lookups which missed the cache.",
+  );
+  writer.sample("maxwell_master_topic_cache_misses_total", &[], TOPIC_MGR.cache_misses());
+
+  let db_pool_stats = DB_POOL.stats();
+  writer.header(
+    "maxwell_master_db_pool_queue_depth",
+    "gauge",
+    "Db calls queued or running on the db pool.",
+  );
+  writer.sample("maxwell_master_db_pool_queue_depth", &[], db_pool_stats.queue_depth);
+  writer.header(
+    "maxwell_master_db_pool_completed_total",
+    "counter",
+    "Db calls run by the db pool.",
+  );
+  writer.sample("maxwell_master_db_pool_completed_total", &[], db_pool_stats.completed);
+  writer.header(
+    "maxwell_master_db_pool_rejected_total",
+    "counter",
+    "Db calls rejected as the queue of the db pool was full.",
+  );
+  writer.sample("maxwell_master_db_pool_rejected_total", &[], db_pool_stats.rejected);
+  writer.header(
+    "maxwell_master_db_pool_timed_out_total",
+    "counter",
+    "Db calls whose callers stopped waiting.",
+  );
+  writer.sample("maxwell_master_db_pool_timed_out_total", &[], db_pool_stats.timed_out);
+
+  let route_health = ROUTE_MGR.health();
+  let now = clock::now();
+  writer.header(
+    "maxwell_master_route_healthy_endpoints",
+    "gauge",
+    "Number of healthy endpoints serving the path.",
+  );
+  for route in &route_health {
+    writer.sample(
+      "maxwell_master_route_healthy_endpoints",
+      &[("tenant", route.tenant.as_str()), ("method", route.method), ("path", route.path.as_str())],
+      route.healthy_count,
+    );
+  }
+  writer.header(
+    "maxwell_master_route_unhealthy_endpoints",
+    "gauge",
+    "Number of unhealthy endpoints serving the path.",
+  );
+  for route in &route_health {
+    writer.sample(
+      "maxwell_master_route_unhealthy_endpoints",
+      &[("tenant", route.tenant.as_str()), ("method", route.method), ("path", route.path.as_str())],
+      route.unhealthy_count,
+    );
+  }
+  writer.header(
+    "maxwell_master_route_down_seconds",
+    "gauge",
+    "Seconds since the path has had no healthy endpoint, 0 if it has any.",
+  );
+  for route in &route_health {
+    writer.sample(
+      "maxwell_master_route_down_seconds",
+      &[("tenant", route.tenant.as_str()), ("method", route.method), ("path", route.path.as_str())],
+      route.down_since.map_or(0, |down_since| now.saturating_sub(down_since)),
+    );
+  }
+
+  writer.header(
+    "maxwell_master_read_only",
+    "gauge",
+    "1 if the writes are rejected for maintenance.",
+  );
+  writer.sample("maxwell_master_read_only", &[], maintenance::is_read_only() as u8);
+
+  let backup_stats = backup::stats();
+  writer.header(
+    "maxwell_master_scheduled_backups_total",
+    "counter",
+    "Number of scheduled backups by result.",
+  );
+  writer.sample(
+    "maxwell_master_scheduled_backups_total",
+    &[("result", "succeeded")],
+    backup_stats.succeeded,
+  );
+  writer.sample(
+    "maxwell_master_scheduled_backups_total",
+    &[("result", "failed")],
+    backup_stats.failed,
+  );
+  writer.header(
+    "maxwell_master_last_backup_timestamp_seconds",
+    "gauge",
+    "Unix time of the last scheduled backup succeeded, 0 if none has.",
+  );
+  writer.sample(
+    "maxwell_master_last_backup_timestamp_seconds",
+    &[],
+    backup_stats.last_succeeded_at,
+  );
+  writer.header(
+    "maxwell_master_last_backup_size_bytes",
+    "gauge",
+    "Size of the last scheduled backup succeeded.",
+  );
+  writer.sample("maxwell_master_last_backup_size_bytes", &[], backup_stats.last_size);
+
+  if let Some(db_stats) = db::stats() {
+    for (name, help, value) in [
+      (
+        "maxwell_master_db_live_data_bytes",
+        "Estimated size of the live data in the db.",
+        db_stats.live_data_size,
+      ),
+      (
+        "maxwell_master_db_sst_files_bytes",
+        "Size of the live sst files of the db.",
+        db_stats.sst_files_size,
+      ),
+      ("maxwell_master_db_sst_files", "Number of the sst files of the db.", db_stats.sst_files),
+      (
+        "maxwell_master_db_level_zero_files",
+        "Number of the sst files at level 0.",
+        db_stats.level_zero_files,
+      ),
+      (
+        "maxwell_master_db_pending_compaction_bytes",
+        "Estimated bytes the compactions have to rewrite.",
+        db_stats.pending_compaction_bytes,
+      ),
+      (
+        "maxwell_master_db_running_compactions",
+        "Number of the compactions running.",
+        db_stats.running_compactions,
+      ),
+      (
+        "maxwell_master_db_delayed_write_rate",
+        "Bytes per second the writes are delayed to, 0 if they are not.",
+        db_stats.delayed_write_rate,
+      ),
+      (
+        "maxwell_master_db_write_stopped",
+        "1 if the writes are stopped until the compactions catch up.",
+        db_stats.is_write_stopped as u64,
+      ),
+    ] {
+      writer.header(name, "gauge", help);
+      writer.sample(name, &[], value);
+    }
+  }
+
+  writer.into_string()
+}
+
+struct MetricWriter {
+  buf: String,
+}
+
+impl MetricWriter {
+  #[inline]
+  fn new() -> Self {
+    MetricWriter { buf: String::with_capacity(4096) }
+  }
+
+  #[inline]
+  fn header(&mut self, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(self.buf, "# HELP {} {}", name, help);
+    let _ = writeln!(self.buf, "# TYPE {} {}", name, kind);
+  }
+
+  #[inline]
+  fn sample<V: Display>(&mut self, name: &str, labels: &[(&str, &str)], value: V) {
+    self.buf.push_str(name);
+    if !labels.is_empty() {
+      self.buf.push('{');
+      for (i, (label, label_value)) in labels.iter().enumerate() {
+        if i > 0 {
+          self.buf.push(',');
+        }
+        let _ = write!(self.buf, "{}=\"{}\"", label, escape(label_value));
+      }
+      self.buf.push('}');
+    }
+    let _ = writeln!(self.buf, " {}", value);
+  }
+
+  #[inline]
+  fn into_string(self) -> String {
+    self.buf
+  }
+}
+
+#[inline]
+fn escape(label_value: &str) -> String {
+  label_value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
@@ -0,0 +1,202 @@
+use std::borrow::Borrow;
+
+use anyhow::{bail, Context, Result};
+use bytes::{Bytes, BytesMut};
+use seriesdb::coder::Coder;
+
+use crate::{
+  config::{DbEngine, CONFIG},
+  encryption,
+  store::{open_raw_table, open_store, RawTable, Store},
+};
+
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+// The encoded entries of the tables cannot change as the structs do, bincode
+// having no notion of fields, so each change of a table comes with a migration
+// of the tables in the db, run on start before the managers open them. The
+// version of the schema the db is at is kept in migration.infos, 0 if it was
+// never migrated, and each migration brings the db to its version from the
+// version before, e.g. by renaming a table, or by decoding the entries by the
+// older struct and encoding them by the newer one. Appended only, in order.
+pub(crate) struct Migration {
+  pub version: u32,
+  pub desc: &'static str,
+  // Run again if the master stops before its version is recorded
+  pub migrate: fn() -> Result<()>,
+}
+
+static MIGRATIONS: &[Migration] = &[];
+
+type InfoKey = String;
+type InfoValue = String;
+type InfoStore = dyn Store<InfoKey, InfoValue>;
+
+pub(crate) struct InfoCoder;
+
+impl Coder<InfoKey, InfoValue> for InfoCoder {
+  type EncodedKey = Bytes;
+  type EncodedValue = Bytes;
+
+  #[inline(always)]
+  fn encode_key<K: Borrow<InfoKey>>(key: K) -> Self::EncodedKey {
+    BytesMut::from(key.borrow().as_bytes()).freeze()
+  }
+
+  #[inline(always)]
+  fn decode_key(key: &[u8]) -> InfoKey {
+    std::str::from_utf8(key).unwrap().to_string()
+  }
+
+  #[inline(always)]
+  fn encode_value<V: Borrow<InfoValue>>(value: V) -> Self::EncodedValue {
+    BytesMut::from(value.borrow().as_bytes()).freeze()
+  }
+
+  #[inline(always)]
+  fn decode_value(value: &[u8]) -> InfoValue {
+    std::str::from_utf8(value).unwrap().to_string()
+  }
+}
+
+#[inline]
+pub(crate) fn latest_version() -> u32 {
+  MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+// Must be run before any manager opens its tables. The memory engine starts
+// empty, so there is nothing to migrate.
+pub fn run() -> Result<()> {
+  if CONFIG.load().db.engine != DbEngine::Seriesdb {
+    return Ok(());
+  }
+  let info_store = open_store::<InfoKey, InfoValue, InfoCoder>("migration.infos")?;
+  let version = migrate(&*info_store, MIGRATIONS)?;
+  log::info!("The db is at schema version: {}", version);
+  Ok(())
+}
+
+// Returns the version the db is at afterwards
+fn migrate(info_store: &InfoStore, migrations: &[Migration]) -> Result<u32> {
+  let latest_version = migrations.last().map_or(0, |migration| migration.version);
+  let mut version = match info_store.get(&SCHEMA_VERSION_KEY.to_owned())? {
+    Some(version) => {
+      version.parse().with_context(|| format!("Invalid schema version: {:?}", version))?
+    }
+    None => 0,
+  };
+  // Rather than misreading the tables, as after a rollback of the master
+  if version > latest_version {
+    bail!(
+      "The db is at schema version {}, newer than the {} of this master",
+      version,
+      latest_version
+    );
+  }
+  for migration in migrations.iter().filter(|migration| migration.version > version) {
+    log::info!(
+      "Migrating db: from: {:?}, to: {:?}, desc: {:?}",
+      version,
+      migration.version,
+      migration.desc
+    );
+    (migration.migrate)()
+      .with_context(|| format!("Failed to migrate db to schema version {}", migration.version))?;
+    version = migration.version;
+    info_store.put(&SCHEMA_VERSION_KEY.to_owned(), &version.to_string())?;
+  }
+  Ok(version)
+}
+
+// For a migration renaming a table, the entries are moved unless the table
+// from is empty, which it is once moved
+#[allow(dead_code)]
+pub(crate) fn rename_table(from: &str, to: &str) -> Result<()> {
+  move_entries(&*open_raw_table(from)?, &*open_raw_table(to)?)
+}
+
+fn move_entries(from: &dyn RawTable, to: &dyn RawTable) -> Result<()> {
+  let entries = from.dump();
+  if entries.is_empty() {
+    return Ok(());
+  }
+  to.load(&entries)?;
+  from.load(&[])
+}
+
+// For a migration changing the encoding of a table, the entries for which
+// reencode returns None are dropped, the values are decrypted before and
+// encrypted after, if a key is configured
+#[allow(dead_code)]
+pub(crate) fn reencode_table(
+  name: &str, mut reencode: impl FnMut(Bytes, Bytes) -> Option<(Bytes, Bytes)>,
+) -> Result<()> {
+  reencode_entries(&*open_raw_table(name)?, |key, value| {
+    reencode(key, encryption::open(&value)).map(|(key, value)| (key, encryption::seal(&value)))
+  })
+}
+
+fn reencode_entries(
+  table: &dyn RawTable, mut reencode: impl FnMut(Bytes, Bytes) -> Option<(Bytes, Bytes)>,
+) -> Result<()> {
+  let entries: Vec<(Bytes, Bytes)> =
+    table.dump().into_iter().filter_map(|(key, value)| reencode(key, value)).collect();
+  table.load(&entries)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Mutex;
+
+  use super::*;
+  use crate::store::{MemoryStore, RawCoder};
+
+  static MIGRATED: Mutex<Vec<u32>> = Mutex::new(vec![]);
+
+  fn record_1() -> Result<()> {
+    MIGRATED.lock().unwrap().push(1);
+    Ok(())
+  }
+
+  fn record_2() -> Result<()> {
+    MIGRATED.lock().unwrap().push(2);
+    Ok(())
+  }
+
+  #[test]
+  fn test_migrate() {
+    let info_store = MemoryStore::<_, _, InfoCoder>::new();
+    let migrations = [
+      Migration { version: 1, desc: "one", migrate: record_1 },
+      Migration { version: 2, desc: "two", migrate: record_2 },
+    ];
+    assert_eq!(migrate(&info_store, &migrations[..1]).unwrap(), 1);
+    assert_eq!(migrate(&info_store, &migrations).unwrap(), 2);
+    assert_eq!(migrate(&info_store, &migrations).unwrap(), 2);
+    assert_eq!(*MIGRATED.lock().unwrap(), vec![1, 2]);
+    assert_eq!(info_store.get(&SCHEMA_VERSION_KEY.to_owned()).unwrap(), Some("2".to_owned()));
+
+    // Rolled back to a master knowing version 1 only
+    assert!(migrate(&info_store, &migrations[..1]).is_err());
+  }
+
+  #[test]
+  fn test_move_and_reencode_entries() {
+    let from = MemoryStore::<Bytes, Bytes, RawCoder>::new();
+    let to = MemoryStore::<Bytes, Bytes, RawCoder>::new();
+    from.put(&Bytes::from("a"), &Bytes::from("1")).unwrap();
+    from.put(&Bytes::from("b"), &Bytes::from("2")).unwrap();
+    move_entries(&from, &to).unwrap();
+    assert!(from.dump().is_empty());
+    assert_eq!(to.dump().len(), 2);
+    // Once moved, the empty table from leaves to as it is
+    move_entries(&from, &to).unwrap();
+    assert_eq!(to.dump().len(), 2);
+
+    reencode_entries(&to, |key, value| {
+      (key != "b").then(|| (key, Bytes::from(format!("{}0", String::from_utf8_lossy(&value)))))
+    })
+    .unwrap();
+    assert_eq!(to.dump(), vec![(Bytes::from("a"), Bytes::from("10"))]);
+  }
+}
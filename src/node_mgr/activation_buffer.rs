@@ -0,0 +1,60 @@
+use ahash::RandomState as AHasher;
+use dashmap::DashMap;
+
+use super::NodeId;
+
+// Holds the latest active_at of the nodes activated since the last flush, so
+// that a node pinging every few seconds is persisted once per flush instead of
+// on every ping
+pub struct ActivationBuffer {
+  pending: DashMap<NodeId, u32, AHasher>,
+}
+
+impl ActivationBuffer {
+  #[inline]
+  pub fn new() -> Self {
+    ActivationBuffer { pending: DashMap::with_capacity_and_hasher(64, AHasher::default()) }
+  }
+
+  #[inline]
+  pub fn mark(&self, id: &NodeId, active_at: u32) {
+    if let Some(mut pending_active_at) = self.pending.get_mut(id) {
+      *pending_active_at = active_at;
+    } else {
+      self.pending.insert(id.clone(), active_at);
+    }
+  }
+
+  #[inline]
+  pub fn remove(&self, id: &NodeId) {
+    self.pending.remove(id);
+  }
+
+  // The nodes marked meanwhile are kept for the next drain
+  pub fn drain(&self) -> Vec<(NodeId, u32)> {
+    let ids: Vec<NodeId> = self.pending.iter().map(|entry| entry.key().clone()).collect();
+    ids.into_iter().filter_map(|id| self.pending.remove(&id)).collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_drain() {
+    let buffer = ActivationBuffer::new();
+    let id_0 = "service-0".to_owned();
+    let id_1 = "service-1".to_owned();
+    buffer.mark(&id_0, 100);
+    buffer.mark(&id_0, 105);
+    buffer.mark(&id_1, 103);
+    buffer.remove(&id_1);
+
+    assert_eq!(buffer.drain(), vec![(id_0.clone(), 105)]);
+    assert!(buffer.drain().is_empty());
+
+    buffer.mark(&id_0, 110);
+    assert_eq!(buffer.drain(), vec![(id_0, 110)]);
+  }
+}
@@ -1,24 +1,40 @@
-use std::net::IpAddr;
+use std::{
+  net::IpAddr,
+  sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc, RwLock,
+  },
+};
 
 use ahash::RandomState as AHasher;
-use chrono::Utc;
 use dashmap::DashMap;
 use once_cell::sync::Lazy;
 
-use super::{Node, NodeId, NodeIter, NodeRef};
-use crate::config::CONFIG;
+use super::{Node, NodeId, NodeIter, NodeRef, NodeType};
+use crate::{clock, config::BackendConfig, discovery, event_bus, health_policy};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Backend {
   pub(crate) id: NodeId,
   pub(crate) private_ip: IpAddr,
   pub(crate) http_port: u32,
+  pub(crate) capacity: u32,
   pub(crate) active_at: u32,
 }
 
 impl Backend {
-  pub fn new(id: String, private_ip: IpAddr, http_port: u32) -> Self {
-    Backend { id, private_ip, http_port, active_at: 0 }
+  pub fn new(id: String, private_ip: IpAddr, http_port: u32, capacity: u32) -> Self {
+    Backend { id, private_ip, http_port, capacity, active_at: 0 }
+  }
+
+  #[inline]
+  pub fn private_endpoint(&self) -> String {
+    format!("{}:{}", self.private_ip, self.http_port)
+  }
+
+  #[inline]
+  pub fn is_healthy(&self) -> bool {
+    health_policy::is_healthy(self)
   }
 
   pub fn checksum(&self) -> u32 {
@@ -27,6 +43,8 @@ impl Backend {
 }
 
 impl Node for Backend {
+  const NODE_TYPE: NodeType = NodeType::Backend;
+
   fn id(&self) -> &NodeId {
     &self.id
   }
@@ -45,15 +63,20 @@ pub type BackendIter<'a> = NodeIter<'a, Backend>;
 
 pub struct BackendMgr {
   backends: DashMap<NodeId, Backend, AHasher>,
-  backend_ids: Vec<NodeId>,
-  checksum: u32,
+  // Sorted, replaced as a whole once reloaded
+  backend_ids: RwLock<Arc<Vec<NodeId>>>,
+  checksum: AtomicU32,
 }
 
 impl BackendMgr {
   #[inline]
   pub(crate) fn new() -> Self {
     let backends = DashMap::with_capacity_and_hasher(64, AHasher::default());
-    let mut backend_mgr = BackendMgr { backends, backend_ids: Vec::with_capacity(64), checksum: 0 };
+    let backend_mgr = BackendMgr {
+      backends,
+      backend_ids: RwLock::new(Arc::new(Vec::new())),
+      checksum: AtomicU32::new(0),
+    };
     backend_mgr.initialize();
     backend_mgr
   }
@@ -61,7 +84,7 @@ impl BackendMgr {
   #[inline]
   pub fn activate(&self, id: &NodeId) {
     if let Some(mut backend) = self.backends.get_mut(id) {
-      backend.active_at = Utc::now().timestamp() as u32;
+      backend.active_at = clock::now();
     }
   }
 
@@ -75,9 +98,8 @@ impl BackendMgr {
   }
 
   #[inline]
-  pub fn pick_with<'a, F>(&'a self, with: F) -> Option<BackendRef<'a>>
-  where F: Fn(&'a DashMap<NodeId, Backend, AHasher>, &'a Vec<NodeId>) -> Option<&'a NodeId> {
-    with(&self.backends, &self.backend_ids).and_then(|backend_id| self.backends.get(backend_id))
+  pub fn ids(&self) -> Arc<Vec<NodeId>> {
+    self.backend_ids.read().unwrap().clone()
   }
 
   #[allow(dead_code)]
@@ -86,30 +108,76 @@ impl BackendMgr {
     self.backends.iter()
   }
 
+  #[inline]
+  pub fn count(&self) -> usize {
+    self.backends.len()
+  }
+
   #[inline]
   pub fn checksum(&self) -> u32 {
-    self.checksum
+    self.checksum.load(Ordering::Acquire)
+  }
+
+  // Applies the reloaded config and the backends discovered, the backends kept
+  // stay active, returns whether the checksum changed, which moves the topics
+  // as a restart would
+  pub(crate) fn reload(&self) -> bool {
+    let prev_checksum = self.checksum();
+    let backend_configs = &discovery::backend_configs();
+    let mut removed_ids = vec![];
+    self.backends.retain(|id, _| {
+      let is_kept = backend_configs.iter().any(|config| &config.id == id);
+      if !is_kept {
+        removed_ids.push(id.clone());
+      }
+      is_kept
+    });
+    let mut added_ids = vec![];
+    for backend_config in backend_configs {
+      let active_at = self.backends.get(&backend_config.id).map(|backend| backend.active_at);
+      let mut backend = Self::build_backend(backend_config);
+      match active_at {
+        Some(active_at) => backend.active_at = active_at,
+        None => added_ids.push(backend.id.clone()),
+      }
+      self.backends.insert(backend.id.clone(), backend);
+    }
+    self.update_ids();
+    event_bus::publish_node_changes(NodeType::Backend, added_ids, removed_ids);
+    self.checksum() != prev_checksum
   }
 
   #[inline]
-  fn initialize(&mut self) {
-    CONFIG.backend_mgr.backends.iter().for_each(|backend_config| {
-      let backend = Backend::new(
-        backend_config.id.clone(),
-        backend_config.private_ip,
-        backend_config.http_port,
-      );
-      self.backend_ids.push(backend.id.clone());
+  fn initialize(&self) {
+    discovery::backend_configs().iter().for_each(|backend_config| {
+      let backend = Self::build_backend(backend_config);
       self.backends.insert(backend.id.clone(), backend.clone());
     });
-    self.backend_ids.sort();
-    let mut checksums = Vec::with_capacity(self.backend_ids.len());
-    for backend_id in &self.backend_ids {
+    self.update_ids();
+  }
+
+  fn update_ids(&self) {
+    let mut backend_ids: Vec<NodeId> =
+      self.backends.iter().map(|backend| backend.id.clone()).collect();
+    backend_ids.sort();
+    let mut checksums = Vec::with_capacity(backend_ids.len());
+    for backend_id in &backend_ids {
       if let Some(backend) = self.backends.get(backend_id) {
         checksums.push(backend.checksum());
       }
     }
-    self.checksum = crc32fast::hash(format!("{:?}", checksums).as_bytes());
+    self.checksum.store(crc32fast::hash(format!("{:?}", checksums).as_bytes()), Ordering::Release);
+    *self.backend_ids.write().unwrap() = Arc::new(backend_ids);
+  }
+
+  #[inline]
+  fn build_backend(backend_config: &BackendConfig) -> Backend {
+    Backend::new(
+      backend_config.id.clone(),
+      backend_config.private_ip,
+      backend_config.http_port,
+      backend_config.capacity,
+    )
   }
 }
 
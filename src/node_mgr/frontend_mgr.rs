@@ -1,13 +1,15 @@
-use std::net::IpAddr;
+use std::{
+  net::IpAddr,
+  sync::{Arc, RwLock},
+};
 
 use ahash::RandomState as AHasher;
-use chrono::Utc;
 use dashmap::DashMap;
 use once_cell::sync::Lazy;
 use rand::{thread_rng, Rng};
 
-use super::{Node, NodeId, NodeIter, NodeRef, NodeRefMulti};
-use crate::config::CONFIG;
+use super::{Node, NodeId, NodeIter, NodeRef, NodeType};
+use crate::{clock, config::FrontendConfig, discovery, event_bus, health_policy};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Frontend {
@@ -27,9 +29,16 @@ impl Frontend {
   ) -> Self {
     Frontend { id, domain, public_ip, private_ip, http_port, https_port, active_at: 0 }
   }
+
+  #[inline]
+  pub fn is_healthy(&self) -> bool {
+    health_policy::is_healthy(self)
+  }
 }
 
 impl Node for Frontend {
+  const NODE_TYPE: NodeType = NodeType::Frontend;
+
   fn id(&self) -> &NodeId {
     &self.id
   }
@@ -44,26 +53,31 @@ impl Node for Frontend {
 }
 
 pub type FrontendRef<'a> = NodeRef<'a, Frontend>;
-pub type FrontendRefMulti<'a> = NodeRefMulti<'a, Frontend>;
 pub type FrontendIter<'a> = NodeIter<'a, Frontend>;
 
 pub struct FrontendMgr {
   frontends: DashMap<NodeId, Frontend, AHasher>,
+  // Indexed by pick, replaced as a whole once reloaded
+  frontend_ids: RwLock<Arc<Vec<NodeId>>>,
 }
 
 impl FrontendMgr {
   #[inline]
   pub(crate) fn new() -> Self {
+    Self::with_configs(&discovery::frontend_configs())
+  }
+
+  fn with_configs(frontend_configs: &[FrontendConfig]) -> Self {
     let frontends = DashMap::with_capacity_and_hasher(64, AHasher::default());
-    let frontend_mgr = FrontendMgr { frontends };
-    frontend_mgr.initialize();
+    let frontend_mgr = FrontendMgr { frontends, frontend_ids: RwLock::new(Arc::new(Vec::new())) };
+    frontend_mgr.initialize(frontend_configs);
     frontend_mgr
   }
 
   #[inline]
   pub fn activate(&self, id: &NodeId) {
     if let Some(mut frontend) = self.frontends.get_mut(id) {
-      frontend.active_at = Utc::now().timestamp() as u32;
+      frontend.active_at = clock::now();
     }
   }
 
@@ -76,11 +90,17 @@ impl FrontendMgr {
     }
   }
 
-  #[inline]
-  pub fn pick<'a>(&'a self) -> Option<FrontendRefMulti<'a>> {
-    let mut rng = thread_rng();
-    let index = rng.gen_range(0..self.frontends.len());
-    self.frontends.iter().nth(index).map(|frontend| frontend)
+  // Uniformly at random, the ids are a snapshot, so a frontend removed
+  // meanwhile is skipped for the next one
+  pub fn pick<'a>(&'a self) -> Option<FrontendRef<'a>> {
+    let frontend_ids = self.frontend_ids.read().unwrap().clone();
+    if frontend_ids.is_empty() {
+      return None;
+    }
+    let index = thread_rng().gen_range(0..frontend_ids.len());
+    (0..frontend_ids.len())
+      .map(|offset| &frontend_ids[(index + offset) % frontend_ids.len()])
+      .find_map(|id| self.frontends.get(id))
   }
 
   #[inline]
@@ -89,19 +109,149 @@ impl FrontendMgr {
   }
 
   #[inline]
-  fn initialize(&self) {
-    CONFIG.frontend_mgr.frontends.iter().for_each(|frontend_config| {
-      let frontend = Frontend::new(
-        frontend_config.id.clone(),
-        frontend_config.domain.clone(),
-        frontend_config.public_ip,
-        frontend_config.private_ip,
-        frontend_config.http_port,
-        frontend_config.https_port,
-      );
+  pub fn count(&self) -> usize {
+    self.frontends.len()
+  }
+
+  // Applies the reloaded config and the frontends discovered, the frontends
+  // kept stay active
+  pub(crate) fn reload(&self) {
+    self.reload_configs(&discovery::frontend_configs());
+  }
+
+  fn reload_configs(&self, frontend_configs: &[FrontendConfig]) {
+    let mut removed_ids = vec![];
+    self.frontends.retain(|id, _| {
+      let is_kept = frontend_configs.iter().any(|config| &config.id == id);
+      if !is_kept {
+        removed_ids.push(id.clone());
+      }
+      is_kept
+    });
+    let mut added_ids = vec![];
+    for frontend_config in frontend_configs {
+      let active_at = self.frontends.get(&frontend_config.id).map(|frontend| frontend.active_at);
+      let mut frontend = Self::build_frontend(frontend_config);
+      match active_at {
+        Some(active_at) => frontend.active_at = active_at,
+        None => added_ids.push(frontend.id.clone()),
+      }
+      self.frontends.insert(frontend.id.clone(), frontend);
+    }
+    self.update_ids();
+    event_bus::publish_node_changes(NodeType::Frontend, added_ids, removed_ids);
+  }
+
+  #[inline]
+  fn initialize(&self, frontend_configs: &[FrontendConfig]) {
+    frontend_configs.iter().for_each(|frontend_config| {
+      let frontend = Self::build_frontend(frontend_config);
       self.frontends.insert(frontend.id.clone(), frontend.clone());
     });
+    self.update_ids();
+  }
+
+  #[inline]
+  fn update_ids(&self) {
+    let mut frontend_ids: Vec<NodeId> =
+      self.frontends.iter().map(|frontend| frontend.id.clone()).collect();
+    frontend_ids.sort();
+    *self.frontend_ids.write().unwrap() = Arc::new(frontend_ids);
+  }
+
+  #[inline]
+  fn build_frontend(frontend_config: &FrontendConfig) -> Frontend {
+    Frontend::new(
+      frontend_config.id.clone(),
+      frontend_config.domain.clone(),
+      frontend_config.public_ip,
+      frontend_config.private_ip,
+      frontend_config.http_port,
+      frontend_config.https_port,
+    )
   }
 }
 
 pub static FRONTEND_MGR: Lazy<FrontendMgr> = Lazy::new(|| FrontendMgr::new());
+
+#[cfg(test)]
+mod tests {
+  use std::{thread, time::Instant};
+
+  use ahash::HashMap;
+
+  use super::*;
+
+  fn build_configs(count: usize) -> Vec<FrontendConfig> {
+    (0..count)
+      .map(|i| FrontendConfig {
+        id: format!("frontend-{}", i),
+        domain: "maxwell.local".to_owned(),
+        http_port: 10000 + i as u32,
+        https_port: 20000 + i as u32,
+        public_ip: "127.0.0.1".parse().unwrap(),
+        private_ip: "127.0.0.1".parse().unwrap(),
+      })
+      .collect()
+  }
+
+  #[test]
+  fn test_pick() {
+    let frontend_mgr = FrontendMgr::with_configs(&[]);
+    assert!(frontend_mgr.pick().is_none());
+
+    let frontend_mgr = FrontendMgr::with_configs(&build_configs(4));
+    let mut picked_counts: HashMap<NodeId, u32> = HashMap::default();
+    for _ in 0..4000 {
+      *picked_counts.entry(frontend_mgr.pick().unwrap().id.clone()).or_default() += 1;
+    }
+    assert_eq!(picked_counts.len(), 4);
+    assert!(picked_counts.values().all(|count| *count > 800));
+
+    frontend_mgr.reload_configs(&build_configs(2));
+    for _ in 0..100 {
+      let frontend = frontend_mgr.pick().unwrap();
+      assert!(frontend.id == "frontend-0" || frontend.id == "frontend-1");
+    }
+  }
+
+  // cargo test --release bench_pick -- --ignored --nocapture
+  #[test]
+  #[ignore]
+  fn bench_pick() {
+    const THREADS: usize = 8;
+    const PICKS: usize = 100_000;
+
+    for count in [10, 100, 1000] {
+      let frontend_mgr = FrontendMgr::with_configs(&build_configs(count));
+      let elapsed = run_concurrently(THREADS, || {
+        for _ in 0..PICKS {
+          let index = thread_rng().gen_range(0..frontend_mgr.frontends.len());
+          assert!(frontend_mgr.frontends.iter().nth(index).is_some());
+        }
+      });
+      println!("iter().nth(): frontends: {}, ns per pick: {}", count, ns_per_pick(elapsed, PICKS));
+      let elapsed = run_concurrently(THREADS, || {
+        for _ in 0..PICKS {
+          assert!(frontend_mgr.pick().is_some());
+        }
+      });
+      println!("indexed: frontends: {}, ns per pick: {}", count, ns_per_pick(elapsed, PICKS));
+    }
+  }
+
+  fn run_concurrently<F: Fn() + Sync>(threads: usize, f: F) -> std::time::Duration {
+    let started_at = Instant::now();
+    thread::scope(|scope| {
+      for _ in 0..threads {
+        scope.spawn(&f);
+      }
+    });
+    started_at.elapsed()
+  }
+
+  #[inline]
+  fn ns_per_pick(elapsed: std::time::Duration, picks: usize) -> u128 {
+    elapsed.as_nanos() / picks as u128
+  }
+}
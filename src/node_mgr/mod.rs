@@ -6,6 +6,7 @@ use dashmap::{
   mapref::{multiple::RefMulti, one::Ref},
 };
 
+pub mod activation_buffer;
 pub mod backend_mgr;
 pub mod frontend_mgr;
 pub mod service_mgr;
@@ -18,9 +19,25 @@ pub enum NodeType {
   Frontend,
   Backend,
   Service,
+  // Another master
+  Peer,
+}
+
+impl NodeType {
+  #[inline]
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      NodeType::Unknown => "unknown",
+      NodeType::Frontend => "frontend",
+      NodeType::Backend => "backend",
+      NodeType::Service => "service",
+      NodeType::Peer => "peer",
+    }
+  }
 }
 
 pub trait Node: Clone + Debug {
+  const NODE_TYPE: NodeType;
   fn id(&self) -> &NodeId;
   #[allow(dead_code)]
   fn active_at(&self) -> u32;
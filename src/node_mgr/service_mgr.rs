@@ -2,27 +2,34 @@ use std::{
   borrow::Borrow,
   fmt::Debug,
   net::IpAddr,
-  sync::atomic::{AtomicU32, Ordering},
+  sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+  },
+  time::Duration,
 };
 
 use ahash::RandomState as AHasher;
+use anyhow::Result;
 use bytes::{Bytes, BytesMut};
-use chrono::Utc;
 use dashmap::{
   mapref::{entry::Entry, one::Ref},
   DashMap,
 };
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
-use seriesdb::{
-  coder::Coder,
-  prelude::Db,
-  table::{NormalTable, Table, TableEnhanced},
+use seriesdb::coder::Coder;
+
+use super::{activation_buffer::ActivationBuffer, Node, NodeId, NodeIter, NodeType};
+use crate::{
+  clock,
+  config::CONFIG,
+  event_bus::{self, Event},
+  health_policy,
+  raft::{self, Command},
+  store::{open_store, Store},
 };
 
-use super::{Node, NodeId, NodeIter};
-use crate::{config::CONFIG, db::DB};
-
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Service {
   pub(crate) id: NodeId,
@@ -32,6 +39,8 @@ pub struct Service {
 }
 
 impl Node for Service {
+  const NODE_TYPE: NodeType = NodeType::Service;
+
   fn id(&self) -> &NodeId {
     &self.id
   }
@@ -47,7 +56,7 @@ impl Node for Service {
 
 impl Service {
   pub fn new(id: String, private_ip: IpAddr, http_port: u32) -> Self {
-    Service { id, http_port, private_ip, active_at: Utc::now().timestamp() as u32 }
+    Service { id, http_port, private_ip, active_at: clock::now() }
   }
 
   #[inline]
@@ -57,16 +66,12 @@ impl Service {
 
   #[inline]
   pub fn is_healthy(&self) -> bool {
-    if Utc::now().timestamp() as u32 - self.active_at > CONFIG.service_mgr.unhealthy_threshold {
-      false
-    } else {
-      true
-    }
+    health_policy::is_healthy(self)
   }
 
   #[inline]
   pub fn is_stale(&self) -> bool {
-    if Utc::now().timestamp() as u32 - self.active_at > CONFIG.service_mgr.stale_threshold {
+    if clock::now() - self.active_at > CONFIG.load().service_mgr.stale_threshold {
       true
     } else {
       false
@@ -101,35 +106,73 @@ impl Coder<NodeId, Service> for ServiceCoder {
   }
 }
 
+type InfoKey = String;
+type InfoValue = String;
+type InfoStore = dyn Store<InfoKey, InfoValue>;
+
+pub(crate) struct InfoCoder;
+
+impl Coder<InfoKey, InfoValue> for InfoCoder {
+  type EncodedKey = Bytes;
+  type EncodedValue = Bytes;
+
+  #[inline(always)]
+  fn encode_key<K: Borrow<InfoKey>>(key: K) -> Self::EncodedKey {
+    BytesMut::from(key.borrow().as_bytes()).freeze()
+  }
+
+  #[inline(always)]
+  fn decode_key(key: &[u8]) -> InfoKey {
+    std::str::from_utf8(key).unwrap().to_string()
+  }
+
+  #[inline(always)]
+  fn encode_value<V: Borrow<InfoValue>>(value: V) -> Self::EncodedValue {
+    BytesMut::from(value.borrow().as_bytes()).freeze()
+  }
+
+  #[inline(always)]
+  fn decode_value(value: &[u8]) -> InfoValue {
+    std::str::from_utf8(value).unwrap().to_string()
+  }
+}
+
 pub type ServiceRef<'a> = Ref<'a, NodeId, Service>;
-type ServiceStore = TableEnhanced<NormalTable, NodeId, Service, ServiceCoder>;
+type ServiceStore = dyn Store<NodeId, Service>;
 pub type ServiceIter<'a> = NodeIter<'a, Service>;
 
 pub struct ServiceMgr {
   cache: DashMap<NodeId, Service, AHasher>,
-  service_store: ServiceStore,
+  service_store: Arc<ServiceStore>,
+  info_store: Arc<InfoStore>,
   version: AtomicU32,
+  activations: ActivationBuffer,
 }
 
 impl ServiceMgr {
   #[inline]
-  pub(crate) fn new(service_store: ServiceStore) -> Self {
+  pub(crate) fn new(service_store: Arc<ServiceStore>, info_store: Arc<InfoStore>) -> Self {
     let cache = DashMap::with_capacity_and_hasher(64, AHasher::default());
     let service_mgr = ServiceMgr {
       cache,
       service_store,
-      version: AtomicU32::new(crc32fast::hash(
-        format!("{}", Utc::now().timestamp_millis()).as_bytes(),
-      )),
+      info_store,
+      version: AtomicU32::new(crc32fast::hash(format!("{}", clock::now_millis()).as_bytes())),
+      activations: ActivationBuffer::new(),
     };
     service_mgr.recover();
+    service_mgr.recover_version();
     service_mgr
   }
 
+  // Replicated by raft, which applies it to SERVICE_MGR, see raft::replicate
   #[inline]
-  pub fn add(&self, service: Service) {
-    let id_bytes = <ServiceCoder as Coder<NodeId, Service>>::encode_key(&service.id);
-    let service_bytes = <ServiceCoder as Coder<NodeId, Service>>::encode_value(&service);
+  pub fn add(&self, service: Service) -> Result<()> {
+    raft::replicate(Command::SetService { service })
+  }
+
+  // Fails if the service could not be stored, which is then left as it was
+  pub(crate) fn apply_add(&self, service: Service) -> Result<()> {
     match self.cache.entry(service.id.clone()) {
       Entry::Occupied(mut entry) => {
         let curr_service = entry.get();
@@ -146,79 +189,95 @@ impl ServiceMgr {
           log::debug!("The service is the same, no need to update version.");
           false
         };
+        self.service_store.put(entry.key(), &service)?;
         entry.insert(service);
-        self
-          .service_store
-          .raw()
-          .put(id_bytes, service_bytes)
-          .unwrap_or_else(|err| log::warn!("Failed to add service: err: {:?}", err));
         if changed {
           self.update_version();
         }
       }
       Entry::Vacant(entry) => {
         log::debug!("Adding service: {:?}", service);
+        self.service_store.put(entry.key(), &service)?;
+        event_bus::publish(Event::NodeAdded { node_type: "service", node_id: service.id.clone() });
         entry.insert(service);
-        self
-          .service_store
-          .raw()
-          .put(id_bytes, service_bytes)
-          .unwrap_or_else(|err| log::warn!("Failed to add service: err: {:?}", err));
         self.update_version();
       }
     }
+    Ok(())
   }
 
   #[inline]
-  pub fn remove(&self, id: &NodeId) {
+  pub(crate) fn apply_remove(&self, id: &NodeId) {
     if self.cache.remove(id).is_some() {
+      self.activations.remove(id);
       self
         .service_store
         .delete(id)
         .unwrap_or_else(|err| log::warn!("Failed to remove service: err: {:?}", err));
+      event_bus::publish(Event::NodeRemoved { node_type: "service", node_id: id.clone() });
       self.update_version();
     }
   }
 
+  // Persisted by flush_activations, unless the persist interval is 0
   #[inline]
   pub fn activate(&self, id: &NodeId) {
     if let Some(mut service) = self.cache.get_mut(id) {
-      let now = Utc::now().timestamp() as u32;
+      let now = clock::now();
       service.active_at = now;
-      self
-        .service_store
-        .put(id, &*service)
-        .unwrap_or_else(|err| log::warn!("Failed to activate node: err: {:?}", err));
+      if CONFIG.load().service_mgr.persist_interval == 0 {
+        self
+          .service_store
+          .put(id, &*service)
+          .unwrap_or_else(|err| log::warn!("Failed to activate node: err: {:?}", err));
+      } else {
+        self.activations.mark(id, now);
+      }
     }
   }
 
-  #[inline]
-  pub fn get<'a>(&'a self, id: &NodeId) -> Option<ServiceRef<'a>> {
-    match self.cache.entry(id.clone()) {
-      Entry::Occupied(entry) => {
-        let service = entry.get();
-        if service.is_stale() {
-          entry.remove();
-          self
-            .service_store
-            .delete(id)
-            .unwrap_or_else(|err| log::warn!("Failed to remove service: err: {:?}", err));
-          self.update_version();
-          None
-        } else {
-          Some(entry.into_ref().downgrade())
-        }
+  // Persists the services activated since the last flush, returns how many
+  pub fn flush_activations(&self) -> usize {
+    let mut count = 0;
+    for (id, _) in self.activations.drain() {
+      if let Some(service) = self.cache.get(&id) {
+        self
+          .service_store
+          .put(&id, &*service)
+          .unwrap_or_else(|err| log::warn!("Failed to activate node: err: {:?}", err));
+        count += 1;
       }
-      Entry::Vacant(_) => None,
     }
+    count
+  }
+
+  // Stale services are invisible, but only removed by the sweep
+  #[inline]
+  pub fn get<'a>(&'a self, id: &NodeId) -> Option<ServiceRef<'a>> {
+    self.cache.get(id).filter(|service| !service.is_stale())
+  }
+
+  // The services which have not been active within the stale threshold, which
+  // the sweep removes
+  pub fn stale_ids(&self) -> Vec<NodeId> {
+    self
+      .cache
+      .iter()
+      .filter(|service| service.is_stale())
+      .map(|service| service.key().clone())
+      .collect()
   }
 
-  #[allow(dead_code)]
   #[inline]
   pub fn iter<'a>(&'a self) -> ServiceIter<'a> {
     self.cache.iter()
   }
 
+  #[inline]
+  pub fn count(&self) -> usize {
+    self.cache.len()
+  }
+
   #[inline]
   pub fn version(&self) -> u32 {
     self.version.load(Ordering::SeqCst)
@@ -226,46 +285,73 @@ impl ServiceMgr {
 
   #[inline]
   fn update_version(&self) {
-    self.version.fetch_add(1, Ordering::SeqCst);
+    let version = self.version.fetch_add(1, Ordering::SeqCst).wrapping_add(1);
+    self.info_store.put(&"version".to_owned(), &format!("{}", version)).unwrap_or_else(|err| {
+      log::warn!("Failed to store service version: {:?}", err);
+    });
+  }
+
+  // Continues from the stored version rather than starting over, so that a
+  // checksum seen before the restart is never handed out again for other data
+  #[inline]
+  fn recover_version(&self) {
+    if let Some(version) = self.info_store.get(&"version".to_owned()).unwrap() {
+      self.version.store(version.parse().unwrap_or(0), Ordering::SeqCst);
+    }
+    self.update_version();
   }
 
   #[inline]
   fn recover(&self) {
-    let mut cursor = self.service_store.new_cursor();
-    cursor.seek_to_first();
-    while cursor.is_valid() {
-      let id = cursor.key().unwrap();
-      let service: Service = cursor.value().unwrap();
+    self.service_store.scan(None, &mut |id, service| {
       self.cache.insert(id, service);
-      cursor.next();
-    }
+      true
+    });
   }
 }
 
 pub static SERVICE_MGR: Lazy<ServiceMgr> = Lazy::new(|| {
   ServiceMgr::new(
-    DB.open_table("node_mgr.service_mgr.services")
-      .unwrap()
-      .enhance::<NodeId, Service, ServiceCoder>(),
+    open_store::<NodeId, Service, ServiceCoder>("node_mgr.service_mgr.services").unwrap().into(),
+    open_store::<InfoKey, InfoValue, InfoCoder>("node_mgr.service_mgr.infos").unwrap().into(),
   )
 });
 
+// So that up to a persist interval of activations are lost on a crash, which
+// only makes the services look older right after the restart
+pub fn spawn_activation_flush_task() {
+  let persist_interval = CONFIG.load().service_mgr.persist_interval;
+  if persist_interval == 0 {
+    return;
+  }
+  actix_web::rt::spawn(async move {
+    let mut interval = tokio::time::interval(Duration::from_secs(persist_interval));
+    loop {
+      interval.tick().await;
+      let count = SERVICE_MGR.flush_activations();
+      log::debug!("Flushed service activations: count: {:?}", count);
+    }
+  });
+}
+
 #[cfg(test)]
 mod tests {
   use std::net::{IpAddr, Ipv4Addr};
-  use std::sync::Arc;
-
-  use seriesdb::prelude::{Db, NormalDb, Options};
 
   use super::*;
+  use crate::store::MemoryStore;
+
+  fn stores() -> (Arc<ServiceStore>, Arc<InfoStore>) {
+    (
+      Arc::new(MemoryStore::<_, _, ServiceCoder>::new()),
+      Arc::new(MemoryStore::<_, _, InfoCoder>::new()),
+    )
+  }
 
   #[test]
   fn test_basic() {
-    let db = Arc::new(NormalDb::open("data/test_basic", &mut Options::new()).unwrap());
-    db.truncate_table("test_services").unwrap();
-    let table = db.open_table("test_services").unwrap().enhance();
-
-    let service_mgr = ServiceMgr::new(table);
+    let (service_store, info_store) = stores();
+    let service_mgr = ServiceMgr::new(service_store, info_store);
 
     let id = "service-0";
     let ip = "127.0.0.1".parse::<Ipv4Addr>().unwrap();
@@ -275,8 +361,56 @@ mod tests {
     let id = input_service.id().clone();
     let output_service = service_mgr.get(&id);
     assert!(output_service.is_none());
-    service_mgr.add(input_service);
+    service_mgr.apply_add(input_service).unwrap();
     let output_node = service_mgr.get(&id);
     assert!(output_node.is_some());
   }
+
+  #[test]
+  fn test_stale_ids() {
+    let clock = clock::mock();
+    let (service_store, info_store) = stores();
+    let service_mgr = ServiceMgr::new(service_store, info_store);
+    let id = "service-0".to_owned();
+    service_mgr
+      .apply_add(Service::new(id.clone(), IpAddr::V4(Ipv4Addr::LOCALHOST), 10000))
+      .unwrap();
+
+    clock.advance(Duration::from_secs(CONFIG.load().service_mgr.unhealthy_threshold as u64 + 1));
+    assert!(!service_mgr.get(&id).unwrap().is_healthy());
+    assert!(service_mgr.stale_ids().is_empty());
+
+    clock.advance(Duration::from_secs(CONFIG.load().service_mgr.stale_threshold as u64));
+    assert!(service_mgr.get(&id).is_none());
+    assert_eq!(service_mgr.stale_ids(), vec![id.clone()]);
+    service_mgr.apply_remove(&id);
+    assert_eq!(service_mgr.count(), 0);
+  }
+
+  #[test]
+  fn test_recover_version() {
+    let (service_store, info_store) = stores();
+    let service_mgr = ServiceMgr::new(service_store.clone(), info_store.clone());
+    let version = service_mgr.version();
+
+    let service_mgr = ServiceMgr::new(service_store, info_store);
+    assert_eq!(service_mgr.version(), version.wrapping_add(1));
+  }
+
+  #[test]
+  fn test_flush_activations() {
+    let (service_store, info_store) = stores();
+    let service_mgr = ServiceMgr::new(service_store, info_store);
+    let mut service = Service::new("service-0".to_owned(), "127.0.0.1".parse().unwrap(), 10000);
+    service.active_at = 1;
+    let id = service.id().clone();
+    service_mgr.apply_add(service).unwrap();
+
+    service_mgr.activate(&id);
+    assert_eq!(service_mgr.service_store.get(&id).unwrap().unwrap().active_at, 1);
+    assert_eq!(service_mgr.flush_activations(), 1);
+    let active_at = service_mgr.service_store.get(&id).unwrap().unwrap().active_at;
+    assert_eq!(active_at, service_mgr.cache.get(&id).unwrap().active_at);
+    assert_eq!(service_mgr.flush_activations(), 0);
+  }
 }
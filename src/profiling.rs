@@ -0,0 +1,173 @@
+use std::{
+  fmt,
+  sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::config::CONFIG;
+
+// Samples per second, a prime so that the samples do not line up with timers
+#[cfg(unix)]
+const CPU_PROFILE_FREQUENCY: i32 = 99;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfileFormat {
+  // Protobuf, as read by `go tool pprof`
+  Pprof,
+  // Svg
+  Flamegraph,
+}
+
+impl ProfileFormat {
+  #[inline]
+  pub fn content_type(self) -> &'static str {
+    match self {
+      ProfileFormat::Pprof => "application/octet-stream",
+      ProfileFormat::Flamegraph => "image/svg+xml",
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum ProfilingError {
+  Disabled,
+  InvalidDuration { seconds: u64, max: u64 },
+  AlreadyRunning,
+  Unsupported,
+  Failed(anyhow::Error),
+}
+
+impl fmt::Display for ProfilingError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ProfilingError::Disabled => write!(f, "Profiling is disabled."),
+      ProfilingError::InvalidDuration { seconds, max } => {
+        write!(f, "Invalid profile duration: {}, expected: 1..={}", seconds, max)
+      }
+      ProfilingError::AlreadyRunning => write!(f, "A cpu profile is already running."),
+      ProfilingError::Unsupported => write!(f, "Profiling is not supported on this platform."),
+      ProfilingError::Failed(err) => write!(f, "Failed to profile: {}", err),
+    }
+  }
+}
+
+impl std::error::Error for ProfilingError {}
+
+// Bytes, as reported by the allocator
+#[derive(Debug, Serialize)]
+pub struct HeapStats {
+  pub allocated: usize,
+  pub active: usize,
+  pub metadata: usize,
+  pub resident: usize,
+  pub mapped: usize,
+  pub retained: usize,
+}
+
+// The profiler hooks SIGPROF, so only one can run at a time
+static CPU_PROFILING: AtomicBool = AtomicBool::new(false);
+
+struct CpuProfilingGuard;
+
+impl CpuProfilingGuard {
+  #[inline]
+  fn acquire() -> Result<Self, ProfilingError> {
+    CPU_PROFILING
+      .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+      .map(|_| CpuProfilingGuard)
+      .map_err(|_| ProfilingError::AlreadyRunning)
+  }
+}
+
+impl Drop for CpuProfilingGuard {
+  #[inline]
+  fn drop(&mut self) {
+    CPU_PROFILING.store(false, Ordering::Release);
+  }
+}
+
+#[inline]
+fn check_enabled() -> Result<(), ProfilingError> {
+  if CONFIG.load().profiling.enabled {
+    Ok(())
+  } else {
+    Err(ProfilingError::Disabled)
+  }
+}
+
+// Samples the whole process for the seconds, while the server keeps serving
+#[cfg(unix)]
+pub async fn profile_cpu(seconds: u64, format: ProfileFormat) -> Result<Vec<u8>, ProfilingError> {
+  use pprof::protos::Message;
+
+  check_enabled()?;
+  let max = CONFIG.load().profiling.max_duration;
+  if seconds == 0 || seconds > max {
+    return Err(ProfilingError::InvalidDuration { seconds, max });
+  }
+  let _guard = CpuProfilingGuard::acquire()?;
+  log::info!("Profiling cpu: seconds: {:?}, format: {:?}", seconds, format);
+  let profiler = pprof::ProfilerGuardBuilder::default()
+    .frequency(CPU_PROFILE_FREQUENCY)
+    .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+    .build()
+    .map_err(|err| ProfilingError::Failed(err.into()))?;
+  actix_web::rt::time::sleep(std::time::Duration::from_secs(seconds)).await;
+  let report = profiler.report().build().map_err(|err| ProfilingError::Failed(err.into()))?;
+  let mut body = vec![];
+  match format {
+    ProfileFormat::Pprof => {
+      let profile = report.pprof().map_err(|err| ProfilingError::Failed(err.into()))?;
+      profile.encode(&mut body).map_err(|err| ProfilingError::Failed(err.into()))?;
+    }
+    ProfileFormat::Flamegraph => {
+      report.flamegraph(&mut body).map_err(|err| ProfilingError::Failed(err.into()))?;
+    }
+  }
+  Ok(body)
+}
+
+#[cfg(not(unix))]
+pub async fn profile_cpu(_seconds: u64, _format: ProfileFormat) -> Result<Vec<u8>, ProfilingError> {
+  check_enabled()?;
+  Err(ProfilingError::Unsupported)
+}
+
+#[cfg(unix)]
+pub fn heap_stats() -> Result<HeapStats, ProfilingError> {
+  use tikv_jemalloc_ctl::{epoch, stats};
+
+  check_enabled()?;
+  // The stats are cached until the epoch is advanced
+  epoch::advance().map_err(|err| ProfilingError::Failed(err.into()))?;
+  let read = |result: tikv_jemalloc_ctl::Result<usize>| {
+    result.map_err(|err| ProfilingError::Failed(err.into()))
+  };
+  Ok(HeapStats {
+    allocated: read(stats::allocated::read())?,
+    active: read(stats::active::read())?,
+    metadata: read(stats::metadata::read())?,
+    resident: read(stats::resident::read())?,
+    mapped: read(stats::mapped::read())?,
+    retained: read(stats::retained::read())?,
+  })
+}
+
+#[cfg(not(unix))]
+pub fn heap_stats() -> Result<HeapStats, ProfilingError> {
+  check_enabled()?;
+  Err(ProfilingError::Unsupported)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_cpu_profiling_guard() {
+    let guard = CpuProfilingGuard::acquire().unwrap();
+    assert!(matches!(CpuProfilingGuard::acquire(), Err(ProfilingError::AlreadyRunning)));
+    drop(guard);
+    assert!(CpuProfilingGuard::acquire().is_ok());
+  }
+}
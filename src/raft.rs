@@ -0,0 +1,1107 @@
+use std::{
+  borrow::Borrow,
+  collections::{BTreeMap, HashMap, HashSet},
+  fmt, mem,
+  sync::{
+    mpsc::{self, SyncSender},
+    Mutex,
+  },
+  time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::{Bytes, BytesMut};
+use futures::future::join_all;
+use once_cell::sync::Lazy;
+use rand::{thread_rng, Rng};
+use serde::{de::DeserializeOwned, Serialize};
+use seriesdb::coder::Coder;
+use tokio::sync::Notify;
+
+use crate::{
+  cluster::{self, LeaderInfo, NotLeader},
+  config::{DbPoolConfig, CONFIG},
+  db_pool::DbPool,
+  node_mgr::{NodeId, Service, SERVICE_MGR},
+  route_mgr::{PathBundle, PathRateLimit, ROUTE_MGR},
+  snapshot::PEER_TOKEN_HEADER,
+  standby::{self, StateDelta},
+  store::{open_store, Store},
+  topic_mgr::{Topic, TOPIC_MGR},
+};
+
+// The masters configured as peers elect a leader by raft, which alone takes the
+// writes, and replicate the nodes, routes and topics through its log: every
+// write of them is appended by the leader as a command, and applied in the
+// order of the log by every master, the leader included, once a majority has
+// it. A write is replied once applied by the leader, so a leader lost meanwhile
+// never leaves a write replied but unreplicated, though one timed out may still
+// be committed. The commands are idempotent, so that replaying them is safe,
+// and the log is compacted by appending a snapshot of the whole state, the
+// entries before it are dropped once it is applied.
+
+// Of the entries sent per append, a snapshot is always sent alone
+const MAX_ENTRIES_PER_APPEND: usize = 64;
+const HARD_STATE_KEY: &str = "hard_state";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+  Follower,
+  Candidate,
+  Leader,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Command {
+  // Appended by a new leader, so that the entries of the previous terms are
+  // committed along with it
+  Noop,
+  SetService {
+    service: Service,
+  },
+  // Along with the routes, rate limits and tenant of the service
+  RemoveService {
+    service_id: NodeId,
+  },
+  SetTenant {
+    service_id: NodeId,
+    tenant: String,
+  },
+  SetRoutes {
+    service_id: NodeId,
+    paths: PathBundle,
+  },
+  RemoveRoutes {
+    service_id: NodeId,
+  },
+  SetWeight {
+    service_id: NodeId,
+    weight: u32,
+  },
+  SetRateLimits {
+    service_id: NodeId,
+    rate_limits: Vec<PathRateLimit>,
+  },
+  // A new topic, unlike a reassigned one, changes no version
+  AssignTopic {
+    topic: Topic,
+    backend_id: NodeId,
+  },
+  ReassignTopic {
+    topic: Topic,
+    backend_id: NodeId,
+  },
+  RemoveTopic {
+    topic: Topic,
+  },
+  // Appended by the leaders before every write went through the log
+  Delta {
+    delta: StateDelta,
+  },
+  // The state applied when it was taken, then the commands appended before it
+  // but not applied yet, which are applied again after it
+  Snapshot {
+    snapshot: standby::StateSnapshot,
+    #[serde(default)]
+    commands: Vec<Command>,
+  },
+}
+
+// The write was appended by the leader, but not applied within the commit
+// timeout, or the leader was deposed meanwhile, it may still be committed, or
+// dropped by the next leader
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotCommitted {
+  pub index: u64,
+}
+
+impl fmt::Display for NotCommitted {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "The write is not known to be committed: index: {}", self.index)
+  }
+}
+
+impl std::error::Error for NotCommitted {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogEntry {
+  pub index: u64,
+  pub term: u64,
+  pub command: Command,
+}
+
+// Saved before replying, the entries up to base_index are dropped
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct HardState {
+  term: u64,
+  voted_for: Option<String>,
+  base_index: u64,
+  base_term: u64,
+  // As the state applied is kept in the db as well
+  last_applied: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VoteReq {
+  pub term: u64,
+  pub candidate_id: String,
+  pub last_index: u64,
+  pub last_term: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VoteRep {
+  pub term: u64,
+  pub granted: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppendReq {
+  pub term: u64,
+  pub leader_id: String,
+  pub prev_index: u64,
+  pub prev_term: u64,
+  pub entries: Vec<LogEntry>,
+  pub leader_commit: u64,
+  // The entries start with a snapshot, which replaces the log of the follower
+  // unless it has the snapshot already
+  #[serde(default)]
+  pub reset: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppendRep {
+  pub term: u64,
+  pub success: bool,
+  // The last entry matching the leader if success, else the last one the
+  // follower may have, where the leader goes back to
+  pub last_index: u64,
+}
+
+type LogStore = dyn Store<u64, LogEntry>;
+type HardStateStore = dyn Store<String, HardState>;
+
+pub(crate) struct LogCoder;
+
+impl Coder<u64, LogEntry> for LogCoder {
+  type EncodedKey = Bytes;
+  type EncodedValue = Bytes;
+
+  // Big endian, so that the entries are scanned in order
+  #[inline(always)]
+  fn encode_key<K: Borrow<u64>>(key: K) -> Self::EncodedKey {
+    Bytes::copy_from_slice(&key.borrow().to_be_bytes())
+  }
+
+  #[inline(always)]
+  fn decode_key(key: &[u8]) -> u64 {
+    u64::from_be_bytes(key.try_into().unwrap())
+  }
+
+  #[inline(always)]
+  fn encode_value<V: Borrow<LogEntry>>(value: V) -> Self::EncodedValue {
+    serde_json::to_vec(value.borrow()).unwrap().into()
+  }
+
+  #[inline(always)]
+  fn decode_value(value: &[u8]) -> LogEntry {
+    serde_json::from_slice(value).unwrap()
+  }
+}
+
+pub(crate) struct HardStateCoder;
+
+impl Coder<String, HardState> for HardStateCoder {
+  type EncodedKey = Bytes;
+  type EncodedValue = Bytes;
+
+  #[inline(always)]
+  fn encode_key<K: Borrow<String>>(key: K) -> Self::EncodedKey {
+    BytesMut::from(key.borrow().as_bytes()).freeze()
+  }
+
+  #[inline(always)]
+  fn decode_key(key: &[u8]) -> String {
+    std::str::from_utf8(key).unwrap().to_string()
+  }
+
+  #[inline(always)]
+  fn encode_value<V: Borrow<HardState>>(value: V) -> Self::EncodedValue {
+    serde_json::to_vec(value.borrow()).unwrap().into()
+  }
+
+  #[inline(always)]
+  fn decode_value(value: &[u8]) -> HardState {
+    serde_json::from_slice(value).unwrap()
+  }
+}
+
+pub(crate) struct Raft {
+  id: String,
+  peer_ids: Vec<String>,
+  hard_state: HardState,
+  role: Role,
+  leader_id: Option<String>,
+  commit_index: u64,
+  last_index: u64,
+  last_term: u64,
+  // Of the last snapshot entry, 0 if none is in the log
+  snapshot_index: u64,
+  // The first entry of the term of the leader, the ones before are committed
+  // along with it
+  term_start_index: u64,
+  votes: HashSet<String>,
+  next_indexes: HashMap<String, u64>,
+  match_indexes: HashMap<String, u64>,
+  // The writes replicated by the leader, by the index of their entries
+  waiters: BTreeMap<u64, SyncSender<Result<(), NotCommitted>>>,
+  election_deadline: Instant,
+  log_store: Box<LogStore>,
+  hard_state_store: Box<HardStateStore>,
+}
+
+impl Raft {
+  fn new(
+    id: String, peer_ids: Vec<String>, log_store: Box<LogStore>,
+    hard_state_store: Box<HardStateStore>,
+  ) -> Result<Self> {
+    let hard_state = hard_state_store.get(&HARD_STATE_KEY.to_owned())?.unwrap_or_default();
+    let (mut last_index, mut last_term) = (hard_state.base_index, hard_state.base_term);
+    let mut snapshot_index = 0;
+    log_store.scan(Some(&(hard_state.base_index + 1)), &mut |index, entry| {
+      if matches!(entry.command, Command::Snapshot { .. }) {
+        snapshot_index = index;
+      }
+      (last_index, last_term) = (index, entry.term);
+      true
+    });
+    log::info!(
+      "Loaded raft log: term: {:?}, base_index: {:?}, last_index: {:?}, last_applied: {:?}",
+      hard_state.term,
+      hard_state.base_index,
+      last_index,
+      hard_state.last_applied
+    );
+    Ok(Raft {
+      id,
+      peer_ids,
+      role: Role::Follower,
+      leader_id: None,
+      // Committed since applied
+      commit_index: hard_state.last_applied,
+      last_index,
+      last_term,
+      snapshot_index,
+      term_start_index: 0,
+      votes: HashSet::new(),
+      next_indexes: HashMap::new(),
+      match_indexes: HashMap::new(),
+      waiters: BTreeMap::new(),
+      election_deadline: Instant::now() + election_timeout(),
+      hard_state,
+      log_store,
+      hard_state_store,
+    })
+  }
+
+  #[inline]
+  fn quorum(&self) -> usize {
+    (self.peer_ids.len() + 1) / 2 + 1
+  }
+
+  #[inline]
+  fn entry(&self, index: u64) -> Option<LogEntry> {
+    self.log_store.get(&index).ok().flatten()
+  }
+
+  fn term_at(&self, index: u64) -> Option<u64> {
+    if index == self.hard_state.base_index {
+      Some(self.hard_state.base_term)
+    } else if index == self.last_index {
+      Some(self.last_term)
+    } else if index < self.hard_state.base_index || index > self.last_index {
+      None
+    } else {
+      self.entry(index).map(|entry| entry.term)
+    }
+  }
+
+  #[inline]
+  fn save_hard_state(&self) -> Result<()> {
+    self.hard_state_store.put(&HARD_STATE_KEY.to_owned(), &self.hard_state)
+  }
+
+  #[inline]
+  fn reset_election_deadline(&mut self) {
+    self.election_deadline = Instant::now() + election_timeout();
+  }
+
+  fn leader_info(&self) -> Option<LeaderInfo> {
+    let leader_id = self.leader_id.as_ref()?;
+    let endpoint = if *leader_id == self.id {
+      CONFIG.load().cluster.endpoint.clone()
+    } else {
+      CONFIG.load().cluster.peers.iter().find(|peer| peer.master_id == *leader_id)?.endpoint.clone()
+    };
+    Some(LeaderInfo { master_id: leader_id.clone(), endpoint, term: self.hard_state.term })
+  }
+
+  fn become_follower(&mut self, term: u64, leader_id: Option<String>) -> Result<()> {
+    if term > self.hard_state.term {
+      self.hard_state.term = term;
+      self.hard_state.voted_for = None;
+      self.save_hard_state()?;
+    }
+    if self.role != Role::Follower {
+      log::info!("Became follower: term: {:?}, leader: {:?}", term, leader_id);
+      self.role = Role::Follower;
+      // Their entries may be committed by the next leader, or be dropped by it
+      for (index, waiter) in mem::take(&mut self.waiters) {
+        let _ = waiter.try_send(Err(NotCommitted { index }));
+      }
+    }
+    self.leader_id = leader_id;
+    Ok(())
+  }
+
+  fn start_election(&mut self) -> Result<VoteReq> {
+    self.hard_state.term += 1;
+    self.hard_state.voted_for = Some(self.id.clone());
+    self.save_hard_state()?;
+    self.role = Role::Candidate;
+    self.leader_id = None;
+    self.votes = HashSet::from([self.id.clone()]);
+    self.reset_election_deadline();
+    log::info!("Standing for election: term: {:?}", self.hard_state.term);
+    Ok(VoteReq {
+      term: self.hard_state.term,
+      candidate_id: self.id.clone(),
+      last_index: self.last_index,
+      last_term: self.last_term,
+    })
+  }
+
+  // Granted once per term, to the candidates whose log is not behind
+  fn handle_vote(&mut self, req: VoteReq) -> Result<VoteRep> {
+    if req.term > self.hard_state.term {
+      self.become_follower(req.term, None)?;
+    }
+    let granted = req.term == self.hard_state.term
+      && self.hard_state.voted_for.as_ref().is_none_or(|voted_for| *voted_for == req.candidate_id)
+      && (req.last_term, req.last_index) >= (self.last_term, self.last_index);
+    if granted {
+      self.hard_state.voted_for = Some(req.candidate_id);
+      self.save_hard_state()?;
+      self.reset_election_deadline();
+    }
+    Ok(VoteRep { term: self.hard_state.term, granted })
+  }
+
+  fn on_vote_rep(&mut self, peer_id: &str, rep: VoteRep) -> Result<()> {
+    if rep.term > self.hard_state.term {
+      return self.become_follower(rep.term, None);
+    }
+    if self.role != Role::Candidate || rep.term != self.hard_state.term || !rep.granted {
+      return Ok(());
+    }
+    self.votes.insert(peer_id.to_owned());
+    if self.votes.len() >= self.quorum() {
+      self.become_leader()?;
+    }
+    Ok(())
+  }
+
+  fn become_leader(&mut self) -> Result<()> {
+    self.role = Role::Leader;
+    self.leader_id = Some(self.id.clone());
+    for peer_id in &self.peer_ids {
+      self.next_indexes.insert(peer_id.clone(), self.last_index + 1);
+      self.match_indexes.insert(peer_id.clone(), 0);
+    }
+    log::info!("Became leader: term: {:?}", self.hard_state.term);
+    self.term_start_index = self.append(Command::Noop)?;
+    Ok(())
+  }
+
+  // Fails with cluster::NotLeader unless leader, as the writes of the others
+  // are replicated from it
+  fn propose(&mut self, command: Command) -> Result<u64> {
+    if self.role != Role::Leader {
+      return Err(NotLeader { leader: self.leader_info() }.into());
+    }
+    self.append(command)
+  }
+
+  fn append(&mut self, command: Command) -> Result<u64> {
+    let entry = LogEntry { index: self.last_index + 1, term: self.hard_state.term, command };
+    self.put_entry(entry)
+  }
+
+  fn put_entry(&mut self, entry: LogEntry) -> Result<u64> {
+    self.log_store.put(&entry.index, &entry)?;
+    if matches!(entry.command, Command::Snapshot { .. }) {
+      self.snapshot_index = entry.index;
+    }
+    (self.last_index, self.last_term) = (entry.index, entry.term);
+    Ok(entry.index)
+  }
+
+  // Drops the entries from the index on
+  fn truncate_from(&mut self, index: u64) -> Result<()> {
+    for index in index..=self.last_index {
+      self.log_store.delete(&index)?;
+    }
+    self.last_index = index - 1;
+    self.last_term = if self.last_index == self.hard_state.base_index {
+      self.hard_state.base_term
+    } else {
+      self.entry(self.last_index).map_or(0, |entry| entry.term)
+    };
+    if self.snapshot_index >= index {
+      self.snapshot_index = 0;
+    }
+    Ok(())
+  }
+
+  fn reset_log(&mut self, base_index: u64, base_term: u64) -> Result<()> {
+    log::info!("Resetting raft log: base_index: {:?}, base_term: {:?}", base_index, base_term);
+    self.truncate_from(self.hard_state.base_index + 1)?;
+    self.hard_state.base_index = base_index;
+    self.hard_state.base_term = base_term;
+    self.save_hard_state()?;
+    (self.last_index, self.last_term) = (base_index, base_term);
+    self.commit_index = self.commit_index.max(base_index);
+    Ok(())
+  }
+
+  // From the next entry the peer is expected to miss, none unless leader
+  fn append_req(&self, peer_id: &str) -> Option<AppendReq> {
+    if self.role != Role::Leader {
+      return None;
+    }
+    let next_index = self.next_indexes.get(peer_id).copied().unwrap_or(self.last_index + 1);
+    let base_index = self.hard_state.base_index;
+    // The entries it misses are dropped, the snapshot after them replaces them
+    let reset = next_index <= base_index;
+    let from = if reset { base_index + 1 } else { next_index };
+    let prev_term = self.term_at(from - 1)?;
+    let mut entries = vec![];
+    if from <= self.last_index {
+      self.log_store.scan(Some(&from), &mut |_, entry| {
+        let is_snapshot = matches!(entry.command, Command::Snapshot { .. });
+        if is_snapshot && !entries.is_empty() {
+          return false;
+        }
+        entries.push(entry);
+        !is_snapshot && entries.len() < MAX_ENTRIES_PER_APPEND
+      });
+    }
+    Some(AppendReq {
+      term: self.hard_state.term,
+      leader_id: self.id.clone(),
+      prev_index: from - 1,
+      prev_term,
+      entries,
+      leader_commit: self.commit_index,
+      reset,
+    })
+  }
+
+  fn handle_append(&mut self, req: AppendReq) -> Result<AppendRep> {
+    if req.term < self.hard_state.term {
+      return Ok(AppendRep {
+        term: self.hard_state.term,
+        success: false,
+        last_index: self.last_index,
+      });
+    }
+    self.become_follower(req.term, Some(req.leader_id))?;
+    self.reset_election_deadline();
+
+    let AppendReq { mut prev_index, mut prev_term, mut entries, .. } = req;
+    if req.reset {
+      let has_snapshot =
+        entries.first().is_some_and(|first| self.term_at(first.index) == Some(first.term));
+      if !has_snapshot {
+        self.reset_log(prev_index, prev_term)?;
+      }
+    }
+    // The entries up to the base are committed, so they match
+    let base_index = self.hard_state.base_index;
+    if prev_index < base_index {
+      entries.retain(|entry| entry.index > base_index);
+      (prev_index, prev_term) = (base_index, self.hard_state.base_term);
+    }
+    if self.term_at(prev_index) != Some(prev_term) {
+      let last_index = self.last_index.min(prev_index.saturating_sub(1));
+      return Ok(AppendRep { term: req.term, success: false, last_index });
+    }
+
+    let match_index = prev_index + entries.len() as u64;
+    for entry in entries {
+      match self.term_at(entry.index) {
+        Some(term) if term == entry.term => continue,
+        Some(_) => self.truncate_from(entry.index)?,
+        None => {}
+      }
+      self.put_entry(entry)?;
+    }
+    self.commit_index = self.commit_index.max(req.leader_commit.min(match_index));
+    Ok(AppendRep { term: req.term, success: true, last_index: match_index })
+  }
+
+  fn on_append_rep(&mut self, peer_id: &str, rep: AppendRep) -> Result<()> {
+    if rep.term > self.hard_state.term {
+      return self.become_follower(rep.term, None);
+    }
+    if self.role != Role::Leader || rep.term != self.hard_state.term {
+      return Ok(());
+    }
+    if rep.success {
+      let match_index = self.match_indexes.entry(peer_id.to_owned()).or_default();
+      *match_index = (*match_index).max(rep.last_index);
+      let next_index = *match_index + 1;
+      self.next_indexes.insert(peer_id.to_owned(), next_index);
+      self.advance_commit();
+    } else {
+      let next_index = self.next_indexes.entry(peer_id.to_owned()).or_insert(self.last_index + 1);
+      *next_index = next_index.saturating_sub(1).min(rep.last_index + 1).max(1);
+    }
+    Ok(())
+  }
+
+  // Only the entries of its own term are committed by counting
+  fn advance_commit(&mut self) {
+    let from = (self.commit_index + 1).max(self.term_start_index);
+    for index in (from..=self.last_index).rev() {
+      let count =
+        1 + self.match_indexes.values().filter(|match_index| **match_index >= index).count();
+      if count >= self.quorum() {
+        self.commit_index = index;
+        break;
+      }
+    }
+  }
+
+  // The entries committed but not applied yet, and the last of them
+  fn committed_entries(&self) -> (Vec<LogEntry>, u64) {
+    let from = self.hard_state.last_applied.max(self.hard_state.base_index) + 1;
+    let commit_index = self.commit_index;
+    let mut entries = vec![];
+    let mut last_index = 0;
+    if from <= commit_index {
+      self.log_store.scan(Some(&from), &mut |index, entry| {
+        if index > commit_index {
+          return false;
+        }
+        last_index = index;
+        entries.push(entry);
+        entries.len() < MAX_ENTRIES_PER_APPEND
+      });
+    }
+    (entries, last_index)
+  }
+
+  // The writes waiting for the entries are replied
+  fn mark_applied(&mut self, index: u64) -> Result<()> {
+    if index > self.hard_state.last_applied {
+      self.hard_state.last_applied = index;
+      self.save_hard_state()?;
+    }
+    let waiters = self.waiters.split_off(&(index + 1));
+    for waiter in mem::replace(&mut self.waiters, waiters).into_values() {
+      let _ = waiter.try_send(Ok(()));
+    }
+    Ok(())
+  }
+
+  // Not before the last snapshot is applied, which the next one would include
+  #[inline]
+  fn is_snapshot_due(&self, max_log_entries: u64) -> bool {
+    self.role == Role::Leader
+      && self.snapshot_index <= self.hard_state.last_applied
+      && self.last_index - self.snapshot_index.max(self.hard_state.base_index) > max_log_entries
+  }
+
+  // Drops the entries before the last snapshot applied
+  fn compact(&mut self) -> Result<()> {
+    let index = self.snapshot_index;
+    if index <= self.hard_state.base_index + 1 || index > self.hard_state.last_applied {
+      return Ok(());
+    }
+    let base_index = self.hard_state.base_index;
+    self.hard_state.base_term = self.term_at(index - 1).unwrap_or_default();
+    self.hard_state.base_index = index - 1;
+    // Saved first, the entries left by a crash are below the base, never read
+    self.save_hard_state()?;
+    for index in base_index + 1..index {
+      self.log_store.delete(&index)?;
+    }
+    log::info!("Compacted raft log: base_index: {:?}", self.hard_state.base_index);
+    Ok(())
+  }
+
+  // Of the entries after the index, which a snapshot of the state applied up to
+  // it is appended along with
+  fn commands_after(&self, index: u64) -> Vec<Command> {
+    let mut commands = vec![];
+    if index < self.last_index {
+      self.log_store.scan(Some(&(index + 1)), &mut |_, entry| {
+        commands.push(entry.command);
+        true
+      });
+    }
+    commands
+  }
+}
+
+static RAFT: Lazy<Option<Mutex<Raft>>> = Lazy::new(|| {
+  if !is_enabled() {
+    return None;
+  }
+  let peer_ids = CONFIG.load().cluster.peers.iter().map(|peer| peer.master_id.clone()).collect();
+  let raft = Raft::new(
+    CONFIG.load().cluster.master_id.clone(),
+    peer_ids,
+    open_store::<u64, LogEntry, LogCoder>("raft.log").unwrap(),
+    open_store::<String, HardState, HardStateCoder>("raft.hard_states").unwrap(),
+  )
+  .unwrap();
+  Some(Mutex::new(raft))
+});
+
+// Raft reads and writes its log on a thread of its own, as the writers wait on
+// the db pool for the tick to commit and apply their entries
+static RAFT_POOL: Lazy<DbPool> =
+  Lazy::new(|| DbPool::new(&DbPoolConfig { threads: 1, max_queue_depth: 0, timeout: 0 }));
+
+// Ticks at once, so that the entries proposed are sent without waiting for the
+// next heartbeat
+static PROPOSED: Lazy<Notify> = Lazy::new(Notify::new);
+
+#[inline]
+pub fn is_enabled() -> bool {
+  !CONFIG.load().cluster.peers.is_empty()
+}
+
+// The followers leave sweeping the services and collecting the topics to the
+// leader, whose state they apply
+#[inline]
+pub fn is_follower() -> bool {
+  is_enabled() && cluster::check_leader().is_err()
+}
+
+// The leader known to the cluster is updated with every change of the state
+fn with_raft<T>(f: impl FnOnce(&mut Raft) -> T) -> T {
+  let mut raft = RAFT.as_ref().expect("Raft is disabled").lock().unwrap();
+  let result = f(&mut raft);
+  cluster::set_elected(raft.leader_info());
+  result
+}
+
+// Replicates a write of the nodes, routes or topics, and returns once the
+// leader applied it, failing with cluster::NotLeader unless this master is the
+// leader, or with NotCommitted. Applied at once unless raft is enabled. It
+// blocks, so it is called on the db pool only.
+pub fn replicate(command: Command) -> Result<()> {
+  if !is_enabled() {
+    return apply(command);
+  }
+  let (waiter, receiver) = mpsc::sync_channel(1);
+  let index = with_raft(|raft| -> Result<u64> {
+    let index = raft.propose(command)?;
+    raft.waiters.insert(index, waiter);
+    Ok(index)
+  })?;
+  PROPOSED.notify_one();
+  match receiver.recv_timeout(Duration::from_millis(CONFIG.load().cluster.commit_timeout)) {
+    Ok(result) => Ok(result?),
+    Err(_) => {
+      with_raft(|raft| raft.waiters.remove(&index));
+      Err(NotCommitted { index }.into())
+    }
+  }
+}
+
+// Same as replicate, without waiting for the write to be applied, for the
+// upkeep of the leader, which nobody waits for
+pub fn propose(command: Command) -> Result<()> {
+  if !is_enabled() {
+    return apply(command);
+  }
+  with_raft(|raft| raft.propose(command))?;
+  PROPOSED.notify_one();
+  Ok(())
+}
+
+pub async fn handle_vote(req: VoteReq) -> Result<VoteRep> {
+  RAFT_POOL.run(move || with_raft(|raft| raft.handle_vote(req))).await?
+}
+
+pub async fn handle_append(req: AppendReq) -> Result<AppendRep> {
+  RAFT_POOL.run(move || with_raft(|raft| raft.handle_append(req))).await?
+}
+
+#[inline]
+fn election_timeout() -> Duration {
+  let config = CONFIG.load();
+  let cluster = &config.cluster;
+  Duration::from_millis(
+    thread_rng().gen_range(cluster.election_timeout_min..=cluster.election_timeout_max),
+  )
+}
+
+// Elects, sends the appends and applies what is committed, every heartbeat,
+// and whenever an entry was proposed
+pub fn spawn_tick_task() {
+  if !is_enabled() {
+    return;
+  }
+  actix_web::rt::spawn(async {
+    let client = awc::Client::builder()
+      .timeout(Duration::from_millis(CONFIG.load().cluster.election_timeout_min))
+      .finish();
+    let mut interval =
+      tokio::time::interval(Duration::from_millis(CONFIG.load().cluster.heartbeat_interval));
+    loop {
+      tokio::select! {
+        _ = interval.tick() => {}
+        _ = PROPOSED.notified() => {}
+      }
+      if let Err(err) = tick(&client).await {
+        log::warn!("Failed to tick raft: err: {:?}", err);
+      }
+    }
+  });
+}
+
+async fn tick(client: &awc::Client) -> Result<()> {
+  let (vote_req, append_reqs) = RAFT_POOL
+    .run(|| {
+      with_raft(|raft| -> Result<_> {
+        if raft.role == Role::Leader {
+          let append_reqs: Vec<(String, AppendReq)> = raft
+            .peer_ids
+            .iter()
+            .filter_map(|peer_id| raft.append_req(peer_id).map(|req| (peer_id.clone(), req)))
+            .collect();
+          Ok((None, append_reqs))
+        } else if Instant::now() >= raft.election_deadline {
+          Ok((Some(raft.start_election()?), vec![]))
+        } else {
+          Ok((None, vec![]))
+        }
+      })
+    })
+    .await??;
+
+  if let Some(vote_req) = vote_req {
+    let peer_ids: Vec<String> =
+      CONFIG.load().cluster.peers.iter().map(|peer| peer.master_id.clone()).collect();
+    let reps = join_all(
+      peer_ids.iter().map(|peer_id| post::<_, VoteRep>(client, peer_id, "vote", &vote_req)),
+    )
+    .await;
+    let reps = received_reps(peer_ids, reps);
+    let is_elected = RAFT_POOL
+      .run(move || {
+        with_raft(|raft| -> Result<bool> {
+          reps.into_iter().try_for_each(|(peer_id, rep)| raft.on_vote_rep(&peer_id, rep))?;
+          Ok(raft.role == Role::Leader)
+        })
+      })
+      .await??;
+    // The followers leave the orphans to the leader, which may be elected after
+    // the backends changed
+    if is_elected {
+      RAFT_POOL.run(|| TOPIC_MGR.check_orphans()).await?;
+    }
+  }
+
+  if !append_reqs.is_empty() {
+    let reps = join_all(
+      append_reqs.iter().map(|(peer_id, req)| post::<_, AppendRep>(client, peer_id, "append", req)),
+    )
+    .await;
+    let reps = received_reps(append_reqs.into_iter().map(|(peer_id, _)| peer_id).collect(), reps);
+    RAFT_POOL
+      .run(move || {
+        with_raft(|raft| {
+          reps.into_iter().try_for_each(|(peer_id, rep)| raft.on_append_rep(&peer_id, rep))
+        })
+      })
+      .await??;
+  }
+
+  RAFT_POOL.run(apply_committed).await?
+}
+
+// The peers which failed to reply are tried again with the next tick
+fn received_reps<Rep>(peer_ids: Vec<String>, reps: Vec<Result<Rep>>) -> Vec<(String, Rep)> {
+  peer_ids
+    .into_iter()
+    .zip(reps)
+    .filter_map(|(peer_id, rep)| match rep {
+      Ok(rep) => Some((peer_id, rep)),
+      Err(err) => {
+        log::debug!("Failed to reach peer: master_id: {:?}, err: {:?}", peer_id, err);
+        None
+      }
+    })
+    .collect()
+}
+
+async fn post<Req: Serialize, Rep: DeserializeOwned>(
+  client: &awc::Client, peer_id: &str, action: &str, req: &Req,
+) -> Result<Rep> {
+  let config = CONFIG.load();
+  let peer = config
+    .cluster
+    .peers
+    .iter()
+    .find(|peer| peer.master_id == peer_id)
+    .ok_or_else(|| anyhow!("Unknown peer: {}", peer_id))?;
+  let url = format!("{}/$peer/raft/{}", peer.url.trim_end_matches('/'), action);
+  let mut rep = client
+    .post(&url)
+    .insert_header((PEER_TOKEN_HEADER, config.cluster.peer_token.as_str()))
+    .send_json(req)
+    .await
+    .map_err(|err| anyhow!("Failed to send req: err: {:?}", err))?;
+  let body = rep
+    .body()
+    .limit(config.server.max_frame_size)
+    .await
+    .map_err(|err| anyhow!("Failed to receive body: err: {:?}", err))?;
+  if !rep.status().is_success() {
+    bail!(
+      "Rejected by the peer: status: {}, body: {}",
+      rep.status(),
+      String::from_utf8_lossy(&body)
+    );
+  }
+  Ok(serde_json::from_slice(&body)?)
+}
+
+// On the raft pool, the lock is not held while applying, which only the tick
+// task does, so the entries are applied in the order of the log
+fn apply_committed() -> Result<()> {
+  loop {
+    let (entries, last_index) = with_raft(|raft| raft.committed_entries());
+    if last_index == 0 {
+      break;
+    }
+    for entry in entries {
+      log::debug!("Applying raft entry: index: {:?}, term: {:?}", entry.index, entry.term);
+      let index = entry.index;
+      apply(entry.command)
+        .with_context(|| format!("Failed to apply raft entry: index: {}", index))?;
+    }
+    with_raft(|raft| {
+      raft.mark_applied(last_index)?;
+      raft.compact()
+    })?;
+  }
+  if with_raft(|raft| raft.is_snapshot_due(CONFIG.load().cluster.max_log_entries)) {
+    append_snapshot()?;
+  }
+  Ok(())
+}
+
+// The only way the nodes, routes and topics are written, by the entries of the
+// log, or by the writes at once unless raft is enabled
+fn apply(command: Command) -> Result<()> {
+  match command {
+    Command::Noop => Ok(()),
+    Command::SetService { service } => SERVICE_MGR.apply_add(service),
+    Command::RemoveService { service_id } => {
+      SERVICE_MGR.apply_remove(&service_id);
+      ROUTE_MGR.apply_forget_service(&service_id);
+      Ok(())
+    }
+    Command::SetTenant { service_id, tenant } => ROUTE_MGR.apply_tenant(&service_id, &tenant),
+    Command::SetRoutes { service_id, paths } => {
+      ROUTE_MGR.apply_reverse_route_group(service_id, paths)
+    }
+    Command::RemoveRoutes { service_id } => {
+      ROUTE_MGR.apply_remove_reverse_route_group(&service_id);
+      Ok(())
+    }
+    Command::SetWeight { service_id, weight } => ROUTE_MGR.apply_weight(&service_id, weight),
+    Command::SetRateLimits { service_id, rate_limits } => {
+      ROUTE_MGR.apply_rate_limits(&service_id, rate_limits)
+    }
+    Command::AssignTopic { topic, backend_id } => TOPIC_MGR.apply_assign(topic, backend_id),
+    Command::ReassignTopic { topic, backend_id } => {
+      TOPIC_MGR.apply_reassign(topic, backend_id).map(|_| ())
+    }
+    Command::RemoveTopic { topic } => TOPIC_MGR.apply_remove(&topic).map(|_| ()),
+    Command::Delta { delta } => standby::apply_delta(delta),
+    Command::Snapshot { snapshot, commands } => {
+      standby::apply_snapshot(snapshot)?;
+      commands.into_iter().try_for_each(apply)
+    }
+  }
+}
+
+// Of the state applied, which nothing but the raft pool changes meanwhile, the
+// commands appended since are added under the lock, so that none is missed
+fn append_snapshot() -> Result<()> {
+  let applied_index = with_raft(|raft| raft.hard_state.last_applied);
+  let snapshot = standby::snapshot();
+  with_raft(|raft| {
+    let commands = raft.commands_after(applied_index);
+    raft.propose(Command::Snapshot { snapshot, commands }).map(|_| ())
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{standby::StateSnapshot, store::MemoryStore};
+
+  fn raft_of(id: &str, peer_ids: &[&str]) -> Raft {
+    Raft::new(
+      id.to_owned(),
+      peer_ids.iter().map(|peer_id| peer_id.to_string()).collect(),
+      Box::new(MemoryStore::<u64, LogEntry, LogCoder>::new()),
+      Box::new(MemoryStore::<String, HardState, HardStateCoder>::new()),
+    )
+    .unwrap()
+  }
+
+  fn elect(candidate: &mut Raft, voter: &mut Raft) {
+    let req = candidate.start_election().unwrap();
+    let rep = voter.handle_vote(req).unwrap();
+    assert!(rep.granted);
+    candidate.on_vote_rep(&voter.id, rep).unwrap();
+    assert_eq!(candidate.role, Role::Leader);
+  }
+
+  // Until the follower has every entry of the leader
+  fn replicate(leader: &mut Raft, follower: &mut Raft) {
+    for _ in 0..10 {
+      let req = leader.append_req(&follower.id).unwrap();
+      let rep = follower.handle_append(req).unwrap();
+      leader.on_append_rep(&follower.id, rep).unwrap();
+    }
+    assert_eq!(follower.last_index, leader.last_index);
+  }
+
+  fn assign_topic(topic: &str) -> Command {
+    Command::AssignTopic { topic: topic.to_owned(), backend_id: "backend-0".to_owned() }
+  }
+
+  #[test]
+  fn test_elect_and_replicate() {
+    let mut a = raft_of("master-0", &["master-1", "master-2"]);
+    let mut b = raft_of("master-1", &["master-0", "master-2"]);
+    let mut c = raft_of("master-2", &["master-0", "master-1"]);
+    elect(&mut a, &mut b);
+
+    // b voted in the term already
+    let req = c.start_election().unwrap();
+    assert!(!b.handle_vote(req).unwrap().granted);
+
+    // Only the leader takes the writes
+    assert!(b.propose(assign_topic("topic-0")).unwrap_err().is::<NotLeader>());
+    let index = a.propose(assign_topic("topic-0")).unwrap();
+    assert_eq!(index, 2);
+    let (waiter, receiver) = mpsc::sync_channel(1);
+    a.waiters.insert(index, waiter);
+    replicate(&mut a, &mut b);
+    assert_eq!(a.commit_index, 2);
+    // The leader applies its entries as the others, before the write is replied
+    assert!(receiver.try_recv().is_err());
+    let (entries, last_index) = a.committed_entries();
+    assert_eq!((entries.len(), last_index), (2, 2));
+    a.mark_applied(last_index).unwrap();
+    assert_eq!(receiver.try_recv().unwrap(), Ok(()));
+    assert_eq!(b.commit_index, 2);
+    let (entries, last_index) = b.committed_entries();
+    assert_eq!((entries.len(), last_index), (2, 2));
+    b.mark_applied(last_index).unwrap();
+    assert_eq!(b.committed_entries().0.len(), 0);
+
+    // c stood for election of the same term, it follows a once it appends
+    replicate(&mut a, &mut c);
+    assert_eq!(c.role, Role::Follower);
+    assert_eq!(c.leader_id.as_deref(), Some("master-0"));
+
+    // A stale leader is rejected, and turned into a follower by the rep
+    let mut stale = a.append_req("master-1").unwrap();
+    stale.term = 0;
+    let rep = b.handle_append(stale).unwrap();
+    assert!(!rep.success);
+    b.hard_state.term += 1;
+    let rep = b.handle_append(a.append_req("master-1").unwrap()).unwrap();
+    a.on_append_rep("master-1", rep).unwrap();
+    assert_eq!(a.role, Role::Follower);
+  }
+
+  #[test]
+  fn test_truncate_conflicting_entries() {
+    let mut a = raft_of("master-0", &["master-1", "master-2"]);
+    let mut b = raft_of("master-1", &["master-0", "master-2"]);
+    let mut c = raft_of("master-2", &["master-0", "master-1"]);
+    elect(&mut a, &mut b);
+    replicate(&mut a, &mut b);
+    replicate(&mut a, &mut c);
+    // Taken by a but never replicated, which fails the write once deposed
+    let index = a.propose(assign_topic("topic-0")).unwrap();
+    let (waiter, receiver) = mpsc::sync_channel(1);
+    a.waiters.insert(index, waiter);
+
+    elect(&mut c, &mut b);
+    c.propose(assign_topic("topic-1")).unwrap();
+    replicate(&mut c, &mut a);
+    assert_eq!(a.role, Role::Follower);
+    assert_eq!(receiver.try_recv().unwrap(), Err(NotCommitted { index }));
+    assert_eq!(a.last_term, c.hard_state.term);
+    let Command::AssignTopic { topic, .. } = a.entry(3).unwrap().command else {
+      panic!("Not a topic assignment");
+    };
+    assert_eq!(topic, "topic-1");
+  }
+
+  #[test]
+  fn test_compact_and_reset() {
+    let mut a = raft_of("master-0", &["master-1", "master-2"]);
+    let mut b = raft_of("master-1", &["master-0", "master-2"]);
+    let mut c = raft_of("master-2", &["master-0", "master-1"]);
+    elect(&mut a, &mut b);
+    a.propose(assign_topic("topic-0")).unwrap();
+    assert!(a.is_snapshot_due(1));
+    let snapshot = StateSnapshot { services: vec![], routes: vec![], topics: vec![] };
+    let commands = a.commands_after(1);
+    assert!(matches!(commands[..], [Command::AssignTopic { .. }]));
+    a.propose(Command::Snapshot { snapshot, commands }).unwrap();
+    assert!(!a.is_snapshot_due(1));
+    a.propose(assign_topic("topic-1")).unwrap();
+    replicate(&mut a, &mut b);
+    assert_eq!(a.commit_index, 4);
+
+    // Not before the snapshot is applied
+    a.compact().unwrap();
+    assert_eq!(a.hard_state.base_index, 0);
+    a.mark_applied(4).unwrap();
+    a.compact().unwrap();
+    assert_eq!(a.hard_state.base_index, 2);
+    assert!(a.entry(2).is_none());
+
+    // c misses the entries dropped, so it is sent the snapshot instead
+    let req = a.append_req("master-2").unwrap();
+    assert!(req.reset);
+    assert!(matches!(
+      req.entries[..],
+      [LogEntry { index: 3, command: Command::Snapshot { .. }, .. }]
+    ));
+    replicate(&mut a, &mut c);
+    assert_eq!(c.hard_state.base_index, 2);
+    assert_eq!(c.commit_index, 4);
+    let (entries, _) = c.committed_entries();
+    assert_eq!(entries.iter().map(|entry| entry.index).collect::<Vec<_>>(), vec![3, 4]);
+  }
+}
@@ -0,0 +1,117 @@
+use std::sync::{
+  atomic::{AtomicU64, Ordering},
+  RwLock,
+};
+
+use once_cell::sync::Lazy;
+
+use super::ROUTE_MGR;
+use crate::node_mgr::{NodeId, SERVICE_MGR};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct CacheKey {
+  service_version: u32,
+  route_version: u32,
+  health_epoch: u64,
+}
+
+// The checksum frontends poll to know when to refetch the routes, recomputed
+// only when the versions it covers changed or a service changed its health,
+// so that polling it does not scan the services
+pub struct RouteDistChecksum {
+  health_epoch: AtomicU64,
+  cached: RwLock<Option<(CacheKey, u32)>>,
+}
+
+impl RouteDistChecksum {
+  #[inline]
+  fn new() -> Self {
+    RouteDistChecksum { health_epoch: AtomicU64::new(0), cached: RwLock::new(None) }
+  }
+
+  pub fn get(&self) -> u32 {
+    // Read before computing, so that a change meanwhile recomputes next time
+    let key = CacheKey {
+      service_version: SERVICE_MGR.version(),
+      route_version: ROUTE_MGR.version(),
+      health_epoch: self.health_epoch.load(Ordering::Acquire),
+    };
+    if let Some((cached_key, checksum)) = *self.cached.read().unwrap() {
+      if cached_key == key {
+        return checksum;
+      }
+    }
+    let checksum = compute(key.service_version, key.route_version);
+    *self.cached.write().unwrap() = Some((key, checksum));
+    checksum
+  }
+
+  // Called as the health of any service changed
+  #[inline]
+  pub fn invalidate(&self) {
+    self.health_epoch.fetch_add(1, Ordering::AcqRel);
+  }
+}
+
+// Over the health of every service having routes rather than the time, so
+// that it changes only as the set of healthy services changes, and a flapping
+// service makes frontends refetch the routes once per flap, not once per poll
+fn compute(service_version: u32, route_version: u32) -> u32 {
+  let healths = ROUTE_MGR
+    .reverse_route_group_iter()
+    .map(|reverse_route_group| {
+      let health = match SERVICE_MGR.get(reverse_route_group.key()) {
+        Some(service) if service.is_healthy() => Health::Healthy,
+        Some(_) => Health::Unhealthy,
+        None => Health::Stale,
+      };
+      (reverse_route_group.key().clone(), health)
+    })
+    .collect();
+  checksum_of(service_version, route_version, healths)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Health {
+  Healthy,
+  Unhealthy,
+  // Has routes but is gone
+  Stale,
+}
+
+// Sorted first, as the services are iterated in no particular order
+fn checksum_of(
+  service_version: u32, route_version: u32, mut healths: Vec<(NodeId, Health)>,
+) -> u32 {
+  healths.sort_unstable();
+  let mut hasher = crc32fast::Hasher::new();
+  hasher.update(&service_version.to_be_bytes());
+  hasher.update(&route_version.to_be_bytes());
+  for (service_id, health) in &healths {
+    hasher.update(service_id.as_bytes());
+    hasher.update(&[b'|', *health as u8, b'\n']);
+  }
+  hasher.finalize()
+}
+
+pub static ROUTE_DIST_CHECKSUM: Lazy<RouteDistChecksum> = Lazy::new(RouteDistChecksum::new);
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_checksum_of() {
+    let healths = |health_b| {
+      vec![("service-b".to_owned(), health_b), ("service-a".to_owned(), Health::Healthy)]
+    };
+    let checksum = checksum_of(1, 1, healths(Health::Unhealthy));
+    assert_eq!(checksum_of(1, 1, healths(Health::Unhealthy)), checksum);
+    let mut reversed = healths(Health::Unhealthy);
+    reversed.reverse();
+    assert_eq!(checksum_of(1, 1, reversed), checksum);
+    assert_ne!(checksum_of(1, 1, healths(Health::Healthy)), checksum);
+    assert_ne!(checksum_of(1, 1, healths(Health::Stale)), checksum);
+    assert_ne!(checksum_of(1, 2, healths(Health::Unhealthy)), checksum);
+  }
+}
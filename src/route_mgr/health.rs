@@ -0,0 +1,95 @@
+use std::sync::{Arc, Mutex};
+
+use ahash::HashMap;
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use super::{Path, RouteTable};
+use crate::clock;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteHealth {
+  #[serde(skip_serializing_if = "String::is_empty")]
+  pub tenant: String,
+  pub method: &'static str,
+  pub path: Path,
+  pub healthy_count: usize,
+  pub unhealthy_count: usize,
+  // When the path lost its last healthy endpoint, none if it has any
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub down_since: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum RouteAlert {
+  RouteDown { tenant: String, method: &'static str, path: Path, down_since: u32 },
+  RouteRecovered { tenant: String, method: &'static str, path: Path, down_for: u32 },
+}
+
+type RouteKey = (String, &'static str, Arc<str>);
+
+// Remembers since when each path of each tenant has had no healthy endpoint,
+// and reports the paths going down or recovering as alerts
+pub(crate) struct HealthTracker {
+  down_since: Mutex<HashMap<RouteKey, u32>>,
+  alert_sender: mpsc::UnboundedSender<RouteAlert>,
+}
+
+impl HealthTracker {
+  #[inline]
+  pub(crate) fn new(alert_sender: mpsc::UnboundedSender<RouteAlert>) -> Self {
+    HealthTracker { down_since: Mutex::new(HashMap::default()), alert_sender }
+  }
+
+  pub(crate) fn track(&self, tenant: &str, table: &RouteTable) {
+    let now = clock::now();
+    let mut down_since = self.down_since.lock().unwrap();
+    down_since.retain(|(key_tenant, method, path), _| {
+      key_tenant != tenant || table.route_group(method, path).is_some()
+    });
+    for (method, group) in table.iter() {
+      let key = (tenant.to_owned(), method, group.path.clone());
+      if group.healthy_endpoints.is_empty() {
+        if !down_since.contains_key(&key) {
+          down_since.insert(key, now);
+          self.alert(RouteAlert::RouteDown {
+            tenant: tenant.to_owned(),
+            method,
+            path: group.path.to_string(),
+            down_since: now,
+          });
+        }
+      } else if let Some(since) = down_since.remove(&key) {
+        self.alert(RouteAlert::RouteRecovered {
+          tenant: tenant.to_owned(),
+          method,
+          path: group.path.to_string(),
+          down_for: now.saturating_sub(since),
+        });
+      }
+    }
+  }
+
+  pub(crate) fn health(&self, tenant: &str, table: &RouteTable) -> Vec<RouteHealth> {
+    let down_since = self.down_since.lock().unwrap();
+    table
+      .iter()
+      .map(|(method, group)| RouteHealth {
+        tenant: tenant.to_owned(),
+        method,
+        path: group.path.to_string(),
+        healthy_count: group.healthy_endpoints.len(),
+        unhealthy_count: group.unhealthy_endpoints.len(),
+        down_since: down_since.get(&(tenant.to_owned(), method, group.path.clone())).copied(),
+      })
+      .collect()
+  }
+
+  #[inline]
+  fn alert(&self, alert: RouteAlert) {
+    log::warn!("Route health changed: {:?}", alert);
+    // Nobody receives the alerts if no webhook is configured
+    let _ = self.alert_sender.send(alert);
+  }
+}
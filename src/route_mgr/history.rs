@@ -0,0 +1,76 @@
+use std::borrow::Borrow;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+use seriesdb::coder::Coder;
+
+use super::PathBundle;
+use crate::{node_mgr::NodeId, store::Store};
+
+// Revisions of a service are stored next to each other, ordered by revision
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RevisionKey {
+  pub service_id: NodeId,
+  pub revision: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Revision {
+  pub revision: u32,
+  pub created_at: u32,
+  pub paths: PathBundle,
+}
+
+pub(crate) type HistoryStore = dyn Store<RevisionKey, Revision>;
+
+pub(crate) struct HistoryCoder;
+
+impl Coder<RevisionKey, Revision> for HistoryCoder {
+  type EncodedKey = Bytes;
+  type EncodedValue = Bytes;
+
+  #[inline(always)]
+  fn encode_key<K: Borrow<RevisionKey>>(key: K) -> Self::EncodedKey {
+    let key = key.borrow();
+    let mut buf = BytesMut::with_capacity(key.service_id.len() + 5);
+    buf.put_slice(key.service_id.as_bytes());
+    buf.put_u8(0);
+    buf.put_u32(key.revision);
+    buf.freeze()
+  }
+
+  #[inline(always)]
+  fn decode_key(key: &[u8]) -> RevisionKey {
+    let (service_id, revision) = key.split_at(key.len() - 4);
+    RevisionKey {
+      service_id: std::str::from_utf8(&service_id[..service_id.len() - 1]).unwrap().to_string(),
+      revision: u32::from_be_bytes(revision.try_into().unwrap()),
+    }
+  }
+
+  #[inline(always)]
+  fn encode_value<V: Borrow<Revision>>(value: V) -> Self::EncodedValue {
+    bincode::serialize(value.borrow()).unwrap().into()
+  }
+
+  #[inline(always)]
+  fn decode_value(value: &[u8]) -> Revision {
+    bincode::deserialize(value).unwrap()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_key_coder() {
+    let key = RevisionKey { service_id: "service-0".to_owned(), revision: 258 };
+    let encoded = <HistoryCoder as Coder<RevisionKey, Revision>>::encode_key(&key);
+    assert_eq!(<HistoryCoder as Coder<RevisionKey, Revision>>::decode_key(&encoded), key);
+
+    let next_key = RevisionKey { service_id: "service-0".to_owned(), revision: 259 };
+    let next_encoded = <HistoryCoder as Coder<RevisionKey, Revision>>::encode_key(&next_key);
+    assert!(encoded < next_encoded);
+  }
+}
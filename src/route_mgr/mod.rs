@@ -0,0 +1,897 @@
+use std::sync::{
+  atomic::{AtomicU32, Ordering},
+  Arc, Mutex,
+};
+use std::{
+  borrow::Borrow,
+  collections::{HashMap, HashSet, VecDeque},
+  fmt,
+  time::Duration,
+};
+
+use ahash::RandomState as AHasher;
+use anyhow::Result;
+use bytes::{Bytes, BytesMut};
+use dashmap::{iter::Iter, mapref::entry::Entry, DashMap};
+use once_cell::sync::Lazy;
+use seriesdb::coder::Coder;
+use tokio::sync::{mpsc, watch, Notify};
+
+use crate::node_mgr::{NodeId, SERVICE_MGR};
+use crate::{
+  clock,
+  config::CONFIG,
+  event_bus::{self, Event},
+  maintenance,
+  raft::{self, Command},
+  standby,
+  store::{open_store, Store},
+  telemetry,
+};
+
+pub mod dist_checksum;
+pub mod health;
+pub mod history;
+pub mod owner;
+pub mod path_pattern;
+pub mod rate_limit;
+pub mod route_table;
+pub mod tenant;
+pub mod weight;
+
+pub use dist_checksum::*;
+pub use health::*;
+pub use history::*;
+pub use owner::*;
+pub use path_pattern::*;
+pub use rate_limit::*;
+pub use route_table::*;
+pub use tenant::*;
+pub use weight::*;
+
+pub(crate) type Path = String;
+pub(crate) type PathSet = HashSet<Path, AHasher>;
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, PartialEq)]
+pub struct PathBundle {
+  pub(crate) ws_paths: PathSet,
+  pub(crate) get_paths: PathSet,
+  pub(crate) post_paths: PathSet,
+  pub(crate) put_paths: PathSet,
+  pub(crate) patch_paths: PathSet,
+  pub(crate) delete_paths: PathSet,
+  pub(crate) head_paths: PathSet,
+  pub(crate) options_paths: PathSet,
+  pub(crate) trace_paths: PathSet,
+}
+
+impl PathBundle {
+  #[inline]
+  pub(crate) fn path_sets(&self) -> [(&'static str, &PathSet); 9] {
+    [
+      ("ws", &self.ws_paths),
+      ("get", &self.get_paths),
+      ("post", &self.post_paths),
+      ("put", &self.put_paths),
+      ("patch", &self.patch_paths),
+      ("delete", &self.delete_paths),
+      ("head", &self.head_paths),
+      ("options", &self.options_paths),
+      ("trace", &self.trace_paths),
+    ]
+  }
+
+  #[inline]
+  pub(crate) fn path_set_mut(&mut self, method: &str) -> Option<&mut PathSet> {
+    match method {
+      "ws" => Some(&mut self.ws_paths),
+      "get" => Some(&mut self.get_paths),
+      "post" => Some(&mut self.post_paths),
+      "put" => Some(&mut self.put_paths),
+      "patch" => Some(&mut self.patch_paths),
+      "delete" => Some(&mut self.delete_paths),
+      "head" => Some(&mut self.head_paths),
+      "options" => Some(&mut self.options_paths),
+      "trace" => Some(&mut self.trace_paths),
+      _ => None,
+    }
+  }
+
+  // Normalizes every path and checks it is a valid literal, parameterized or
+  // wildcard path, within the configured limits
+  pub fn normalize(self) -> Result<Self, InvalidRoutes> {
+    let count: usize = self.path_sets().iter().map(|(_, paths)| paths.len()).sum();
+    let max_count = CONFIG.load().route_mgr.max_paths;
+    if max_count > 0 && count > max_count {
+      return Err(InvalidRoutes::TooManyPaths { count, max_count });
+    }
+    let mut pb = PathBundle::default();
+    for (method, paths) in self.path_sets() {
+      let normalized_paths = pb.path_set_mut(method).unwrap();
+      for path in paths {
+        let path = normalize_path(path, CONFIG.load().route_mgr.max_path_len)
+          .map_err(|err| InvalidRoutes::InvalidPath { method, err })?;
+        PathPattern::parse(&path).map_err(|err| InvalidRoutes::InvalidPath { method, err })?;
+        normalized_paths.insert(path);
+      }
+    }
+    Ok(pb)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InvalidRoutes {
+  TooManyPaths { count: usize, max_count: usize },
+  InvalidPath { method: &'static str, err: InvalidPath },
+}
+
+impl fmt::Display for InvalidRoutes {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      InvalidRoutes::TooManyPaths { count, max_count } => {
+        write!(f, "Too many paths: count: {}, max_count: {}", count, max_count)
+      }
+      InvalidRoutes::InvalidPath { method, err } => write!(f, "{}, method: {}", err, method),
+    }
+  }
+}
+
+impl std::error::Error for InvalidRoutes {}
+
+type RouteStore = dyn Store<NodeId, PathBundle>;
+
+pub(crate) struct RouteCoder;
+
+impl Coder<NodeId, PathBundle> for RouteCoder {
+  type EncodedKey = Bytes;
+  type EncodedValue = Bytes;
+
+  #[inline(always)]
+  fn encode_key<K: Borrow<NodeId>>(key: K) -> Self::EncodedKey {
+    BytesMut::from(key.borrow().as_bytes()).freeze()
+  }
+
+  #[inline(always)]
+  fn decode_key(key: &[u8]) -> NodeId {
+    std::str::from_utf8(key).unwrap().to_string()
+  }
+
+  #[inline(always)]
+  fn encode_value<V: Borrow<PathBundle>>(value: V) -> Self::EncodedValue {
+    bincode::serialize(value.borrow()).unwrap().into()
+  }
+
+  #[inline(always)]
+  fn decode_value(value: &[u8]) -> PathBundle {
+    bincode::deserialize(value).unwrap()
+  }
+}
+
+type InfoKey = String;
+type InfoValue = String;
+type InfoStore = dyn Store<InfoKey, InfoValue>;
+
+pub(crate) struct InfoCoder;
+
+impl Coder<InfoKey, InfoValue> for InfoCoder {
+  type EncodedKey = Bytes;
+  type EncodedValue = Bytes;
+
+  #[inline(always)]
+  fn encode_key<K: Borrow<InfoKey>>(key: K) -> Self::EncodedKey {
+    BytesMut::from(key.borrow().as_bytes()).freeze()
+  }
+
+  #[inline(always)]
+  fn decode_key(key: &[u8]) -> InfoKey {
+    std::str::from_utf8(key).unwrap().to_string()
+  }
+
+  #[inline(always)]
+  fn encode_value<V: Borrow<InfoValue>>(value: V) -> Self::EncodedValue {
+    BytesMut::from(value.borrow().as_bytes()).freeze()
+  }
+
+  #[inline(always)]
+  fn decode_value(value: &[u8]) -> InfoValue {
+    std::str::from_utf8(value).unwrap().to_string()
+  }
+}
+
+// The route tables seen by the services and frontends of a tenant
+struct RouteView {
+  recent_tables: Mutex<VecDeque<Arc<RouteTable>>>,
+  table_sender: watch::Sender<Arc<RouteTable>>,
+}
+
+impl RouteView {
+  #[inline]
+  fn new() -> Self {
+    RouteView {
+      recent_tables: Mutex::new(VecDeque::new()),
+      table_sender: watch::channel(Arc::new(RouteTable::default())).0,
+    }
+  }
+}
+
+pub struct RouteMgr {
+  cache: DashMap<NodeId, PathBundle, AHasher>,
+  route_store: Arc<RouteStore>,
+  history_store: Arc<HistoryStore>,
+  owner_store: Arc<OwnerStore>,
+  weight_store: Arc<WeightStore>,
+  weights: DashMap<NodeId, u32, AHasher>,
+  rate_limit_store: Arc<RateLimitStore>,
+  rate_limits: DashMap<NodeId, Vec<PathRateLimit>, AHasher>,
+  tenant_store: Arc<TenantStore>,
+  info_store: Arc<InfoStore>,
+  tenants: DashMap<NodeId, String, AHasher>,
+  latest_revisions: DashMap<NodeId, u32, AHasher>,
+  views: HashMap<String, RouteView, AHasher>,
+  health_tracker: HealthTracker,
+  alert_receiver: Mutex<Option<mpsc::UnboundedReceiver<RouteAlert>>>,
+  changed: Notify,
+  version: AtomicU32,
+}
+
+impl RouteMgr {
+  #[inline]
+  fn new(
+    route_store: Arc<RouteStore>, history_store: Arc<HistoryStore>, owner_store: Arc<OwnerStore>,
+    weight_store: Arc<WeightStore>, rate_limit_store: Arc<RateLimitStore>,
+    tenant_store: Arc<TenantStore>, info_store: Arc<InfoStore>,
+  ) -> Self {
+    let cache = DashMap::with_capacity_and_hasher(512, AHasher::default());
+    let (alert_sender, alert_receiver) = mpsc::unbounded_channel();
+    let route_mgr = RouteMgr {
+      cache,
+      route_store,
+      history_store,
+      owner_store,
+      weight_store,
+      weights: DashMap::with_capacity_and_hasher(64, AHasher::default()),
+      rate_limit_store,
+      rate_limits: DashMap::with_capacity_and_hasher(64, AHasher::default()),
+      tenant_store,
+      info_store,
+      tenants: DashMap::with_capacity_and_hasher(512, AHasher::default()),
+      latest_revisions: DashMap::with_capacity_and_hasher(512, AHasher::default()),
+      views: tenant_names().into_iter().map(|tenant| (tenant, RouteView::new())).collect(),
+      health_tracker: HealthTracker::new(alert_sender),
+      alert_receiver: Mutex::new(if CONFIG.load().route_mgr.alert_webhook.is_empty() {
+        None
+      } else {
+        Some(alert_receiver)
+      }),
+      changed: Notify::new(),
+      version: AtomicU32::new(crc32fast::hash(format!("{}", clock::now_millis()).as_bytes())),
+    };
+    route_mgr.recover();
+    route_mgr.recover_latest_revisions();
+    route_mgr.recover_weights();
+    route_mgr.recover_rate_limits();
+    route_mgr.recover_tenants();
+    route_mgr.recover_version();
+    route_mgr
+  }
+
+  // The writes of the routes, tenants, weights and rate limits are replicated
+  // by raft, which applies them to ROUTE_MGR, see raft::replicate
+  #[inline]
+  pub fn set_reverse_route_group(&self, service_id: NodeId, pb: PathBundle) -> Result<()> {
+    if self.cache.get(&service_id).is_some_and(|curr_pb| *curr_pb == pb) {
+      log::debug!("Reverse route group is the same, no need to update.");
+      return Ok(());
+    }
+    raft::replicate(Command::SetRoutes { service_id, paths: pb })
+  }
+
+  // Fails if the routes could not be stored, which are then left as they were
+  pub(crate) fn apply_reverse_route_group(&self, service_id: NodeId, pb: PathBundle) -> Result<()> {
+    let _span_guard = telemetry::enter_span("route_mgr.set_reverse_route_group");
+    match self.cache.entry(service_id) {
+      Entry::Occupied(mut entry) => {
+        if entry.get() != &pb {
+          log::debug!("Updating reverse route group: {:?}", pb);
+          self.route_store.put(entry.key(), &pb)?;
+          self.record_revision(entry.key(), &pb);
+          entry.insert(pb);
+          self.update_version();
+        } else {
+          log::debug!("Reverse route group is the same, no need to update.");
+        }
+      }
+      Entry::Vacant(entry) => {
+        log::debug!("Adding reverse route group: {:?}", pb);
+        self.route_store.put(entry.key(), &pb)?;
+        self.record_revision(entry.key(), &pb);
+        entry.insert(pb);
+        self.update_version();
+      }
+    }
+    Ok(())
+  }
+
+  // Claims the paths first in strict mode, which are released again if the
+  // routes could not be set, so that a failed attempt leaves no owner behind
+  pub fn claim_and_set_routes(&self, service_id: NodeId, pb: PathBundle) -> Result<()> {
+    let claimed_keys =
+      if CONFIG.load().route_mgr.strict { self.claim_paths(&service_id, &pb)? } else { vec![] };
+    if let Err(err) = self.set_reverse_route_group(service_id, pb) {
+      self.release_claims(&claimed_keys);
+      return Err(err);
+    }
+    Ok(())
+  }
+
+  #[inline]
+  pub fn reverse_route_group(&self, service_id: &NodeId) -> Option<PathBundle> {
+    self.cache.get(service_id).map(|pb| pb.clone())
+  }
+
+  // Returns false if the service had no routes
+  #[inline]
+  pub fn remove_reverse_route_group(&self, service_id: &NodeId) -> Result<bool> {
+    if !self.cache.contains_key(service_id) {
+      return Ok(false);
+    }
+    raft::replicate(Command::RemoveRoutes { service_id: service_id.clone() })?;
+    Ok(true)
+  }
+
+  #[inline]
+  pub(crate) fn apply_remove_reverse_route_group(&self, service_id: &NodeId) -> bool {
+    if self.cache.remove(service_id).is_some() {
+      self.route_store.delete(service_id).unwrap_or_else(|err| {
+        log::warn!("Failed to remove reverse route group from store: {:?}", err);
+      });
+      self.update_version();
+      true
+    } else {
+      false
+    }
+  }
+
+  // Every change of a service's routes is kept as a revision, only the latest
+  // history_limit revisions are kept
+  fn record_revision(&self, service_id: &NodeId, pb: &PathBundle) {
+    let revision = {
+      let mut latest_revision = self.latest_revisions.entry(service_id.clone()).or_insert(0);
+      *latest_revision += 1;
+      *latest_revision
+    };
+    let key = RevisionKey { service_id: service_id.clone(), revision };
+    let value = Revision { revision, created_at: clock::now(), paths: pb.clone() };
+    self.history_store.put(&key, &value).unwrap_or_else(|err| {
+      log::warn!("Failed to add route revision into store: {:?}, err: {:?}", key, err);
+    });
+
+    let history_limit = CONFIG.load().route_mgr.history_limit;
+    if history_limit > 0 && revision > history_limit {
+      let expired_key =
+        RevisionKey { service_id: service_id.clone(), revision: revision - history_limit };
+      self.history_store.delete(&expired_key).unwrap_or_else(|err| {
+        log::warn!("Failed to remove route revision from store: {:?}, err: {:?}", expired_key, err);
+      });
+    }
+  }
+
+  // Returns the revisions of the service, the latest first
+  pub fn history(&self, service_id: &NodeId) -> Vec<Revision> {
+    let mut revisions = vec![];
+    let from = RevisionKey { service_id: service_id.clone(), revision: 0 };
+    self.history_store.scan(Some(&from), &mut |key, revision| {
+      if &key.service_id != service_id {
+        return false;
+      }
+      revisions.push(revision);
+      true
+    });
+    revisions.reverse();
+    revisions
+  }
+
+  #[inline]
+  pub fn latest_revision(&self, service_id: &NodeId) -> Option<u32> {
+    self.latest_revisions.get(service_id).map(|revision| *revision)
+  }
+
+  // Restores the routes of the revision, which are recorded as a new revision,
+  // returns false if the revision was not found
+  pub fn rollback(&self, service_id: &NodeId, revision: u32) -> Result<bool> {
+    let key = RevisionKey { service_id: service_id.clone(), revision };
+    match self.history_store.get(&key)? {
+      Some(revision) => {
+        log::info!(
+          "Rolling back routes: service_id: {:?}, revision: {:?}",
+          service_id,
+          key.revision
+        );
+        self.set_reverse_route_group(service_id.clone(), revision.paths)?;
+        Ok(true)
+      }
+      None => Ok(false),
+    }
+  }
+
+  // The route table shared by all handlers of the tenant, it is rebuilt at once
+  // when routes or services changed, and periodically by the refresh task as
+  // health changes.
+  pub fn snapshot(&self, tenant: &str) -> Arc<RouteTable> {
+    let _span_guard = telemetry::enter_span("route_mgr.snapshot");
+    let table = self.views[tenant].table_sender.borrow().clone();
+    if table.is_built_from(self.version(), SERVICE_MGR.version()) {
+      table
+    } else {
+      self.refresh_route_table(tenant)
+    }
+  }
+
+  // The delta since the table with the given checksum if it is still
+  // remembered, otherwise the client needs a full sync
+  pub fn route_delta(
+    &self, tenant: &str, since: Option<u32>,
+  ) -> (Arc<RouteTable>, Option<RouteDelta>) {
+    let table = self.snapshot(tenant);
+    let delta = since.and_then(|since| {
+      let recent_tables = self.views[tenant].recent_tables.lock().unwrap();
+      recent_tables
+        .iter()
+        .find(|recent_table| recent_table.checksum() == since)
+        .map(|old_table| table.diff(old_table))
+    });
+    (table, delta)
+  }
+
+  // Watches the latest route table of the tenant, which is refreshed by the
+  // refresh task
+  #[inline]
+  pub fn watch(&self, tenant: &str) -> watch::Receiver<Arc<RouteTable>> {
+    self.views[tenant].table_sender.subscribe()
+  }
+
+  pub fn refresh_route_tables(&self) {
+    for tenant in self.views.keys() {
+      self.refresh_route_table(tenant);
+    }
+  }
+
+  // Rebuilds the route table, only notifies the watchers if it changed
+  pub fn refresh_route_table(&self, tenant: &str) -> Arc<RouteTable> {
+    let view = &self.views[tenant];
+    let table = Arc::new(RouteTable::build(tenant));
+    self.health_tracker.track(tenant, &table);
+
+    {
+      let mut recent_tables = view.recent_tables.lock().unwrap();
+      if recent_tables.back().map(|recent_table| recent_table.checksum()) != Some(table.checksum())
+      {
+        recent_tables.push_back(table.clone());
+        while recent_tables.len() > CONFIG.load().route_mgr.delta_window.max(1) {
+          recent_tables.pop_front();
+        }
+      }
+    }
+
+    let changed = view.table_sender.send_if_modified(|curr_table| {
+      let changed = curr_table.checksum() != table.checksum();
+      *curr_table = table.clone();
+      changed
+    });
+    if changed {
+      event_bus::publish(Event::RoutesChanged {
+        tenant: tenant.to_owned(),
+        checksum: table.checksum(),
+      });
+    }
+    table
+  }
+
+  // The endpoint counts of every path of every tenant
+  pub fn health(&self) -> Vec<RouteHealth> {
+    let mut health = vec![];
+    for tenant in self.views.keys() {
+      let table = self.snapshot(tenant);
+      health.extend(self.health_tracker.health(tenant, &table));
+    }
+    health
+  }
+
+  // Services of different tenants never see each other's routes
+  #[inline]
+  pub fn set_tenant(&self, service_id: &NodeId, tenant: &str) -> Result<()> {
+    if self.tenant_of(service_id) == tenant {
+      return Ok(());
+    }
+    raft::replicate(Command::SetTenant {
+      service_id: service_id.clone(),
+      tenant: tenant.to_owned(),
+    })
+  }
+
+  pub(crate) fn apply_tenant(&self, service_id: &NodeId, tenant: &str) -> Result<()> {
+    if self.tenant_of(service_id) == tenant {
+      return Ok(());
+    }
+    log::info!("Setting tenant: service_id: {:?}, tenant: {:?}", service_id, tenant);
+    if tenant == DEFAULT_TENANT {
+      self.tenant_store.delete(service_id)?;
+      self.tenants.remove(service_id);
+    } else {
+      self.tenant_store.put(service_id, &tenant.to_owned())?;
+      self.tenants.insert(service_id.clone(), tenant.to_owned());
+    }
+    self.update_version();
+    Ok(())
+  }
+
+  #[inline]
+  pub fn tenant_of(&self, service_id: &NodeId) -> String {
+    self.tenants.get(service_id).map_or_else(|| DEFAULT_TENANT.to_owned(), |tenant| tenant.clone())
+  }
+
+  // The share of traffic of the service relative to the other endpoints of a
+  // route group, e.g. 1 against 99 for a canary
+  #[inline]
+  pub fn set_weight(&self, service_id: &NodeId, weight: u32) -> Result<()> {
+    if self.weight(service_id) == weight {
+      return Ok(());
+    }
+    raft::replicate(Command::SetWeight { service_id: service_id.clone(), weight })
+  }
+
+  pub(crate) fn apply_weight(&self, service_id: &NodeId, weight: u32) -> Result<()> {
+    if self.weight(service_id) == weight {
+      return Ok(());
+    }
+    log::info!("Setting weight: service_id: {:?}, weight: {:?}", service_id, weight);
+    if weight == DEFAULT_WEIGHT {
+      self.weight_store.delete(service_id)?;
+      self.weights.remove(service_id);
+    } else {
+      self.weight_store.put(service_id, &weight)?;
+      self.weights.insert(service_id.clone(), weight);
+    }
+    self.update_version();
+    Ok(())
+  }
+
+  #[inline]
+  pub fn weight(&self, service_id: &NodeId) -> u32 {
+    self.weights.get(service_id).map_or(DEFAULT_WEIGHT, |weight| *weight)
+  }
+
+  // Replaces all the rate limits declared by the service, normalized against its
+  // routes, empty to declare none
+  #[inline]
+  pub fn set_rate_limits(&self, service_id: &NodeId, limits: Vec<PathRateLimit>) -> Result<()> {
+    if self.rate_limits(service_id) == limits {
+      return Ok(());
+    }
+    raft::replicate(Command::SetRateLimits { service_id: service_id.clone(), rate_limits: limits })
+  }
+
+  pub(crate) fn apply_rate_limits(
+    &self, service_id: &NodeId, limits: Vec<PathRateLimit>,
+  ) -> Result<()> {
+    if self.rate_limits(service_id) == limits {
+      return Ok(());
+    }
+    log::info!("Setting rate limits: service_id: {:?}, limits: {:?}", service_id, limits);
+    if limits.is_empty() {
+      self.rate_limit_store.delete(service_id)?;
+      self.rate_limits.remove(service_id);
+    } else {
+      self.rate_limit_store.put(service_id, &limits)?;
+      self.rate_limits.insert(service_id.clone(), limits);
+    }
+    self.update_version();
+    Ok(())
+  }
+
+  #[inline]
+  pub fn rate_limits(&self, service_id: &NodeId) -> Vec<PathRateLimit> {
+    self.rate_limits.get(service_id).map_or_else(Vec::new, |limits| limits.clone())
+  }
+
+  // Used in strict mode: no path of the bundle may be owned by another logical
+  // service of the same tenant, either recorded or by its current routes, the
+  // unowned paths are claimed for the logical service of the given one, and
+  // their owner keys are returned.
+  fn claim_paths(
+    &self, service_id: &NodeId, pb: &PathBundle,
+  ) -> Result<Vec<String>, RouteConflict> {
+    let tenant = self.tenant_of(service_id);
+    let claimer = logical_service_of(service_id);
+    let mut current_owners: HashMap<String, String, AHasher> = HashMap::default();
+    for reverse_route_group in self.cache.iter() {
+      let owner = logical_service_of(reverse_route_group.key());
+      if owner == claimer || self.tenant_of(reverse_route_group.key()) != tenant {
+        continue;
+      }
+      for (method, paths) in reverse_route_group.value().path_sets() {
+        for path in paths {
+          current_owners
+            .entry(owner_key(&tenant, method, path))
+            .or_insert_with(|| owner.to_owned());
+        }
+      }
+    }
+
+    let mut unowned_keys = vec![];
+    for (method, paths) in pb.path_sets() {
+      for path in paths {
+        let key = owner_key(&tenant, method, path);
+        let owner = self.owner_store.get(&key).unwrap_or_else(|err| {
+          log::warn!("Failed to get route owner: {:?}, err: {:?}", key, err);
+          None
+        });
+        match owner.or_else(|| current_owners.get(&key).cloned()) {
+          Some(owner) if owner != claimer => {
+            return Err(RouteConflict { tenant, method, path: path.clone(), owner });
+          }
+          Some(_) => {}
+          None => unowned_keys.push(key),
+        }
+      }
+    }
+
+    let claimer = claimer.to_owned();
+    for key in &unowned_keys {
+      self.owner_store.put(key, &claimer).unwrap_or_else(|err| {
+        log::warn!("Failed to add route owner into store: {:?}, err: {:?}", key, err);
+      });
+    }
+    Ok(unowned_keys)
+  }
+
+  #[inline]
+  fn release_claims(&self, keys: &[String]) {
+    for key in keys {
+      self.owner_store.delete(key).unwrap_or_else(|err| {
+        log::warn!("Failed to remove route owner from store: {:?}, err: {:?}", key, err);
+      });
+    }
+  }
+
+  // Makes the logical service the owner of the path, and drops the path from
+  // the routes of all other logical services of the tenant
+  pub fn transfer_path(&self, tenant: &str, method: &str, path: &str, owner: &str) -> Result<()> {
+    log::info!("Transferring path: {} {}, to: {:?}, tenant: {:?}", method, path, owner, tenant);
+    self.owner_store.put(&owner_key(tenant, method, path), &owner.to_owned())?;
+
+    let mut changed_groups = vec![];
+    for reverse_route_group in self.cache.iter() {
+      if logical_service_of(reverse_route_group.key()) == owner
+        || self.tenant_of(reverse_route_group.key()) != tenant
+      {
+        continue;
+      }
+      let mut pb = reverse_route_group.value().clone();
+      if pb.path_set_mut(method).is_some_and(|paths| paths.remove(path)) {
+        changed_groups.push((reverse_route_group.key().clone(), pb));
+      }
+    }
+    for (service_id, pb) in changed_groups {
+      self.set_reverse_route_group(service_id, pb)?;
+    }
+    Ok(())
+  }
+
+  #[inline]
+  pub fn set_owner(&self, tenant: &str, method: &str, path: &str, owner: &str) -> Result<()> {
+    self.owner_store.put(&owner_key(tenant, method, path), &owner.to_owned())?;
+    Ok(())
+  }
+
+  // Returns the recorded owners as (tenant, method, path, owner)
+  pub fn owners(&self) -> Vec<(String, String, Path, String)> {
+    let mut owners = vec![];
+    self.owner_store.scan(None, &mut |key, owner| {
+      if let Some((tenant, method, path)) = parse_owner_key(&key) {
+        owners.push((tenant.to_owned(), method.to_owned(), path.to_owned(), owner));
+      }
+      true
+    });
+    owners
+  }
+
+  // Removes the stale services and their routes, as well as the routes of the
+  // services which are already gone, by the leader, without waiting for them
+  // to be committed, see raft::propose
+  pub fn sweep(&self) {
+    let stale_ids = SERVICE_MGR.stale_ids();
+    let orphan_ids: Vec<NodeId> = self
+      .cache
+      .iter()
+      .filter(|reverse_route_group| SERVICE_MGR.get(reverse_route_group.key()).is_none())
+      .map(|reverse_route_group| reverse_route_group.key().clone())
+      .filter(|service_id| !stale_ids.contains(service_id))
+      .collect();
+    for service_id in stale_ids {
+      log::info!("Removing a stale service: id: {:?}", service_id);
+      raft::propose(Command::RemoveService { service_id }).unwrap_or_else(|err| {
+        log::warn!("Failed to remove stale service: err: {:?}", err);
+      });
+    }
+    for service_id in orphan_ids {
+      log::info!("Removing routes of a missing service: id: {:?}", service_id);
+      raft::propose(Command::RemoveService { service_id }).unwrap_or_else(|err| {
+        log::warn!("Failed to remove routes of missing service: err: {:?}", err);
+      });
+    }
+  }
+
+  // The tenant is kept as long as the service, as it may set routes again
+  pub(crate) fn apply_forget_service(&self, service_id: &NodeId) {
+    self.apply_remove_reverse_route_group(service_id);
+    if self.rate_limits.remove(service_id).is_some() {
+      self.rate_limit_store.delete(service_id).unwrap_or_else(|err| {
+        log::warn!("Failed to remove rate limits from store: {:?}", err);
+      });
+    }
+    if self.tenants.remove(service_id).is_some() {
+      self.tenant_store.delete(service_id).unwrap_or_else(|err| {
+        log::warn!("Failed to remove tenant from store: {:?}", err);
+      });
+    }
+  }
+
+  // Finds the services serving the concrete path, ordered by the specificity of
+  // the matched patterns, literal ones first
+  pub fn match_path(&self, method: &str, path: &str) -> Vec<(NodeId, Path)> {
+    let mut matched = vec![];
+    for reverse_route_group in self.cache.iter() {
+      for (name, paths) in reverse_route_group.value().path_sets() {
+        if name != method {
+          continue;
+        }
+        for pattern_str in paths {
+          if let Ok(pattern) = PathPattern::parse(pattern_str) {
+            if pattern.matches(path) {
+              matched.push((
+                pattern.is_literal(),
+                reverse_route_group.key().clone(),
+                pattern_str.clone(),
+              ));
+            }
+          }
+        }
+      }
+    }
+    matched.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| b.2.len().cmp(&a.2.len())));
+    matched.into_iter().map(|(_, service_id, path)| (service_id, path)).collect()
+  }
+
+  #[inline]
+  pub fn reverse_route_group_iter(&self) -> Iter<NodeId, PathBundle, AHasher> {
+    self.cache.iter()
+  }
+
+  #[inline]
+  pub fn version(&self) -> u32 {
+    self.version.load(Ordering::SeqCst)
+  }
+
+  #[inline]
+  fn update_version(&self) {
+    let version = self.version.fetch_add(1, Ordering::SeqCst).wrapping_add(1);
+    self.info_store.put(&"version".to_owned(), &format!("{}", version)).unwrap_or_else(|err| {
+      log::warn!("Failed to store route version: {:?}", err);
+    });
+    self.changed.notify_one();
+  }
+
+  // Continues from the stored version rather than starting over, so that a
+  // checksum seen before the restart is never handed out again for other data
+  #[inline]
+  fn recover_version(&self) {
+    if let Some(version) = self.info_store.get(&"version".to_owned()).unwrap() {
+      self.version.store(version.parse().unwrap_or(0), Ordering::SeqCst);
+    }
+    self.update_version();
+  }
+
+  fn recover_weights(&self) {
+    self.weight_store.scan(None, &mut |service_id, weight| {
+      self.weights.insert(service_id, weight);
+      true
+    });
+  }
+
+  fn recover_rate_limits(&self) {
+    self.rate_limit_store.scan(None, &mut |service_id, limits| {
+      self.rate_limits.insert(service_id, limits);
+      true
+    });
+  }
+
+  fn recover_tenants(&self) {
+    self.tenant_store.scan(None, &mut |service_id, tenant| {
+      self.tenants.insert(service_id, tenant);
+      true
+    });
+  }
+
+  fn recover_latest_revisions(&self) {
+    self.history_store.scan(None, &mut |key, _| {
+      self.latest_revisions.insert(key.service_id, key.revision);
+      true
+    });
+  }
+
+  #[inline]
+  fn recover(&self) {
+    self.route_store.scan(None, &mut |service_id, path_set| {
+      self.cache.insert(service_id, path_set);
+      true
+    });
+  }
+}
+
+pub static ROUTE_MGR: Lazy<RouteMgr> = Lazy::new(|| {
+  RouteMgr::new(
+    open_store::<NodeId, PathBundle, RouteCoder>("route_mgr.routes").unwrap().into(),
+    open_store::<RevisionKey, Revision, HistoryCoder>("route_mgr.history").unwrap().into(),
+    open_store::<String, String, OwnerCoder>("route_mgr.owners").unwrap().into(),
+    open_store::<NodeId, u32, WeightCoder>("route_mgr.weights").unwrap().into(),
+    open_store::<NodeId, Vec<PathRateLimit>, RateLimitCoder>("route_mgr.rate_limits")
+      .unwrap()
+      .into(),
+    open_store::<NodeId, String, TenantCoder>("route_mgr.tenants").unwrap().into(),
+    open_store::<InfoKey, InfoValue, InfoCoder>("route_mgr.infos").unwrap().into(),
+  )
+});
+
+// Refreshes the route table whenever routes changed, and periodically, as the
+// health of services changes with time only
+pub fn spawn_refresh_task() {
+  actix_web::rt::spawn(async {
+    let mut interval = tokio::time::interval(Duration::from_millis(
+      CONFIG.load().route_mgr.refresh_interval.max(100),
+    ));
+    loop {
+      tokio::select! {
+        _ = interval.tick() => {}
+        _ = ROUTE_MGR.changed.notified() => {}
+      }
+      ROUTE_MGR.refresh_route_tables();
+    }
+  });
+}
+
+// Stale services are removed in the background only, so that serving requests
+// never modifies services or routes
+pub fn spawn_sweep_task() {
+  actix_web::rt::spawn(async {
+    let mut interval =
+      tokio::time::interval(Duration::from_secs(CONFIG.load().service_mgr.sweep_interval.max(1)));
+    loop {
+      interval.tick().await;
+      // Nor while read only, as the services swept could not register again
+      if standby::is_standby() || raft::is_follower() || maintenance::is_read_only() {
+        continue;
+      }
+      ROUTE_MGR.sweep();
+    }
+  });
+}
+
+// Posts the route alerts to the webhook one by one, if it is configured
+pub fn spawn_alert_task() {
+  let Some(mut alert_receiver) = ROUTE_MGR.alert_receiver.lock().unwrap().take() else {
+    return;
+  };
+  actix_web::rt::spawn(async move {
+    let client = awc::Client::default();
+    while let Some(alert) = alert_receiver.recv().await {
+      match client.post(&CONFIG.load().route_mgr.alert_webhook).send_json(&alert).await {
+        Ok(rep) if rep.status().is_success() => {}
+        Ok(rep) => {
+          log::warn!("Failed to post route alert: {:?}, status: {:?}", alert, rep.status())
+        }
+        Err(err) => log::warn!("Failed to post route alert: {:?}, err: {:?}", alert, err),
+      }
+    }
+  });
+}
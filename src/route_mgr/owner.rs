@@ -0,0 +1,112 @@
+use std::borrow::Borrow;
+use std::fmt;
+
+use bytes::{Bytes, BytesMut};
+use seriesdb::coder::Coder;
+
+use crate::store::Store;
+
+// Instances of a logical service are registered as `<name>-<n>`, the ids
+// without such a suffix are logical services on their own.
+#[inline]
+pub fn logical_service_of(service_id: &str) -> &str {
+  match service_id.rsplit_once('-') {
+    Some((name, suffix))
+      if !name.is_empty() && !suffix.is_empty() && suffix.bytes().all(|b| b.is_ascii_digit()) =>
+    {
+      name
+    }
+    _ => service_id,
+  }
+}
+
+// Paths are owned within a tenant, the keys of the default tenant have no prefix
+#[inline]
+pub(crate) fn owner_key(tenant: &str, method: &str, path: &str) -> String {
+  if tenant.is_empty() {
+    format!("{} {}", method, path)
+  } else {
+    format!("{}:{} {}", tenant, method, path)
+  }
+}
+
+// Splits an owner key into (tenant, method, path)
+#[inline]
+pub(crate) fn parse_owner_key(key: &str) -> Option<(&str, &str, &str)> {
+  let (prefix, path) = key.split_once(' ')?;
+  match prefix.split_once(':') {
+    Some((tenant, method)) => Some((tenant, method, path)),
+    None => Some(("", prefix, path)),
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteConflict {
+  pub tenant: String,
+  pub method: &'static str,
+  pub path: String,
+  pub owner: String,
+}
+
+impl fmt::Display for RouteConflict {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "The path is owned by another service: {} {}, owner: {}, tenant: {:?}",
+      self.method, self.path, self.owner, self.tenant
+    )
+  }
+}
+
+impl std::error::Error for RouteConflict {}
+
+pub(crate) type OwnerStore = dyn Store<String, String>;
+
+pub(crate) struct OwnerCoder;
+
+impl Coder<String, String> for OwnerCoder {
+  type EncodedKey = Bytes;
+  type EncodedValue = Bytes;
+
+  #[inline(always)]
+  fn encode_key<K: Borrow<String>>(key: K) -> Self::EncodedKey {
+    BytesMut::from(key.borrow().as_bytes()).freeze()
+  }
+
+  #[inline(always)]
+  fn decode_key(key: &[u8]) -> String {
+    std::str::from_utf8(key).unwrap().to_string()
+  }
+
+  #[inline(always)]
+  fn encode_value<V: Borrow<String>>(value: V) -> Self::EncodedValue {
+    BytesMut::from(value.borrow().as_bytes()).freeze()
+  }
+
+  #[inline(always)]
+  fn decode_value(value: &[u8]) -> String {
+    std::str::from_utf8(value).unwrap().to_string()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_logical_service_of() {
+    assert_eq!(logical_service_of("order-service-0"), "order-service");
+    assert_eq!(logical_service_of("order-service-12"), "order-service");
+    assert_eq!(logical_service_of("order-service"), "order-service");
+    assert_eq!(logical_service_of("10.0.0.1:8080"), "10.0.0.1:8080");
+    assert_eq!(logical_service_of("-1"), "-1");
+  }
+
+  #[test]
+  fn test_owner_key() {
+    let key = owner_key("", "get", "/a/:id");
+    assert_eq!(parse_owner_key(&key), Some(("", "get", "/a/:id")));
+    let key = owner_key("team-a", "get", "/a/:id");
+    assert_eq!(parse_owner_key(&key), Some(("team-a", "get", "/a/:id")));
+  }
+}
@@ -0,0 +1,175 @@
+use std::fmt;
+
+// A registered path is either literal (`/api/users`), parameterized
+// (`/api/users/{id}`), or ends with a wildcard (`/static/*`). RouteGroup.path
+// carries the pattern as is, frontends parse it with the same syntax.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+  Literal(String),
+  Param,
+  Wildcard,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathPattern {
+  segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidPath {
+  pub path: String,
+  pub reason: &'static str,
+}
+
+impl fmt::Display for InvalidPath {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "Invalid path: {:?}, reason: {}", self.path, self.reason)
+  }
+}
+
+impl std::error::Error for InvalidPath {}
+
+// Collapses duplicate slashes, rejects relative segments and the characters
+// which can not be routed, so that equal routes are registered equally
+pub fn normalize_path(path: &str, max_len: usize) -> Result<String, InvalidPath> {
+  let invalid = |reason| Err(InvalidPath { path: path.to_owned(), reason });
+
+  if !path.starts_with('/') {
+    return invalid("must start with '/'");
+  }
+  if max_len > 0 && path.len() > max_len {
+    return invalid("too long");
+  }
+  if path.chars().any(|c| c.is_control() || c.is_whitespace()) {
+    return invalid("must not contain whitespaces or control characters");
+  }
+  let mut normalized = String::with_capacity(path.len());
+  for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+    if segment == "." || segment == ".." {
+      return invalid("must not contain '.' or '..' segments");
+    }
+    normalized.push('/');
+    normalized.push_str(segment);
+  }
+  if normalized.is_empty() {
+    normalized.push('/');
+  } else if path.ends_with('/') {
+    normalized.push('/');
+  }
+  Ok(normalized)
+}
+
+impl PathPattern {
+  pub fn parse(path: &str) -> Result<Self, InvalidPath> {
+    let invalid = |reason| Err(InvalidPath { path: path.to_owned(), reason });
+
+    let Some(rest) = path.strip_prefix('/') else {
+      return invalid("must start with '/'");
+    };
+    let mut segments = Vec::new();
+    let mut param_names = Vec::new();
+    if rest.is_empty() {
+      return Ok(PathPattern { segments });
+    }
+    let raw_segments: Vec<&str> = rest.split('/').collect();
+    for (i, raw_segment) in raw_segments.iter().enumerate() {
+      let segment = if *raw_segment == "*" {
+        if i != raw_segments.len() - 1 {
+          return invalid("'*' is only allowed as the last segment");
+        }
+        Segment::Wildcard
+      } else if let Some(name) =
+        raw_segment.strip_prefix('{').and_then(|segment| segment.strip_suffix('}'))
+      {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+          return invalid("param names must be non-empty [A-Za-z0-9_]");
+        }
+        if param_names.contains(&name) {
+          return invalid("param names must be unique");
+        }
+        param_names.push(name);
+        Segment::Param
+      } else {
+        if raw_segment.contains(['{', '}', '*']) {
+          return invalid("'{', '}' and '*' must take a whole segment");
+        }
+        Segment::Literal((*raw_segment).to_owned())
+      };
+      segments.push(segment);
+    }
+    Ok(PathPattern { segments })
+  }
+
+  #[inline]
+  pub fn is_literal(&self) -> bool {
+    self.segments.iter().all(|segment| matches!(segment, Segment::Literal(_)))
+  }
+
+  // Whether the concrete path is served by this pattern, a trailing wildcard
+  // matches any number of remaining segments including none
+  pub fn matches(&self, path: &str) -> bool {
+    let rest = path.strip_prefix('/').unwrap_or(path);
+    let parts: Vec<&str> = if rest.is_empty() { vec![] } else { rest.split('/').collect() };
+    for (i, segment) in self.segments.iter().enumerate() {
+      match segment {
+        Segment::Wildcard => return true,
+        Segment::Param => {
+          if !matches!(parts.get(i), Some(part) if !part.is_empty()) {
+            return false;
+          }
+        }
+        Segment::Literal(literal) => {
+          if parts.get(i) != Some(&literal.as_str()) {
+            return false;
+          }
+        }
+      }
+    }
+    parts.len() == self.segments.len()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse() {
+    assert!(PathPattern::parse("/").unwrap().is_literal());
+    assert!(PathPattern::parse("/api/users").unwrap().is_literal());
+    assert!(!PathPattern::parse("/api/users/{id}").unwrap().is_literal());
+    assert!(!PathPattern::parse("/static/*").unwrap().is_literal());
+    assert!(PathPattern::parse("api").is_err());
+    assert!(PathPattern::parse("/static/*/x").is_err());
+    assert!(PathPattern::parse("/api/{}").is_err());
+    assert!(PathPattern::parse("/api/{id}/{id}").is_err());
+    assert!(PathPattern::parse("/api/user{id}").is_err());
+  }
+
+  #[test]
+  fn test_normalize_path() {
+    assert_eq!(normalize_path("/", 0).unwrap(), "/");
+    assert_eq!(normalize_path("//", 0).unwrap(), "/");
+    assert_eq!(normalize_path("/api//users", 0).unwrap(), "/api/users");
+    assert_eq!(normalize_path("/api/users/", 0).unwrap(), "/api/users/");
+    assert!(normalize_path("api", 0).is_err());
+    assert!(normalize_path("/api/../admin", 0).is_err());
+    assert!(normalize_path("/api/./users", 0).is_err());
+    assert!(normalize_path("/api users", 0).is_err());
+    assert!(normalize_path("/api/users", 4).is_err());
+  }
+
+  #[test]
+  fn test_matches() {
+    let pattern = PathPattern::parse("/api/users/{id}").unwrap();
+    assert!(pattern.matches("/api/users/1"));
+    assert!(!pattern.matches("/api/users"));
+    assert!(!pattern.matches("/api/users/"));
+    assert!(!pattern.matches("/api/users/1/posts"));
+
+    let pattern = PathPattern::parse("/static/*").unwrap();
+    assert!(pattern.matches("/static"));
+    assert!(pattern.matches("/static/js/app.js"));
+    assert!(!pattern.matches("/api"));
+  }
+}
@@ -0,0 +1,189 @@
+use std::{borrow::Borrow, collections::BTreeMap, fmt};
+
+use bytes::{Bytes, BytesMut};
+use seriesdb::coder::Coder;
+
+use super::{normalize_path, Path, PathBundle, METHODS};
+use crate::{config::CONFIG, node_mgr::NodeId, store::Store};
+
+// Reqs per second a frontend lets through to a path, as a token bucket holding
+// up to burst tokens, 0 burst means as many as the rate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimit {
+  pub rate: u32,
+  pub burst: u32,
+}
+
+impl RateLimit {
+  // The stricter of the two, when services of a path declare differently
+  #[inline]
+  fn min(self, other: RateLimit) -> RateLimit {
+    RateLimit { rate: self.rate.min(other.rate), burst: self.burst.min(other.burst) }
+  }
+}
+
+// As declared by a service along with its routes, for one of its paths
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathRateLimit {
+  pub method: String,
+  pub path: Path,
+  pub rate: u32,
+  #[serde(default)]
+  pub burst: u32,
+}
+
+impl PathRateLimit {
+  #[inline]
+  pub fn limit(&self) -> RateLimit {
+    RateLimit { rate: self.rate, burst: if self.burst == 0 { self.rate } else { self.burst } }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidRateLimit {
+  pub method: String,
+  pub path: Path,
+  pub reason: &'static str,
+}
+
+impl fmt::Display for InvalidRateLimit {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "Invalid rate limit: method: {}, path: {:?}, reason: {}",
+      self.method, self.path, self.reason
+    )
+  }
+}
+
+impl std::error::Error for InvalidRateLimit {}
+
+// Normalizes the paths the same way as the routes, each limit must be for a
+// path of the routes, once, and sorted so that equal limits are stored equally
+pub fn normalize_rate_limits(
+  limits: Vec<PathRateLimit>, pb: &PathBundle,
+) -> Result<Vec<PathRateLimit>, InvalidRateLimit> {
+  let mut normalized: BTreeMap<(String, Path), PathRateLimit> = BTreeMap::new();
+  for limit in limits {
+    let invalid = |reason| {
+      Err(InvalidRateLimit { method: limit.method.clone(), path: limit.path.clone(), reason })
+    };
+    let Some(method) = METHODS.iter().find(|method| **method == limit.method) else {
+      return invalid("unknown method");
+    };
+    let Ok(path) = normalize_path(&limit.path, CONFIG.load().route_mgr.max_path_len) else {
+      return invalid("invalid path");
+    };
+    let is_routed =
+      pb.path_sets().iter().any(|(name, paths)| name == method && paths.contains(&path));
+    if !is_routed {
+      return invalid("not a path of the routes");
+    }
+    if limit.rate == 0 {
+      return invalid("rate must be positive");
+    }
+    if limit.burst != 0 && limit.burst < limit.rate {
+      return invalid("burst must not be less than rate");
+    }
+    let key = (limit.method.clone(), path.clone());
+    if normalized.contains_key(&key) {
+      return invalid("duplicate");
+    }
+    normalized.insert(key, PathRateLimit { path, ..limit });
+  }
+  Ok(normalized.into_values().collect())
+}
+
+pub(crate) type RateLimitStore = dyn Store<NodeId, Vec<PathRateLimit>>;
+
+pub(crate) struct RateLimitCoder;
+
+impl Coder<NodeId, Vec<PathRateLimit>> for RateLimitCoder {
+  type EncodedKey = Bytes;
+  type EncodedValue = Bytes;
+
+  #[inline(always)]
+  fn encode_key<K: Borrow<NodeId>>(key: K) -> Self::EncodedKey {
+    BytesMut::from(key.borrow().as_bytes()).freeze()
+  }
+
+  #[inline(always)]
+  fn decode_key(key: &[u8]) -> NodeId {
+    std::str::from_utf8(key).unwrap().to_string()
+  }
+
+  #[inline(always)]
+  fn encode_value<V: Borrow<Vec<PathRateLimit>>>(value: V) -> Self::EncodedValue {
+    bincode::serialize(value.borrow()).unwrap().into()
+  }
+
+  #[inline(always)]
+  fn decode_value(value: &[u8]) -> Vec<PathRateLimit> {
+    bincode::deserialize(value).unwrap()
+  }
+}
+
+// Of a route table, by method then path, the stricter limit wins if the
+// services of a path declare differently
+pub type RateLimits = BTreeMap<&'static str, BTreeMap<Path, RateLimit>>;
+
+pub(crate) fn merge_rate_limit(
+  rate_limits: &mut RateLimits, method: &'static str, path: &Path, limit: RateLimit,
+) {
+  rate_limits
+    .entry(method)
+    .or_default()
+    .entry(path.clone())
+    .and_modify(|merged| *merged = merged.min(limit))
+    .or_insert(limit);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn limit(method: &str, path: &str, rate: u32, burst: u32) -> PathRateLimit {
+    PathRateLimit { method: method.to_owned(), path: path.to_owned(), rate, burst }
+  }
+
+  #[test]
+  fn test_normalize_rate_limits() {
+    let mut pb = PathBundle::default();
+    pb.get_paths.insert("/users/:id".to_owned());
+    pb.post_paths.insert("/users".to_owned());
+
+    let normalized = normalize_rate_limits(
+      vec![limit("post", "//users", 10, 0), limit("get", "/users/:id", 100, 200)],
+      &pb,
+    )
+    .unwrap();
+    assert_eq!(
+      normalized,
+      vec![limit("get", "/users/:id", 100, 200), limit("post", "/users", 10, 0)]
+    );
+    assert_eq!(normalized[1].limit(), RateLimit { rate: 10, burst: 10 });
+
+    for (limits, reason) in [
+      (vec![limit("fetch", "/users", 10, 0)], "unknown method"),
+      (vec![limit("get", "/users", 10, 0)], "not a path of the routes"),
+      (vec![limit("post", "/users", 0, 0)], "rate must be positive"),
+      (vec![limit("post", "/users", 10, 5)], "burst must not be less than rate"),
+      (
+        vec![limit("post", "/users", 10, 0), limit("post", "/users/", 20, 0)],
+        "not a path of the routes",
+      ),
+      (vec![limit("post", "/users", 10, 0), limit("post", "//users", 20, 0)], "duplicate"),
+    ] {
+      assert_eq!(normalize_rate_limits(limits, &pb).unwrap_err().reason, reason);
+    }
+  }
+
+  #[test]
+  fn test_merge_rate_limit() {
+    let mut rate_limits = RateLimits::new();
+    let path = "/users".to_owned();
+    merge_rate_limit(&mut rate_limits, "get", &path, RateLimit { rate: 100, burst: 100 });
+    merge_rate_limit(&mut rate_limits, "get", &path, RateLimit { rate: 50, burst: 200 });
+    assert_eq!(rate_limits["get"][&path], RateLimit { rate: 50, burst: 100 });
+  }
+}
@@ -0,0 +1,342 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use maxwell_protocol::RouteGroup;
+use serde::Serialize;
+
+use super::{merge_rate_limit, PathSet, RateLimits, DEFAULT_WEIGHT, ROUTE_MGR};
+use crate::{
+  clock,
+  config::CONFIG,
+  node_mgr::{Service, SERVICE_MGR},
+};
+
+pub const METHODS: [&str; 9] =
+  ["ws", "get", "post", "put", "patch", "delete", "head", "options", "trace"];
+
+// A route group sharing its path and endpoints with the table it belongs to,
+// so that replying the routes copies pointers rather than strings. Serialized
+// like RouteGroup.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SharedRouteGroup {
+  pub path: Arc<str>,
+  pub healthy_endpoints: Vec<Arc<str>>,
+  pub unhealthy_endpoints: Vec<Arc<str>>,
+}
+
+impl SharedRouteGroup {
+  #[inline]
+  fn new(path: Arc<str>) -> Self {
+    SharedRouteGroup { path, healthy_endpoints: Vec::new(), unhealthy_endpoints: Vec::new() }
+  }
+
+  // For maxwell-protocol, whose msgs own their strings
+  pub fn to_route_group(&self) -> RouteGroup {
+    RouteGroup {
+      path: self.path.to_string(),
+      healthy_endpoints: self
+        .healthy_endpoints
+        .iter()
+        .map(|endpoint| endpoint.to_string())
+        .collect(),
+      unhealthy_endpoints: self
+        .unhealthy_endpoints
+        .iter()
+        .map(|endpoint| endpoint.to_string())
+        .collect(),
+    }
+  }
+}
+
+// All route groups indexed by method and path, with a checksum of the content,
+// so that two tables with the same routes and health have the same checksum.
+#[derive(Debug, Clone, Default)]
+pub struct RouteTable {
+  groups: [BTreeMap<Arc<str>, Arc<SharedRouteGroup>>; 9],
+  // Endpoint to weight, only the ones other than the default weight
+  weights: BTreeMap<String, u32>,
+  // Only the paths having a limit declared, for the frontends to enforce
+  rate_limits: RateLimits,
+  checksum: u32,
+  route_version: Option<u32>,
+  service_version: Option<u32>,
+}
+
+// The changes needed to turn an older table into a newer one
+#[derive(Debug, Default)]
+pub struct RouteDelta {
+  pub updated: BTreeMap<&'static str, Vec<Arc<SharedRouteGroup>>>,
+  pub removed: BTreeMap<&'static str, Vec<Arc<str>>>,
+  // All weights, only present if they changed
+  pub weights: Option<BTreeMap<String, u32>>,
+  // All rate limits, only present if they changed
+  pub rate_limits: Option<RateLimits>,
+}
+
+impl RouteTable {
+  // Only the routes of the services of the tenant
+  pub fn build(tenant: &str) -> Self {
+    let mut table = RouteTable {
+      route_version: Some(ROUTE_MGR.version()),
+      service_version: Some(SERVICE_MGR.version()),
+      ..Default::default()
+    };
+    let mut groups: [BTreeMap<Arc<str>, SharedRouteGroup>; 9] = Default::default();
+    for reverse_route_group in ROUTE_MGR.reverse_route_group_iter() {
+      let service_id = reverse_route_group.key();
+      if ROUTE_MGR.tenant_of(service_id) != tenant {
+        continue;
+      }
+      let (endpoint, is_healthy) = match SERVICE_MGR.get(service_id) {
+        Some(service) if !is_lease_expired(&service) => {
+          (Arc::<str>::from(service.private_endpoint()), service.is_healthy())
+        }
+        _ => continue,
+      };
+      let path_sets = reverse_route_group.value().path_sets();
+      for (i, (_, paths)) in path_sets.into_iter().enumerate() {
+        Self::add_paths(&mut groups[i], paths, &endpoint, is_healthy);
+      }
+      // The limits of the paths no longer routed are left out
+      for limit in ROUTE_MGR.rate_limits(service_id) {
+        if let Some((method, _)) = path_sets
+          .into_iter()
+          .find(|(method, paths)| *method == limit.method && paths.contains(&limit.path))
+        {
+          merge_rate_limit(&mut table.rate_limits, method, &limit.path, limit.limit());
+        }
+      }
+      let weight = ROUTE_MGR.weight(service_id);
+      if weight != DEFAULT_WEIGHT {
+        table.weights.insert(endpoint.to_string(), weight);
+      }
+    }
+    for (i, groups) in groups.into_iter().enumerate() {
+      table.groups[i] = groups
+        .into_iter()
+        .map(|(path, mut group)| {
+          group.healthy_endpoints.sort();
+          group.unhealthy_endpoints.sort();
+          (path, Arc::new(group))
+        })
+        .collect();
+    }
+    table.checksum = table.calc_checksum();
+    table
+  }
+
+  #[inline]
+  pub fn is_built_from(&self, route_version: u32, service_version: u32) -> bool {
+    self.route_version == Some(route_version) && self.service_version == Some(service_version)
+  }
+
+  #[inline]
+  pub fn route_groups(&self, method: &str) -> Vec<Arc<SharedRouteGroup>> {
+    match METHODS.iter().position(|name| *name == method) {
+      Some(i) => self.groups[i].values().cloned().collect(),
+      None => vec![],
+    }
+  }
+
+  // Copies the strings, only for the replies of maxwell-protocol
+  #[inline]
+  pub fn proto_route_groups(&self, method: &str) -> Vec<RouteGroup> {
+    match METHODS.iter().position(|name| *name == method) {
+      Some(i) => self.groups[i].values().map(|group| group.to_route_group()).collect(),
+      None => vec![],
+    }
+  }
+
+  #[inline]
+  pub fn route_group(&self, method: &str, path: &str) -> Option<&SharedRouteGroup> {
+    METHODS
+      .iter()
+      .position(|name| *name == method)
+      .and_then(|i| self.groups[i].get(path))
+      .map(|group| &**group)
+  }
+
+  // All route groups with their methods, in the order of METHODS
+  pub fn iter(&self) -> impl Iterator<Item = (&'static str, &SharedRouteGroup)> {
+    self
+      .groups
+      .iter()
+      .enumerate()
+      .flat_map(|(i, groups)| groups.values().map(move |group| (METHODS[i], &**group)))
+  }
+
+  #[inline]
+  pub fn weights(&self) -> &BTreeMap<String, u32> {
+    &self.weights
+  }
+
+  #[inline]
+  pub fn rate_limits(&self) -> &RateLimits {
+    &self.rate_limits
+  }
+
+  // A path is allocated once per table, however many services serve it
+  #[inline]
+  fn add_paths(
+    groups: &mut BTreeMap<Arc<str>, SharedRouteGroup>, paths: &PathSet, endpoint: &Arc<str>,
+    is_healthy: bool,
+  ) {
+    for path in paths {
+      if !groups.contains_key(path.as_str()) {
+        let path = Arc::<str>::from(path.as_str());
+        groups.insert(path.clone(), SharedRouteGroup::new(path));
+      }
+      let group = groups.get_mut(path.as_str()).unwrap();
+      if is_healthy {
+        group.healthy_endpoints.push(endpoint.clone());
+      } else {
+        group.unhealthy_endpoints.push(endpoint.clone());
+      }
+    }
+  }
+
+  fn calc_checksum(&self) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    for (i, groups) in self.groups.iter().enumerate() {
+      for group in groups.values() {
+        hasher.update(METHODS[i].as_bytes());
+        hasher.update(b"|");
+        hasher.update(group.path.as_bytes());
+        for endpoint in &group.healthy_endpoints {
+          hasher.update(b"|+");
+          hasher.update(endpoint.as_bytes());
+        }
+        for endpoint in &group.unhealthy_endpoints {
+          hasher.update(b"|-");
+          hasher.update(endpoint.as_bytes());
+        }
+        hasher.update(b"\n");
+      }
+    }
+    for (endpoint, weight) in &self.weights {
+      hasher.update(endpoint.as_bytes());
+      hasher.update(b"=");
+      hasher.update(&weight.to_be_bytes());
+      hasher.update(b"\n");
+    }
+    for (method, limits) in &self.rate_limits {
+      for (path, limit) in limits {
+        hasher.update(method.as_bytes());
+        hasher.update(b"|");
+        hasher.update(path.as_bytes());
+        hasher.update(b"<");
+        hasher.update(&limit.rate.to_be_bytes());
+        hasher.update(&limit.burst.to_be_bytes());
+        hasher.update(b"\n");
+      }
+    }
+    hasher.finalize()
+  }
+
+  #[inline]
+  pub fn checksum(&self) -> u32 {
+    self.checksum
+  }
+
+  // Every route group as updated, for the clients which have nothing to diff against
+  pub fn full(&self) -> RouteDelta {
+    let mut delta = RouteDelta::default();
+    for (i, groups) in self.groups.iter().enumerate() {
+      if !groups.is_empty() {
+        delta.updated.insert(METHODS[i], groups.values().cloned().collect());
+      }
+    }
+    delta.weights = Some(self.weights.clone());
+    delta.rate_limits = Some(self.rate_limits.clone());
+    delta
+  }
+
+  pub fn diff(&self, old: &RouteTable) -> RouteDelta {
+    let mut delta = RouteDelta::default();
+    for (i, (groups, old_groups)) in self.groups.iter().zip(old.groups.iter()).enumerate() {
+      let updated: Vec<Arc<SharedRouteGroup>> = groups
+        .iter()
+        .filter(|(path, group)| old_groups.get(*path) != Some(*group))
+        .map(|(_, group)| group.clone())
+        .collect();
+      if !updated.is_empty() {
+        delta.updated.insert(METHODS[i], updated);
+      }
+      let removed: Vec<Arc<str>> =
+        old_groups.keys().filter(|path| !groups.contains_key(*path)).cloned().collect();
+      if !removed.is_empty() {
+        delta.removed.insert(METHODS[i], removed);
+      }
+    }
+    if self.weights != old.weights {
+      delta.weights = Some(self.weights.clone());
+    }
+    if self.rate_limits != old.rate_limits {
+      delta.rate_limits = Some(self.rate_limits.clone());
+    }
+    delta
+  }
+}
+
+// The routes of a service are left out once it has been unhealthy for longer
+// than the grace period, and are back as soon as it is active again, they are
+// only removed along with the service when it is stale.
+#[inline]
+pub fn is_lease_expired(service: &Service) -> bool {
+  let lease_grace = CONFIG.load().route_mgr.lease_grace;
+  lease_grace > 0
+    && clock::now().saturating_sub(service.active_at)
+      > CONFIG.load().service_mgr.unhealthy_threshold + lease_grace
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::route_mgr::RateLimit;
+
+  fn group(path: &str, healthy_endpoints: &[&str]) -> SharedRouteGroup {
+    SharedRouteGroup {
+      path: Arc::from(path),
+      healthy_endpoints: healthy_endpoints.iter().map(|e| Arc::from(*e)).collect(),
+      unhealthy_endpoints: vec![],
+    }
+  }
+
+  fn table(get_groups: Vec<SharedRouteGroup>) -> RouteTable {
+    let mut table = RouteTable::default();
+    for group in get_groups {
+      table.groups[1].insert(group.path.clone(), Arc::new(group));
+    }
+    table.checksum = table.calc_checksum();
+    table
+  }
+
+  #[test]
+  fn test_diff() {
+    let old = table(vec![group("/a", &["1.1.1.1:80"]), group("/b", &["1.1.1.1:80"])]);
+    let new = table(vec![group("/a", &["1.1.1.1:80", "2.2.2.2:80"]), group("/c", &["1.1.1.1:80"])]);
+    let delta = new.diff(&old);
+    let updated: Vec<&str> = delta.updated["get"].iter().map(|g| &*g.path).collect();
+    assert_eq!(updated, vec!["/a", "/c"]);
+    assert_eq!(delta.removed["get"], vec![Arc::<str>::from("/b")]);
+    assert_ne!(old.checksum(), new.checksum());
+    assert_eq!(new.diff(&new).updated.len(), 0);
+    assert_eq!(new.diff(&old).rate_limits, None);
+  }
+
+  #[test]
+  fn test_diff_rate_limits() {
+    let old = table(vec![group("/a", &["1.1.1.1:80"])]);
+    let mut new = old.clone();
+    merge_rate_limit(
+      &mut new.rate_limits,
+      "get",
+      &"/a".to_owned(),
+      RateLimit { rate: 10, burst: 10 },
+    );
+    new.checksum = new.calc_checksum();
+    let delta = new.diff(&old);
+    assert!(delta.updated.is_empty());
+    assert_eq!(delta.rate_limits, Some(new.rate_limits.clone()));
+    assert_ne!(old.checksum(), new.checksum());
+  }
+}
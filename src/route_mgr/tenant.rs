@@ -0,0 +1,80 @@
+use std::borrow::Borrow;
+use std::fmt;
+
+use ahash::HashMap;
+use bytes::{Bytes, BytesMut};
+use once_cell::sync::Lazy;
+use seriesdb::coder::Coder;
+
+use crate::{config::CONFIG, node_mgr::NodeId, store::Store};
+
+// The services and frontends connecting without credentials
+pub const DEFAULT_TENANT: &str = "";
+
+// Token to tenant name, as configured
+static TENANTS: Lazy<HashMap<String, String>> = Lazy::new(|| {
+  let mut tenants = HashMap::default();
+  for tenant in &CONFIG.load().route_mgr.tenants {
+    tenants.insert(tenant.token.clone(), tenant.name.clone());
+  }
+  tenants
+});
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownCredentials;
+
+impl fmt::Display for UnknownCredentials {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "The credentials do not match any tenant")
+  }
+}
+
+impl std::error::Error for UnknownCredentials {}
+
+#[inline]
+pub fn resolve_tenant(token: Option<&str>) -> Result<String, UnknownCredentials> {
+  match token {
+    Some(token) => TENANTS.get(token).cloned().ok_or(UnknownCredentials),
+    None => Ok(DEFAULT_TENANT.to_owned()),
+  }
+}
+
+// All tenants, the default one first
+pub fn tenant_names() -> Vec<String> {
+  let mut names = vec![DEFAULT_TENANT.to_owned()];
+  for tenant in &CONFIG.load().route_mgr.tenants {
+    if !names.contains(&tenant.name) {
+      names.push(tenant.name.clone());
+    }
+  }
+  names
+}
+
+pub(crate) type TenantStore = dyn Store<NodeId, String>;
+
+pub(crate) struct TenantCoder;
+
+impl Coder<NodeId, String> for TenantCoder {
+  type EncodedKey = Bytes;
+  type EncodedValue = Bytes;
+
+  #[inline(always)]
+  fn encode_key<K: Borrow<NodeId>>(key: K) -> Self::EncodedKey {
+    BytesMut::from(key.borrow().as_bytes()).freeze()
+  }
+
+  #[inline(always)]
+  fn decode_key(key: &[u8]) -> NodeId {
+    std::str::from_utf8(key).unwrap().to_string()
+  }
+
+  #[inline(always)]
+  fn encode_value<V: Borrow<String>>(value: V) -> Self::EncodedValue {
+    BytesMut::from(value.borrow().as_bytes()).freeze()
+  }
+
+  #[inline(always)]
+  fn decode_value(value: &[u8]) -> String {
+    std::str::from_utf8(value).unwrap().to_string()
+  }
+}
@@ -0,0 +1,38 @@
+use std::borrow::Borrow;
+
+use bytes::{Bytes, BytesMut};
+use seriesdb::coder::Coder;
+
+use crate::{node_mgr::NodeId, store::Store};
+
+// Services without a weight get this one, 0 means no traffic at all
+pub const DEFAULT_WEIGHT: u32 = 1;
+
+pub(crate) type WeightStore = dyn Store<NodeId, u32>;
+
+pub(crate) struct WeightCoder;
+
+impl Coder<NodeId, u32> for WeightCoder {
+  type EncodedKey = Bytes;
+  type EncodedValue = Bytes;
+
+  #[inline(always)]
+  fn encode_key<K: Borrow<NodeId>>(key: K) -> Self::EncodedKey {
+    BytesMut::from(key.borrow().as_bytes()).freeze()
+  }
+
+  #[inline(always)]
+  fn decode_key(key: &[u8]) -> NodeId {
+    std::str::from_utf8(key).unwrap().to_string()
+  }
+
+  #[inline(always)]
+  fn encode_value<V: Borrow<u32>>(value: V) -> Self::EncodedValue {
+    Bytes::copy_from_slice(&value.borrow().to_be_bytes())
+  }
+
+  #[inline(always)]
+  fn decode_value(value: &[u8]) -> u32 {
+    u32::from_be_bytes(value.try_into().unwrap())
+  }
+}
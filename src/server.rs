@@ -0,0 +1,1348 @@
+use std::{
+  fs::File,
+  io::{self, BufReader},
+  net::SocketAddr,
+  path::{Path, PathBuf},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+  time::Instant,
+};
+
+use actix_cors::Cors;
+use actix_web::{
+  dev::{Server, ServerHandle, Service, ServiceRequest, ServiceResponse},
+  http::header::{
+    CacheControl, CacheDirective, ContentDisposition, ContentEncoding, ContentType, ETag,
+    EntityTag, Header, HeaderName, HeaderValue, IfNoneMatch,
+  },
+  middleware, rt, web, App, Error, HttpRequest, HttpResponse, HttpServer,
+};
+use actix_web_actors::ws;
+use anyhow::{anyhow, bail, Context, Result};
+use futures::future;
+use maxwell_protocol::ErrorCode;
+use opentelemetry::KeyValue;
+use rustls::{server::WebPkiClientVerifier, RootCertStore, ServerConfig};
+use rustls_pemfile::{certs, private_key};
+use serde::Serialize;
+use serde_json::json;
+use tokio::{net::TcpListener, sync::oneshot};
+use tonic::transport::server::TcpIncoming;
+
+use crate::{
+  acme, audit, backup, build_info, cluster,
+  config::{self, Config, ConfigSource, CONFIG},
+  config_mgr,
+  db_pool::DB_POOL,
+  discovery, encryption,
+  error_code::ExtErrorCode,
+  event_bus,
+  handler::{
+    admin_auth::{authorize, build_rejection},
+    admin_handler::{
+      AdminHandler, BackupReq, GetAuditRecordsReq, GetCpuProfileReq, GetEventsReq,
+      GetRouteHealthReq, GetRouteHistoryReq, GetTopologyReq, ImportRoutesReq, ImportTopicsReq,
+      ListConnectionsReq, ListNodesReq, ListRoutesReq, ListTopicsReq, MatchRouteReq, PinTopicReq,
+      ReassignTopicReq, RemoveSettingReq, RemoveTopicNamespaceReq, RollbackRoutesReq,
+      SetServiceWeightReq, SetSettingReq, TransferRouteReq, UnpinTopicReq,
+    },
+    client_cert,
+    compression::{filter_accept_encoding, skip_small_body},
+    error_rep::ErrorRep,
+    grpc_handler::{GrpcHandler, MasterGrpcServer},
+    http_handler::{
+      tenant_of, GetRoutesDeltaReq, GetRoutesReq, HeartbeatReq, HttpHandler, ListQuery,
+      LocateTopicReq, LocateTopicsReq, PickFrontendReq, RegisterServiceReq, SetRoutesReq,
+    },
+    ws_handler::Handler,
+  },
+  health::{self, HealthStatus},
+  health_policy, hot_reload, metrics, migration,
+  node_mgr::{
+    self,
+    backend_mgr::{BackendMgr, BACKEND_MGR},
+    frontend_mgr::{FrontendMgr, FRONTEND_MGR},
+    service_mgr::{ServiceMgr, SERVICE_MGR},
+  },
+  offsite,
+  raft::{self, AppendReq, VoteReq},
+  route_mgr::{self, RouteMgr, ROUTE_MGR},
+  slow_log,
+  snapshot::{self, PEER_TOKEN_HEADER},
+  standby, stats, statsd, telemetry,
+  topic_mgr::{self, Namespace, TopicMgr, TOPIC_MGR},
+  trace::{new_trace_id, traced, TraceScope, TRACE_ID_HEADER},
+};
+
+static SERVER_NAME: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
+
+// Embedded, so that the binary is all that needs to be deployed
+static DASHBOARD_HTML: &str = include_str!("../assets/dashboard.html");
+
+// The managers and the config are process wide, so is the master
+static IS_STARTED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Default)]
+pub struct MasterServerBuilder {
+  config: Option<Config>,
+  restore_path: Option<PathBuf>,
+  restore_offsite: bool,
+}
+
+impl MasterServerBuilder {
+  // Used instead of the config file, whose path is then never read, set
+  // server.http_port to 0 to listen on an ephemeral port
+  #[inline]
+  pub fn config(mut self, config: Config) -> Self {
+    self.config = Some(config);
+    self
+  }
+
+  // A backup of POST /$admin/backup, whose tables replace those of the db
+  #[inline]
+  pub fn restore(mut self, path: impl Into<PathBuf>) -> Self {
+    self.restore_path = Some(path.into());
+    self
+  }
+
+  // The newest backup in the bucket of db.offsite, fetched into db.backup.dir
+  // and restored, for a master replacing the ones of a lost region
+  #[inline]
+  pub fn restore_offsite(mut self) -> Self {
+    self.restore_offsite = true;
+    self
+  }
+
+  // Must be called within an actix system, the tasks spawned run until it stops
+  pub async fn start(self) -> Result<MasterServer> {
+    if IS_STARTED.swap(true, Ordering::AcqRel) {
+      bail!("A master was already started in this process");
+    }
+    if let Some(config) = self.config {
+      config::init(ConfigSource { config: Some(config), ..Default::default() });
+    }
+    CONFIG.load().validate()?;
+    encryption::init()?;
+    // Before any manager reads its tables
+    if self.restore_offsite {
+      backup::restore(&offsite::fetch_newest(Path::new(&CONFIG.load().db.backup.dir)).await?)?;
+    } else if let Some(path) = &self.restore_path {
+      backup::restore(path)?;
+    }
+    // After the restore, whose tables may be of an older schema
+    migration::run()?;
+    // Before anything reads the tunable settings
+    config_mgr::CONFIG_MGR.apply()?;
+    telemetry::init()?;
+    health::mark_started();
+    log::info!("Starting: {:?}", build_info::build_info());
+    topic_mgr::spawn_gc_task();
+    route_mgr::spawn_refresh_task();
+    route_mgr::spawn_sweep_task();
+    node_mgr::spawn_activation_flush_task();
+    route_mgr::spawn_alert_task();
+    health_policy::spawn_probe_task();
+    event_bus::spawn_health_watch_task();
+    event_bus::spawn_webhook_task();
+    audit::spawn_event_task();
+    audit::spawn_prune_task();
+    stats::spawn_rate_task();
+    statsd::spawn_export_task();
+    hot_reload::spawn_signal_task();
+    standby::spawn_sync_task();
+    raft::spawn_tick_task();
+    backup::spawn_schedule_task();
+    offsite::spawn_sync_task();
+    discovery::spawn_poll_task();
+    health::mark_ready();
+    if CONFIG.load().acme.enabled {
+      acme::spawn_renew_task();
+    }
+    if CONFIG.load().admin.api_keys.is_empty() {
+      log::warn!("No admin api keys are configured, the admin endpoints are open to anyone.");
+    }
+
+    let mut servers = vec![];
+    let mut http_addrs = vec![];
+    let mut https_addrs = vec![];
+    if CONFIG.load().server.enable_http {
+      let (server, addrs) = create_http_server(false)?;
+      servers.push(server);
+      http_addrs = addrs;
+    }
+    if CONFIG.load().server.enable_https {
+      let (server, addrs) = create_http_server(true)?;
+      servers.push(server);
+      https_addrs = addrs;
+    }
+    #[cfg(unix)]
+    if !CONFIG.load().server.unix_socket_path.is_empty() {
+      servers.push(create_uds_server()?);
+    }
+    let grpc_server =
+      if CONFIG.load().server.enable_grpc { Some(start_grpc_server().await?) } else { None };
+    Ok(MasterServer {
+      http_addrs,
+      https_addrs,
+      handles: servers.iter().map(Server::handle).collect(),
+      tasks: servers.into_iter().map(rt::spawn).collect(),
+      grpc_server,
+    })
+  }
+}
+
+// A started master, listening until stopped, or until a signal stops every
+// listener
+pub struct MasterServer {
+  http_addrs: Vec<SocketAddr>,
+  https_addrs: Vec<SocketAddr>,
+  handles: Vec<ServerHandle>,
+  tasks: Vec<rt::task::JoinHandle<io::Result<()>>>,
+  grpc_server: Option<GrpcServer>,
+}
+
+// Stopped once the http servers are, as it does not listen to the signals
+struct GrpcServer {
+  addr: SocketAddr,
+  shutdown_sender: oneshot::Sender<()>,
+  task: rt::task::JoinHandle<Result<(), tonic::transport::Error>>,
+}
+
+impl MasterServer {
+  #[inline]
+  pub fn builder() -> MasterServerBuilder {
+    MasterServerBuilder::default()
+  }
+
+  // With the port assigned when configured as 0
+  #[inline]
+  pub fn http_addr(&self) -> Option<SocketAddr> {
+    self.http_addrs.first().copied()
+  }
+
+  #[inline]
+  pub fn https_addr(&self) -> Option<SocketAddr> {
+    self.https_addrs.first().copied()
+  }
+
+  #[inline]
+  pub fn grpc_addr(&self) -> Option<SocketAddr> {
+    self.grpc_server.as_ref().map(|grpc_server| grpc_server.addr)
+  }
+
+  #[inline]
+  pub fn frontend_mgr(&self) -> &'static FrontendMgr {
+    &FRONTEND_MGR
+  }
+
+  #[inline]
+  pub fn backend_mgr(&self) -> &'static BackendMgr {
+    &BACKEND_MGR
+  }
+
+  #[inline]
+  pub fn service_mgr(&self) -> &'static ServiceMgr {
+    &SERVICE_MGR
+  }
+
+  #[inline]
+  pub fn route_mgr(&self) -> &'static RouteMgr {
+    &ROUTE_MGR
+  }
+
+  #[inline]
+  pub fn topic_mgr(&self) -> &'static TopicMgr {
+    &TOPIC_MGR
+  }
+
+  // Stops accepting, and waits for the connections open to close, up to the
+  // shutdown timeout of actix
+  pub async fn stop(self) -> Result<()> {
+    future::join_all(self.handles.iter().map(|handle| handle.stop(true))).await;
+    self.wait().await
+  }
+
+  pub async fn wait(self) -> Result<()> {
+    let results = future::join_all(self.tasks).await;
+    if let Some(grpc_server) = self.grpc_server {
+      let _ = grpc_server.shutdown_sender.send(());
+      grpc_server
+        .task
+        .await
+        .map_err(|err| anyhow!("The grpc server panicked: err: {:?}", err))?
+        .map_err(|err| anyhow!("Failed to run the grpc server: err: {:?}", err))?;
+    }
+    SERVICE_MGR.flush_activations();
+    telemetry::shutdown();
+    for result in results {
+      result
+        .map_err(|err| anyhow!("The server panicked: err: {:?}", err))?
+        .map_err(|err| anyhow!("Failed to run the server: err: {:?}", err))?;
+    }
+    Ok(())
+  }
+}
+
+async fn get_version(req: HttpRequest) -> HttpResponse {
+  let rep = HttpResponse::Ok()
+    .content_type(ContentType::json())
+    .force_close()
+    .json(build_info::build_info());
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+// Alive as long as it can answer
+async fn live(_req: HttpRequest) -> HttpResponse {
+  HttpResponse::Ok().body("")
+}
+
+async fn ready(req: HttpRequest) -> HttpResponse {
+  let readiness = health::check_ready();
+  let mut rep =
+    if readiness.is_ready() { HttpResponse::Ok() } else { HttpResponse::ServiceUnavailable() };
+  let rep = rep.content_type(ContentType::json()).force_close().json(readiness);
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+#[derive(Debug, Deserialize)]
+struct HealthReq {
+  #[serde(default)]
+  verbose: Option<String>,
+}
+
+impl HealthReq {
+  #[inline]
+  fn is_verbose(&self) -> bool {
+    matches!(self.verbose.as_deref(), Some(verbose) if verbose != "0" && verbose != "false")
+  }
+}
+
+// Empty unless verbose, in which case the components are checked and reported,
+// and 503 is returned once the db can not be used.
+async fn health(req: HttpRequest, query: web::Query<HealthReq>) -> Result<HttpResponse, Error> {
+  if !query.is_verbose() {
+    return Ok(HttpResponse::Ok().body(""));
+  }
+  let report = health::check();
+  let mut rep = match report.status() {
+    HealthStatus::Unavailable => HttpResponse::ServiceUnavailable(),
+    HealthStatus::Ok | HealthStatus::Degraded => HttpResponse::Ok(),
+  };
+  let rep = rep.content_type(ContentType::json()).force_close().json(report);
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  Ok(rep)
+}
+
+// The errors of all the endpoints are answered alike, with the status mapped
+// from the code
+fn build_rep<T: Serialize>(result: Result<T, ErrorRep>) -> HttpResponse {
+  match result {
+    Ok(rep) => HttpResponse::Ok().content_type(ContentType::json()).force_close().json(rep),
+    Err(err) => err.to_response(),
+  }
+}
+
+// For the handlers reading or writing the db, which must not block the worker
+async fn run_on_db_pool<T, F>(f: F) -> Result<T, ErrorRep>
+where
+  F: FnOnce() -> Result<T, ErrorRep> + Send + 'static,
+  T: Send + 'static, {
+  DB_POOL.run(f).await.map_err(ErrorRep::from).and_then(|result| result)
+}
+
+// For the reqs failing to be extracted, e.g. with a malformed query or body
+fn reject_invalid_req<E: std::fmt::Display>(err: E, req: &HttpRequest) -> Error {
+  log::warn!("Invalid http req: {:?}, err: {}", req, err);
+  ErrorRep::new(ExtErrorCode::InvalidQuery as i32, format!("Invalid req: {}", err)).into()
+}
+
+fn create_json_config() -> web::JsonConfig {
+  web::JsonConfig::default().error_handler(reject_invalid_req)
+}
+
+async fn not_found(req: HttpRequest) -> HttpResponse {
+  let rep = ErrorRep::new(ExtErrorCode::NotFound as i32, format!("Not found: {}", req.path()))
+    .to_response();
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+// The admin reqs which may change anything, whether they succeeded or not
+fn audit_admin_req<B>(res: &ServiceResponse<B>) {
+  let req = res.request();
+  if req.method().is_safe() {
+    return;
+  }
+  audit::record(
+    format!(
+      "admin@{}",
+      req.peer_addr().map_or_else(|| "local".to_owned(), |addr| addr.to_string())
+    ),
+    "admin-req",
+    json!({
+      "method": req.method().as_str(),
+      "path": req.path(),
+      "query": req.query_string(),
+      "status": res.status().as_u16(),
+    }),
+  );
+}
+
+// Answers the http-01 challenges of the acme ca
+async fn get_acme_challenge(req: HttpRequest, token: web::Path<String>) -> HttpResponse {
+  let rep = match acme::key_authorization_of(&token) {
+    Some(key_authorization) => {
+      HttpResponse::Ok().content_type("text/plain").body(key_authorization)
+    }
+    None => ErrorRep::new(ExtErrorCode::NotFound as i32, format!("Unknown token: {}", token))
+      .to_response(),
+  };
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn get_metrics(_req: HttpRequest) -> HttpResponse {
+  HttpResponse::Ok().content_type("text/plain; version=0.0.4").body(metrics::render())
+}
+
+async fn get_prometheus_sd(_req: HttpRequest) -> HttpResponse {
+  HttpResponse::Ok().content_type(ContentType::json()).json(metrics::sd_target_groups())
+}
+
+async fn ws(req: HttpRequest, stream: web::Payload) -> Result<HttpResponse, Error> {
+  let tenant = match tenant_of(&req) {
+    Ok(tenant) => tenant,
+    Err(err) => {
+      log::warn!("ws req: {:?}, err: {:?}", req, err);
+      return Ok(ErrorRep::from(err).to_response());
+    }
+  };
+  let rep = ws::WsResponseBuilder::new(Handler::new(&req, tenant), &req, stream)
+    .frame_size(CONFIG.load().server.max_frame_size)
+    .start();
+  log::info!("ws req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn pick_frontend(req: HttpRequest, query: web::Query<PickFrontendReq>) -> HttpResponse {
+  let rep = build_rep(HttpHandler::new(&req).pick_frontend(query.into_inner()));
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn pick_frontends(req: HttpRequest, query: web::Query<PickFrontendReq>) -> HttpResponse {
+  let rep = HttpResponse::Ok()
+    .content_type(ContentType::json())
+    .force_close()
+    .json(HttpHandler::new(&req).pick_frontends(query.into_inner()));
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn get_routes(req: HttpRequest, query: web::Query<GetRoutesReq>) -> HttpResponse {
+  let tenant = match tenant_of(&req) {
+    Ok(tenant) => tenant,
+    Err(err) => return ErrorRep::from(err).to_response(),
+  };
+  let http_handler = HttpHandler::new(&req);
+  let etag = EntityTag::new_strong(http_handler.routes_checksum(&tenant).to_string());
+  let is_not_modified = match IfNoneMatch::parse(&req) {
+    Ok(IfNoneMatch::Items(etags)) => etags.iter().any(|curr_etag| curr_etag.weak_eq(&etag)),
+    Ok(IfNoneMatch::Any) => true,
+    Err(_) => false,
+  };
+  if is_not_modified {
+    let rep = HttpResponse::NotModified().insert_header(ETag(etag)).force_close().finish();
+    log::info!("http req: {:?}, rep: {:?}", req, rep);
+    return rep;
+  }
+  let rep = http_handler.get_routes(&tenant, &query);
+  let mut builder = HttpResponse::Ok();
+  builder
+    .content_type(ContentType::json())
+    .insert_header(ETag(EntityTag::new_strong(rep.checksum().to_string())))
+    .force_close();
+  let rep = if query.pretty() {
+    builder.body(serde_json::to_string_pretty(&rep).unwrap())
+  } else {
+    builder.json(rep)
+  };
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn get_routes_delta(req: HttpRequest, query: web::Query<GetRoutesDeltaReq>) -> HttpResponse {
+  let tenant = match tenant_of(&req) {
+    Ok(tenant) => tenant,
+    Err(err) => return ErrorRep::from(err).to_response(),
+  };
+  let rep = HttpResponse::Ok()
+    .content_type(ContentType::json())
+    .force_close()
+    .json(HttpHandler::new(&req).get_routes_delta(&tenant, query.into_inner()));
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn register_service(req: HttpRequest, body: web::Json<RegisterServiceReq>) -> HttpResponse {
+  let tenant = match tenant_of(&req) {
+    Ok(tenant) => tenant,
+    Err(err) => return ErrorRep::from(err).to_response(),
+  };
+  let handler = HttpHandler::new(&req);
+  let rep =
+    build_rep(run_on_db_pool(move || handler.register_service(&tenant, body.into_inner())).await);
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn set_routes(req: HttpRequest, body: web::Json<SetRoutesReq>) -> HttpResponse {
+  let tenant = match tenant_of(&req) {
+    Ok(tenant) => tenant,
+    Err(err) => return ErrorRep::from(err).to_response(),
+  };
+  let handler = HttpHandler::new(&req);
+  let rep = build_rep(run_on_db_pool(move || handler.set_routes(&tenant, body.into_inner())).await);
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn heartbeat(req: HttpRequest, body: web::Json<HeartbeatReq>) -> HttpResponse {
+  let tenant = match tenant_of(&req) {
+    Ok(tenant) => tenant,
+    Err(err) => return ErrorRep::from(err).to_response(),
+  };
+  let rep = build_rep(HttpHandler::new(&req).heartbeat(&tenant, body.into_inner()));
+  log::debug!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn get_route_dist_checksum(req: HttpRequest) -> HttpResponse {
+  let rep = HttpResponse::Ok()
+    .content_type(ContentType::json())
+    .force_close()
+    .json(HttpHandler::new(&req).get_route_dist_checksum());
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn get_topic_dist_checksum(req: HttpRequest) -> HttpResponse {
+  let rep = HttpResponse::Ok()
+    .content_type(ContentType::json())
+    .force_close()
+    .json(HttpHandler::new(&req).get_topic_dist_checksum());
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn resolve_ip(req: HttpRequest) -> HttpResponse {
+  let rep = build_rep(HttpHandler::new(&req).resolve_ip());
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn locate_topic(req: HttpRequest, query: web::Query<LocateTopicReq>) -> HttpResponse {
+  let handler = HttpHandler::new(&req);
+  let rep = build_rep(run_on_db_pool(move || handler.locate_topic(query.into_inner())).await);
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn locate_topics(req: HttpRequest, body: web::Json<LocateTopicsReq>) -> HttpResponse {
+  let handler = HttpHandler::new(&req);
+  let rep = build_rep(run_on_db_pool(move || Ok(handler.locate_topics(body.into_inner()))).await);
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn get_topic_dist(req: HttpRequest) -> HttpResponse {
+  let handler = HttpHandler::new(&req);
+  let rep = build_rep(run_on_db_pool(move || Ok(handler.get_topic_dist())).await);
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn reassign_topic(req: HttpRequest, query: web::Query<ReassignTopicReq>) -> HttpResponse {
+  let handler = AdminHandler::new(&req);
+  let rep = build_rep(run_on_db_pool(move || handler.reassign_topic(query.into_inner())).await);
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn get_topic_pins(req: HttpRequest) -> HttpResponse {
+  let rep = HttpResponse::Ok()
+    .content_type(ContentType::json())
+    .force_close()
+    .json(AdminHandler::new(&req).get_topic_pins());
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn pin_topic(req: HttpRequest, query: web::Query<PinTopicReq>) -> HttpResponse {
+  let handler = AdminHandler::new(&req);
+  let rep = build_rep(run_on_db_pool(move || handler.pin_topic(query.into_inner())).await);
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn unpin_topic(req: HttpRequest, query: web::Query<UnpinTopicReq>) -> HttpResponse {
+  let handler = AdminHandler::new(&req);
+  let rep = build_rep(run_on_db_pool(move || handler.unpin_topic(query.into_inner())).await);
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn get_topic_stats(req: HttpRequest) -> HttpResponse {
+  let rep = HttpResponse::Ok()
+    .content_type(ContentType::json())
+    .force_close()
+    .json(AdminHandler::new(&req).get_topic_stats());
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn get_topic_namespaces(req: HttpRequest) -> HttpResponse {
+  let rep = HttpResponse::Ok()
+    .content_type(ContentType::json())
+    .force_close()
+    .json(AdminHandler::new(&req).get_topic_namespaces());
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn set_topic_namespace(req: HttpRequest, body: web::Json<Namespace>) -> HttpResponse {
+  let handler = AdminHandler::new(&req);
+  let rep = build_rep(run_on_db_pool(move || handler.set_topic_namespace(body.into_inner())).await);
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn remove_topic_namespace(
+  req: HttpRequest, query: web::Query<RemoveTopicNamespaceReq>,
+) -> HttpResponse {
+  let handler = AdminHandler::new(&req);
+  let rep =
+    build_rep(run_on_db_pool(move || handler.remove_topic_namespace(query.into_inner())).await);
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn export_topics(req: HttpRequest) -> HttpResponse {
+  let handler = AdminHandler::new(&req);
+  let rep = build_rep(run_on_db_pool(move || Ok(handler.export_topics())).await);
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn import_topics(req: HttpRequest, body: web::Json<ImportTopicsReq>) -> HttpResponse {
+  let handler = AdminHandler::new(&req);
+  let rep = build_rep(run_on_db_pool(move || handler.import_topics(body.into_inner())).await);
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn match_route(req: HttpRequest, query: web::Query<MatchRouteReq>) -> HttpResponse {
+  let rep = HttpResponse::Ok()
+    .content_type(ContentType::json())
+    .force_close()
+    .json(AdminHandler::new(&req).match_route(query.into_inner()));
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn get_route_history(
+  req: HttpRequest, query: web::Query<GetRouteHistoryReq>,
+) -> HttpResponse {
+  let rep = HttpResponse::Ok()
+    .content_type(ContentType::json())
+    .force_close()
+    .json(AdminHandler::new(&req).get_route_history(query.into_inner()));
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn rollback_routes(req: HttpRequest, query: web::Query<RollbackRoutesReq>) -> HttpResponse {
+  let handler = AdminHandler::new(&req);
+  let rep = build_rep(run_on_db_pool(move || handler.rollback_routes(query.into_inner())).await);
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn get_route_owners(req: HttpRequest) -> HttpResponse {
+  let rep = HttpResponse::Ok()
+    .content_type(ContentType::json())
+    .force_close()
+    .json(AdminHandler::new(&req).get_route_owners());
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn transfer_route(req: HttpRequest, query: web::Query<TransferRouteReq>) -> HttpResponse {
+  let handler = AdminHandler::new(&req);
+  let rep = build_rep(run_on_db_pool(move || handler.transfer_route(query.into_inner())).await);
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn set_service_weight(
+  req: HttpRequest, query: web::Query<SetServiceWeightReq>,
+) -> HttpResponse {
+  let handler = AdminHandler::new(&req);
+  let rep = build_rep(run_on_db_pool(move || handler.set_service_weight(query.into_inner())).await);
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn get_admin_routes(
+  req: HttpRequest, list: web::Query<ListQuery>, query: web::Query<ListRoutesReq>,
+) -> HttpResponse {
+  let rep = build_rep(AdminHandler::new(&req).get_routes(&list, query.into_inner()));
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn remove_admin_routes(req: HttpRequest, service_id: web::Path<String>) -> HttpResponse {
+  let handler = AdminHandler::new(&req);
+  let rep = build_rep(run_on_db_pool(move || handler.remove_routes(service_id.into_inner())).await);
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn export_routes(req: HttpRequest) -> HttpResponse {
+  let handler = AdminHandler::new(&req);
+  let rep = build_rep(run_on_db_pool(move || Ok(handler.export_routes())).await);
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn import_routes(req: HttpRequest, body: web::Json<ImportRoutesReq>) -> HttpResponse {
+  let handler = AdminHandler::new(&req);
+  let rep = build_rep(run_on_db_pool(move || handler.import_routes(body.into_inner())).await);
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn get_route_health(
+  req: HttpRequest, list: web::Query<ListQuery>, query: web::Query<GetRouteHealthReq>,
+) -> HttpResponse {
+  let rep = build_rep(AdminHandler::new(&req).get_route_health(&list, query.into_inner()));
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn get_nodes(
+  req: HttpRequest, list: web::Query<ListQuery>, query: web::Query<ListNodesReq>,
+) -> HttpResponse {
+  let rep = build_rep(AdminHandler::new(&req).get_nodes(&list, query.into_inner()));
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+// Logged without the rep, which may be large
+async fn get_topics(req: HttpRequest, query: web::Query<ListTopicsReq>) -> HttpResponse {
+  let handler = AdminHandler::new(&req);
+  let rep = build_rep(run_on_db_pool(move || Ok(handler.get_topics(query.into_inner()))).await);
+  log::info!("http req: {:?}, rep status: {:?}", req, rep.status());
+  rep
+}
+
+// Only the page, the data comes from the admin endpoints it polls
+async fn get_dashboard(_req: HttpRequest) -> HttpResponse {
+  HttpResponse::Ok().content_type(ContentType::html()).body(DASHBOARD_HTML)
+}
+
+async fn get_connections(
+  req: HttpRequest, list: web::Query<ListQuery>, query: web::Query<ListConnectionsReq>,
+) -> HttpResponse {
+  let rep = build_rep(AdminHandler::new(&req).get_connections(&list, query.into_inner()));
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn close_connection(req: HttpRequest, id: web::Path<u32>) -> HttpResponse {
+  let rep = build_rep(AdminHandler::new(&req).close_connection(id.into_inner()));
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn get_settings(req: HttpRequest) -> HttpResponse {
+  let rep = HttpResponse::Ok()
+    .content_type(ContentType::json())
+    .force_close()
+    .json(AdminHandler::new(&req).get_settings());
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn set_setting(req: HttpRequest, body: web::Json<SetSettingReq>) -> HttpResponse {
+  let rep = build_rep(AdminHandler::new(&req).set_setting(body.into_inner()));
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn remove_setting(req: HttpRequest, query: web::Query<RemoveSettingReq>) -> HttpResponse {
+  let rep = build_rep(AdminHandler::new(&req).remove_setting(query.into_inner()));
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn get_audit_records(
+  req: HttpRequest, query: web::Query<GetAuditRecordsReq>,
+) -> HttpResponse {
+  let rep = HttpResponse::Ok()
+    .content_type(ContentType::json())
+    .force_close()
+    .json(AdminHandler::new(&req).get_audit_records(query.into_inner()));
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn get_cpu_profile(req: HttpRequest, query: web::Query<GetCpuProfileReq>) -> HttpResponse {
+  let rep = match AdminHandler::new(&req).get_cpu_profile(query.into_inner()).await {
+    Ok((profile, format)) => {
+      HttpResponse::Ok().content_type(format.content_type()).force_close().body(profile)
+    }
+    Err(err) => err.to_response(),
+  };
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+// Logged without the rep, which may be large
+async fn get_topology(req: HttpRequest, query: web::Query<GetTopologyReq>) -> HttpResponse {
+  let rep = match AdminHandler::new(&req).get_topology(query.into_inner()) {
+    Ok((topology, format)) => {
+      HttpResponse::Ok().content_type(format.content_type()).force_close().body(topology)
+    }
+    Err(err) => err.to_response(),
+  };
+  log::info!("http req: {:?}, rep status: {:?}", req, rep.status());
+  rep
+}
+
+// Streamed until the client goes away, left uncompressed, as the compressor
+// would hold the events back
+async fn get_events(req: HttpRequest, query: web::Query<GetEventsReq>) -> HttpResponse {
+  let rep = match AdminHandler::new(&req).get_events(query.into_inner()) {
+    Ok(kinds) => HttpResponse::Ok()
+      .content_type("text/event-stream")
+      .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+      .insert_header(ContentEncoding::Identity)
+      .streaming(event_bus::sse_stream(kinds)),
+    Err(err) => err.to_response(),
+  };
+  log::info!("http req: {:?}, rep status: {:?}", req, rep.status());
+  rep
+}
+
+async fn get_heap_stats(req: HttpRequest) -> HttpResponse {
+  let rep = build_rep(AdminHandler::new(&req).get_heap_stats());
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn get_standby(req: HttpRequest) -> HttpResponse {
+  let rep = HttpResponse::Ok()
+    .content_type(ContentType::json())
+    .force_close()
+    .json(AdminHandler::new(&req).get_standby());
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn promote(req: HttpRequest) -> HttpResponse {
+  let rep = build_rep(AdminHandler::new(&req).promote());
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+// The peers authenticate with the peer token instead of an api key
+fn authenticate_peer_req(req: &HttpRequest) -> Result<(), ErrorRep> {
+  let token =
+    req.headers().get(PEER_TOKEN_HEADER).and_then(|token| token.to_str().ok()).unwrap_or_default();
+  cluster::authenticate_peer(token)
+    .map_err(|err| ErrorRep::new(ExtErrorCode::NotPeer as i32, err.to_string()))
+}
+
+async fn prepare_snapshot(req: HttpRequest) -> HttpResponse {
+  let rep = match authenticate_peer_req(&req) {
+    Ok(()) => build_rep(
+      run_on_db_pool(|| {
+        snapshot::prepare().map_err(|err| {
+          log::error!("Failed to prepare snapshot: err: {:?}", err);
+          ErrorRep::new(
+            ErrorCode::MasterError as i32,
+            format!("Failed to prepare snapshot: err: {}", err),
+          )
+        })
+      })
+      .await,
+    ),
+    Err(err) => err.to_response(),
+  };
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn get_snapshot_manifest(req: HttpRequest, id: web::Path<String>) -> HttpResponse {
+  let rep = build_rep(
+    authenticate_peer_req(&req).and_then(|()| snapshot::manifest(&id).map_err(ErrorRep::from)),
+  );
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn get_snapshot_chunk(req: HttpRequest, path: web::Path<(String, u32)>) -> HttpResponse {
+  let (id, index) = path.into_inner();
+  let rep = match authenticate_peer_req(&req)
+    .and_then(|()| snapshot::chunk(&id, index).map_err(ErrorRep::from))
+  {
+    Ok(chunk) => {
+      HttpResponse::Ok().content_type(ContentType::octet_stream()).force_close().body(chunk)
+    }
+    Err(err) => err.to_response(),
+  };
+  log::debug!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+// Of the peers replicating by raft with this master only
+fn authenticate_raft_req(req: &HttpRequest) -> Result<(), ErrorRep> {
+  authenticate_peer_req(req)?;
+  if !raft::is_enabled() {
+    return Err(ErrorRep::new(ExtErrorCode::NotPeer as i32, "Raft is disabled".to_owned()));
+  }
+  Ok(())
+}
+
+#[inline]
+fn raft_error_rep(err: anyhow::Error) -> ErrorRep {
+  log::error!("Failed to handle raft req: err: {:?}", err);
+  ErrorRep::new(ErrorCode::MasterError as i32, format!("Failed to handle raft req: err: {}", err))
+}
+
+async fn raft_vote(req: HttpRequest, body: web::Json<VoteReq>) -> HttpResponse {
+  let rep = match authenticate_raft_req(&req) {
+    Ok(()) => build_rep(raft::handle_vote(body.into_inner()).await.map_err(raft_error_rep)),
+    Err(err) => err.to_response(),
+  };
+  log::debug!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn raft_append(req: HttpRequest, body: web::Json<AppendReq>) -> HttpResponse {
+  let rep = match authenticate_raft_req(&req) {
+    Ok(()) => build_rep(raft::handle_append(body.into_inner()).await.map_err(raft_error_rep)),
+    Err(err) => err.to_response(),
+  };
+  log::debug!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn backup(req: HttpRequest, query: web::Query<BackupReq>) -> HttpResponse {
+  let handler = AdminHandler::new(&req);
+  let rep = build_rep(run_on_db_pool(move || handler.backup(query.into_inner())).await);
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn download_backup(req: HttpRequest) -> HttpResponse {
+  let handler = AdminHandler::new(&req);
+  let rep = match run_on_db_pool(move || handler.download_backup()).await {
+    Ok((bytes, info)) => HttpResponse::Ok()
+      .content_type(ContentType::octet_stream())
+      .insert_header(ContentDisposition::attachment(
+        backup::file_name(info.created_at).display().to_string(),
+      ))
+      .force_close()
+      .body(bytes),
+    Err(err) => err.to_response(),
+  };
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn get_db_stats(req: HttpRequest) -> HttpResponse {
+  let handler = AdminHandler::new(&req);
+  let rep = build_rep(run_on_db_pool(move || Ok(handler.get_db_stats())).await);
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn compact_db(req: HttpRequest) -> HttpResponse {
+  let handler = AdminHandler::new(&req);
+  let rep = build_rep(run_on_db_pool(move || handler.compact_db()).await);
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+async fn reload_config(req: HttpRequest) -> HttpResponse {
+  let rep = build_rep(AdminHandler::new(&req).reload_config());
+  log::info!("http req: {:?}, rep: {:?}", req, rep);
+  rep
+}
+
+fn create_cors() -> Cors {
+  let config = CONFIG.load();
+  let cors_config = &config.server.cors;
+  let mut cors = Cors::default().block_on_origin_mismatch(false).expose_any_header();
+  if cors_config.allows_any_origin() {
+    cors = cors.allow_any_origin();
+    // Echoing the origin instead, as the wildcard is not allowed with credentials
+    if !cors_config.supports_credentials {
+      cors = cors.send_wildcard();
+    }
+  } else {
+    // Matched on each request, so that reloaded origins apply at once
+    cors = cors.allowed_origin_fn(|origin, _| {
+      CONFIG
+        .load()
+        .server
+        .cors
+        .allowed_origins
+        .iter()
+        .any(|allowed_origin| origin == allowed_origin.as_str())
+    });
+  }
+  cors = if cors_config.allowed_methods.is_empty() {
+    cors.allow_any_method()
+  } else {
+    cors.allowed_methods(cors_config.allowed_methods.iter().map(String::as_str))
+  };
+  cors = if cors_config.allowed_headers.is_empty() {
+    cors.allow_any_header()
+  } else {
+    cors.allowed_headers(cors_config.allowed_headers.iter().map(String::as_str))
+  };
+  cors = cors.max_age(if cors_config.max_age == 0 { None } else { Some(cors_config.max_age) });
+  if cors_config.supports_credentials {
+    cors = cors.supports_credentials();
+  }
+  cors
+}
+
+fn create_default_headers() -> middleware::DefaultHeaders {
+  let default_headers = middleware::DefaultHeaders::new().add(("Server", SERVER_NAME));
+  if CONFIG.load().server.cors.allows_any_origin()
+    && !CONFIG.load().server.cors.supports_credentials
+  {
+    default_headers.add(("Access-Control-Allow-Origin", "*"))
+  } else {
+    default_headers
+  }
+}
+
+// Shared by every listener
+fn configure_extractors(cfg: &mut web::ServiceConfig) {
+  cfg
+    .app_data(create_json_config())
+    .app_data(web::QueryConfig::default().error_handler(reject_invalid_req))
+    .app_data(web::PathConfig::default().error_handler(reject_invalid_req));
+}
+
+// The metrics and the scrape targets list every node, so they need a read-only
+// api key, which scrapers send as the password of basic credentials
+fn configure_health_routes(cfg: &mut web::ServiceConfig, is_local: bool) {
+  cfg
+    .route("/$health", web::get().to(health))
+    .route("/$version", web::get().to(get_version))
+    .route("/$live", web::get().to(live))
+    .route("/$ready", web::get().to(ready))
+    .service(
+      web::resource("/$metrics")
+        .wrap_fn(move |req, srv| authorize_admin_req(req, srv, is_local))
+        .route(web::get().to(get_metrics)),
+    )
+    .service(
+      web::resource("/$prometheus-sd")
+        .wrap_fn(move |req, srv| authorize_admin_req(req, srv, is_local))
+        .route(web::get().to(get_prometheus_sd)),
+    );
+}
+
+// Rejects the reqs the api keys do not allow, but the local ones, as access to
+// the unix socket is left to the permissions of the socket file
+fn authorize_admin_req<S: Service<ServiceRequest, Response = ServiceResponse, Error = Error>>(
+  req: ServiceRequest, srv: &S, is_local: bool,
+) -> future::Either<future::Ready<Result<ServiceResponse, Error>>, S::Future> {
+  if !is_local {
+    if let Err(err) = authorize(req.request()) {
+      log::warn!("Rejected admin req: path: {:?}, err: {}", req.path(), err);
+      let rep = build_rejection(&err);
+      return future::Either::Left(future::ok(req.into_response(rep)));
+    }
+  }
+  future::Either::Right(srv.call(req))
+}
+
+// Scoped, so that the routes are matched on the decoded path, as is the auth,
+// which no encoding of the path escapes then
+fn configure_admin_routes(cfg: &mut web::ServiceConfig, is_local: bool) {
+  cfg.service(
+    web::scope("/$admin")
+      // Innermost, so that rejections are still audited
+      .wrap_fn(move |req, srv| authorize_admin_req(req, srv, is_local))
+      .wrap_fn(|req, srv| {
+        let fut = srv.call(req);
+        async move {
+          let res = fut.await?;
+          audit_admin_req(&res);
+          Ok(res)
+        }
+      })
+      .route("/reassign-topic", web::post().to(reassign_topic))
+      .route("/topic-pins", web::get().to(get_topic_pins))
+      .route("/topic-pins", web::post().to(pin_topic))
+      .route("/topic-pins", web::delete().to(unpin_topic))
+      .route("/topic-stats", web::get().to(get_topic_stats))
+      .route("/topic-namespaces", web::get().to(get_topic_namespaces))
+      .route("/topic-namespaces", web::post().to(set_topic_namespace))
+      .route("/topic-namespaces", web::delete().to(remove_topic_namespace))
+      .route("/export-topics", web::get().to(export_topics))
+      .route("/match-route", web::get().to(match_route))
+      .route("/route-history", web::get().to(get_route_history))
+      .route("/rollback-routes", web::post().to(rollback_routes))
+      .route("/route-owners", web::get().to(get_route_owners))
+      .route("/transfer-route", web::post().to(transfer_route))
+      .route("/service-weight", web::post().to(set_service_weight))
+      .route("/routes", web::get().to(get_admin_routes))
+      .route("/route-health", web::get().to(get_route_health))
+      .route("/nodes", web::get().to(get_nodes))
+      .route("/topics", web::get().to(get_topics))
+      .route("/routes/{service_id}", web::delete().to(remove_admin_routes))
+      .route("/dashboard", web::get().to(get_dashboard))
+      .route("/connections", web::get().to(get_connections))
+      .route("/connections/{id}", web::delete().to(close_connection))
+      .route("/reload-config", web::post().to(reload_config))
+      .route("/standby", web::get().to(get_standby))
+      .route("/promote", web::post().to(promote))
+      .route("/backup", web::get().to(download_backup))
+      .route("/backup", web::post().to(backup))
+      .route("/db-stats", web::get().to(get_db_stats))
+      .route("/compact-db", web::post().to(compact_db))
+      .route("/audit", web::get().to(get_audit_records))
+      .route("/settings", web::get().to(get_settings))
+      .route("/settings", web::post().to(set_setting))
+      .route("/settings", web::delete().to(remove_setting))
+      .route("/topology", web::get().to(get_topology))
+      .route("/events", web::get().to(get_events))
+      .route("/profile/cpu", web::get().to(get_cpu_profile))
+      .route("/profile/heap", web::get().to(get_heap_stats))
+      .service(
+        web::resource("/import-topics")
+          .app_data(create_json_config().limit(CONFIG.load().server.max_frame_size))
+          .route(web::post().to(import_topics)),
+      )
+      .route("/export-routes", web::get().to(export_routes))
+      .service(
+        web::resource("/import-routes")
+          .app_data(create_json_config().limit(CONFIG.load().server.max_frame_size))
+          .route(web::post().to(import_routes)),
+      ),
+  );
+}
+
+// Served by tonic on the runtime of the actix system, the handlers leave the db
+// to the db pool as the http ones do
+async fn start_grpc_server() -> Result<GrpcServer> {
+  let listener =
+    TcpListener::bind(format!("{}:{}", "0.0.0.0", CONFIG.load().server.grpc_port)).await?;
+  let addr = listener.local_addr()?;
+  let incoming = TcpIncoming::from_listener(listener, true, None)
+    .map_err(|err| anyhow!("Failed to listen for grpc: err: {:?}", err))?;
+  let (shutdown_sender, shutdown_receiver) = oneshot::channel();
+  let task = rt::spawn(
+    tonic::transport::Server::builder()
+      .add_service(MasterGrpcServer::new(GrpcHandler))
+      .serve_with_incoming_shutdown(incoming, async {
+        let _ = shutdown_receiver.await;
+      }),
+  );
+  log::info!("Serving grpc on: {:?}", addr);
+  Ok(GrpcServer { addr, shutdown_sender, task })
+}
+
+fn create_http_server(is_https: bool) -> Result<(Server, Vec<SocketAddr>)> {
+  let mut http_server = HttpServer::new(move || {
+    App::new()
+      .wrap_fn(|req, srv| {
+        let fut = srv.call(req);
+        async move {
+          let mut res = fut.await?;
+          skip_small_body(&mut res);
+          Ok(res)
+        }
+      })
+      .wrap(middleware::Condition::new(
+        CONFIG.load().server.compression.enabled,
+        middleware::Compress::default(),
+      ))
+      .wrap_fn(|mut req, srv| {
+        filter_accept_encoding(&mut req);
+        srv.call(req)
+      })
+      .wrap(middleware::Logger::default())
+      .wrap(create_cors())
+      .wrap(create_default_headers())
+      // Tags the log lines of the req with the trace id it came with, or a new one,
+      // which is echoed in the rep
+      .wrap_fn(|req, srv| {
+        let trace_id = req
+          .headers()
+          .get(TRACE_ID_HEADER)
+          .and_then(|trace_id| trace_id.to_str().ok())
+          .map_or_else(new_trace_id, str::to_owned);
+        let trace_id_value = HeaderValue::from_str(&trace_id);
+        let received_at = Instant::now();
+        let method = req.method().to_string();
+        let path = req.path().to_owned();
+        let peer_addr = req.peer_addr();
+        // Current until the traced future is created, which keeps it until the rep
+        let _span_guard = telemetry::enter_http_span(
+          req.headers(),
+          vec![
+            KeyValue::new("http.method", method.clone()),
+            KeyValue::new("http.path", path.clone()),
+            KeyValue::new("trace_id", trace_id.clone()),
+          ],
+        );
+        let fut = {
+          let _scope = TraceScope::enter(&trace_id);
+          srv.call(req)
+        };
+        traced(trace_id, async move {
+          let mut res = fut.await?;
+          slow_log::check_http(&method, &path, peer_addr, received_at.elapsed());
+          telemetry::set_attribute("http.status_code", res.status().as_u16() as i64);
+          if let Ok(trace_id_value) = trace_id_value {
+            res.headers_mut().insert(HeaderName::from_static(TRACE_ID_HEADER), trace_id_value);
+          }
+          Ok(res)
+        })
+      })
+      .configure(configure_extractors)
+      .configure(|cfg| configure_health_routes(cfg, false))
+      .configure(|cfg| configure_admin_routes(cfg, false))
+      .route("/.well-known/acme-challenge/{token}", web::get().to(get_acme_challenge))
+      .route("/$ws", web::get().to(ws))
+      .route("/$peer/snapshot", web::get().to(prepare_snapshot))
+      .route("/$peer/snapshot/{id}", web::get().to(get_snapshot_manifest))
+      .route("/$peer/snapshot/{id}/{index}", web::get().to(get_snapshot_chunk))
+      .route("/$peer/raft/vote", web::post().to(raft_vote))
+      // As the snapshots of the state are appended as well
+      .service(
+        web::resource("/$peer/raft/append")
+          .app_data(create_json_config().limit(CONFIG.load().server.max_frame_size))
+          .route(web::post().to(raft_append)),
+      )
+      .route("/$pick-frontend", web::get().to(pick_frontend))
+      .route("/$pick-frontends", web::get().to(pick_frontends))
+      .route("/$get-routes", web::get().to(get_routes))
+      .route("/$get-routes-delta", web::get().to(get_routes_delta))
+      .route("/$register-service", web::post().to(register_service))
+      .route("/$set-routes", web::post().to(set_routes))
+      .route("/$heartbeat", web::post().to(heartbeat))
+      .route("/$route-dist-checksum", web::get().to(get_route_dist_checksum))
+      .route("/$topic-dist-checksum", web::get().to(get_topic_dist_checksum))
+      .route("/$resolve-ip", web::get().to(resolve_ip))
+      .route("/$locate-topic", web::get().to(locate_topic))
+      .route("/$locate-topics", web::post().to(locate_topics))
+      .route("/$topic-dist", web::get().to(get_topic_dist))
+      .default_service(web::to(not_found))
+  })
+  .backlog(CONFIG.load().server.backlog)
+  .keep_alive(CONFIG.load().server.keep_alive)
+  .max_connection_rate(CONFIG.load().server.max_connection_rate)
+  .max_connections(CONFIG.load().server.max_connections);
+  // Otherwise one per cpu
+  if CONFIG.load().server.workers > 0 {
+    http_server = http_server.workers(CONFIG.load().server.workers);
+  }
+
+  let http_server = if is_https {
+    http_server.on_connect(client_cert::on_connect).bind_rustls_0_23(
+      format!("{}:{}", "0.0.0.0", CONFIG.load().server.https_port),
+      create_tls_config()?,
+    )?
+  } else {
+    http_server.bind(format!("{}:{}", "0.0.0.0", CONFIG.load().server.http_port))?
+  };
+  let addrs = http_server.addrs();
+  Ok((http_server.run(), addrs))
+}
+
+// Only the health and admin endpoints, for local tooling, without tls nor api
+// keys, access is left to the permissions of the socket file
+#[cfg(unix)]
+fn create_uds_server() -> Result<Server> {
+  use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+  let config = CONFIG.load();
+  let path = &config.server.unix_socket_path;
+  // Left by a previous run, which would fail the bind
+  if std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+    std::fs::remove_file(path)?;
+  }
+  let uds_server = HttpServer::new(|| {
+    App::new()
+      .wrap(middleware::Logger::default())
+      .configure(configure_extractors)
+      .configure(|cfg| configure_health_routes(cfg, true))
+      .configure(|cfg| configure_admin_routes(cfg, true))
+      .default_service(web::to(not_found))
+  })
+  .workers(1)
+  .bind_uds(path)
+  .with_context(|| format!("Failed to bind unix socket: {:?}", path))?;
+  std::fs::set_permissions(path, std::fs::Permissions::from_mode(config.server.unix_socket_mode))?;
+  Ok(uds_server.run())
+}
+
+fn create_tls_config() -> Result<ServerConfig> {
+  let config = CONFIG.load();
+  let builder = ServerConfig::builder();
+  let builder = if config.server.client_ca_file.is_empty() {
+    if config.server.require_client_cert {
+      return Err(anyhow!("require_client_cert needs a client_ca_file"));
+    }
+    builder.with_no_client_auth()
+  } else {
+    let ca_file = File::open(config.server.client_ca_file.clone())?;
+    let mut roots = RootCertStore::empty();
+    for ca_cert in certs(&mut BufReader::new(ca_file)) {
+      roots.add(ca_cert?)?;
+    }
+    let verifier = WebPkiClientVerifier::builder(Arc::new(roots));
+    let verifier = if config.server.require_client_cert {
+      verifier.build()?
+    } else {
+      verifier.allow_unauthenticated().build()?
+    };
+    builder.with_client_cert_verifier(verifier)
+  };
+
+  // Handshakes fail until the first certificate is issued
+  if config.acme.enabled {
+    return Ok(builder.with_cert_resolver(acme::CERT_RESOLVER.clone()));
+  }
+
+  if config.server.cert_file.is_empty() || config.server.key_file.is_empty() {
+    return Err(anyhow!("enable_https needs a cert_file and a key_file"));
+  }
+  let cert_file = File::open(&config.server.cert_file)
+    .with_context(|| format!("Failed to open cert_file: {:?}", config.server.cert_file))?;
+  let key_file = File::open(&config.server.key_file)
+    .with_context(|| format!("Failed to open key_file: {:?}", config.server.key_file))?;
+
+  let cert_buf = &mut BufReader::new(cert_file);
+  let key_buf = &mut BufReader::new(key_file);
+
+  let cert_chain = certs(cert_buf).collect::<Result<Vec<_>, _>>()?;
+  let key = private_key(key_buf)?.ok_or(anyhow!("no key found"))?;
+
+  Ok(builder.with_single_cert(cert_chain, key)?)
+}
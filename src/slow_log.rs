@@ -0,0 +1,63 @@
+use std::{fmt, time::Duration};
+
+use crate::{
+  config::{SlowLogConfig, CONFIG},
+  stats::ManagerSizes,
+};
+
+// The sizes are logged to tell a slow handler from a big state
+pub fn check_msg(msg_type: &str, peer: impl fmt::Debug, elapsed: Duration) {
+  let threshold = msg_threshold(&CONFIG.load().slow_log, msg_type);
+  if is_slow(elapsed, threshold) {
+    log::warn!(
+      "Slow msg: msg_type: {:?}, peer: {:?}, duration_ms: {:?}, threshold_ms: {:?}, sizes: {:?}",
+      msg_type,
+      peer,
+      elapsed.as_millis(),
+      threshold,
+      ManagerSizes::current()
+    );
+  }
+}
+
+pub fn check_http(method: &str, path: &str, peer: impl fmt::Debug, elapsed: Duration) {
+  let threshold = CONFIG.load().slow_log.http_threshold;
+  if is_slow(elapsed, threshold) {
+    log::warn!(
+      "Slow http req: method: {:?}, path: {:?}, peer: {:?}, duration_ms: {:?}, threshold_ms: {:?}, sizes: {:?}",
+      method,
+      path,
+      peer,
+      elapsed.as_millis(),
+      threshold,
+      ManagerSizes::current()
+    );
+  }
+}
+
+#[inline]
+fn msg_threshold(config: &SlowLogConfig, msg_type: &str) -> u64 {
+  config.msg_thresholds.get(msg_type).copied().unwrap_or(config.msg_threshold)
+}
+
+#[inline]
+fn is_slow(elapsed: Duration, threshold: u64) -> bool {
+  threshold > 0 && elapsed >= Duration::from_millis(threshold)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_threshold() {
+    let mut config = SlowLogConfig::default();
+    config.msg_thresholds.insert("get_routes_req".to_owned(), 20);
+    assert_eq!(msg_threshold(&config, "get_routes_req"), 20);
+    assert_eq!(msg_threshold(&config, "locate_topic_req"), 100);
+
+    assert!(is_slow(Duration::from_millis(20), 20));
+    assert!(!is_slow(Duration::from_millis(19), 20));
+    assert!(!is_slow(Duration::from_secs(60), 0));
+  }
+}
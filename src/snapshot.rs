@@ -0,0 +1,273 @@
+use std::{
+  fmt, fs,
+  path::{Path, PathBuf},
+  sync::Arc,
+  time::Duration,
+};
+
+use ahash::RandomState as AHasher;
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::Bytes;
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+
+use crate::{
+  clock,
+  config::CONFIG,
+  standby::{self, StateSnapshot},
+};
+
+// Lowercase, as header names are case insensitive
+pub const PEER_TOKEN_HEADER: &str = "x-peer-token";
+
+// Attempts of each chunk before the transfer is given up, which can then be
+// resumed from the chunks already received
+const CHUNK_ATTEMPTS: u32 = 3;
+
+// A master joining or recovering catches up from a snapshot of the state of the
+// primary. It is taken from the managers rather than as a checkpoint of the db,
+// so that it works with the memory engine too, and is serialized once, then
+// kept for snapshot_ttl, so that an interrupted transfer can resume from the
+// chunks already received, each verified against its checksum.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+  pub id: String,
+  pub size: u64,
+  pub chunk_size: u64,
+  // Of the whole snapshot
+  pub checksum: u32,
+  pub chunk_checksums: Vec<u32>,
+}
+
+impl SnapshotManifest {
+  fn of(bytes: &[u8], chunk_size: usize, prepared_at: i64) -> Self {
+    let checksum = crc32fast::hash(bytes);
+    SnapshotManifest {
+      id: format!("{}-{:08x}", prepared_at, checksum),
+      size: bytes.len() as u64,
+      chunk_size: chunk_size as u64,
+      checksum,
+      chunk_checksums: bytes.chunks(chunk_size).map(crc32fast::hash).collect(),
+    }
+  }
+
+  #[inline]
+  pub fn chunk_count(&self) -> u32 {
+    self.chunk_checksums.len() as u32
+  }
+
+  #[inline]
+  fn is_valid_chunk(&self, index: u32, chunk: &[u8]) -> bool {
+    self.chunk_checksums.get(index as usize) == Some(&crc32fast::hash(chunk))
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapshotError {
+  Expired(String),
+  ChunkOutOfRange { id: String, index: u32 },
+}
+
+impl fmt::Display for SnapshotError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SnapshotError::Expired(id) => write!(f, "The snapshot expired or never existed: id: {}", id),
+      SnapshotError::ChunkOutOfRange { id, index } => {
+        write!(f, "The chunk is out of range: id: {}, index: {}", id, index)
+      }
+    }
+  }
+}
+
+impl std::error::Error for SnapshotError {}
+
+struct PreparedSnapshot {
+  manifest: SnapshotManifest,
+  bytes: Bytes,
+  prepared_at: u32,
+}
+
+static PREPARED: Lazy<DashMap<String, Arc<PreparedSnapshot>, AHasher>> =
+  Lazy::new(|| DashMap::with_hasher(AHasher::new()));
+
+// A new one each time, as the peer applies the deltas it subscribed to before
+// asking, which an older snapshot would not be followed by
+pub fn prepare() -> Result<SnapshotManifest> {
+  let now = clock::now();
+  PREPARED.retain(|_, prepared| !is_expired(prepared, now));
+
+  let bytes = Bytes::from(serde_json::to_vec(&standby::snapshot())?);
+  let manifest = SnapshotManifest::of(
+    &bytes,
+    CONFIG.load().cluster.snapshot_chunk_size.max(1),
+    clock::now_millis(),
+  );
+  log::info!(
+    "Prepared snapshot: id: {:?}, size: {:?}, chunks: {:?}",
+    manifest.id,
+    manifest.size,
+    manifest.chunk_count()
+  );
+  PREPARED.insert(
+    manifest.id.clone(),
+    Arc::new(PreparedSnapshot { manifest: manifest.clone(), bytes, prepared_at: now }),
+  );
+  Ok(manifest)
+}
+
+// Of a snapshot prepared before, for resuming its transfer
+pub fn manifest(id: &str) -> Result<SnapshotManifest, SnapshotError> {
+  get_prepared(id).map(|prepared| prepared.manifest.clone())
+}
+
+pub fn chunk(id: &str, index: u32) -> Result<Bytes, SnapshotError> {
+  let prepared = get_prepared(id)?;
+  if index >= prepared.manifest.chunk_count() {
+    return Err(SnapshotError::ChunkOutOfRange { id: id.to_owned(), index });
+  }
+  let chunk_size = prepared.manifest.chunk_size as usize;
+  let from = index as usize * chunk_size;
+  Ok(prepared.bytes.slice(from..(from + chunk_size).min(prepared.bytes.len())))
+}
+
+#[inline]
+fn get_prepared(id: &str) -> Result<Arc<PreparedSnapshot>, SnapshotError> {
+  PREPARED
+    .get(id)
+    .map(|prepared| Arc::clone(prepared.value()))
+    .filter(|prepared| !is_expired(prepared, clock::now()))
+    .ok_or_else(|| SnapshotError::Expired(id.to_owned()))
+}
+
+#[inline]
+fn is_expired(prepared: &PreparedSnapshot, now: u32) -> bool {
+  now >= prepared.prepared_at.saturating_add(CONFIG.load().cluster.snapshot_ttl)
+}
+
+// Where the chunks received are kept until the snapshot is complete
+pub fn download_dir() -> PathBuf {
+  PathBuf::from(format!("{}.snapshot", CONFIG.load().db.path))
+}
+
+// Downloads a snapshot from the url of the snapshots of a peer, the chunks are
+// kept in dir, so that with resume, the snapshot downloaded last is continued if
+// the peer still has it
+pub async fn fetch(url: &str, dir: &Path, resume: bool) -> Result<StateSnapshot> {
+  let client = awc::Client::builder().timeout(Duration::from_secs(60)).finish();
+  let url = url.trim_end_matches('/');
+  let manifest_path = dir.join("manifest.json");
+
+  let mut manifest = None;
+  if resume {
+    if let Some(prev) = read_manifest(&manifest_path) {
+      match get(&client, &format!("{}/{}", url, prev.id)).await {
+        Ok(bytes)
+          if serde_json::from_slice::<SnapshotManifest>(&bytes).ok() == Some(prev.clone()) =>
+        {
+          log::info!("Resuming snapshot: id: {:?}", prev.id);
+          manifest = Some(prev);
+        }
+        Ok(_) => log::warn!("Not resuming the changed snapshot: id: {:?}", prev.id),
+        Err(err) => log::warn!("Not resuming snapshot: id: {:?}, err: {:?}", prev.id, err),
+      }
+    }
+  }
+  let manifest = match manifest {
+    Some(manifest) => manifest,
+    None => {
+      let manifest: SnapshotManifest = serde_json::from_slice(&get(&client, url).await?)?;
+      // The chunks of any other snapshot are useless now
+      if dir.exists() {
+        fs::remove_dir_all(dir).with_context(|| format!("Failed to clear: {:?}", dir))?;
+      }
+      fs::create_dir_all(dir).with_context(|| format!("Failed to create: {:?}", dir))?;
+      fs::write(&manifest_path, serde_json::to_vec(&manifest)?)?;
+      manifest
+    }
+  };
+
+  let mut bytes = Vec::with_capacity(manifest.size as usize);
+  for index in 0..manifest.chunk_count() {
+    let chunk_path = dir.join(index.to_string());
+    let chunk = match fs::read(&chunk_path) {
+      Ok(chunk) if manifest.is_valid_chunk(index, &chunk) => chunk,
+      _ => {
+        let chunk = fetch_chunk(&client, url, &manifest, index).await?;
+        fs::write(&chunk_path, &chunk)?;
+        chunk
+      }
+    };
+    bytes.extend_from_slice(&chunk);
+  }
+  if crc32fast::hash(&bytes) != manifest.checksum {
+    fs::remove_dir_all(dir)?;
+    bail!("The snapshot does not match its checksum: id: {}", manifest.id);
+  }
+  let snapshot = serde_json::from_slice(&bytes)?;
+  fs::remove_dir_all(dir)?;
+  log::info!("Fetched snapshot: id: {:?}, size: {:?}", manifest.id, manifest.size);
+  Ok(snapshot)
+}
+
+async fn fetch_chunk(
+  client: &awc::Client, url: &str, manifest: &SnapshotManifest, index: u32,
+) -> Result<Vec<u8>> {
+  let chunk_url = format!("{}/{}/{}", url, manifest.id, index);
+  let mut attempt = 1;
+  loop {
+    match get(client, &chunk_url).await {
+      Ok(chunk) if manifest.is_valid_chunk(index, &chunk) => return Ok(chunk.to_vec()),
+      Ok(_) => log::warn!("The chunk does not match its checksum: url: {:?}", chunk_url),
+      Err(err) => log::warn!("Failed to fetch chunk: url: {:?}, err: {:?}", chunk_url, err),
+    }
+    if attempt >= CHUNK_ATTEMPTS {
+      bail!("Failed to fetch chunk: url: {}, attempts: {}", chunk_url, attempt);
+    }
+    attempt += 1;
+  }
+}
+
+async fn get(client: &awc::Client, url: &str) -> Result<Bytes> {
+  let mut rep = client
+    .get(url)
+    .insert_header((PEER_TOKEN_HEADER, CONFIG.load().cluster.peer_token.as_str()))
+    .send()
+    .await
+    .map_err(|err| anyhow!("Failed to send req: err: {:?}", err))?;
+  let body = rep
+    .body()
+    .limit(CONFIG.load().cluster.snapshot_chunk_size.max(usize::from(u16::MAX)) * 2)
+    .await
+    .map_err(|err| anyhow!("Failed to receive body: err: {:?}", err))?;
+  if !rep.status().is_success() {
+    bail!(
+      "Rejected by the peer: status: {}, body: {}",
+      rep.status(),
+      String::from_utf8_lossy(&body)
+    );
+  }
+  Ok(body)
+}
+
+#[inline]
+fn read_manifest(path: &Path) -> Option<SnapshotManifest> {
+  fs::read(path).ok().and_then(|bytes| serde_json::from_slice(&bytes).ok())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_manifest() {
+    let bytes = b"0123456789";
+    let manifest = SnapshotManifest::of(bytes, 4, 1_000);
+    assert_eq!(manifest.chunk_count(), 3);
+    assert_eq!(manifest.size, 10);
+    assert!(manifest.id.starts_with("1000-"));
+    assert!(manifest.is_valid_chunk(0, b"0123"));
+    assert!(manifest.is_valid_chunk(2, b"89"));
+    assert!(!manifest.is_valid_chunk(1, b"4567x"));
+    assert!(!manifest.is_valid_chunk(3, b""));
+  }
+}
@@ -0,0 +1,421 @@
+use std::{
+  collections::{HashMap, HashSet},
+  sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+  time::Duration,
+};
+
+use anyhow::{anyhow, bail, Result};
+use awc::ws;
+use futures::{SinkExt, StreamExt};
+use once_cell::sync::Lazy;
+use serde_json::json;
+use tokio::sync::Notify;
+
+use crate::{
+  clock,
+  config::CONFIG,
+  db_pool::DB_POOL,
+  event_bus::Event,
+  handler::protocol_version::{Feature, PROTOCOL_VERSION},
+  node_mgr::{NodeId, Service, SERVICE_MGR},
+  route_mgr::{PathBundle, PathRateLimit, ROUTE_MGR},
+  snapshot,
+  topic_mgr::{Topic, TopicChange, TOPIC_MGR},
+};
+
+// A standby streams the services, routes and topics of the primary into its
+// own managers, so that it can take over with them once promoted. The pins and
+// namespaces of topics are config, and the activity of the services is not
+// streamed, the services are given a full lease on promotion instead.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceState {
+  pub service: Service,
+  pub tenant: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteGroupState {
+  pub service_id: NodeId,
+  pub paths: PathBundle,
+  pub weight: u32,
+  // Missing from the primaries older than the rate limits
+  #[serde(default)]
+  pub rate_limits: Vec<PathRateLimit>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateSnapshot {
+  pub services: Vec<ServiceState>,
+  pub routes: Vec<RouteGroupState>,
+  pub topics: Vec<(Topic, NodeId)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StateDelta {
+  ServiceSet { state: ServiceState },
+  ServiceRemoved { service_id: NodeId },
+  // Every route group of the tenant, the others of the tenant are removed
+  RoutesSet { tenant: String, routes: Vec<RouteGroupState> },
+  // backend_id is none if the topic was deleted
+  TopicSet { topic: Topic, backend_id: Option<NodeId> },
+}
+
+impl StateDelta {
+  // The changes of the topics come from the topic mgr instead, as the new ones
+  // are not published on the event bus
+  pub fn of_event(event: &Event) -> Option<StateDelta> {
+    match event {
+      Event::NodeAdded { node_type: "service", node_id } => {
+        service_state(node_id).map(|state| StateDelta::ServiceSet { state })
+      }
+      Event::NodeRemoved { node_type: "service", node_id } => {
+        Some(StateDelta::ServiceRemoved { service_id: node_id.clone() })
+      }
+      Event::RoutesChanged { tenant, .. } => Some(StateDelta::RoutesSet {
+        tenant: tenant.clone(),
+        routes: route_group_states(|service_id| &ROUTE_MGR.tenant_of(service_id) == tenant),
+      }),
+      _ => None,
+    }
+  }
+
+  #[inline]
+  pub fn of_topic_change(change: TopicChange) -> StateDelta {
+    StateDelta::TopicSet { topic: change.topic, backend_id: change.backend_id }
+  }
+}
+
+#[derive(Debug, Serialize)]
+pub struct StandbyStatus {
+  pub is_standby: bool,
+  pub primary_url: String,
+  pub is_connected: bool,
+  // When the last snapshot or delta was applied, 0 if none was
+  pub synced_at: u32,
+  pub applied_count: u64,
+}
+
+// What a standby expects from the primary, the other msgs are ignored
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum PrimaryMsg {
+  PeerHelloRep {
+    master_id: String,
+  },
+  // None if the snapshot is fetched from snapshot_url
+  WatchStateRep {
+    #[serde(default)]
+    snapshot: Option<StateSnapshot>,
+  },
+  StateSnapshotMsg {
+    snapshot: StateSnapshot,
+  },
+  StateDeltaMsg {
+    delta: StateDelta,
+  },
+  ErrorRep {
+    code: i32,
+    desc: String,
+  },
+  #[serde(other)]
+  Other,
+}
+
+static IS_STANDBY: Lazy<AtomicBool> =
+  Lazy::new(|| AtomicBool::new(!CONFIG.load().cluster.primary_url.is_empty()));
+static IS_CONNECTED: AtomicBool = AtomicBool::new(false);
+static SYNCED_AT: AtomicU32 = AtomicU32::new(0);
+static APPLIED_COUNT: AtomicU64 = AtomicU64::new(0);
+static PROMOTED: Lazy<Notify> = Lazy::new(Notify::new);
+
+// While standby, the nodes are not expected to connect, so the services are
+// neither swept nor the topics collected, which the primary does
+#[inline]
+pub fn is_standby() -> bool {
+  IS_STANDBY.load(Ordering::Acquire)
+}
+
+pub fn status() -> StandbyStatus {
+  StandbyStatus {
+    is_standby: is_standby(),
+    primary_url: CONFIG.load().cluster.primary_url.clone(),
+    is_connected: IS_CONNECTED.load(Ordering::Relaxed),
+    synced_at: SYNCED_AT.load(Ordering::Relaxed),
+    applied_count: APPLIED_COUNT.load(Ordering::Relaxed),
+  }
+}
+
+// Stops streaming from the primary, returns false if this master was not a
+// standby
+pub fn promote() -> bool {
+  if !IS_STANDBY.swap(false, Ordering::AcqRel) {
+    return false;
+  }
+  PROMOTED.notify_one();
+  // As if they had just pinged, so that they have the unhealthy threshold to
+  // reconnect to this master
+  let service_ids: Vec<NodeId> = SERVICE_MGR.iter().map(|service| service.key().clone()).collect();
+  for service_id in &service_ids {
+    SERVICE_MGR.activate(service_id);
+  }
+  log::info!("Promoted to primary: services: {:?}", service_ids.len());
+  true
+}
+
+pub fn snapshot() -> StateSnapshot {
+  StateSnapshot {
+    services: SERVICE_MGR
+      .iter()
+      .map(|service| ServiceState {
+        service: service.value().clone(),
+        tenant: ROUTE_MGR.tenant_of(service.key()),
+      })
+      .collect(),
+    routes: route_group_states(|_| true),
+    topics: TOPIC_MGR.dump(),
+  }
+}
+
+#[inline]
+fn service_state(service_id: &NodeId) -> Option<ServiceState> {
+  SERVICE_MGR.get(service_id).map(|service| ServiceState {
+    service: service.value().clone(),
+    tenant: ROUTE_MGR.tenant_of(service_id),
+  })
+}
+
+fn route_group_states(filter: impl Fn(&NodeId) -> bool) -> Vec<RouteGroupState> {
+  ROUTE_MGR
+    .reverse_route_group_iter()
+    .filter(|group| filter(group.key()))
+    .map(|group| RouteGroupState {
+      service_id: group.key().clone(),
+      paths: group.value().clone(),
+      weight: ROUTE_MGR.weight(group.key()),
+      rate_limits: ROUTE_MGR.rate_limits(group.key()),
+    })
+    .collect()
+}
+
+pub fn apply_snapshot(snapshot: StateSnapshot) -> Result<()> {
+  let service_ids: HashSet<&NodeId> =
+    snapshot.services.iter().map(|state| &state.service.id).collect();
+  let gone_ids: Vec<NodeId> = SERVICE_MGR
+    .iter()
+    .map(|service| service.key().clone())
+    .filter(|service_id| !service_ids.contains(service_id))
+    .collect();
+  for service_id in &gone_ids {
+    remove_service(service_id);
+  }
+  for state in snapshot.services {
+    apply_service(state)?;
+  }
+
+  apply_routes(snapshot.routes, |_| true)?;
+
+  let topics: HashMap<Topic, NodeId> = snapshot.topics.into_iter().collect();
+  for (topic, _) in TOPIC_MGR.dump() {
+    if !topics.contains_key(&topic) {
+      TOPIC_MGR.apply_remove(&topic)?;
+    }
+  }
+  for (topic, backend_id) in topics {
+    TOPIC_MGR.apply_reassign(topic, backend_id)?;
+  }
+  Ok(())
+}
+
+pub fn apply_delta(delta: StateDelta) -> Result<()> {
+  match delta {
+    StateDelta::ServiceSet { state } => apply_service(state),
+    StateDelta::ServiceRemoved { service_id } => {
+      remove_service(&service_id);
+      Ok(())
+    }
+    StateDelta::RoutesSet { tenant, routes } => {
+      apply_routes(routes, |service_id| ROUTE_MGR.tenant_of(service_id) == tenant)
+    }
+    StateDelta::TopicSet { topic, backend_id: Some(backend_id) } => {
+      TOPIC_MGR.apply_reassign(topic, backend_id)?;
+      Ok(())
+    }
+    StateDelta::TopicSet { topic, backend_id: None } => {
+      TOPIC_MGR.apply_remove(&topic)?;
+      Ok(())
+    }
+  }
+}
+
+#[inline]
+fn apply_service(state: ServiceState) -> Result<()> {
+  let service = state.service;
+  ROUTE_MGR.apply_tenant(&service.id, &state.tenant)?;
+  SERVICE_MGR.apply_add(Service::new(service.id, service.private_ip, service.http_port))
+}
+
+#[inline]
+fn remove_service(service_id: &NodeId) {
+  ROUTE_MGR.apply_remove_reverse_route_group(service_id);
+  SERVICE_MGR.apply_remove(service_id);
+}
+
+// The route groups of the services replaced but not in the routes are removed
+fn apply_routes(routes: Vec<RouteGroupState>, is_replaced: impl Fn(&NodeId) -> bool) -> Result<()> {
+  let service_ids: HashSet<&NodeId> = routes.iter().map(|route| &route.service_id).collect();
+  let gone_ids: Vec<NodeId> = ROUTE_MGR
+    .reverse_route_group_iter()
+    .map(|group| group.key().clone())
+    .filter(|service_id| !service_ids.contains(service_id) && is_replaced(service_id))
+    .collect();
+  for service_id in &gone_ids {
+    ROUTE_MGR.apply_remove_reverse_route_group(service_id);
+  }
+  for route in routes {
+    if ROUTE_MGR.weight(&route.service_id) != route.weight {
+      ROUTE_MGR.apply_weight(&route.service_id, route.weight)?;
+    }
+    ROUTE_MGR.apply_rate_limits(&route.service_id, route.rate_limits)?;
+    ROUTE_MGR.apply_reverse_route_group(route.service_id, route.paths)?;
+  }
+  Ok(())
+}
+
+// Streams the state of the primary until promoted, reconnecting as it is lost
+pub fn spawn_sync_task() {
+  if !is_standby() {
+    return;
+  }
+  actix_web::rt::spawn(async {
+    let config = CONFIG.load();
+    let url = &config.cluster.primary_url;
+    while is_standby() {
+      if let Err(err) = sync(url).await {
+        log::warn!("Lost the primary: url: {:?}, err: {:?}", url, err);
+      }
+      IS_CONNECTED.store(false, Ordering::Relaxed);
+      if !is_standby() {
+        break;
+      }
+      tokio::time::sleep(Duration::from_secs(CONFIG.load().cluster.standby_retry_interval.max(1)))
+        .await;
+    }
+    log::info!("Stopped streaming from the primary: url: {:?}", url);
+  });
+}
+
+async fn sync(url: &str) -> Result<()> {
+  let config = CONFIG.load();
+  let (_, mut framed) = awc::Client::new()
+    .ws(url)
+    .max_frame_size(config.server.max_frame_size)
+    .connect()
+    .await
+    .map_err(|err| anyhow!("Failed to connect: err: {:?}", err))?;
+  // For the state_sync feature, which watch_state_req needs
+  let negotiate_req = json!({
+    "type": "negotiate_req",
+    "version": PROTOCOL_VERSION,
+    "features": [Feature::StateSync],
+    "ref": 0,
+  });
+  framed.send(ws::Message::Text(negotiate_req.to_string().into())).await?;
+  let hello_req = json!({
+    "type": "peer_hello_req",
+    "master_id": config.cluster.master_id,
+    "token": config.cluster.peer_token,
+    "ref": 1,
+  });
+  framed.send(ws::Message::Text(hello_req.to_string().into())).await?;
+  let snapshot_url = &config.cluster.snapshot_url;
+  let watch_req =
+    json!({ "type": "watch_state_req", "skip_snapshot": !snapshot_url.is_empty(), "ref": 2 });
+  framed.send(ws::Message::Text(watch_req.to_string().into())).await?;
+
+  loop {
+    let frame = tokio::select! {
+      frame = framed.next() => frame,
+      _ = PROMOTED.notified() => return Ok(()),
+    };
+    let bytes = match frame {
+      Some(Ok(ws::Frame::Text(bytes))) => bytes,
+      Some(Ok(ws::Frame::Ping(bytes))) => {
+        framed.send(ws::Message::Pong(bytes)).await?;
+        continue;
+      }
+      Some(Ok(ws::Frame::Close(reason))) => bail!("Closed by the primary: {:?}", reason),
+      Some(Ok(_)) => continue,
+      Some(Err(err)) => bail!("Failed to receive msg: {:?}", err),
+      None => bail!("Closed by the primary"),
+    };
+    if !is_standby() {
+      return Ok(());
+    }
+    match serde_json::from_slice::<PrimaryMsg>(&bytes)? {
+      PrimaryMsg::PeerHelloRep { master_id } => {
+        log::info!("Connected to the primary: url: {:?}, master_id: {:?}", url, master_id);
+        IS_CONNECTED.store(true, Ordering::Relaxed);
+      }
+      PrimaryMsg::WatchStateRep { snapshot: Some(snapshot) }
+      | PrimaryMsg::StateSnapshotMsg { snapshot } => apply_received_snapshot(snapshot).await?,
+      PrimaryMsg::WatchStateRep { snapshot: None } => {
+        // Only now, so that the deltas pushed since the subscription follow it,
+        // which are read once it is applied
+        let snapshot = snapshot::fetch(snapshot_url, &snapshot::download_dir(), false).await?;
+        apply_received_snapshot(snapshot).await?
+      }
+      PrimaryMsg::StateDeltaMsg { delta } => {
+        log::debug!("Applying delta: {:?}", delta);
+        DB_POOL.run(move || apply_delta(delta)).await??;
+        on_applied();
+      }
+      PrimaryMsg::ErrorRep { code, desc } => {
+        bail!("Rejected by the primary: code: {:?}, desc: {:?}", code, desc)
+      }
+      PrimaryMsg::Other => {}
+    }
+  }
+}
+
+async fn apply_received_snapshot(snapshot: StateSnapshot) -> Result<()> {
+  log::info!(
+    "Applying snapshot: services: {:?}, routes: {:?}, topics: {:?}",
+    snapshot.services.len(),
+    snapshot.routes.len(),
+    snapshot.topics.len()
+  );
+  DB_POOL.run(move || apply_snapshot(snapshot)).await??;
+  on_applied();
+  Ok(())
+}
+
+#[inline]
+fn on_applied() {
+  SYNCED_AT.store(clock::now(), Ordering::Relaxed);
+  APPLIED_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_primary_msg() {
+    let delta = StateDelta::TopicSet { topic: "topic-0".to_owned(), backend_id: None };
+    let msg = json!({ "type": "state_delta_msg", "delta": delta, "trace_id": "abc" });
+    assert!(matches!(
+      serde_json::from_value::<PrimaryMsg>(msg).unwrap(),
+      PrimaryMsg::StateDeltaMsg { delta: StateDelta::TopicSet { backend_id: None, .. } }
+    ));
+
+    let msg = json!({ "type": "error_rep", "code": 1008, "desc": "not a peer", "ref": 1 });
+    assert!(matches!(
+      serde_json::from_value::<PrimaryMsg>(msg).unwrap(),
+      PrimaryMsg::ErrorRep { code: 1008, .. }
+    ));
+
+    let msg = json!({ "type": "report_load_rep", "ref": 3 });
+    assert!(matches!(serde_json::from_value::<PrimaryMsg>(msg).unwrap(), PrimaryMsg::Other));
+  }
+}
@@ -0,0 +1,138 @@
+use std::{collections::BTreeMap, sync::RwLock, time::Duration};
+
+use ahash::RandomState as AHasher;
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+
+use crate::{
+  handler::connection_registry::CONNECTION_REGISTRY,
+  node_mgr::{BACKEND_MGR, FRONTEND_MGR, SERVICE_MGR},
+  route_mgr::ROUTE_MGR,
+  topic_mgr::TOPIC_MGR,
+};
+
+// Seconds the req rates are averaged over
+const RATE_INTERVAL: u64 = 10;
+
+#[derive(Debug, Serialize)]
+pub struct ManagerSizes {
+  pub frontends: usize,
+  pub backends: usize,
+  pub services: usize,
+  pub topics: u64,
+  pub connections: usize,
+}
+
+impl ManagerSizes {
+  #[inline]
+  pub fn current() -> Self {
+    ManagerSizes {
+      frontends: FRONTEND_MGR.count(),
+      backends: BACKEND_MGR.count(),
+      services: SERVICE_MGR.count(),
+      topics: TOPIC_MGR.total_topic_count(),
+      connections: CONNECTION_REGISTRY.count(),
+    }
+  }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Stats {
+  #[serde(flatten)]
+  pub sizes: ManagerSizes,
+  pub topic_dist_version: u32,
+  pub route_version: u32,
+  // Of the routes of the tenant asking
+  pub route_checksum: u32,
+  pub service_version: u32,
+  // Reqs per second by msg type, over the last RATE_INTERVAL
+  pub req_rates: BTreeMap<&'static str, f64>,
+}
+
+impl Stats {
+  pub fn current(tenant: &str) -> Self {
+    Stats {
+      sizes: ManagerSizes::current(),
+      topic_dist_version: TOPIC_MGR.version(),
+      route_version: ROUTE_MGR.version(),
+      route_checksum: ROUTE_MGR.snapshot(tenant).checksum(),
+      service_version: SERVICE_MGR.version(),
+      req_rates: REQ_RATES.rates(),
+    }
+  }
+}
+
+// Counts the reqs by msg type, turned into rates once per interval
+pub struct ReqRates {
+  counts: DashMap<&'static str, u64, AHasher>,
+  rates: RwLock<BTreeMap<&'static str, f64>>,
+}
+
+impl ReqRates {
+  #[inline]
+  fn new() -> Self {
+    ReqRates {
+      counts: DashMap::with_capacity_and_hasher(32, AHasher::default()),
+      rates: RwLock::new(BTreeMap::new()),
+    }
+  }
+
+  #[inline]
+  pub fn record(&self, msg_type: &'static str) {
+    *self.counts.entry(msg_type).or_insert(0) += 1;
+  }
+
+  #[inline]
+  pub fn rates(&self) -> BTreeMap<&'static str, f64> {
+    self.rates.read().unwrap().clone()
+  }
+
+  // The msg types not seen during the interval are dropped
+  fn update(&self, interval: Duration) {
+    let secs = interval.as_secs_f64();
+    let mut rates = BTreeMap::new();
+    self.counts.retain(|msg_type, count| {
+      rates.insert(*msg_type, *count as f64 / secs);
+      false
+    });
+    *self.rates.write().unwrap() = rates;
+  }
+}
+
+pub static REQ_RATES: Lazy<ReqRates> = Lazy::new(ReqRates::new);
+
+pub fn spawn_rate_task() {
+  actix_web::rt::spawn(async {
+    let interval = Duration::from_secs(RATE_INTERVAL);
+    let mut ticker = tokio::time::interval(interval);
+    // The first tick completes at once
+    ticker.tick().await;
+    loop {
+      ticker.tick().await;
+      REQ_RATES.update(interval);
+    }
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_req_rates() {
+    let req_rates = ReqRates::new();
+    for _ in 0..20 {
+      req_rates.record("get_routes_req");
+    }
+    req_rates.record("locate_topic_req");
+    assert!(req_rates.rates().is_empty());
+
+    req_rates.update(Duration::from_secs(10));
+    let rates = req_rates.rates();
+    assert_eq!(rates.get("get_routes_req"), Some(&2.0));
+    assert_eq!(rates.get("locate_topic_req"), Some(&0.1));
+
+    req_rates.update(Duration::from_secs(10));
+    assert!(req_rates.rates().is_empty());
+  }
+}
@@ -0,0 +1,159 @@
+use std::{fmt::Display, time::Duration};
+
+use tokio::net::UdpSocket;
+
+use crate::{
+  config::CONFIG,
+  db_pool::DB_POOL,
+  route_mgr::ROUTE_MGR,
+  stats::{ManagerSizes, REQ_RATES},
+  topic_mgr::TOPIC_MGR,
+};
+
+// So that a packet fits in the mtu of most networks
+const MAX_PACKET_SIZE: usize = 1432;
+
+// Pushes the key metrics to statsd, for the setups which can not scrape
+// /$metrics
+pub fn spawn_export_task() {
+  if CONFIG.load().statsd.address.is_empty() {
+    return;
+  }
+  actix_web::rt::spawn(async {
+    let socket = match UdpSocket::bind("0.0.0.0:0").await {
+      Ok(socket) => socket,
+      Err(err) => {
+        log::error!("Failed to bind statsd socket: {:?}", err);
+        return;
+      }
+    };
+    let mut interval = tokio::time::interval(Duration::from_secs(CONFIG.load().statsd.interval));
+    let address = CONFIG.load().statsd.address.clone();
+    let mut exporter = Exporter::new(&CONFIG.load().statsd.prefix);
+    loop {
+      interval.tick().await;
+      for packet in exporter.collect() {
+        if let Err(err) = socket.send_to(packet.as_bytes(), address.as_str()).await {
+          log::warn!("Failed to send metrics to statsd: address: {:?}, err: {:?}", address, err);
+          break;
+        }
+      }
+    }
+  });
+}
+
+struct Exporter {
+  prefix: String,
+  // The counters are sent as the deltas since the last export
+  cache_hits: u64,
+  cache_misses: u64,
+}
+
+impl Exporter {
+  #[inline]
+  fn new(prefix: &str) -> Self {
+    Exporter {
+      prefix: prefix.to_owned(),
+      cache_hits: TOPIC_MGR.cache_hits(),
+      cache_misses: TOPIC_MGR.cache_misses(),
+    }
+  }
+
+  fn collect(&mut self) -> Vec<String> {
+    let mut writer = PacketWriter::new(&self.prefix);
+    let sizes = ManagerSizes::current();
+    writer.gauge("frontends", sizes.frontends);
+    writer.gauge("backends", sizes.backends);
+    writer.gauge("services", sizes.services);
+    writer.gauge("topics", sizes.topics);
+    writer.gauge("connections", sizes.connections);
+    writer.gauge("topic_dist_version", TOPIC_MGR.version());
+    writer.gauge("route_version", ROUTE_MGR.version());
+    writer.gauge("db_pool_queue_depth", DB_POOL.stats().queue_depth);
+
+    let cache_hits = TOPIC_MGR.cache_hits();
+    writer.counter("topic_cache_hits", cache_hits.saturating_sub(self.cache_hits));
+    self.cache_hits = cache_hits;
+    let cache_misses = TOPIC_MGR.cache_misses();
+    writer.counter("topic_cache_misses", cache_misses.saturating_sub(self.cache_misses));
+    self.cache_misses = cache_misses;
+
+    for (msg_type, rate) in REQ_RATES.rates() {
+      writer.gauge(&format!("req_rate.{}", msg_type), rate);
+    }
+    writer.into_packets()
+  }
+}
+
+// Packs the lines into as few packets as fit
+struct PacketWriter<'a> {
+  prefix: &'a str,
+  packets: Vec<String>,
+  buf: String,
+}
+
+impl<'a> PacketWriter<'a> {
+  #[inline]
+  fn new(prefix: &'a str) -> Self {
+    PacketWriter { prefix, packets: vec![], buf: String::with_capacity(MAX_PACKET_SIZE) }
+  }
+
+  #[inline]
+  fn gauge<V: Display>(&mut self, name: &str, value: V) {
+    self.write(name, value, "g");
+  }
+
+  #[inline]
+  fn counter<V: Display>(&mut self, name: &str, value: V) {
+    self.write(name, value, "c");
+  }
+
+  fn write<V: Display>(&mut self, name: &str, value: V, kind: &str) {
+    let line = if self.prefix.is_empty() {
+      format!("{}:{}|{}", name, value, kind)
+    } else {
+      format!("{}.{}:{}|{}", self.prefix, name, value, kind)
+    };
+    if !self.buf.is_empty() && self.buf.len() + 1 + line.len() > MAX_PACKET_SIZE {
+      self.packets.push(std::mem::take(&mut self.buf));
+    }
+    if !self.buf.is_empty() {
+      self.buf.push('\n');
+    }
+    self.buf.push_str(&line);
+  }
+
+  #[inline]
+  fn into_packets(mut self) -> Vec<String> {
+    if !self.buf.is_empty() {
+      self.packets.push(self.buf);
+    }
+    self.packets
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_packet_writer() {
+    let mut writer = PacketWriter::new("maxwell_master");
+    writer.gauge("topics", 3);
+    writer.counter("topic_cache_hits", 7);
+    assert_eq!(
+      writer.into_packets(),
+      vec!["maxwell_master.topics:3|g\nmaxwell_master.topic_cache_hits:7|c".to_owned()]
+    );
+
+    let mut writer = PacketWriter::new("");
+    for i in 0..200 {
+      writer.gauge(&format!("req_rate.msg_{}", i), 1.5);
+    }
+    let packets = writer.into_packets();
+    assert!(packets.len() > 1);
+    assert!(packets.iter().all(|packet| packet.len() <= MAX_PACKET_SIZE));
+    assert_eq!(packets.iter().map(|packet| packet.lines().count()).sum::<usize>(), 200);
+    assert!(packets[0].starts_with("req_rate.msg_0:1.5|g\n"));
+  }
+}
@@ -0,0 +1,430 @@
+use std::{
+  borrow::Borrow,
+  collections::BTreeMap,
+  marker::PhantomData,
+  ops::Bound,
+  sync::{Arc, Mutex, RwLock},
+};
+
+use anyhow::Result;
+use bytes::Bytes;
+use once_cell::sync::Lazy;
+use seriesdb::{
+  coder::Coder,
+  prelude::Db,
+  table::{NormalTable, Table, TableEnhanced},
+};
+
+use crate::{
+  config::{DbEngine, CONFIG},
+  db::DB,
+  encryption::EncryptedCoder,
+};
+
+// A table of the managers, kept in the db, or in memory for the tests and the
+// deployments which rebuild their state from the nodes on start
+pub(crate) trait Store<K, V>: Send + Sync {
+  fn get(&self, key: &K) -> Result<Option<V>>;
+
+  fn put(&self, key: &K, value: &V) -> Result<()>;
+
+  fn delete(&self, key: &K) -> Result<()>;
+
+  // Visits the entries in the order of their encoded keys, from the key if
+  // any, until the visitor returns false
+  fn scan(&self, from: Option<&K>, visit: &mut dyn FnMut(K, V) -> bool);
+
+  fn clear(&self) -> Result<()>;
+}
+
+impl<K, V, S> Store<K, V> for Arc<S>
+where S: Store<K, V> + ?Sized
+{
+  #[inline]
+  fn get(&self, key: &K) -> Result<Option<V>> {
+    (**self).get(key)
+  }
+
+  #[inline]
+  fn put(&self, key: &K, value: &V) -> Result<()> {
+    (**self).put(key, value)
+  }
+
+  #[inline]
+  fn delete(&self, key: &K) -> Result<()> {
+    (**self).delete(key)
+  }
+
+  #[inline]
+  fn scan(&self, from: Option<&K>, visit: &mut dyn FnMut(K, V) -> bool) {
+    (**self).scan(from, visit)
+  }
+
+  #[inline]
+  fn clear(&self) -> Result<()> {
+    (**self).clear()
+  }
+}
+
+// A table as its encoded entries, so that the tables can be backed up and
+// restored alike, whatever they keep
+pub(crate) trait RawTable: Send + Sync {
+  fn dump(&self) -> Vec<(Bytes, Bytes)>;
+
+  // Replaces all the entries
+  fn load(&self, entries: &[(Bytes, Bytes)]) -> Result<()>;
+
+  // By a scan, as the tables share the key space of the db
+  fn stats(&self) -> TableStats {
+    let entries = self.dump();
+    TableStats {
+      entries: entries.len(),
+      size: entries.iter().map(|(key, value)| (key.len() + value.len()) as u64).sum(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct TableStats {
+  pub entries: usize,
+  // Of the encoded keys and values
+  pub size: u64,
+}
+
+// Every table of the db, which the encryption rewrites once enabled, so that
+// a table must be listed before it is opened
+pub(crate) static TABLE_NAMES: &[&str] = &[
+  "audit.records",
+  "config_mgr.settings",
+  "health.probes",
+  "migration.infos",
+  "node_mgr.service_mgr.infos",
+  "node_mgr.service_mgr.services",
+  "raft.hard_states",
+  "raft.log",
+  "route_mgr.history",
+  "route_mgr.infos",
+  "route_mgr.owners",
+  "route_mgr.rate_limits",
+  "route_mgr.routes",
+  "route_mgr.tenants",
+  "route_mgr.weights",
+  "topic_mgr.infos",
+  "topic_mgr.located_ats",
+  "topic_mgr.namespaces",
+  "topic_mgr.pins",
+  "topic_mgr.topics",
+];
+
+// Every table opened, which the managers all do on start
+static TABLES: Lazy<RwLock<BTreeMap<String, Arc<dyn RawTable>>>> =
+  Lazy::new(|| RwLock::new(BTreeMap::new()));
+
+// Restored into the tables once opened, before their managers read them
+static PENDING_RESTORES: Lazy<Mutex<BTreeMap<String, Vec<(Bytes, Bytes)>>>> =
+  Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+// Opens the table in the engine configured
+pub(crate) fn open_store<K, V, C>(name: &str) -> Result<Box<dyn Store<K, V>>>
+where
+  K: Send + Sync + 'static,
+  V: Send + Sync + 'static,
+  C: Coder<K, V, EncodedKey = Bytes, EncodedValue = Bytes> + Send + Sync + 'static, {
+  debug_assert!(TABLE_NAMES.contains(&name), "Unlisted table: {}", name);
+  let (store, table): (Box<dyn Store<K, V>>, Arc<dyn RawTable>) = match CONFIG.load().db.engine {
+    DbEngine::Seriesdb => {
+      let store = Arc::new(DbStore::<K, V, EncryptedCoder<C>>::open(name)?);
+      (Box::new(Arc::clone(&store)), store)
+    }
+    DbEngine::Memory => {
+      let store = Arc::new(MemoryStore::<K, V, C>::new());
+      (Box::new(Arc::clone(&store)), store)
+    }
+  };
+  restore_pending(name, &*table)?;
+  TABLES.write().unwrap().insert(name.to_owned(), table);
+  Ok(store)
+}
+
+// Opens the table of the db as its entries are stored, encrypted if they are,
+// for the migrations, which run before the managers open the tables. It is not
+// backed up, unlike those.
+pub(crate) fn open_raw_table(name: &str) -> Result<Arc<dyn RawTable>> {
+  let table: Arc<dyn RawTable> = Arc::new(DbStore::<Bytes, Bytes, RawCoder>::open(name)?);
+  restore_pending(name, &*table)?;
+  Ok(table)
+}
+
+#[inline]
+fn restore_pending(name: &str, table: &dyn RawTable) -> Result<()> {
+  if let Some(entries) = PENDING_RESTORES.lock().unwrap().remove(name) {
+    log::info!("Restoring table: name: {:?}, entries: {:?}", name, entries.len());
+    table.load(&entries)?;
+  }
+  Ok(())
+}
+
+pub(crate) struct RawCoder;
+
+impl Coder<Bytes, Bytes> for RawCoder {
+  type EncodedKey = Bytes;
+  type EncodedValue = Bytes;
+
+  #[inline(always)]
+  fn encode_key<K: Borrow<Bytes>>(key: K) -> Self::EncodedKey {
+    key.borrow().clone()
+  }
+
+  #[inline(always)]
+  fn decode_key(key: &[u8]) -> Bytes {
+    Bytes::copy_from_slice(key)
+  }
+
+  #[inline(always)]
+  fn encode_value<V: Borrow<Bytes>>(value: V) -> Self::EncodedValue {
+    value.borrow().clone()
+  }
+
+  #[inline(always)]
+  fn decode_value(value: &[u8]) -> Bytes {
+    Bytes::copy_from_slice(value)
+  }
+}
+
+// The entries of every table opened, by name
+pub(crate) fn dump_tables() -> Vec<(String, Vec<(Bytes, Bytes)>)> {
+  let tables: Vec<(String, Arc<dyn RawTable>)> =
+    TABLES.read().unwrap().iter().map(|(name, table)| (name.clone(), Arc::clone(table))).collect();
+  tables.into_iter().map(|(name, table)| (name, table.dump())).collect()
+}
+
+// Of every table opened, by name
+pub(crate) fn table_stats() -> Vec<(String, TableStats)> {
+  let tables: Vec<(String, Arc<dyn RawTable>)> =
+    TABLES.read().unwrap().iter().map(|(name, table)| (name.clone(), Arc::clone(table))).collect();
+  tables.into_iter().map(|(name, table)| (name, table.stats())).collect()
+}
+
+// The tables not opened yet are restored once opened, the others at once,
+// though their managers would not see it, so this is done before they start
+pub(crate) fn restore_tables(tables: Vec<(String, Vec<(Bytes, Bytes)>)>) -> Result<()> {
+  for (name, entries) in tables {
+    let table = TABLES.read().unwrap().get(&name).cloned();
+    match table {
+      Some(table) => {
+        log::warn!("Restoring the table already opened: name: {:?}", name);
+        table.load(&entries)?;
+      }
+      None => {
+        PENDING_RESTORES.lock().unwrap().insert(name, entries);
+      }
+    }
+  }
+  Ok(())
+}
+
+pub(crate) struct DbStore<K, V, C> {
+  name: String,
+  table: TableEnhanced<NormalTable, K, V, C>,
+}
+
+impl<K, V, C> DbStore<K, V, C>
+where C: Coder<K, V>
+{
+  #[inline]
+  pub(crate) fn open(name: &str) -> Result<Self> {
+    Ok(DbStore { name: name.to_owned(), table: DB.open_table(name)?.enhance::<K, V, C>() })
+  }
+}
+
+impl<K, V, C> Store<K, V> for DbStore<K, V, C>
+where
+  K: Send + Sync + 'static,
+  V: Send + Sync + 'static,
+  C: Coder<K, V> + Send + Sync + 'static,
+{
+  #[inline]
+  fn get(&self, key: &K) -> Result<Option<V>> {
+    Ok(self.table.get(key)?)
+  }
+
+  #[inline]
+  fn put(&self, key: &K, value: &V) -> Result<()> {
+    Ok(self.table.put(key, value)?)
+  }
+
+  #[inline]
+  fn delete(&self, key: &K) -> Result<()> {
+    Ok(self.table.delete(key)?)
+  }
+
+  fn scan(&self, from: Option<&K>, visit: &mut dyn FnMut(K, V) -> bool) {
+    let mut cursor = self.table.new_cursor();
+    match from {
+      Some(key) => cursor.seek(key),
+      None => cursor.seek_to_first(),
+    }
+    while cursor.is_valid() {
+      if !visit(cursor.key().unwrap(), cursor.value().unwrap()) {
+        break;
+      }
+      cursor.next();
+    }
+  }
+
+  #[inline]
+  fn clear(&self) -> Result<()> {
+    Ok(DB.truncate_table(&self.name)?)
+  }
+}
+
+impl<K, V, C> RawTable for DbStore<K, V, C>
+where
+  K: Send + Sync + 'static,
+  V: Send + Sync + 'static,
+  C: Coder<K, V, EncodedKey = Bytes, EncodedValue = Bytes> + Send + Sync + 'static,
+{
+  // By a single cursor, which sees the table as of its creation
+  fn dump(&self) -> Vec<(Bytes, Bytes)> {
+    let mut entries = vec![];
+    self.scan(None, &mut |key, value| {
+      entries.push((C::encode_key(&key), C::encode_value(&value)));
+      true
+    });
+    entries
+  }
+
+  fn load(&self, entries: &[(Bytes, Bytes)]) -> Result<()> {
+    self.clear()?;
+    for (key, value) in entries {
+      self.put(&C::decode_key(key), &C::decode_value(value))?;
+    }
+    Ok(())
+  }
+}
+
+// Keeps the entries encoded by the coder of the table, so that they are
+// ordered as in the db
+pub(crate) struct MemoryStore<K, V, C> {
+  entries: RwLock<BTreeMap<Bytes, Bytes>>,
+  _marker: PhantomData<fn() -> (K, V, C)>,
+}
+
+impl<K, V, C> MemoryStore<K, V, C> {
+  #[inline]
+  pub(crate) fn new() -> Self {
+    MemoryStore { entries: RwLock::new(BTreeMap::new()), _marker: PhantomData }
+  }
+}
+
+impl<K, V, C> Store<K, V> for MemoryStore<K, V, C>
+where C: Coder<K, V, EncodedKey = Bytes, EncodedValue = Bytes>
+{
+  #[inline]
+  fn get(&self, key: &K) -> Result<Option<V>> {
+    let entries = self.entries.read().unwrap();
+    Ok(entries.get(&C::encode_key(key)).map(|value| C::decode_value(value)))
+  }
+
+  #[inline]
+  fn put(&self, key: &K, value: &V) -> Result<()> {
+    self.entries.write().unwrap().insert(C::encode_key(key), C::encode_value(value));
+    Ok(())
+  }
+
+  #[inline]
+  fn delete(&self, key: &K) -> Result<()> {
+    self.entries.write().unwrap().remove(&C::encode_key(key));
+    Ok(())
+  }
+
+  // The entries are copied out first, so that the visitor may write the store
+  fn scan(&self, from: Option<&K>, visit: &mut dyn FnMut(K, V) -> bool) {
+    let start = from.map_or(Bound::Unbounded, |key| Bound::Included(C::encode_key(key)));
+    let entries: Vec<(Bytes, Bytes)> = self
+      .entries
+      .read()
+      .unwrap()
+      .range((start, Bound::Unbounded))
+      .map(|(key, value)| (key.clone(), value.clone()))
+      .collect();
+    for (key, value) in entries {
+      if !visit(C::decode_key(&key), C::decode_value(&value)) {
+        break;
+      }
+    }
+  }
+
+  #[inline]
+  fn clear(&self) -> Result<()> {
+    self.entries.write().unwrap().clear();
+    Ok(())
+  }
+}
+
+impl<K, V, C> RawTable for MemoryStore<K, V, C>
+where C: Coder<K, V, EncodedKey = Bytes, EncodedValue = Bytes>
+{
+  #[inline]
+  fn dump(&self) -> Vec<(Bytes, Bytes)> {
+    let entries = self.entries.read().unwrap();
+    entries.iter().map(|(key, value)| (key.clone(), value.clone())).collect()
+  }
+
+  #[inline]
+  fn load(&self, entries: &[(Bytes, Bytes)]) -> Result<()> {
+    *self.entries.write().unwrap() = entries.iter().cloned().collect();
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::route_mgr::{WeightCoder, WeightStore};
+
+  #[test]
+  fn test_memory_store() {
+    let store: Box<WeightStore> = Box::new(MemoryStore::<_, _, WeightCoder>::new());
+    for (id, weight) in [("c", 3), ("a", 1), ("b", 2)] {
+      store.put(&id.to_owned(), &weight).unwrap();
+    }
+    assert_eq!(store.get(&"b".to_owned()).unwrap(), Some(2));
+    store.delete(&"b".to_owned()).unwrap();
+    assert_eq!(store.get(&"b".to_owned()).unwrap(), None);
+
+    let mut entries = vec![];
+    store.scan(None, &mut |id, weight| {
+      entries.push((id, weight));
+      true
+    });
+    assert_eq!(entries, vec![("a".to_owned(), 1), ("c".to_owned(), 3)]);
+
+    let mut ids = vec![];
+    store.scan(Some(&"b".to_owned()), &mut |id, _| {
+      ids.push(id);
+      false
+    });
+    assert_eq!(ids, vec!["c".to_owned()]);
+
+    store.clear().unwrap();
+    assert_eq!(store.get(&"a".to_owned()).unwrap(), None);
+  }
+
+  #[test]
+  fn test_raw_table() {
+    let store = MemoryStore::<_, _, WeightCoder>::new();
+    store.put(&"a".to_owned(), &1).unwrap();
+    let entries = store.dump();
+
+    let restored = MemoryStore::<_, _, WeightCoder>::new();
+    restored.put(&"b".to_owned(), &2).unwrap();
+    restored.load(&entries).unwrap();
+    assert_eq!(restored.get(&"a".to_owned()).unwrap(), Some(1));
+    assert_eq!(
+      restored.stats(),
+      TableStats { entries: 1, size: entries[0].0.len() as u64 + entries[0].1.len() as u64 }
+    );
+    assert_eq!(restored.get(&"b".to_owned()).unwrap(), None);
+  }
+}
@@ -0,0 +1,109 @@
+use actix_web::http::header::HeaderMap;
+use anyhow::{Context as _, Result};
+use opentelemetry::{
+  global::{self, BoxedTracer},
+  propagation::Extractor,
+  trace::{SpanKind, TraceContextExt, Tracer},
+  Context, ContextGuard, KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+  propagation::TraceContextPropagator,
+  runtime,
+  trace::{Config as TraceConfig, Sampler},
+  Resource,
+};
+
+use crate::config::CONFIG;
+
+const TRACER_NAME: &str = "maxwell-master";
+
+// Exports the spans over otlp, the spans are no-ops unless called
+pub fn init() -> Result<()> {
+  let config = CONFIG.load();
+  let tracing_config = &config.tracing;
+  if tracing_config.otlp_endpoint.is_empty() {
+    return Ok(());
+  }
+  let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+    tracing_config.sampling_ratio.clamp(0.0, 1.0),
+  )));
+  let tracer_provider = opentelemetry_otlp::new_pipeline()
+    .tracing()
+    .with_exporter(
+      opentelemetry_otlp::new_exporter().tonic().with_endpoint(&tracing_config.otlp_endpoint),
+    )
+    .with_trace_config(TraceConfig::default().with_sampler(sampler).with_resource(Resource::new(
+      vec![KeyValue::new("service.name", tracing_config.service_name.clone())],
+    )))
+    .install_batch(runtime::Tokio)
+    .with_context(|| {
+      format!("Failed to init otlp exporter to: {:?}", tracing_config.otlp_endpoint)
+    })?;
+  global::set_tracer_provider(tracer_provider);
+  global::set_text_map_propagator(TraceContextPropagator::new());
+  log::info!(
+    "Exporting spans to: {:?}, sampling_ratio: {:?}",
+    tracing_config.otlp_endpoint,
+    tracing_config.sampling_ratio
+  );
+  Ok(())
+}
+
+// Flushes the spans not yet exported
+pub fn shutdown() {
+  if !CONFIG.load().tracing.otlp_endpoint.is_empty() {
+    global::shutdown_tracer_provider();
+  }
+}
+
+#[inline]
+fn tracer() -> BoxedTracer {
+  global::tracer(TRACER_NAME)
+}
+
+// Starts a span, a child of the current one if any, which stays current and
+// ends once the guard is dropped
+#[inline]
+pub fn enter_span(name: &'static str) -> ContextGuard {
+  Context::current_with_span(tracer().start(name)).attach()
+}
+
+// Same as enter_span, with the attributes known upfront
+#[inline]
+pub fn enter_span_with(name: &'static str, attributes: Vec<KeyValue>) -> ContextGuard {
+  let tracer = tracer();
+  Context::current_with_span(tracer.span_builder(name).with_attributes(attributes).start(&tracer))
+    .attach()
+}
+
+// The root span of an http req, a child of the span in its traceparent header if any
+pub fn enter_http_span(headers: &HeaderMap, attributes: Vec<KeyValue>) -> ContextGuard {
+  let parent_context =
+    global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+  let tracer = tracer();
+  let span = tracer
+    .span_builder("http.req")
+    .with_kind(SpanKind::Server)
+    .with_attributes(attributes)
+    .start_with_context(&tracer, &parent_context);
+  parent_context.with_span(span).attach()
+}
+
+// Of the current span
+#[inline]
+pub fn set_attribute(key: &'static str, value: impl Into<opentelemetry::Value>) {
+  Context::current().span().set_attribute(KeyValue::new(key, value));
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl<'a> Extractor for HeaderExtractor<'a> {
+  fn get(&self, key: &str) -> Option<&str> {
+    self.0.get(key).and_then(|value| value.to_str().ok())
+  }
+
+  fn keys(&self) -> Vec<&str> {
+    self.0.keys().map(|key| key.as_str()).collect()
+  }
+}
@@ -0,0 +1,169 @@
+use std::{
+  hash::Hasher,
+  sync::atomic::{AtomicUsize, Ordering},
+};
+
+use ahash::AHasher;
+
+use super::Topic;
+use crate::{config::AssignPolicyKind, node_mgr::*};
+
+// Picks the backend that a new topic will be assigned to
+pub trait AssignPolicy: Send + Sync {
+  fn pick<'a>(
+    &self, topic: &Topic, backend_ids: &'a [NodeId], topic_count: &dyn Fn(&NodeId) -> u64,
+  ) -> Option<&'a NodeId>;
+}
+
+pub fn build_assign_policy(kind: AssignPolicyKind) -> Box<dyn AssignPolicy> {
+  match kind {
+    AssignPolicyKind::Hash => Box::new(HashPolicy),
+    AssignPolicyKind::RoundRobin => Box::new(RoundRobinPolicy { next: AtomicUsize::new(0) }),
+    AssignPolicyKind::LeastTopics => Box::new(LeastTopicsPolicy),
+    AssignPolicyKind::CapacityWeighted => Box::new(CapacityWeightedPolicy),
+  }
+}
+
+pub struct HashPolicy;
+
+impl AssignPolicy for HashPolicy {
+  fn pick<'a>(
+    &self, topic: &Topic, backend_ids: &'a [NodeId], _topic_count: &dyn Fn(&NodeId) -> u64,
+  ) -> Option<&'a NodeId> {
+    if backend_ids.is_empty() {
+      return None;
+    }
+    let index = hash(&[topic]) % backend_ids.len() as u64;
+    backend_ids.get(index as usize)
+  }
+}
+
+pub struct RoundRobinPolicy {
+  next: AtomicUsize,
+}
+
+impl AssignPolicy for RoundRobinPolicy {
+  fn pick<'a>(
+    &self, _topic: &Topic, backend_ids: &'a [NodeId], _topic_count: &dyn Fn(&NodeId) -> u64,
+  ) -> Option<&'a NodeId> {
+    if backend_ids.is_empty() {
+      return None;
+    }
+    let index = self.next.fetch_add(1, Ordering::Relaxed) % backend_ids.len();
+    backend_ids.get(index)
+  }
+}
+
+pub struct LeastTopicsPolicy;
+
+impl AssignPolicy for LeastTopicsPolicy {
+  fn pick<'a>(
+    &self, _topic: &Topic, backend_ids: &'a [NodeId], topic_count: &dyn Fn(&NodeId) -> u64,
+  ) -> Option<&'a NodeId> {
+    backend_ids.iter().min_by_key(|backend_id| topic_count(backend_id))
+  }
+}
+
+// Weighted rendezvous hashing: stable like hash, but proportional to capacities
+pub struct CapacityWeightedPolicy;
+
+impl AssignPolicy for CapacityWeightedPolicy {
+  fn pick<'a>(
+    &self, topic: &Topic, backend_ids: &'a [NodeId], _topic_count: &dyn Fn(&NodeId) -> u64,
+  ) -> Option<&'a NodeId> {
+    pick_weighted(topic, backend_ids, &|backend_id| {
+      BACKEND_MGR.get(backend_id).map_or(0, |backend| backend.capacity)
+    })
+  }
+}
+
+// Backends of 0 capacity are never picked
+fn pick_weighted<'a>(
+  topic: &Topic, backend_ids: &'a [NodeId], capacity_of: &dyn Fn(&NodeId) -> u32,
+) -> Option<&'a NodeId> {
+  let mut picked = None;
+  let mut min_score = f64::MAX;
+  for backend_id in backend_ids {
+    let capacity = capacity_of(backend_id);
+    if capacity == 0 {
+      continue;
+    }
+    let unit = (hash(&[topic, backend_id]) as f64 + 1.0) / (u64::MAX as f64 + 2.0);
+    let score = -unit.ln() / capacity as f64;
+    if score < min_score {
+      min_score = score;
+      picked = Some(backend_id);
+    }
+  }
+  picked
+}
+
+#[inline]
+fn hash(parts: &[&str]) -> u64 {
+  let mut hasher = AHasher::default();
+  for part in parts {
+    hasher.write(part.as_bytes());
+  }
+  hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn backend_ids() -> Vec<NodeId> {
+    vec!["backend-0".to_owned(), "backend-1".to_owned(), "backend-2".to_owned()]
+  }
+
+  #[test]
+  fn test_hash_policy() {
+    let backend_ids = backend_ids();
+    let policy = HashPolicy;
+    let topic = "topic-0".to_owned();
+    let picked = policy.pick(&topic, &backend_ids, &|_| 0);
+    assert!(picked.is_some());
+    assert_eq!(picked, policy.pick(&topic, &backend_ids, &|_| 0));
+    assert!(policy.pick(&topic, &[], &|_| 0).is_none());
+  }
+
+  #[test]
+  fn test_round_robin_policy() {
+    let backend_ids = backend_ids();
+    let policy = RoundRobinPolicy { next: AtomicUsize::new(0) };
+    let topic = "topic-0".to_owned();
+    for i in 0..6 {
+      assert_eq!(policy.pick(&topic, &backend_ids, &|_| 0), Some(&backend_ids[i % 3]));
+    }
+  }
+
+  #[test]
+  fn test_least_topics_policy() {
+    let backend_ids = backend_ids();
+    let policy = LeastTopicsPolicy;
+    let topic = "topic-0".to_owned();
+    let topic_count = |id: &NodeId| if id == "backend-1" { 1 } else { 2 };
+    assert_eq!(policy.pick(&topic, &backend_ids, &topic_count), Some(&backend_ids[1]));
+  }
+
+  #[test]
+  fn test_pick_weighted() {
+    let backend_ids = backend_ids();
+    let capacity_of = |id: &NodeId| match id.as_str() {
+      "backend-0" => 1,
+      "backend-1" => 3,
+      _ => 0,
+    };
+    let mut picks = [0u32; 3];
+    for i in 0..10000 {
+      let topic = format!("topic-{}", i);
+      let picked = pick_weighted(&topic, &backend_ids, &capacity_of).unwrap();
+      assert_eq!(Some(picked), pick_weighted(&topic, &backend_ids, &capacity_of));
+      picks[backend_ids.iter().position(|id| id == picked).unwrap()] += 1;
+    }
+    assert_eq!(picks[2], 0);
+    // 1:3 of the picks, give or take
+    assert!((2000..3000).contains(&picks[0]), "picks: {:?}", picks);
+    assert!((7000..8000).contains(&picks[1]), "picks: {:?}", picks);
+    assert!(pick_weighted(&"topic-0".to_owned(), &backend_ids, &|_| 0).is_none());
+  }
+}
@@ -0,0 +1,929 @@
+use std::borrow::Borrow;
+use std::net::IpAddr;
+use std::sync::{
+  atomic::{AtomicU32, AtomicU64, Ordering},
+  Arc,
+};
+use std::time::Duration;
+
+use ahash::RandomState as AHasher;
+use anyhow::{anyhow, Result};
+use bytes::{Bytes, BytesMut};
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use quick_cache::{sync::Cache, OptionsBuilder, Weighter};
+use serde_json::json;
+use seriesdb::coder::Coder;
+use tokio::sync::broadcast;
+
+use crate::node_mgr::NodeId;
+use crate::{
+  audit, clock, cluster,
+  config::{OrphanTopicAction, CONFIG},
+  event_bus::{self, Event},
+  maintenance,
+  node_mgr::BACKEND_MGR,
+  raft::{self, Command},
+  standby,
+  store::{open_store, Store},
+  telemetry,
+};
+
+pub mod assign_policy;
+pub mod namespace;
+pub mod quota;
+pub mod single_flight;
+
+pub use assign_policy::*;
+pub use namespace::*;
+pub use quota::*;
+pub use single_flight::*;
+
+pub type Topic = String;
+type TopicStore = dyn Store<Topic, NodeId>;
+
+pub(crate) struct TopicCoder;
+
+impl Coder<Topic, NodeId> for TopicCoder {
+  type EncodedKey = Bytes;
+  type EncodedValue = Bytes;
+
+  #[inline(always)]
+  fn encode_key<K: Borrow<Topic>>(key: K) -> Self::EncodedKey {
+    BytesMut::from(key.borrow().as_bytes()).freeze()
+  }
+
+  #[inline(always)]
+  fn decode_key(key: &[u8]) -> Topic {
+    std::str::from_utf8(key).unwrap().to_string()
+  }
+
+  #[inline(always)]
+  fn encode_value<V: Borrow<NodeId>>(value: V) -> Self::EncodedValue {
+    BytesMut::from(value.borrow().as_bytes()).freeze()
+  }
+
+  #[inline(always)]
+  fn decode_value(value: &[u8]) -> NodeId {
+    std::str::from_utf8(value).unwrap().to_string()
+  }
+}
+
+type LocatedAtStore = dyn Store<Topic, u32>;
+
+pub(crate) struct LocatedAtCoder;
+
+impl Coder<Topic, u32> for LocatedAtCoder {
+  type EncodedKey = Bytes;
+  type EncodedValue = Bytes;
+
+  #[inline(always)]
+  fn encode_key<K: Borrow<Topic>>(key: K) -> Self::EncodedKey {
+    BytesMut::from(key.borrow().as_bytes()).freeze()
+  }
+
+  #[inline(always)]
+  fn decode_key(key: &[u8]) -> Topic {
+    std::str::from_utf8(key).unwrap().to_string()
+  }
+
+  #[inline(always)]
+  fn encode_value<V: Borrow<u32>>(value: V) -> Self::EncodedValue {
+    Bytes::copy_from_slice(&value.borrow().to_be_bytes())
+  }
+
+  #[inline(always)]
+  fn decode_value(value: &[u8]) -> u32 {
+    u32::from_be_bytes(value.try_into().unwrap())
+  }
+}
+
+type InfoKey = String;
+type InfoValue = String;
+type InfoStore = dyn Store<InfoKey, InfoValue>;
+
+pub(crate) struct InfoCoder;
+
+impl Coder<InfoKey, InfoValue> for InfoCoder {
+  type EncodedKey = Bytes;
+  type EncodedValue = Bytes;
+
+  #[inline(always)]
+  fn encode_key<K: Borrow<InfoKey>>(key: K) -> Self::EncodedKey {
+    BytesMut::from(key.borrow().as_bytes()).freeze()
+  }
+
+  #[inline(always)]
+  fn decode_key(key: &[u8]) -> InfoKey {
+    std::str::from_utf8(key).unwrap().to_string()
+  }
+
+  #[inline(always)]
+  fn encode_value<V: Borrow<InfoValue>>(value: V) -> Self::EncodedValue {
+    BytesMut::from(value.borrow().as_bytes()).freeze()
+  }
+
+  #[inline(always)]
+  fn decode_value(value: &[u8]) -> InfoValue {
+    std::str::from_utf8(value).unwrap().to_string()
+  }
+}
+
+#[derive(Clone)]
+struct TopicWeighter;
+
+impl Weighter<String, String> for TopicWeighter {
+  fn weight(&self, key: &String, val: &String) -> u64 {
+    (key.len() + val.len()) as u64
+  }
+}
+
+// Published when a topic was assigned, moved to another backend, or deleted
+// (backend_id is None)
+#[derive(Debug, Clone)]
+pub struct TopicChange {
+  pub topic: Topic,
+  pub backend_id: Option<NodeId>,
+}
+
+pub struct TopicMgr {
+  cache: Cache<Topic, NodeId, TopicWeighter>,
+  cache_hits: AtomicU64,
+  cache_misses: AtomicU64,
+  topic_store: Arc<TopicStore>,
+  info_store: Arc<InfoStore>,
+  pin_store: Arc<TopicStore>,
+  pins: DashMap<Topic, NodeId, AHasher>,
+  located_at_store: Arc<LocatedAtStore>,
+  located_ats: DashMap<Topic, u32, AHasher>,
+  version: AtomicU32,
+  namespace_store: Arc<NamespaceStore>,
+  namespaces: DashMap<String, NamespaceEntry, AHasher>,
+  topic_counts: DashMap<NodeId, u64, AHasher>,
+  namespace_topic_counts: DashMap<String, u64, AHasher>,
+  assign_policy: Box<dyn AssignPolicy>,
+  creation_quota: CreationQuota,
+  assign_locks: AssignLocks,
+  change_sender: broadcast::Sender<TopicChange>,
+}
+
+impl TopicMgr {
+  #[inline]
+  fn new(
+    topic_store: Arc<TopicStore>, info_store: Arc<InfoStore>, pin_store: Arc<TopicStore>,
+    located_at_store: Arc<LocatedAtStore>, namespace_store: Arc<NamespaceStore>,
+    assign_policy: Box<dyn AssignPolicy>,
+  ) -> Self {
+    let topic_mgr = TopicMgr {
+      cache: Self::build_cache(),
+      cache_hits: AtomicU64::new(0),
+      cache_misses: AtomicU64::new(0),
+      topic_store,
+      info_store,
+      pin_store,
+      pins: DashMap::with_capacity_and_hasher(64, AHasher::default()),
+      located_at_store,
+      located_ats: DashMap::with_capacity_and_hasher(1024, AHasher::default()),
+      version: AtomicU32::new(0),
+      namespace_store,
+      namespaces: DashMap::with_capacity_and_hasher(64, AHasher::default()),
+      topic_counts: DashMap::with_capacity_and_hasher(64, AHasher::default()),
+      namespace_topic_counts: DashMap::with_capacity_and_hasher(64, AHasher::default()),
+      assign_policy,
+      creation_quota: CreationQuota::new(&CONFIG.load().topic_mgr),
+      assign_locks: AssignLocks::new(),
+      change_sender: broadcast::channel(1024).0,
+    };
+    let backends_changed = topic_mgr.check();
+    topic_mgr.recover_version();
+    topic_mgr.recover_pins();
+    topic_mgr.recover_namespaces();
+    topic_mgr.recover_topic_counts();
+    if backends_changed {
+      topic_mgr.check_orphans();
+    }
+    topic_mgr
+  }
+
+  fn build_cache() -> Cache<Topic, NodeId, TopicWeighter> {
+    let config = CONFIG.load().topic_mgr.clone();
+    let mut options_builder = OptionsBuilder::new();
+    options_builder
+      .estimated_items_capacity(config.cache_max_items)
+      .weight_capacity(config.cache_max_weight);
+    if config.cache_shards > 0 {
+      options_builder.shards(config.cache_shards);
+    }
+    let options = options_builder.build().unwrap_or_else(|err| {
+      panic!("Invalid topic cache options: {:?}, err: {:?}", config, err);
+    });
+    Cache::with_options(options, TopicWeighter, Default::default(), Default::default())
+  }
+
+  #[inline]
+  fn assign(&self, topic: Topic, backend_id: NodeId) -> Result<()> {
+    {
+      let _span_guard = telemetry::enter_span("topic_mgr.store_put");
+      self.topic_store.put(&topic, &backend_id)?;
+    }
+    *self.topic_counts.entry(backend_id.clone()).or_insert(0) += 1;
+    self.incr_namespace_topic_count(&topic);
+    self.touch(&topic);
+    self.cache.insert(topic, backend_id);
+    Ok(())
+  }
+
+  #[inline]
+  fn delete(&self, topic: &Topic, backend_id: &NodeId) -> Result<()> {
+    self.topic_store.delete(topic)?;
+    self.located_at_store.delete(topic)?;
+    self.cache.remove(topic);
+    self.decr_topic_count(backend_id);
+    self.decr_namespace_topic_count(topic);
+    self.publish_change(topic.clone(), None);
+    Ok(())
+  }
+
+  // Subscribes to the reassignments and deletions of topics
+  #[inline]
+  pub fn subscribe(&self) -> broadcast::Receiver<TopicChange> {
+    self.change_sender.subscribe()
+  }
+
+  #[inline]
+  fn publish_change(&self, topic: Topic, backend_id: Option<NodeId>) {
+    event_bus::publish(Event::TopicReassigned {
+      topic: topic.clone(),
+      backend_id: backend_id.clone(),
+    });
+    // Failing only means nobody is subscribing
+    let _ = self.change_sender.send(TopicChange { topic, backend_id });
+  }
+
+  // Moves the topic to the given backend, returns the previous backend if any,
+  // fails with cluster::NotLeader unless this master is the leader, or with
+  // maintenance::Maintenance while it is read only
+  #[inline]
+  pub fn reassign(&self, topic: Topic, backend_id: NodeId) -> Result<Option<NodeId>> {
+    cluster::check_leader()?;
+    maintenance::check_writable()?;
+    self.reassign_unchecked(topic, backend_id)
+  }
+
+  // Same as reassign, for pinning, the writes of the topics are replicated by
+  // raft, which applies them to TOPIC_MGR, see raft::replicate
+  fn reassign_unchecked(&self, topic: Topic, backend_id: NodeId) -> Result<Option<NodeId>> {
+    let _guard = self.assign_locks.lock(&topic);
+    let prev_backend_id = self.locate(&topic)?;
+    if prev_backend_id.as_ref() == Some(&backend_id) {
+      log::debug!("The topic is already on the backend: {:?}", backend_id);
+      return Ok(prev_backend_id);
+    }
+    raft::replicate(Command::ReassignTopic { topic, backend_id })?;
+    Ok(prev_backend_id)
+  }
+
+  // Without the assign lock, which the writes hold while waiting for raft to
+  // apply them
+  pub(crate) fn apply_reassign(&self, topic: Topic, backend_id: NodeId) -> Result<Option<NodeId>> {
+    let prev_backend_id = self.locate(&topic)?;
+    if prev_backend_id.as_ref() == Some(&backend_id) {
+      log::debug!("The topic is already on the backend: {:?}", backend_id);
+      return Ok(prev_backend_id);
+    }
+    log::info!("Reassigning topic: {:?}, from: {:?}, to: {:?}", topic, prev_backend_id, backend_id);
+    self.assign(topic.clone(), backend_id.clone())?;
+    if let Some(prev_backend_id) = &prev_backend_id {
+      self.decr_topic_count(prev_backend_id);
+      self.decr_namespace_topic_count(&topic);
+    }
+    self.update_version();
+    self.publish_change(topic, Some(backend_id));
+    Ok(prev_backend_id)
+  }
+
+  // Deletes the topic wherever it is, returns the backend it was on if any
+  pub fn remove(&self, topic: &Topic) -> Result<Option<NodeId>> {
+    let _guard = self.assign_locks.lock(topic);
+    let backend_id = self.locate(topic)?;
+    if backend_id.is_some() {
+      raft::replicate(Command::RemoveTopic { topic: topic.clone() })?;
+    }
+    Ok(backend_id)
+  }
+
+  pub(crate) fn apply_remove(&self, topic: &Topic) -> Result<Option<NodeId>> {
+    let backend_id = self.locate(topic)?;
+    if let Some(backend_id) = &backend_id {
+      log::info!("Removing topic: {:?}, from: {:?}", topic, backend_id);
+      self.delete(topic, backend_id)?;
+      self.update_version();
+    }
+    Ok(backend_id)
+  }
+
+  #[inline]
+  pub fn locate(&self, topic: &Topic) -> Result<Option<NodeId>> {
+    let _span_guard = telemetry::enter_span("topic_mgr.locate");
+    let backend_id = self.cache.get(topic);
+    if backend_id.is_some() {
+      self.cache_hits.fetch_add(1, Ordering::Relaxed);
+      self.touch(topic);
+      Ok(backend_id)
+    } else {
+      self.cache_misses.fetch_add(1, Ordering::Relaxed);
+      telemetry::set_attribute("cache_miss", true);
+      if let Some(backend_id) = self.topic_store.get(topic)? {
+        self.touch(topic);
+        self.cache.insert(topic.clone(), backend_id.clone());
+        Ok(Some(backend_id))
+      } else {
+        Ok(None)
+      }
+    }
+  }
+
+  // Returns all assignments, ordered by topic
+  pub fn dump(&self) -> Vec<(Topic, NodeId)> {
+    dump_topic_store(&self.topic_store)
+  }
+
+  // In the order of the topics, from the given one on, until visit returns false
+  #[inline]
+  pub fn scan(&self, from: Option<&Topic>, visit: &mut dyn FnMut(Topic, NodeId) -> bool) {
+    self.topic_store.scan(from, visit)
+  }
+
+  // Deletes the topics which have not been located within the ttl, by the
+  // leader, without waiting for them to be committed, see raft::propose
+  pub fn gc(&self) {
+    let ttl = CONFIG.load().topic_mgr.topic_ttl;
+    if ttl == 0 {
+      return;
+    }
+    let now = clock::now();
+    self.flush_located_ats();
+
+    let mut untracked_topics = vec![];
+    let mut expired_topics = vec![];
+    self.topic_store.scan(None, &mut |topic, backend_id| {
+      match self.located_at_store.get(&topic) {
+        Ok(Some(located_at)) => {
+          if now.saturating_sub(located_at) > ttl {
+            expired_topics.push((topic, backend_id));
+          }
+        }
+        Ok(None) => untracked_topics.push(topic),
+        Err(err) => log::warn!("Failed to get located_at: topic: {:?}, err: {:?}", topic, err),
+      }
+      true
+    });
+
+    // Topics assigned before the ttl was enabled start their ttl from now
+    for topic in &untracked_topics {
+      self.located_at_store.put(topic, &now).unwrap_or_else(|err| {
+        log::warn!("Failed to put located_at: topic: {:?}, err: {:?}", topic, err);
+      });
+    }
+
+    let mut deleted_count = 0;
+    for (topic, backend_id) in &expired_topics {
+      // Held across the re-check and the delete, which the assignments then wait for
+      let _guard = self.assign_locks.lock(topic);
+      // It was located again after the flush, or was reassigned or removed meanwhile
+      if self.located_ats.contains_key(topic) {
+        continue;
+      }
+      match self.topic_store.get(topic) {
+        Ok(Some(curr_backend_id)) if curr_backend_id == *backend_id => {}
+        Ok(_) => continue,
+        Err(err) => {
+          log::warn!("Failed to get topic: {:?}, err: {:?}", topic, err);
+          continue;
+        }
+      }
+      match raft::propose(Command::RemoveTopic { topic: topic.clone() }) {
+        Ok(()) => deleted_count += 1,
+        Err(err) => log::warn!("Failed to delete topic: {:?}, err: {:?}", topic, err),
+      }
+    }
+    if deleted_count > 0 {
+      log::info!("Deleted expired topics: count: {:?}, ttl: {:?}", deleted_count, ttl);
+    }
+  }
+
+  #[inline]
+  fn touch(&self, topic: &Topic) {
+    if CONFIG.load().topic_mgr.topic_ttl > 0 {
+      self.located_ats.insert(topic.clone(), clock::now());
+    }
+  }
+
+  #[inline]
+  fn flush_located_ats(&self) {
+    let topics: Vec<Topic> = self.located_ats.iter().map(|entry| entry.key().clone()).collect();
+    for topic in topics {
+      if let Some((topic, located_at)) = self.located_ats.remove(&topic) {
+        self.located_at_store.put(&topic, &located_at).unwrap_or_else(|err| {
+          log::warn!("Failed to put located_at: topic: {:?}, err: {:?}", topic, err);
+        });
+      }
+    }
+  }
+
+  // Locates the topic, or assigns it to a picked backend if it was not located,
+  // creating new topics is limited by the quota of the client, and fails with
+  // cluster::NotLeader unless this master is the leader, or with
+  // maintenance::Maintenance while it is read only
+  #[inline]
+  pub fn locate_or_assign(&self, topic: &Topic, client: Option<IpAddr>) -> Result<NodeId> {
+    if let Some(backend_id) = self.locate(topic)? {
+      return Ok(backend_id);
+    }
+    self.assign_new(topic, client, &BACKEND_MGR.ids())
+  }
+
+  // Same as locate_or_assign, but picks backends for all unlocated topics in one pass
+  pub fn locate_or_assign_all(
+    &self, topics: &[Topic], client: Option<IpAddr>,
+  ) -> Vec<(Topic, Result<NodeId>)> {
+    let mut results = Vec::with_capacity(topics.len());
+    let mut unlocated_topics = Vec::new();
+    for topic in topics {
+      match self.locate(topic) {
+        Ok(Some(backend_id)) => results.push((topic.clone(), Ok(backend_id))),
+        Ok(None) => unlocated_topics.push(topic),
+        Err(err) => results.push((topic.clone(), Err(err))),
+      }
+    }
+
+    let backend_ids = BACKEND_MGR.ids();
+    for topic in unlocated_topics {
+      results.push((topic.clone(), self.assign_new(topic, client, &backend_ids)));
+    }
+    results
+  }
+
+  // Assigns the topic unless a concurrent caller just did, in which case its
+  // backend is returned, so that all callers get the same backend
+  fn assign_new(
+    &self, topic: &Topic, client: Option<IpAddr>, backend_ids: &[NodeId],
+  ) -> Result<NodeId> {
+    let _guard = self.assign_locks.lock(topic);
+    if let Some(backend_id) = self.locate(topic)? {
+      log::debug!("The topic was assigned concurrently: {:?}, to: {:?}", topic, backend_id);
+      return Ok(backend_id);
+    }
+    // Only the leader assigns, the others answer for the topics already assigned
+    cluster::check_leader()?;
+    maintenance::check_writable()?;
+    let backend_id = {
+      let _span_guard = telemetry::enter_span("topic_mgr.pick");
+      self.pick(topic, backend_ids)?
+    };
+    // Only once picked, and given back unless assigned
+    self.creation_quota.acquire(client)?;
+    log::info!(
+      "Assigning new topic: {:?}, backend_id: {:?}, client: {:?}",
+      topic,
+      backend_id,
+      client
+    );
+    let command = Command::AssignTopic { topic: topic.clone(), backend_id: backend_id.clone() };
+    if let Err(err) = raft::replicate(command) {
+      self.creation_quota.refund(client);
+      return Err(err);
+    }
+    audit::record(
+      client.map_or_else(|| audit::SYSTEM_ACTOR.to_owned(), |client| client.to_string()),
+      "assign-topic",
+      json!({ "topic": topic, "backend_id": backend_id }),
+    );
+    Ok(backend_id)
+  }
+
+  // Ignored if the topic was assigned already, as when replayed
+  pub(crate) fn apply_assign(&self, topic: Topic, backend_id: NodeId) -> Result<()> {
+    if self.locate(&topic)?.is_some() {
+      return Ok(());
+    }
+    self.assign(topic.clone(), backend_id.clone())?;
+    // Not on the event bus, which would be flooded by the new topics
+    let _ = self.change_sender.send(TopicChange { topic, backend_id: Some(backend_id) });
+    Ok(())
+  }
+
+  // The backends following the primary in its candidate list, ordered by preference
+  pub fn standbys(&self, topic: &Topic, primary: &NodeId) -> Vec<NodeId> {
+    let replication_factor = CONFIG.load().topic_mgr.replication_factor;
+    if replication_factor == 0 {
+      return vec![];
+    }
+    let candidates = match namespace_of(topic).and_then(|namespace| self.namespaces.get(namespace))
+    {
+      Some(entry) => entry.backend_ids.clone(),
+      None => BACKEND_MGR.ids().to_vec(),
+    };
+    let start = candidates.iter().position(|backend_id| backend_id == primary).map_or(0, |i| i + 1);
+    candidates
+      .iter()
+      .cycle()
+      .skip(start)
+      .take(candidates.len())
+      .filter(|backend_id| *backend_id != primary)
+      .take(replication_factor)
+      .cloned()
+      .collect()
+  }
+
+  // Pinned backend first, then the namespace's backends, then all backends
+  #[inline]
+  fn pick(&self, topic: &Topic, backend_ids: &[NodeId]) -> Result<NodeId> {
+    if let Some(backend_id) = self.pick_pinned(topic) {
+      return Ok(backend_id);
+    }
+    if let Some(entry) = namespace_of(topic).and_then(|namespace| self.namespaces.get(namespace)) {
+      let namespace = &entry.namespace;
+      if namespace.max_topics > 0
+        && self.namespace_topic_count(&namespace.name) >= namespace.max_topics
+      {
+        return Err(anyhow!(
+          "The topic quota of the namespace was exceeded: namespace: {}, max_topics: {}",
+          namespace.name,
+          namespace.max_topics
+        ));
+      }
+      return entry
+        .assign_policy
+        .pick(topic, &entry.backend_ids, &|id| self.topic_count(id))
+        .cloned()
+        .ok_or_else(|| {
+          anyhow!(
+            "Failed to find an available backend: topic: {}, namespace: {}",
+            topic,
+            namespace.name
+          )
+        });
+    }
+    self
+      .assign_policy
+      .pick(topic, backend_ids, &|id| self.topic_count(id))
+      .cloned()
+      .ok_or_else(|| anyhow!("Failed to find an available backend: topic: {}", topic))
+  }
+
+  // Pins a topic, or all topics with a prefix if it ends with `*`, to the backend,
+  // fails like reassign before anything was written
+  pub fn pin(&self, topic: Topic, backend_id: NodeId) -> Result<()> {
+    cluster::check_leader()?;
+    maintenance::check_writable()?;
+    log::info!("Pinning topic: {:?}, to: {:?}", topic, backend_id);
+    self.pin_store.put(&topic, &backend_id)?;
+    self.pins.insert(topic.clone(), backend_id.clone());
+    if !topic.ends_with('*') {
+      self.reassign_unchecked(topic, backend_id)?;
+    }
+    Ok(())
+  }
+
+  pub fn unpin(&self, topic: &Topic) -> Result<bool> {
+    if self.pins.remove(topic).is_some() {
+      log::info!("Unpinning topic: {:?}", topic);
+      self.pin_store.delete(topic)?;
+      Ok(true)
+    } else {
+      Ok(false)
+    }
+  }
+
+  #[inline]
+  pub fn pins(&self) -> Vec<(Topic, NodeId)> {
+    self.pins.iter().map(|pin| (pin.key().clone(), pin.value().clone())).collect()
+  }
+
+  // Exact pins take precedence over prefix pins, the longest prefix wins
+  #[inline]
+  fn find_pin(&self, topic: &Topic) -> Option<NodeId> {
+    if let Some(backend_id) = self.pins.get(topic) {
+      return Some(backend_id.clone());
+    }
+    let mut found: Option<(usize, NodeId)> = None;
+    for pin in self.pins.iter() {
+      if let Some(prefix) = pin.key().strip_suffix('*') {
+        if topic.starts_with(prefix) && !matches!(&found, Some((len, _)) if *len >= prefix.len()) {
+          found = Some((prefix.len(), pin.value().clone()));
+        }
+      }
+    }
+    found.map(|(_, backend_id)| backend_id)
+  }
+
+  #[inline]
+  fn pick_pinned(&self, topic: &Topic) -> Option<NodeId> {
+    let backend_id = self.find_pin(topic)?;
+    if BACKEND_MGR.get(&backend_id).is_some() {
+      log::debug!("Found the pinned backend: topic: {:?}, backend_id: {:?}", topic, backend_id);
+      Some(backend_id)
+    } else {
+      log::warn!(
+        "The pinned backend was not found: topic: {:?}, backend_id: {:?}",
+        topic,
+        backend_id
+      );
+      None
+    }
+  }
+
+  #[inline]
+  fn recover_pins(&self) {
+    self.pin_store.scan(None, &mut |topic, backend_id| {
+      self.pins.insert(topic, backend_id);
+      true
+    });
+    for pin_config in &CONFIG.load().topic_mgr.pins {
+      self.pin_store.put(&pin_config.topic, &pin_config.backend_id).unwrap();
+      self.pins.insert(pin_config.topic.clone(), pin_config.backend_id.clone());
+    }
+  }
+
+  pub fn set_namespace(&self, namespace: Namespace) -> Result<()> {
+    log::info!("Setting namespace: {:?}", namespace);
+    self.namespace_store.put(&namespace.name, &namespace)?;
+    let name = namespace.name.clone();
+    let is_new = self.namespaces.insert(name.clone(), NamespaceEntry::new(namespace)).is_none();
+    if is_new {
+      self.namespace_topic_counts.insert(name.clone(), self.count_namespace_topics(&name));
+    }
+    Ok(())
+  }
+
+  pub fn remove_namespace(&self, name: &String) -> Result<bool> {
+    if self.namespaces.remove(name).is_some() {
+      log::info!("Removing namespace: {:?}", name);
+      self.namespace_store.delete(name)?;
+      self.namespace_topic_counts.remove(name);
+      Ok(true)
+    } else {
+      Ok(false)
+    }
+  }
+
+  #[inline]
+  pub fn namespaces(&self) -> Vec<Namespace> {
+    self.namespaces.iter().map(|entry| entry.namespace.clone()).collect()
+  }
+
+  #[inline]
+  pub fn namespace_topic_count(&self, name: &str) -> u64 {
+    self.namespace_topic_counts.get(name).map_or(0, |count| *count)
+  }
+
+  #[inline]
+  fn incr_namespace_topic_count(&self, topic: &Topic) {
+    if let Some(namespace) = namespace_of(topic) {
+      if let Some(mut count) = self.namespace_topic_counts.get_mut(namespace) {
+        *count += 1;
+      }
+    }
+  }
+
+  #[inline]
+  fn decr_namespace_topic_count(&self, topic: &Topic) {
+    if let Some(namespace) = namespace_of(topic) {
+      if let Some(mut count) = self.namespace_topic_counts.get_mut(namespace) {
+        *count = count.saturating_sub(1);
+      }
+    }
+  }
+
+  fn count_namespace_topics(&self, name: &str) -> u64 {
+    let mut count = 0;
+    self.topic_store.scan(None, &mut |topic, _| {
+      if namespace_of(&topic) == Some(name) {
+        count += 1;
+      }
+      true
+    });
+    count
+  }
+
+  #[inline]
+  fn recover_namespaces(&self) {
+    self.namespace_store.scan(None, &mut |_, namespace| {
+      self.namespace_topic_counts.insert(namespace.name.clone(), 0);
+      self.namespaces.insert(namespace.name.clone(), NamespaceEntry::new(namespace));
+      true
+    });
+    for namespace_config in &CONFIG.load().topic_mgr.namespaces {
+      let namespace = Namespace::from(namespace_config);
+      self.namespace_store.put(&namespace.name, &namespace).unwrap();
+      self.namespace_topic_counts.insert(namespace.name.clone(), 0);
+      self.namespaces.insert(namespace.name.clone(), NamespaceEntry::new(namespace));
+    }
+  }
+
+  #[inline]
+  pub fn topic_count(&self, backend_id: &NodeId) -> u64 {
+    self.topic_counts.get(backend_id).map_or(0, |count| *count)
+  }
+
+  #[inline]
+  pub fn total_topic_count(&self) -> u64 {
+    self.topic_counts.iter().map(|count| *count).sum()
+  }
+
+  #[inline]
+  fn decr_topic_count(&self, backend_id: &NodeId) {
+    if let Some(mut count) = self.topic_counts.get_mut(backend_id) {
+      *count = count.saturating_sub(1);
+    }
+  }
+
+  #[inline]
+  fn recover_topic_counts(&self) {
+    self.topic_store.scan(None, &mut |topic, backend_id| {
+      *self.topic_counts.entry(backend_id).or_insert(0) += 1;
+      self.incr_namespace_topic_count(&topic);
+      true
+    });
+  }
+
+  // Changes whenever the backends or any explicit reassignment changed
+  #[inline]
+  pub fn checksum(&self) -> u32 {
+    crc32fast::hash(format!("{}|{}", BACKEND_MGR.checksum(), self.version()).as_bytes())
+  }
+
+  #[inline]
+  pub fn version(&self) -> u32 {
+    self.version.load(Ordering::SeqCst)
+  }
+
+  #[inline]
+  pub fn cache_hits(&self) -> u64 {
+    self.cache_hits.load(Ordering::Relaxed)
+  }
+
+  #[inline]
+  pub fn cache_misses(&self) -> u64 {
+    self.cache_misses.load(Ordering::Relaxed)
+  }
+
+  #[inline]
+  fn update_version(&self) {
+    let version = self.version.fetch_add(1, Ordering::SeqCst) + 1;
+    self.info_store.put(&"version".to_owned(), &format!("{}", version)).unwrap_or_else(|err| {
+      log::warn!("Failed to store topic dist version: {:?}", err);
+    });
+  }
+
+  #[inline]
+  fn recover_version(&self) {
+    if let Some(version) = self.info_store.get(&"version".to_owned()).unwrap() {
+      self.version.store(version.parse().unwrap_or(0), Ordering::SeqCst);
+    }
+  }
+
+  // Finds the topics assigned to backends which are no longer configured,
+  // and repairs them according to the orphan_action config.
+  pub(crate) fn check_orphans(&self) {
+    let mut orphans = vec![];
+    self.topic_store.scan(None, &mut |topic, backend_id| {
+      if BACKEND_MGR.get(&backend_id).is_none() {
+        orphans.push((topic, backend_id));
+      }
+      true
+    });
+    if orphans.is_empty() {
+      return;
+    }
+
+    // Left to the leader, which checks again once elected
+    let action = if raft::is_follower() {
+      OrphanTopicAction::Report
+    } else {
+      CONFIG.load().topic_mgr.orphan_action
+    };
+    let mut repaired_count = 0;
+    for (topic, backend_id) in &orphans {
+      log::warn!("Found orphan topic: {:?}, backend_id: {:?}", topic, backend_id);
+      let result = match action {
+        OrphanTopicAction::Report => continue,
+        OrphanTopicAction::Reassign => self
+          .pick(topic, &BACKEND_MGR.ids())
+          .and_then(|new_backend_id| self.repair_orphan(topic, Some(new_backend_id))),
+        OrphanTopicAction::Delete => self.repair_orphan(topic, None),
+      };
+      match result {
+        Ok(()) => repaired_count += 1,
+        Err(err) => log::error!("Failed to repair orphan topic: {:?}, err: {:?}", topic, err),
+      }
+    }
+    log::warn!(
+      "Checked orphan topics: found: {:?}, repaired: {:?}, action: {:?}",
+      orphans.len(),
+      repaired_count,
+      action
+    );
+  }
+
+  // Moves the topic to the backend, or deletes it if none. Proposed by the
+  // leader without waiting, see raft::propose, or applied to self at once
+  // without raft, as TOPIC_MGR may still be being recovered.
+  fn repair_orphan(&self, topic: &Topic, backend_id: Option<NodeId>) -> Result<()> {
+    if raft::is_enabled() {
+      return raft::propose(match backend_id {
+        Some(backend_id) => Command::ReassignTopic { topic: topic.clone(), backend_id },
+        None => Command::RemoveTopic { topic: topic.clone() },
+      });
+    }
+    let _guard = self.assign_locks.lock(topic);
+    match backend_id {
+      Some(backend_id) => self.apply_reassign(topic.clone(), backend_id).map(|_| ()),
+      None => self.apply_remove(topic).map(|_| ()),
+    }
+  }
+
+  // Rebuilds the state derived from the topic dist once the backends changed at runtime
+  pub(crate) fn on_backends_changed(&self) {
+    let backends_changed = self.check();
+    self.cache.clear();
+    self.topic_counts.clear();
+    self.namespace_topic_counts.iter_mut().for_each(|mut count| *count = 0);
+    self.recover_topic_counts();
+    if backends_changed {
+      self.check_orphans();
+    }
+    self.update_version();
+  }
+
+  #[inline]
+  pub(crate) fn reload_quota(&self) {
+    self.creation_quota.reload(&CONFIG.load().topic_mgr);
+  }
+
+  // Returns whether the checksum of backends changed, the topics of the removed
+  // backends are then repaired by check_orphans, the others are kept
+  #[inline]
+  fn check(&self) -> bool {
+    let info_key = "backend_checksum".to_owned();
+    let curr_backend_checksum = format!("{}", BACKEND_MGR.checksum());
+    let old_backend_checksum = self.info_store.get(&info_key).unwrap();
+    if old_backend_checksum.as_ref() == Some(&curr_backend_checksum) {
+      return false;
+    }
+    if let Some(old_backend_checksum) = &old_backend_checksum {
+      log::info!(
+        "The checksum of backends changed from: [{:?}] to: [{:?}]",
+        old_backend_checksum,
+        curr_backend_checksum
+      );
+    }
+    self.info_store.put(&info_key, &curr_backend_checksum).unwrap();
+    old_backend_checksum.is_some()
+  }
+}
+
+pub static TOPIC_MGR: Lazy<TopicMgr> = Lazy::new(|| {
+  TopicMgr::new(
+    open_store::<Topic, NodeId, TopicCoder>("topic_mgr.topics").unwrap().into(),
+    open_store::<InfoKey, InfoValue, InfoCoder>("topic_mgr.infos").unwrap().into(),
+    open_store::<Topic, NodeId, TopicCoder>("topic_mgr.pins").unwrap().into(),
+    open_store::<Topic, u32, LocatedAtCoder>("topic_mgr.located_ats").unwrap().into(),
+    open_store::<String, Namespace, NamespaceCoder>("topic_mgr.namespaces").unwrap().into(),
+    build_assign_policy(CONFIG.load().topic_mgr.assign_policy),
+  )
+});
+
+// Reads the topics straight from the db, as recovering the topic mgr may
+// repair them, e.g. when the backends changed
+pub fn read_topics() -> Result<Vec<(Topic, NodeId)>> {
+  let topic_store = open_store::<Topic, NodeId, TopicCoder>("topic_mgr.topics")?;
+  Ok(dump_topic_store(&*topic_store))
+}
+
+fn dump_topic_store(topic_store: &TopicStore) -> Vec<(Topic, NodeId)> {
+  let mut assignments = vec![];
+  topic_store.scan(None, &mut |topic, backend_id| {
+    assignments.push((topic, backend_id));
+    true
+  });
+  assignments
+}
+
+pub fn spawn_gc_task() {
+  if CONFIG.load().topic_mgr.topic_ttl == 0 || CONFIG.load().topic_mgr.gc_interval == 0 {
+    return;
+  }
+  actix_web::rt::spawn(async {
+    let mut interval =
+      tokio::time::interval(Duration::from_secs(CONFIG.load().topic_mgr.gc_interval));
+    loop {
+      interval.tick().await;
+      if standby::is_standby() || raft::is_follower() {
+        continue;
+      }
+      TOPIC_MGR.gc();
+    }
+  });
+}
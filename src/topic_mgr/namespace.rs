@@ -0,0 +1,100 @@
+use std::borrow::Borrow;
+
+use bytes::{Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+use seriesdb::coder::Coder;
+
+use super::{build_assign_policy, AssignPolicy, Topic};
+use crate::{
+  config::{AssignPolicyKind, TopicNamespaceConfig, CONFIG},
+  node_mgr::{NodeId, BACKEND_MGR},
+  store::Store,
+};
+
+// Topics named like `<namespace>/<rest>` belong to the namespace if it is defined
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Namespace {
+  pub name: String,
+  pub backends: Vec<NodeId>,
+  #[serde(default)]
+  pub assign_policy: Option<AssignPolicyKind>,
+  // 0 means unlimited
+  #[serde(default)]
+  pub max_topics: u64,
+}
+
+impl From<&TopicNamespaceConfig> for Namespace {
+  fn from(config: &TopicNamespaceConfig) -> Self {
+    Namespace {
+      name: config.name.clone(),
+      backends: config.backends.clone(),
+      assign_policy: config.assign_policy,
+      max_topics: config.max_topics,
+    }
+  }
+}
+
+pub(crate) struct NamespaceEntry {
+  pub(crate) namespace: Namespace,
+  pub(crate) backend_ids: Vec<NodeId>,
+  pub(crate) assign_policy: Box<dyn AssignPolicy>,
+}
+
+impl NamespaceEntry {
+  pub(crate) fn new(namespace: Namespace) -> Self {
+    let mut backend_ids: Vec<NodeId> = namespace
+      .backends
+      .iter()
+      .filter(|backend_id| {
+        let found = BACKEND_MGR.get(backend_id).is_some();
+        if !found {
+          log::warn!(
+            "Backend of the namespace not found in config: namespace: {:?}, backend_id: {:?}",
+            namespace.name,
+            backend_id
+          );
+        }
+        found
+      })
+      .cloned()
+      .collect();
+    backend_ids.sort();
+    let assign_policy =
+      build_assign_policy(namespace.assign_policy.unwrap_or(CONFIG.load().topic_mgr.assign_policy));
+    NamespaceEntry { namespace, backend_ids, assign_policy }
+  }
+}
+
+#[inline]
+pub fn namespace_of(topic: &Topic) -> Option<&str> {
+  topic.split_once('/').map(|(namespace, _)| namespace)
+}
+
+pub(crate) type NamespaceStore = dyn Store<String, Namespace>;
+
+pub(crate) struct NamespaceCoder;
+
+impl Coder<String, Namespace> for NamespaceCoder {
+  type EncodedKey = Bytes;
+  type EncodedValue = Bytes;
+
+  #[inline(always)]
+  fn encode_key<K: Borrow<String>>(key: K) -> Self::EncodedKey {
+    BytesMut::from(key.borrow().as_bytes()).freeze()
+  }
+
+  #[inline(always)]
+  fn decode_key(key: &[u8]) -> String {
+    std::str::from_utf8(key).unwrap().to_string()
+  }
+
+  #[inline(always)]
+  fn encode_value<V: Borrow<Namespace>>(value: V) -> Self::EncodedValue {
+    bincode::serialize(value.borrow()).unwrap().into()
+  }
+
+  #[inline(always)]
+  fn decode_value(value: &[u8]) -> Namespace {
+    bincode::deserialize(value).unwrap()
+  }
+}
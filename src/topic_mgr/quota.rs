@@ -0,0 +1,243 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Mutex, RwLock};
+use std::time::Instant;
+
+use ahash::RandomState as AHasher;
+use dashmap::DashMap;
+
+use crate::config::TopicMgrConfig;
+
+// Idle buckets are purged once there are more clients than this
+const MAX_TRACKED_CLIENTS: usize = 65536;
+
+#[derive(Debug)]
+pub struct QuotaExceeded {
+  pub client: Option<IpAddr>,
+}
+
+impl fmt::Display for QuotaExceeded {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.client {
+      Some(client) => write!(f, "Exceeded the quota of creating topics: client: {}", client),
+      None => write!(f, "Exceeded the quota of creating topics"),
+    }
+  }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+pub(crate) struct TokenBucket {
+  tokens: f64,
+  refilled_at: Instant,
+}
+
+impl TokenBucket {
+  #[inline]
+  pub(crate) fn new(burst: f64) -> Self {
+    TokenBucket { tokens: burst, refilled_at: Instant::now() }
+  }
+
+  #[inline]
+  pub(crate) fn refill(&mut self, rate: f64, burst: f64) {
+    let now = Instant::now();
+    let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+    self.tokens = (self.tokens + elapsed * rate).min(burst);
+    self.refilled_at = now;
+  }
+
+  #[inline]
+  pub(crate) fn try_take(&mut self, rate: f64, burst: f64) -> bool {
+    self.refill(rate, burst);
+    if self.tokens >= 1.0 {
+      self.tokens -= 1.0;
+      true
+    } else {
+      false
+    }
+  }
+
+  #[inline]
+  pub(crate) fn put_back(&mut self, burst: f64) {
+    self.tokens = (self.tokens + 1.0).min(burst);
+  }
+
+  #[inline]
+  pub(crate) fn is_full(&self, burst: f64) -> bool {
+    self.tokens >= burst
+  }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct Limit {
+  pub(crate) rate: f64,
+  pub(crate) burst: f64,
+}
+
+impl Limit {
+  // A rate of 0 means unlimited
+  #[inline]
+  pub(crate) fn new(rate: f64, burst: f64) -> Option<Self> {
+    if rate > 0.0 {
+      Some(Limit { rate, burst: burst.max(rate).max(1.0) })
+    } else {
+      None
+    }
+  }
+}
+
+// Limits how fast new topics can be created, per client ip and globally
+pub struct CreationQuota {
+  limits: RwLock<CreationLimits>,
+  client_buckets: DashMap<IpAddr, TokenBucket, AHasher>,
+  global_bucket: Mutex<TokenBucket>,
+}
+
+#[derive(Clone, Copy)]
+struct CreationLimits {
+  client_limit: Option<Limit>,
+  global_limit: Option<Limit>,
+}
+
+impl CreationLimits {
+  #[inline]
+  fn new(config: &TopicMgrConfig) -> Self {
+    CreationLimits {
+      client_limit: Limit::new(config.client_new_topic_rate, config.client_new_topic_burst),
+      global_limit: Limit::new(config.new_topic_rate, config.new_topic_burst),
+    }
+  }
+}
+
+impl CreationQuota {
+  pub fn new(config: &TopicMgrConfig) -> Self {
+    let limits = CreationLimits::new(config);
+    CreationQuota {
+      limits: RwLock::new(limits),
+      client_buckets: DashMap::with_capacity_and_hasher(1024, AHasher::default()),
+      global_bucket: Mutex::new(TokenBucket::new(
+        limits.global_limit.map_or(0.0, |limit| limit.burst),
+      )),
+    }
+  }
+
+  // Buckets are kept, the new limits apply from the next refill on
+  pub fn reload(&self, config: &TopicMgrConfig) {
+    *self.limits.write().unwrap() = CreationLimits::new(config);
+  }
+
+  pub fn acquire(&self, client: Option<IpAddr>) -> Result<(), QuotaExceeded> {
+    let limits = *self.limits.read().unwrap();
+    if let (Some(limit), Some(ip)) = (limits.client_limit, client) {
+      if self.client_buckets.len() > MAX_TRACKED_CLIENTS {
+        self.purge_idle_clients(limit);
+      }
+      let mut bucket =
+        self.client_buckets.entry(ip).or_insert_with(|| TokenBucket::new(limit.burst));
+      if !bucket.try_take(limit.rate, limit.burst) {
+        return Err(QuotaExceeded { client });
+      }
+    }
+    if let Some(limit) = limits.global_limit {
+      if !self.global_bucket.lock().unwrap().try_take(limit.rate, limit.burst) {
+        self.refund_client(limits, client);
+        return Err(QuotaExceeded { client: None });
+      }
+    }
+    Ok(())
+  }
+
+  // Gives back what acquire took, for the topics which failed to be created
+  pub fn refund(&self, client: Option<IpAddr>) {
+    let limits = *self.limits.read().unwrap();
+    self.refund_client(limits, client);
+    if let Some(limit) = limits.global_limit {
+      self.global_bucket.lock().unwrap().put_back(limit.burst);
+    }
+  }
+
+  #[inline]
+  fn refund_client(&self, limits: CreationLimits, client: Option<IpAddr>) {
+    if let (Some(limit), Some(ip)) = (limits.client_limit, client) {
+      if let Some(mut bucket) = self.client_buckets.get_mut(&ip) {
+        bucket.put_back(limit.burst);
+      }
+    }
+  }
+
+  // A full bucket is the same as no bucket
+  fn purge_idle_clients(&self, limit: Limit) {
+    self.client_buckets.retain(|_, bucket| {
+      bucket.refill(limit.rate, limit.burst);
+      !bucket.is_full(limit.burst)
+    });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_acquire() {
+    let config = TopicMgrConfig {
+      client_new_topic_rate: 0.001,
+      client_new_topic_burst: 2.0,
+      new_topic_rate: 0.001,
+      new_topic_burst: 3.0,
+      ..Default::default()
+    };
+    let quota = CreationQuota::new(&config);
+    let a = Some("10.0.0.1".parse().unwrap());
+    let b = Some("10.0.0.2".parse().unwrap());
+    assert!(quota.acquire(a).is_ok());
+    assert!(quota.acquire(a).is_ok());
+    assert_eq!(quota.acquire(a).unwrap_err().client, a);
+    assert!(quota.acquire(b).is_ok());
+    assert_eq!(quota.acquire(b).unwrap_err().client, None);
+  }
+
+  #[test]
+  fn test_refund() {
+    let config = TopicMgrConfig {
+      client_new_topic_rate: 0.001,
+      client_new_topic_burst: 1.0,
+      new_topic_rate: 0.001,
+      new_topic_burst: 2.0,
+      ..Default::default()
+    };
+    let quota = CreationQuota::new(&config);
+    let a = Some("10.0.0.1".parse().unwrap());
+    let b = Some("10.0.0.2".parse().unwrap());
+    let c = Some("10.0.0.3".parse().unwrap());
+    assert!(quota.acquire(a).is_ok());
+    quota.refund(a);
+    assert!(quota.acquire(a).is_ok());
+    assert!(quota.acquire(b).is_ok());
+    // The token of c is given back once the global bucket is empty
+    assert_eq!(quota.acquire(c).unwrap_err().client, None);
+    quota.refund(b);
+    assert!(quota.acquire(c).is_ok());
+  }
+
+  #[test]
+  fn test_reload() {
+    let quota = CreationQuota::new(&TopicMgrConfig::default());
+    quota.reload(&TopicMgrConfig {
+      client_new_topic_rate: 0.001,
+      client_new_topic_burst: 1.0,
+      ..Default::default()
+    });
+    let a = Some("10.0.0.1".parse().unwrap());
+    assert!(quota.acquire(a).is_ok());
+    assert_eq!(quota.acquire(a).unwrap_err().client, a);
+  }
+
+  #[test]
+  fn test_unlimited() {
+    let quota = CreationQuota::new(&TopicMgrConfig::default());
+    for _ in 0..1000 {
+      assert!(quota.acquire(Some("10.0.0.1".parse().unwrap())).is_ok());
+    }
+  }
+}
@@ -0,0 +1,32 @@
+use std::sync::{Mutex, MutexGuard};
+
+// Enough to rarely make different new topics wait for each other
+const STRIPE_COUNT: usize = 256;
+
+// Serializes the assignments of the same topic, so that the concurrent locates
+// of a new topic coalesce into one assignment: the first caller assigns it,
+// the others find it assigned once they get the lock. Reassignments, removals
+// and gc take it too, so that gc never deletes a topic which is being assigned.
+// Topics share a fixed number of locks to bound the memory.
+pub struct AssignLocks {
+  stripes: Vec<Mutex<()>>,
+}
+
+impl AssignLocks {
+  #[inline]
+  pub fn new() -> Self {
+    AssignLocks { stripes: (0..STRIPE_COUNT).map(|_| Mutex::new(())).collect() }
+  }
+
+  #[inline]
+  pub fn lock(&self, topic: &str) -> MutexGuard<'_, ()> {
+    let stripe = &self.stripes[Self::stripe_of(topic)];
+    // Nothing is guarded but the order, so a poisoned lock is still usable
+    stripe.lock().unwrap_or_else(|err| err.into_inner())
+  }
+
+  #[inline]
+  fn stripe_of(topic: &str) -> usize {
+    crc32fast::hash(topic.as_bytes()) as usize % STRIPE_COUNT
+  }
+}
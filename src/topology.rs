@@ -0,0 +1,189 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use serde::Serialize;
+
+use crate::{
+  node_mgr::{BACKEND_MGR, FRONTEND_MGR, SERVICE_MGR},
+  route_mgr::ROUTE_MGR,
+  topic_mgr::TOPIC_MGR,
+};
+
+const MASTER_ID: &str = "master";
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TopologyFormat {
+  // Nodes and an adjacency list, for the visualization tools reading json
+  Json,
+  // As rendered by graphviz, e.g. `dot -Tsvg`
+  Dot,
+}
+
+impl TopologyFormat {
+  #[inline]
+  pub fn content_type(self) -> &'static str {
+    match self {
+      TopologyFormat::Json => "application/json",
+      TopologyFormat::Dot => "text/vnd.graphviz",
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TopologyNode {
+  pub id: String,
+  pub kind: &'static str,
+  pub label: String,
+  // None for the paths and topics
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub is_healthy: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TopologyEdge {
+  pub to: String,
+  // The method, for the edges from the services to their paths
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub label: Option<String>,
+}
+
+// The master to the frontends, backends and services, the services to the
+// paths they serve, and the topics to the backends they are assigned to. The
+// ids are prefixed by the kind, as a service and a backend may share one.
+#[derive(Debug, Default, Serialize)]
+pub struct Topology {
+  pub nodes: Vec<TopologyNode>,
+  pub adjacency: BTreeMap<String, Vec<TopologyEdge>>,
+}
+
+impl Topology {
+  // Reads the topics from the db, which may be many
+  pub fn current() -> Self {
+    let mut topology = Topology::default();
+    topology.add_node(MASTER_ID.to_owned(), "master", MASTER_ID.to_owned(), None);
+    for frontend in FRONTEND_MGR.iter() {
+      let id = node_id("frontend", &frontend.id);
+      topology.add_node(id.clone(), "frontend", frontend.id.clone(), Some(frontend.is_healthy()));
+      topology.add_edge(MASTER_ID, id, None);
+    }
+    for backend in BACKEND_MGR.iter() {
+      let id = node_id("backend", &backend.id);
+      topology.add_node(id.clone(), "backend", backend.id.clone(), Some(backend.is_healthy()));
+      topology.add_edge(MASTER_ID, id, None);
+    }
+    for service in SERVICE_MGR.iter() {
+      let id = node_id("service", &service.id);
+      topology.add_node(id.clone(), "service", service.id.clone(), Some(service.is_healthy()));
+      topology.add_edge(MASTER_ID, id, None);
+    }
+    for reverse_route_group in ROUTE_MGR.reverse_route_group_iter() {
+      let service_id = node_id("service", reverse_route_group.key());
+      for (method, paths) in reverse_route_group.value().path_sets() {
+        for path in paths {
+          let id = node_id("path", path);
+          if !topology.adjacency.contains_key(&id) {
+            topology.add_node(id.clone(), "path", path.clone(), None);
+          }
+          topology.add_edge(&service_id, id, Some(method.to_owned()));
+        }
+      }
+    }
+    for (topic, backend_id) in TOPIC_MGR.dump() {
+      let id = node_id("topic", &topic);
+      topology.add_node(id.clone(), "topic", topic, None);
+      topology.add_edge(&id, node_id("backend", &backend_id), None);
+    }
+    topology
+  }
+
+  #[inline]
+  fn add_node(&mut self, id: String, kind: &'static str, label: String, is_healthy: Option<bool>) {
+    self.adjacency.entry(id.clone()).or_default();
+    self.nodes.push(TopologyNode { id, kind, label, is_healthy });
+  }
+
+  #[inline]
+  fn add_edge(&mut self, from: &str, to: String, label: Option<String>) {
+    self.adjacency.entry(from.to_owned()).or_default().push(TopologyEdge { to, label });
+  }
+
+  // A node per line, then an edge per line, the unhealthy nodes dashed
+  pub fn to_dot(&self) -> String {
+    let mut buf = String::with_capacity(4096);
+    buf.push_str("digraph maxwell {\n  rankdir=LR;\n");
+    for node in &self.nodes {
+      let _ = write!(
+        buf,
+        "  \"{}\" [label=\"{}\", shape={}",
+        escape(&node.id),
+        escape(&node.label),
+        shape_of(node.kind)
+      );
+      if node.is_healthy == Some(false) {
+        buf.push_str(", style=dashed");
+      }
+      buf.push_str("];\n");
+    }
+    for (from, edges) in &self.adjacency {
+      for edge in edges {
+        let _ = write!(buf, "  \"{}\" -> \"{}\"", escape(from), escape(&edge.to));
+        if let Some(label) = &edge.label {
+          let _ = write!(buf, " [label=\"{}\"]", escape(label));
+        }
+        buf.push_str(";\n");
+      }
+    }
+    buf.push_str("}\n");
+    buf
+  }
+}
+
+#[inline]
+fn node_id(kind: &str, id: &str) -> String {
+  format!("{}:{}", kind, id)
+}
+
+#[inline]
+fn shape_of(kind: &str) -> &'static str {
+  match kind {
+    "master" => "doubleoctagon",
+    "path" => "note",
+    "topic" => "ellipse",
+    _ => "box",
+  }
+}
+
+#[inline]
+fn escape(s: &str) -> String {
+  s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_to_dot() {
+    let mut topology = Topology::default();
+    topology.add_node(MASTER_ID.to_owned(), "master", MASTER_ID.to_owned(), None);
+    topology.add_node("service:s-0".to_owned(), "service", "s-0".to_owned(), Some(false));
+    topology.add_node("path:/a\"b".to_owned(), "path", "/a\"b".to_owned(), None);
+    topology.add_edge(MASTER_ID, "service:s-0".to_owned(), None);
+    topology.add_edge("service:s-0", "path:/a\"b".to_owned(), Some("get".to_owned()));
+    assert_eq!(
+      topology.to_dot(),
+      [
+        "digraph maxwell {",
+        "  rankdir=LR;",
+        "  \"master\" [label=\"master\", shape=doubleoctagon];",
+        "  \"service:s-0\" [label=\"s-0\", shape=box, style=dashed];",
+        "  \"path:/a\\\"b\" [label=\"/a\\\"b\", shape=note];",
+        "  \"master\" -> \"service:s-0\";",
+        "  \"service:s-0\" -> \"path:/a\\\"b\" [label=\"get\"];",
+        "}\n",
+      ]
+      .join("\n")
+    );
+    assert_eq!(topology.adjacency["path:/a\"b"], vec![]);
+  }
+}
@@ -0,0 +1,76 @@
+use std::{
+  future::Future,
+  pin::Pin,
+  task::{Context, Poll},
+};
+
+use opentelemetry::Context as SpanContext;
+use rand::{thread_rng, Rng};
+
+// The mdc key which log4rs.yaml refers to as {X(trace_id)}
+const TRACE_ID_KEY: &str = "trace_id";
+
+// Lowercase, as header names are case insensitive
+pub const TRACE_ID_HEADER: &str = "x-trace-id";
+
+// Generated for the requests coming without one
+#[inline]
+pub fn new_trace_id() -> String {
+  format!("{:016x}", thread_rng().gen::<u64>())
+}
+
+// Of the req being handled on this thread, if any
+#[inline]
+pub fn current_trace_id() -> Option<String> {
+  log_mdc::get(TRACE_ID_KEY, |trace_id| trace_id.map(str::to_owned))
+}
+
+// Tags the log lines of the current thread with the trace id until dropped
+pub struct TraceScope {
+  prev_trace_id: Option<String>,
+}
+
+impl TraceScope {
+  #[inline]
+  pub fn enter(trace_id: &str) -> Self {
+    TraceScope { prev_trace_id: log_mdc::insert(TRACE_ID_KEY, trace_id) }
+  }
+}
+
+impl Drop for TraceScope {
+  #[inline]
+  fn drop(&mut self) {
+    match self.prev_trace_id.take() {
+      Some(prev_trace_id) => {
+        log_mdc::insert(TRACE_ID_KEY, prev_trace_id);
+      }
+      None => {
+        log_mdc::remove(TRACE_ID_KEY);
+      }
+    }
+  }
+}
+
+// Enters the trace scope on every poll, so that the tag never leaks to the
+// other futures running on the same thread in between, and so does the span
+// current when created, so that the spans started inside are its children
+pub struct Traced<F> {
+  trace_id: String,
+  span_context: SpanContext,
+  inner: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for Traced<F> {
+  type Output = F::Output;
+
+  fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let _scope = TraceScope::enter(&self.trace_id);
+    let _span_guard = self.span_context.clone().attach();
+    self.inner.as_mut().poll(cx)
+  }
+}
+
+#[inline]
+pub fn traced<F: Future>(trace_id: String, inner: F) -> Traced<F> {
+  Traced { trace_id, span_context: SpanContext::current(), inner: Box::pin(inner) }
+}
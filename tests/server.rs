@@ -0,0 +1,44 @@
+use awc::ws;
+use futures::{SinkExt, StreamExt};
+use maxwell_master::{
+  config::{Config, DbEngine},
+  MasterServer,
+};
+use maxwell_protocol::{self, *};
+
+// The master is process wide, so every check shares the one started here
+#[actix_web::test]
+async fn test_server() {
+  let mut config = Config::default();
+  config.server.http_port = 0;
+  config.server.enable_https = false;
+  config.db.engine = DbEngine::Memory;
+  let master = MasterServer::builder().config(config).start().await.unwrap();
+  let addr = master.http_addr().unwrap();
+  assert_ne!(addr.port(), 0);
+  assert!(MasterServer::builder().start().await.is_err());
+
+  let rep = awc::Client::new().get(format!("http://{}/$ready", addr)).send().await.unwrap();
+  assert!(rep.status().is_success());
+
+  let (_, mut framed) =
+    awc::Client::new().ws(format!("ws://{}/$ws", addr)).connect().await.unwrap();
+  let req = RegisterServiceReq {
+    id: "service-0".to_owned(),
+    http_port: 10000,
+    r#ref: 1,
+    ..Default::default()
+  }
+  .into_enum();
+  framed.send(ws::Message::Binary(maxwell_protocol::encode(&req).into())).await.unwrap();
+  let rep = loop {
+    if let ws::Frame::Binary(bytes) = framed.next().await.unwrap().unwrap() {
+      break maxwell_protocol::decode(&bytes.into()).unwrap();
+    }
+  };
+  assert!(matches!(rep, ProtocolMsg::RegisterServiceRep(rep) if rep.r#ref == 1));
+  assert!(master.service_mgr().get(&"service-0".to_owned()).is_some());
+
+  framed.send(ws::Message::Close(None)).await.unwrap();
+  master.stop().await.unwrap();
+}